@@ -0,0 +1,72 @@
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+//////////////// Embedded Assets ////////////////
+// Generates the default assets src/assets.rs embeds in the binary into OUT_DIR: the compiled shaders
+// in `shaders/` and a checkerboard to stand in for missing textures, with its size.
+
+/// Side of the checkerboard, in pixels, and of its squares.
+const CHECKERBOARD_SIZE: u32 = 64;
+const CHECKER_SIZE: u32 = 8;
+/// The checkerboard's colors (RGBA), magenta so it stands out.
+const CHECKER_COLORS: [[u8; 4]; 2] = [[255, 0, 255, 255], [0, 0, 0, 255]];
+
+fn main() {
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").expect("OUT_DIR isn't set"));
+    let shader_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("shaders");
+
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed={}", shader_dir.display());
+    embed_shaders(&shader_dir, &out_dir.join("embedded_shaders.rs"));
+    write_checkerboard(
+        &out_dir.join("checkerboard.rgba"),
+        &out_dir.join("checkerboard_size.rs"),
+    );
+}
+
+/// Write a list of every `.spv` file in `shader_dir`, by name, included with include_bytes!.
+fn embed_shaders(shader_dir: &Path, output: &Path) {
+    let mut shaders = fs::read_dir(shader_dir)
+        .map(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().is_some_and(|extension| extension == "spv"))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    shaders.sort();
+
+    let mut code = String::from("pub static EMBEDDED_SHADERS: &[(&str, &[u8])] = &[\n");
+    for path in shaders.iter() {
+        println!("cargo:rerun-if-changed={}", path.display());
+        let name = path.file_name().unwrap().to_string_lossy();
+        writeln!(
+            code,
+            "    ({:?}, include_bytes!({:?})),",
+            name,
+            path.display().to_string()
+        )
+        .unwrap();
+    }
+    code.push_str("];\n");
+    fs::write(output, code).expect("Failed to write the embedded shader list");
+}
+
+/// Write CHECKERBOARD_SIZE squared tightly packed RGBA pixels of CHECKER_COLORS, and the constant
+/// CHECKERBOARD_SIZE for src/assets.rs to include.
+fn write_checkerboard(output: &Path, size_output: &Path) {
+    let pixels = (0..CHECKERBOARD_SIZE)
+        .flat_map(|y| (0..CHECKERBOARD_SIZE).map(move |x| (x, y)))
+        .flat_map(|(x, y)| CHECKER_COLORS[((x / CHECKER_SIZE + y / CHECKER_SIZE) % 2) as usize])
+        .collect::<Vec<u8>>();
+    fs::write(output, pixels).expect("Failed to write the checkerboard texture");
+
+    let code = format!(
+        "/// Side of `CHECKERBOARD`, in pixels.\npub const CHECKERBOARD_SIZE: u32 = {};\n",
+        CHECKERBOARD_SIZE
+    );
+    fs::write(size_output, code).expect("Failed to write the checkerboard size");
+}
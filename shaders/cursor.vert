@@ -0,0 +1,17 @@
+#version 450
+
+// Where the cursor tip is (NDC), and the size of one pixel (NDC), so the cursor is drawn in pixels.
+layout(push_constant) uniform PushConstants {
+    vec2 position;
+    vec2 pixelSize;
+} pc;
+
+layout(location = 0) in vec2 inPosition;
+layout(location = 1) in vec3 inColor;
+
+layout(location = 0) out vec3 fragColor;
+
+void main() {
+    gl_Position = vec4(pc.position + inPosition * pc.pixelSize, 0.0, 1.0);
+    fragColor = inColor;
+}
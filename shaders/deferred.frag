@@ -0,0 +1,34 @@
+#version 450
+
+// The deferred backend's lighting subpass: shades every pixel the geometry subpass covered from the G-buffer,
+// with a point light hovering over the top left of the screen. The G-buffer's color is read as an input
+// attachment, only ever at the pixel being shaded, so tiled GPUs can keep it on chip (see render_pass.rs).
+layout(input_attachment_index = 0, set = 0, binding = 0) uniform subpassInput albedo;
+
+layout(push_constant) uniform PushConstants {
+    // Of the targets, in pixels.
+    vec2 size;
+} pc;
+
+layout(location = 0) out vec4 outColor;
+
+// Where the light is, as a fraction of the screen, how high above it (in pixels) and how far it reaches.
+const vec2 LIGHT_POSITION = vec2(0.3, 0.25);
+const float LIGHT_HEIGHT = 150.0;
+const float LIGHT_RANGE = 500.0;
+const float AMBIENT = 0.2;
+
+void main() {
+    vec4 surface = subpassLoad(albedo);
+    if (surface.a == 0.0) {
+        discard;
+    }
+
+    vec3 toLight = vec3(LIGHT_POSITION * pc.size - gl_FragCoord.xy, LIGHT_HEIGHT);
+    // The surface faces the camera, so the normal points straight at the light's height.
+    float diffuse = normalize(toLight).z;
+    float falloff = 1.0 / (1.0 + dot(toLight, toLight) / (LIGHT_RANGE * LIGHT_RANGE));
+
+    // Alpha 1 marks the pixel as lit, the composite discards the others.
+    outColor = vec4(surface.rgb * (AMBIENT + (1.0 - AMBIENT) * diffuse * falloff), 1.0);
+}
@@ -0,0 +1,21 @@
+#version 450
+
+// The deferred backend's composite, drawn over the screen in the main pass: copies what the lighting
+// subpass lit (see shaders/deferred.frag), and writes the G-buffer's depth, so whatever the main pass
+// draws afterwards is depth tested against the scene. Read a texel at a time as storage images,
+// so no samplers are needed.
+layout(set = 0, binding = 0, rgba8) uniform readonly image2D lit;
+layout(set = 0, binding = 1, r32f) uniform readonly image2D depth;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    ivec2 pixel = ivec2(gl_FragCoord.xy);
+    vec4 color = imageLoad(lit, pixel);
+    if (color.a == 0.0) {
+        discard;
+    }
+
+    outColor = color;
+    gl_FragDepth = imageLoad(depth, pixel).r;
+}
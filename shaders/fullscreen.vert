@@ -0,0 +1,8 @@
+#version 450
+
+// The resolve passes of the deferred and visibility buffer backends (see backend.rs) run once per pixel,
+// so all they draw is one triangle covering the whole screen. No vertex buffer needed.
+void main() {
+    vec2 position = vec2(float((gl_VertexIndex << 1) & 2), float(gl_VertexIndex & 2)) * 2.0 - 1.0;
+    gl_Position = vec4(position, 0.0, 1.0);
+}
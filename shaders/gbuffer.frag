@@ -0,0 +1,15 @@
+#version 450
+
+// The deferred backend's geometry pass (after shaders/shader.vert): instead of shading, write what the
+// lighting pass needs into the G-buffer. The scene is flat and faces the camera, so the normal is implied
+// and only the color and depth are stored.
+layout(location = 0) in vec3 fragColor;
+
+layout(location = 0) out vec4 outAlbedo;
+layout(location = 1) out float outDepth;
+
+void main() {
+    // Alpha 1 marks the pixel as covered, the lighting pass discards the others.
+    outAlbedo = vec4(fragColor, 1.0);
+    outDepth = gl_FragCoord.z;
+}
@@ -0,0 +1,8 @@
+#version 450
+
+// Corners of the plane's patches, passed on to the tessellation control shader as they are.
+layout(location = 0) in vec2 inPosition;
+
+void main() {
+    gl_Position = vec4(inPosition, 0.5, 1.0);
+}
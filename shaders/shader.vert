@@ -0,0 +1,23 @@
+#version 450
+
+layout(binding = 0) uniform UniformBufferObject {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+} ubo;
+
+layout(location = 0) in vec2 inPosition;
+layout(location = 1) in vec3 inColor;
+// Per instance: where this copy of the mesh goes, and how big it is.
+layout(location = 2) in vec2 inOffset;
+layout(location = 3) in float inScale;
+
+layout(location = 0) out vec3 fragColor;
+
+void main() {
+    // Each copy is transformed by the model matrix around its own origin before being placed.
+    vec4 local = ubo.model * vec4(inPosition, 0.0, 1.0);
+    vec4 placed = vec4(local.xy * inScale + inOffset, local.z, 1.0);
+    gl_Position = ubo.proj * ubo.view * placed;
+    fragColor = inColor;
+}
@@ -0,0 +1,13 @@
+#version 450
+
+// Image and sampler are bound separately (SAMPLED_IMAGE and SAMPLER descriptors).
+layout(set = 0, binding = 0) uniform textureCube skyboxTexture;
+layout(set = 0, binding = 1) uniform sampler skyboxSampler;
+
+layout(location = 0) in vec3 direction;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    outColor = texture(samplerCube(skyboxTexture, skyboxSampler), direction);
+}
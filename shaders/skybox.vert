@@ -0,0 +1,16 @@
+#version 450
+
+// Maps clip space positions to directions into the cubemap, e.g. the inverse of the view rotation times projection.
+layout(push_constant) uniform PushConstants {
+    mat4 inverseViewProjection;
+} pc;
+
+layout(location = 0) out vec3 direction;
+
+void main() {
+    // One triangle covering the whole screen, no vertex buffer needed.
+    vec2 position = vec2(float((gl_VertexIndex << 1) & 2), float(gl_VertexIndex & 2)) * 2.0 - 1.0;
+    // On the far plane, so anything drawn before it is in front.
+    gl_Position = vec4(position, 1.0, 1.0);
+    direction = (pc.inverseViewProjection * vec4(position, 1.0, 1.0)).xyz;
+}
@@ -0,0 +1,14 @@
+#version 450
+
+// Image and sampler are bound separately (SAMPLED_IMAGE and SAMPLER descriptors), like the skybox's.
+layout(set = 0, binding = 0) uniform texture2D quadTexture;
+layout(set = 0, binding = 1) uniform sampler quadSampler;
+
+layout(location = 0) in vec3 fragColor;
+layout(location = 1) in vec2 fragTexCoord;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    outColor = texture(sampler2D(quadTexture, quadSampler), fragTexCoord) * vec4(fragColor, 1.0);
+}
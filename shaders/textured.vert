@@ -0,0 +1,20 @@
+#version 450
+
+// Where the quad's center goes and how big it is, both in NDC.
+layout(push_constant) uniform PushConstants {
+    vec2 position;
+    vec2 size;
+} pc;
+
+layout(location = 0) in vec2 inPosition;
+layout(location = 1) in vec3 inColor;
+
+layout(location = 0) out vec3 fragColor;
+layout(location = 1) out vec2 fragTexCoord;
+
+void main() {
+    gl_Position = vec4(pc.position + inPosition * pc.size, 0.0, 1.0);
+    fragColor = inColor;
+    // The mesh is a unit square around the origin, Y down like texture coordinates.
+    fragTexCoord = inPosition + 0.5;
+}
@@ -0,0 +1,13 @@
+#version 450
+
+// Writes which triangle covers the pixel: its instance plus one (so 0 means none) in the high 16 bits,
+// the triangle's index in the mesh in the low 16. Reading gl_PrimitiveID needs the geometryShader feature.
+layout(location = 0) flat in uint instanceIndex;
+
+layout(location = 0) out uint outId;
+layout(location = 1) out float outDepth;
+
+void main() {
+    outId = ((instanceIndex + 1u) << 16) | (uint(gl_PrimitiveID) & 0xffffu);
+    outDepth = gl_FragCoord.z;
+}
@@ -0,0 +1,22 @@
+#version 450
+
+// The visibility buffer backend's geometry pass: positions the triangles like shaders/shader.vert,
+// but only passes on which instance they belong to. The resolve pass fetches everything else itself.
+layout(binding = 0) uniform UniformBufferObject {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+} ubo;
+
+layout(location = 0) in vec2 inPosition;
+layout(location = 2) in vec2 inOffset;
+layout(location = 3) in float inScale;
+
+layout(location = 0) flat out uint instanceIndex;
+
+void main() {
+    vec4 local = ubo.model * vec4(inPosition, 0.0, 1.0);
+    vec4 placed = vec4(local.xy * inScale + inOffset, local.z, 1.0);
+    gl_Position = ubo.proj * ubo.view * placed;
+    instanceIndex = uint(gl_InstanceIndex);
+}
@@ -0,0 +1,91 @@
+#version 450
+
+// The visibility buffer backend's resolve pass, drawn over the screen in the main pass: looks up the
+// triangle covering each pixel, fetches its vertices from the scene's buffers, transforms them again like
+// shaders/shader.vert and interpolates their colors at the pixel. Shading only ever runs once per pixel,
+// however many triangles overlap it. Writes the visibility buffer's depth, like shaders/deferred.frag.
+layout(set = 0, binding = 0) uniform UniformBufferObject {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+} ubo;
+// Read a texel at a time as storage images, so no samplers are needed.
+layout(set = 0, binding = 1, r32ui) uniform readonly uimage2D ids;
+layout(set = 0, binding = 2, r32f) uniform readonly image2D depth;
+// The scene's buffers, laid out as vertex::Vertex (position, color), u16 indices (two per word)
+// and vertex::InstanceData (offset, scale).
+layout(set = 0, binding = 3) readonly buffer Vertices {
+    float vertices[];
+};
+layout(set = 0, binding = 4) readonly buffer Indices {
+    uint indices[];
+};
+layout(set = 0, binding = 5) readonly buffer Instances {
+    float instances[];
+};
+
+layout(location = 0) out vec4 outColor;
+
+const uint VERTEX_FLOATS = 5u;
+const uint INSTANCE_FLOATS = 3u;
+
+uint vertexIndex(uint corner) {
+    uint word = indices[corner / 2u];
+    return (corner % 2u == 0u) ? (word & 0xffffu) : (word >> 16);
+}
+
+vec4 clipPosition(uint index, uint instance) {
+    vec2 position = vec2(vertices[index * VERTEX_FLOATS], vertices[index * VERTEX_FLOATS + 1u]);
+    vec2 offset = vec2(instances[instance * INSTANCE_FLOATS], instances[instance * INSTANCE_FLOATS + 1u]);
+    float scale = instances[instance * INSTANCE_FLOATS + 2u];
+    vec4 local = ubo.model * vec4(position, 0.0, 1.0);
+    vec4 placed = vec4(local.xy * scale + offset, local.z, 1.0);
+    return ubo.proj * ubo.view * placed;
+}
+
+vec3 vertexColor(uint index) {
+    uint first = index * VERTEX_FLOATS + 2u;
+    return vec3(vertices[first], vertices[first + 1u], vertices[first + 2u]);
+}
+
+float cross2(vec2 a, vec2 b) {
+    return a.x * b.y - a.y * b.x;
+}
+
+void main() {
+    ivec2 pixel = ivec2(gl_FragCoord.xy);
+    uint id = imageLoad(ids, pixel).r;
+    if (id == 0u) {
+        discard;
+    }
+    uint instance = (id >> 16) - 1u;
+    uint triangle = id & 0xffffu;
+    vec2 size = vec2(imageSize(ids));
+
+    // The corners in pixels, as the rasterizer saw them.
+    vec2 screen[3];
+    float inverseW[3];
+    vec3 colors[3];
+    for (uint corner = 0u; corner < 3u; corner++) {
+        uint index = vertexIndex(triangle * 3u + corner);
+        vec4 clip = clipPosition(index, instance);
+        inverseW[corner] = 1.0 / clip.w;
+        screen[corner] = (clip.xy * inverseW[corner] * 0.5 + 0.5) * size;
+        colors[corner] = vertexColor(index);
+    }
+
+    // Barycentric coordinates of the pixel center, corrected for perspective.
+    vec2 edge1 = screen[1] - screen[0];
+    vec2 edge2 = screen[2] - screen[0];
+    vec2 toPixel = gl_FragCoord.xy - screen[0];
+    float area = cross2(edge1, edge2);
+    float weight1 = cross2(toPixel, edge2) / area;
+    float weight2 = cross2(edge1, toPixel) / area;
+    vec3 weights = vec3(1.0 - weight1 - weight2, weight1, weight2)
+        * vec3(inverseW[0], inverseW[1], inverseW[2]);
+    weights /= weights.x + weights.y + weights.z;
+
+    vec3 color = colors[0] * weights.x + colors[1] * weights.y + colors[2] * weights.z;
+    outColor = vec4(color, 1.0);
+    gl_FragDepth = imageLoad(depth, pixel).r;
+}
@@ -0,0 +1,26 @@
+#version 450
+
+// One uint per voxel, set to 1 for every voxel a fragment lands in.
+layout(set = 0, binding = 0) buffer Voxels {
+    uint occupied[];
+} voxels;
+
+layout(push_constant) uniform PushConstants {
+    // Voxels along each side of the grid, the same as the framebuffer's width and height.
+    uint size;
+} pc;
+
+// The axis the triangle was projected along, see voxelize.vert.
+layout(location = 0) flat in uint axis;
+
+void main() {
+    // Depth 1.0 would be one slice past the grid.
+    uvec3 projected = min(uvec3(uvec2(gl_FragCoord.xy), uint(gl_FragCoord.z * float(pc.size))), uvec3(pc.size - 1u));
+    uvec3 voxel = projected;
+    if (axis == 0u) {
+        voxel = projected.zxy;
+    } else if (axis == 1u) {
+        voxel = projected.xzy;
+    }
+    voxels.occupied[voxel.x + (voxel.y + voxel.z * pc.size) * pc.size] = 1u;
+}
@@ -0,0 +1,37 @@
+#version 450
+
+// The voxel grid covers x, y and z from -1 to 1. The vertices (vertex::Vertex, a vec2 position and a vec3 color,
+// 5 floats each) are read from a buffer instead of vertex attributes, so every vertex sees its whole triangle.
+layout(set = 0, binding = 1) readonly buffer Vertices {
+    float values[];
+} vertices;
+
+// The axis the triangle is projected along: 0 for X, 1 for Y, 2 for Z (see voxelize.frag).
+layout(location = 0) flat out uint axis;
+
+vec3 vertexPosition(uint index) {
+    // The vertices are 2D, in the z = 0 plane.
+    return vec3(vertices.values[index * 5u], vertices.values[index * 5u + 1u], 0.0);
+}
+
+void main() {
+    uint first = uint(gl_VertexIndex) / 3u * 3u;
+    vec3 a = vertexPosition(first);
+    vec3 b = vertexPosition(first + 1u);
+    vec3 c = vertexPosition(first + 2u);
+    // Project along the axis the triangle faces the most, so it covers a pixel for every voxel it passes through
+    // and the depth goes through the slices.
+    vec3 normal = abs(cross(b - a, c - a));
+
+    vec3 position = vertexPosition(uint(gl_VertexIndex));
+    if (normal.x >= normal.y && normal.x >= normal.z) {
+        axis = 0u;
+        gl_Position = vec4(position.yz, position.x * 0.5 + 0.5, 1.0);
+    } else if (normal.y >= normal.z) {
+        axis = 1u;
+        gl_Position = vec4(position.xz, position.y * 0.5 + 0.5, 1.0);
+    } else {
+        axis = 2u;
+        gl_Position = vec4(position.xy, position.z * 0.5 + 0.5, 1.0);
+    }
+}
@@ -0,0 +1,92 @@
+#[cfg(feature = "asset-import")]
+use ash::{vk, Device};
+#[cfg(feature = "asset-import")]
+use std::error::Error;
+
+#[cfg(feature = "asset-import")]
+use crate::command::TransferQueue;
+#[cfg(feature = "asset-import")]
+use crate::memory::Allocator;
+#[cfg(feature = "asset-import")]
+use crate::texture::{SamplerDesc, Texture, TextureOptions};
+
+//////////////// Default Assets ////////////////
+// Built into the binary (build.rs generates them), so it runs without the asset directories:
+// every compiled shader in `shaders/`, used when its file can't be found (see `shader::load`),
+// and a checkerboard that stands in for textures that can't be found, so they show up as obviously
+// missing instead of failing. The meshes are in vertex.rs (e.g. `vertex::QUAD`).
+
+include!(concat!(env!("OUT_DIR"), "/embedded_shaders.rs"));
+
+#[cfg(feature = "asset-import")]
+include!(concat!(env!("OUT_DIR"), "/checkerboard_size.rs"));
+
+/// Magenta and black squares, tightly packed RGBA rows.
+#[cfg(feature = "asset-import")]
+pub static CHECKERBOARD: &[u8; (CHECKERBOARD_SIZE * CHECKERBOARD_SIZE * 4) as usize] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/checkerboard.rgba"));
+
+/// The SPIR-V shader `name` as it was in `shaders/` when the binary was built.
+pub fn embedded_shader(name: &str) -> Option<&'static [u8]> {
+    EMBEDDED_SHADERS
+        .iter()
+        .find(|(embedded, _)| *embedded == name)
+        .map(|(_, bytes)| *bytes)
+}
+
+/// A cubemap with `CHECKERBOARD` on every face, for a missing one, sampled like `placeholder_texture`.
+/// Blocks until the upload through `transfer_queue` is done.
+#[cfg(feature = "asset-import")]
+pub fn placeholder_cubemap(
+    device: &Device,
+    allocator: &Allocator,
+    transfer_queue: &TransferQueue,
+) -> Result<Texture, Box<dyn Error>> {
+    // Its size is checked at compile time, see `CHECKERBOARD`.
+    let face =
+        ::image::RgbaImage::from_raw(CHECKERBOARD_SIZE, CHECKERBOARD_SIZE, CHECKERBOARD.to_vec())
+            .expect("CHECKERBOARD is CHECKERBOARD_SIZE squared");
+    Texture::cubemap(
+        device,
+        allocator,
+        transfer_queue,
+        &vec![face; 6],
+        &placeholder_options().sampler,
+    )
+}
+
+/// A texture of `CHECKERBOARD`, for a texture that's missing. It's sampled with nearest filtering
+/// so the squares stay sharp. Blocks until the upload through `transfer_queue` is done.
+#[cfg(feature = "asset-import")]
+pub fn placeholder_texture(
+    device: &Device,
+    allocator: &Allocator,
+    transfer_queue: &TransferQueue,
+) -> Result<Texture, Box<dyn Error>> {
+    let extent = vk::Extent2D {
+        width: CHECKERBOARD_SIZE,
+        height: CHECKERBOARD_SIZE,
+    };
+    Texture::from_rgba(
+        device,
+        allocator,
+        transfer_queue,
+        extent,
+        CHECKERBOARD,
+        &placeholder_options(),
+    )
+}
+
+/// Nearest filtering and a single mip, so the squares stay sharp.
+#[cfg(feature = "asset-import")]
+fn placeholder_options() -> TextureOptions {
+    TextureOptions {
+        mipmapped: false,
+        sampler: SamplerDesc {
+            mag_filter: vk::Filter::NEAREST,
+            min_filter: vk::Filter::NEAREST,
+            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+            ..SamplerDesc::default()
+        },
+    }
+}
@@ -0,0 +1,401 @@
+use ash::vk;
+use std::collections::{HashMap, HashSet};
+
+//////////////// Submission Audit ////////////////
+// The validation layers catch most sync mistakes, but only as log messages that are easy to miss,
+// and some (e.g. waiting on a fence that will never signal) just hang.
+// In debug builds the frame loop reports what it does with command buffers, semaphores and fences here,
+// and anything out of order panics right away with a readable message.
+// In release builds every method returns immediately.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CommandBufferState {
+    Recording,
+    Executable,
+    Pending,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FenceState {
+    Unsignaled,
+    Pending,
+    Signaled,
+}
+
+#[derive(Default)]
+pub struct SubmissionAudit {
+    command_buffers: HashMap<vk::CommandBuffer, CommandBufferState>,
+    // Binary semaphores with a signal operation queued that nothing has waited on yet.
+    pending_signals: HashSet<vk::Semaphore>,
+    fences: HashMap<vk::Fence, FenceState>,
+    // Command buffers that become reusable once the fence of their submission has signaled.
+    fence_command_buffers: HashMap<vk::Fence, Vec<vk::CommandBuffer>>,
+    // Command buffers submitted without a fence (e.g. the graphics and compute parts of a chained frame).
+    // They are semaphore-chained into the next fenced submission, so they become reusable with its fence.
+    unfenced_command_buffers: Vec<vk::CommandBuffer>,
+}
+
+impl SubmissionAudit {
+    fn enabled() -> bool {
+        cfg!(debug_assertions)
+    }
+
+    /// A fence was created (signaled or not).
+    pub fn fence_created(&mut self, fence: vk::Fence, signaled: bool) {
+        if !Self::enabled() {
+            return;
+        }
+        let state = if signaled {
+            FenceState::Signaled
+        } else {
+            FenceState::Unsignaled
+        };
+        self.fences.insert(fence, state);
+    }
+
+    /// A wait on `fence` returned successfully.
+    pub fn fence_waited(&mut self, fence: vk::Fence) {
+        if !Self::enabled() {
+            return;
+        }
+        match self.fences.get(&fence) {
+            Some(FenceState::Unsignaled) => panic!(
+                "Waited on fence {:?} which was reset but never submitted; this would block forever.",
+                fence
+            ),
+            None => panic!("Waited on unknown fence {:?}.", fence),
+            _ => {}
+        }
+        self.fences.insert(fence, FenceState::Signaled);
+
+        // The GPU is done with everything that was submitted with this fence.
+        for command_buffer in self
+            .fence_command_buffers
+            .remove(&fence)
+            .unwrap_or_default()
+        {
+            self.command_buffers
+                .insert(command_buffer, CommandBufferState::Executable);
+        }
+    }
+
+    /// `fence` was reset.
+    pub fn fence_reset(&mut self, fence: vk::Fence) {
+        if !Self::enabled() {
+            return;
+        }
+        assert!(
+            self.fences.get(&fence) != Some(&FenceState::Pending),
+            "Reset fence {:?} while its submission is still pending.",
+            fence
+        );
+        self.fences.insert(fence, FenceState::Unsignaled);
+    }
+
+    /// Recording into `command_buffer` is about to start.
+    pub fn command_buffer_recording(&mut self, command_buffer: vk::CommandBuffer) {
+        if !Self::enabled() {
+            return;
+        }
+        assert!(
+            self.command_buffers.get(&command_buffer) != Some(&CommandBufferState::Pending),
+            "Re-recording command buffer {:?} while the GPU may still be executing it.",
+            command_buffer
+        );
+        self.command_buffers
+            .insert(command_buffer, CommandBufferState::Recording);
+    }
+
+    /// Recording into `command_buffer` ended successfully.
+    pub fn command_buffer_recorded(&mut self, command_buffer: vk::CommandBuffer) {
+        if !Self::enabled() {
+            return;
+        }
+        self.command_buffers
+            .insert(command_buffer, CommandBufferState::Executable);
+    }
+
+    /// `vkAcquireNextImageKHR` succeeded and will signal `semaphore`.
+    pub fn acquired(&mut self, semaphore: vk::Semaphore) {
+        if !Self::enabled() {
+            return;
+        }
+        self.signal(semaphore, "acquire");
+    }
+
+    /// About to submit `command_buffers` to a queue.
+    pub fn submitting(
+        &mut self,
+        command_buffers: &[vk::CommandBuffer],
+        wait_semaphores: &[vk::Semaphore],
+        signal_semaphores: &[vk::Semaphore],
+        fence: vk::Fence,
+    ) {
+        if !Self::enabled() {
+            return;
+        }
+
+        for command_buffer in command_buffers.iter() {
+            let state = self.command_buffers.get(command_buffer);
+            assert!(
+                state == Some(&CommandBufferState::Executable),
+                "Submitting command buffer {:?} which is not executable (state: {:?}).",
+                command_buffer,
+                state
+            );
+            self.command_buffers
+                .insert(*command_buffer, CommandBufferState::Pending);
+        }
+
+        wait_semaphores
+            .iter()
+            .for_each(|semaphore| self.wait(*semaphore, "submit"));
+        signal_semaphores
+            .iter()
+            .for_each(|semaphore| self.signal(*semaphore, "submit"));
+
+        if fence != vk::Fence::null() {
+            let state = self.fences.get(&fence);
+            assert!(
+                state == Some(&FenceState::Unsignaled),
+                "Submitting with fence {:?} which hasn't been reset (state: {:?}).",
+                fence,
+                state
+            );
+            self.fences.insert(fence, FenceState::Pending);
+            let fenced = self
+                .unfenced_command_buffers
+                .drain(..)
+                .chain(command_buffers.iter().copied())
+                .collect();
+            self.fence_command_buffers.insert(fence, fenced);
+        } else {
+            self.unfenced_command_buffers
+                .extend_from_slice(command_buffers);
+        }
+    }
+
+    /// About to present, waiting on `wait_semaphores`.
+    pub fn presenting(&mut self, wait_semaphores: &[vk::Semaphore]) {
+        if !Self::enabled() {
+            return;
+        }
+        wait_semaphores
+            .iter()
+            .for_each(|semaphore| self.wait(*semaphore, "present"));
+    }
+
+    /// The device is idle: nothing is pending anymore.
+    pub fn device_idle(&mut self) {
+        if !Self::enabled() {
+            return;
+        }
+        for state in self.fences.values_mut() {
+            if *state == FenceState::Pending {
+                *state = FenceState::Signaled;
+            }
+        }
+        for state in self.command_buffers.values_mut() {
+            if *state == CommandBufferState::Pending {
+                *state = CommandBufferState::Executable;
+            }
+        }
+        self.fence_command_buffers.clear();
+        self.unfenced_command_buffers.clear();
+    }
+
+    /// Semaphores were destroyed (e.g. recreated with the swapchain), stop tracking them.
+    pub fn forget_semaphores(&mut self, semaphores: &[vk::Semaphore]) {
+        if !Self::enabled() {
+            return;
+        }
+        semaphores.iter().for_each(|semaphore| {
+            self.pending_signals.remove(semaphore);
+        });
+    }
+
+    fn signal(&mut self, semaphore: vk::Semaphore, operation: &str) {
+        assert!(
+            self.pending_signals.insert(semaphore),
+            "{} signals binary semaphore {:?} which already has a signal pending.",
+            operation,
+            semaphore
+        );
+    }
+
+    fn wait(&mut self, semaphore: vk::Semaphore, operation: &str) {
+        assert!(
+            self.pending_signals.remove(&semaphore),
+            "{} waits on semaphore {:?} which has no signal pending; it would never be signaled.",
+            operation,
+            semaphore
+        );
+    }
+}
+
+#[cfg(all(test, debug_assertions))]
+mod tests {
+    use super::*;
+    use ash::vk::Handle;
+
+    fn recorded(audit: &mut SubmissionAudit, raw: u64) -> vk::CommandBuffer {
+        let command_buffer = vk::CommandBuffer::from_raw(raw);
+        audit.command_buffer_recording(command_buffer);
+        audit.command_buffer_recorded(command_buffer);
+        command_buffer
+    }
+
+    #[test]
+    fn record_submit_wait_rerecord() {
+        let mut audit = SubmissionAudit::default();
+        let fence = vk::Fence::from_raw(1);
+        audit.fence_created(fence, false);
+        let command_buffer = recorded(&mut audit, 1);
+
+        audit.submitting(&[command_buffer], &[], &[], fence);
+        audit.fence_waited(fence);
+        audit.command_buffer_recording(command_buffer);
+    }
+
+    #[test]
+    #[should_panic(expected = "Re-recording")]
+    fn rerecord_before_wait() {
+        let mut audit = SubmissionAudit::default();
+        let fence = vk::Fence::from_raw(1);
+        audit.fence_created(fence, false);
+        let command_buffer = recorded(&mut audit, 1);
+
+        audit.submitting(&[command_buffer], &[], &[], fence);
+        audit.command_buffer_recording(command_buffer);
+    }
+
+    #[test]
+    #[should_panic(expected = "not executable")]
+    fn submit_while_recording() {
+        let mut audit = SubmissionAudit::default();
+        let command_buffer = vk::CommandBuffer::from_raw(1);
+        audit.command_buffer_recording(command_buffer);
+        audit.submitting(&[command_buffer], &[], &[], vk::Fence::null());
+    }
+
+    #[test]
+    #[should_panic(expected = "hasn't been reset")]
+    fn submit_with_signaled_fence() {
+        let mut audit = SubmissionAudit::default();
+        let fence = vk::Fence::from_raw(1);
+        audit.fence_created(fence, true);
+        let command_buffer = recorded(&mut audit, 1);
+        audit.submitting(&[command_buffer], &[], &[], fence);
+    }
+
+    #[test]
+    #[should_panic(expected = "never submitted")]
+    fn wait_on_reset_fence() {
+        let mut audit = SubmissionAudit::default();
+        let fence = vk::Fence::from_raw(1);
+        audit.fence_created(fence, true);
+        audit.fence_reset(fence);
+        audit.fence_waited(fence);
+    }
+
+    #[test]
+    #[should_panic(expected = "still pending")]
+    fn reset_pending_fence() {
+        let mut audit = SubmissionAudit::default();
+        let fence = vk::Fence::from_raw(1);
+        audit.fence_created(fence, false);
+        let command_buffer = recorded(&mut audit, 1);
+        audit.submitting(&[command_buffer], &[], &[], fence);
+        audit.fence_reset(fence);
+    }
+
+    #[test]
+    #[should_panic(expected = "Re-recording")]
+    fn fenceless_chain_pending_until_fence() {
+        let mut audit = SubmissionAudit::default();
+        let fence = vk::Fence::from_raw(1);
+        audit.fence_created(fence, false);
+        let first = recorded(&mut audit, 1);
+        let second = recorded(&mut audit, 2);
+
+        audit.submitting(&[first], &[], &[], vk::Fence::null());
+        audit.submitting(&[second], &[], &[], fence);
+        audit.command_buffer_recording(first);
+    }
+
+    #[test]
+    fn fenceless_chain_released_by_fence() {
+        let mut audit = SubmissionAudit::default();
+        let fence = vk::Fence::from_raw(1);
+        audit.fence_created(fence, false);
+        let first = recorded(&mut audit, 1);
+        let second = recorded(&mut audit, 2);
+
+        audit.submitting(&[first], &[], &[], vk::Fence::null());
+        audit.submitting(&[second], &[], &[], fence);
+        audit.fence_waited(fence);
+        audit.command_buffer_recording(first);
+        audit.command_buffer_recording(second);
+    }
+
+    #[test]
+    fn fenceless_chain_released_by_device_idle() {
+        let mut audit = SubmissionAudit::default();
+        let command_buffer = recorded(&mut audit, 1);
+
+        audit.submitting(&[command_buffer], &[], &[], vk::Fence::null());
+        audit.device_idle();
+        audit.command_buffer_recording(command_buffer);
+    }
+
+    #[test]
+    #[should_panic(expected = "already has a signal pending")]
+    fn double_signal() {
+        let mut audit = SubmissionAudit::default();
+        let semaphore = vk::Semaphore::from_raw(1);
+        audit.acquired(semaphore);
+        audit.acquired(semaphore);
+    }
+
+    #[test]
+    #[should_panic(expected = "no signal pending")]
+    fn wait_without_signal() {
+        let mut audit = SubmissionAudit::default();
+        audit.presenting(&[vk::Semaphore::from_raw(1)]);
+    }
+
+    // Drives the audit the way `draw_frame` does with the async compute post process:
+    // graphics -> compute dispatch -> blit, where only the blit carries the frame fence.
+    #[test]
+    fn chained_submission_over_two_frames() {
+        let mut audit = SubmissionAudit::default();
+        let fence = vk::Fence::from_raw(1);
+        let graphics = vk::CommandBuffer::from_raw(1);
+        let dispatch = vk::CommandBuffer::from_raw(2);
+        let blit = vk::CommandBuffer::from_raw(3);
+        let image_available = vk::Semaphore::from_raw(1);
+        let scene_done = vk::Semaphore::from_raw(2);
+        let post_done = vk::Semaphore::from_raw(3);
+        let render_finished = vk::Semaphore::from_raw(4);
+        audit.fence_created(fence, true);
+
+        for _ in 0..2 {
+            audit.fence_waited(fence);
+            audit.acquired(image_available);
+            for command_buffer in [graphics, dispatch, blit] {
+                audit.command_buffer_recording(command_buffer);
+                audit.command_buffer_recorded(command_buffer);
+            }
+            audit.fence_reset(fence);
+            audit.submitting(
+                &[graphics],
+                &[image_available],
+                &[scene_done],
+                vk::Fence::null(),
+            );
+            audit.submitting(&[dispatch], &[scene_done], &[post_done], vk::Fence::null());
+            audit.submitting(&[blit], &[post_done], &[render_finished], fence);
+            audit.presenting(&[render_finished]);
+        }
+    }
+}
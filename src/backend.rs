@@ -0,0 +1,715 @@
+use ash::{vk, Device};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::buffer::Buffer;
+use crate::command::{self, Draw, DrawStatistics};
+use crate::deferred::Deferred;
+use crate::image::{self, AllocatedImage};
+use crate::memory::Allocator;
+use crate::pipeline::{self, GraphicsPipelineBuilder};
+use crate::render_pass::{RenderPassBuilder, Subpass};
+use crate::render_target::Rendering;
+use crate::settings::Args;
+use crate::shader_cache::ShaderCache;
+use crate::uniform::{UniformBufferObject, UniformBuffers};
+use crate::util::{self, AppError, DeviceDetails};
+use crate::visibility::VisibilityBuffer;
+
+//////////////// Renderer Backends ////////////////
+// How the scene's triangles are shaded is up to a backend, picked at startup (util::RENDERER_BACKEND) and
+// switchable while running, which tears the old one down and builds the new one from scratch:
+// - Forward draws and shades them right in the main pass, the way the demo always has.
+// - Deferred draws them into a G-buffer (color and depth) first, then lights every pixel they cover once
+//   in a second subpass, and copies the result into the main pass with a fullscreen draw (see deferred.rs).
+// - The visibility buffer only stores which triangle covers each pixel. Its fullscreen draw fetches and
+//   interpolates that triangle's vertices itself (see visibility.rs). A prototype, there to prove the
+//   abstraction holds for more than one way of splitting the work.
+// The geometry passes draw the same buffers, with the same uniform buffer, as the forward backend.
+// The fullscreen draws write the depth their geometry pass stored, so the rest of the main pass
+// (the displaced plane, the skybox, overlays) is depth tested against the scene as usual. Their targets
+// have one sample per pixel, so MSAA doesn't smooth the scene's edges with them, which `create` warns about.
+
+/// The backends there are, see the comment at the top.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    Forward,
+    Deferred,
+    VisibilityBuffer,
+}
+
+impl BackendKind {
+    /// The one after this, wrapping around, for switching through them with a key.
+    pub fn next(self) -> Self {
+        match self {
+            Self::Forward => Self::Deferred,
+            Self::Deferred => Self::VisibilityBuffer,
+            Self::VisibilityBuffer => Self::Forward,
+        }
+    }
+
+    /// Whether MSAA smooths the scene's edges with this backend, see the comment at the top.
+    pub fn multisampled(self) -> bool {
+        self == Self::Forward
+    }
+
+    /// Why `device` can't use this backend, None if it can.
+    pub fn unsupported_reason(self, device_details: &DeviceDetails) -> Option<&'static str> {
+        match self {
+            Self::Forward | Self::Deferred => None,
+            // For gl_PrimitiveID in fragment shaders.
+            Self::VisibilityBuffer if !device_details.geometry_shader => {
+                Some("it needs the geometryShader feature")
+            }
+            // For the scene's buffers in the resolve shader.
+            Self::VisibilityBuffer if !device_details.storage_buffer_storage_class => {
+                Some("it needs Vulkan 1.1 or VK_KHR_storage_buffer_storage_class")
+            }
+            Self::VisibilityBuffer => None,
+        }
+    }
+
+    /// From RENDERER_BACKEND_ARG in `args` (without the program name), or else RENDERER_BACKEND_ENV,
+    /// or else RENDERER_BACKEND.
+    pub fn from_args_and_env(args: &Args) -> Result<Self, Box<dyn Error>> {
+        Ok(match args.value(util::RENDERER_BACKEND_ARG)? {
+            Some(value) => value.parse()?,
+            None => match std::env::var(util::RENDERER_BACKEND_ENV) {
+                Ok(value) => value.parse()?,
+                Err(_) => util::RENDERER_BACKEND,
+            },
+        })
+    }
+}
+
+impl FromStr for BackendKind {
+    type Err = AppError;
+
+    /// The names `Display` gives, any case, with or without the dash.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().replace(['-', '_'], "").as_str() {
+            "forward" => Ok(Self::Forward),
+            "deferred" => Ok(Self::Deferred),
+            "visibilitybuffer" | "visibility" => Ok(Self::VisibilityBuffer),
+            _ => Err(AppError::new(&format!(
+                "Unknown renderer backend {:?}, it's one of forward, deferred or visibility-buffer",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Forward => "forward",
+            Self::Deferred => "deferred",
+            Self::VisibilityBuffer => "visibility-buffer",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// What the backends' pipelines are built for.
+#[derive(Clone, Copy)]
+pub struct PipelineSetup<'a> {
+    /// Where the pipelines' shader modules come from, see shader_cache.
+    pub shader_cache: &'a ShaderCache,
+    /// Of the main pass, which the fullscreen draws are part of, and its sample count.
+    pub rendering: Rendering,
+    pub samples: vk::SampleCountFlags,
+    /// The triangles' descriptor set layout (their uniform buffer), which the geometry passes use too.
+    pub triangle_layout: vk::DescriptorSetLayout,
+    /// Whether the device supports fillModeNonSolid, for wireframe variants of the geometry pipelines.
+    pub fill_mode_non_solid: bool,
+    /// Whether the main pass's draws write to the stencil buffer, see util::ENABLE_STENCIL.
+    pub stencil: bool,
+}
+
+/// Everything a backend is created with.
+pub struct BackendSetup<'a> {
+    pub device_details: &'a DeviceDetails,
+    pub allocator: &'a Allocator,
+    pub pipelines: PipelineSetup<'a>,
+    /// Of the main pass's depth buffer, the geometry passes' depth buffers get it too.
+    pub depth_format: vk::Format,
+    /// Of the swapchain, which the targets are sized like.
+    pub extent: vk::Extent2D,
+    /// One per frame in flight, as the triangles use them.
+    pub uniform_buffers: &'a UniformBuffers<UniformBufferObject>,
+    /// The triangles' vertices (vertex::Vertex), u16 indices and instances (vertex::InstanceData),
+    /// all with STORAGE_BUFFER usage besides their own.
+    pub scene: (&'a Buffer, &'a Buffer, &'a Buffer),
+}
+
+/// A way of shading the scene's triangles, see the comment at the top.
+pub trait RendererBackend {
+    fn kind(&self) -> BackendKind;
+
+    /// Record what has to happen before the main pass into `command_buffer`, which is being recorded:
+    /// e.g. drawing `triangles` (the draw the forward backend uses) into the backend's own targets,
+    /// with its own pipeline, the wireframe variant if `wireframe` is set and there is one.
+    /// Returns what was drawn, None if nothing was recorded.
+    fn record_geometry(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        triangles: &Draw,
+        wireframe: bool,
+    ) -> Option<DrawStatistics>;
+
+    /// What the main pass draws in place of `triangles` in frame in flight `frame_index`:
+    /// `triangles` themselves, or a draw resolving what `record_geometry` drew.
+    fn main_pass_draw<'a>(&'a self, frame_index: usize, triangles: &Draw<'a>) -> Draw<'a>;
+
+    /// Recreate what's sized like the swapchain for a new `extent`, e.g. after a resize.
+    /// The GPU must be done with the previous targets. Keeps them if it fails.
+    fn resize(
+        &mut self,
+        device: &Device,
+        allocator: &Allocator,
+        extent: vk::Extent2D,
+    ) -> Result<(), Box<dyn Error>>;
+
+    /// The shaders the pipelines are built from, for hot reload.
+    #[cfg(feature = "hot-reload")]
+    fn shaders(&self) -> Vec<&'static str>;
+
+    /// Rebuild the pipelines from the current `shaders`, e.g. after they changed on disk.
+    /// Their descriptor sets have to stay the same.
+    /// Keeps the previous pipelines if building fails. The GPU must be done with them.
+    #[cfg(feature = "hot-reload")]
+    fn rebuild_pipelines(
+        &mut self,
+        device: &Device,
+        setup: PipelineSetup<'_>,
+    ) -> Result<(), Box<dyn Error>>;
+
+    /// Destroy everything the backend created. The GPU must be done with it.
+    fn destroy(&mut self, device: &Device);
+}
+
+/// Create the backend of `kind`. Fails if the device doesn't support it (see `unsupported_reason`),
+/// or creating it does.
+pub fn create(
+    kind: BackendKind,
+    device: &Device,
+    setup: &BackendSetup,
+) -> Result<Box<dyn RendererBackend>, Box<dyn Error>> {
+    if let Some(reason) = kind.unsupported_reason(setup.device_details) {
+        return Err(Box::new(AppError::new(&format!(
+            "Can't use the {} renderer backend, {}",
+            kind, reason
+        ))));
+    }
+    if !kind.multisampled() && setup.pipelines.samples != vk::SampleCountFlags::TYPE_1 {
+        log::warn!(
+            "The {} renderer backend draws the scene with one sample per pixel, {:?} MSAA only applies to what's drawn over it",
+            kind,
+            setup.pipelines.samples
+        );
+    }
+    Ok(match kind {
+        BackendKind::Forward => Box::new(Forward),
+        BackendKind::Deferred => Box::new(Deferred::new(device, setup)?),
+        BackendKind::VisibilityBuffer => Box::new(VisibilityBuffer::new(device, setup)?),
+    })
+}
+
+//////////////// Forward ////////////////
+
+/// Draws the triangles in the main pass, so there's nothing to create.
+pub struct Forward;
+
+impl RendererBackend for Forward {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Forward
+    }
+
+    fn record_geometry(
+        &self,
+        _device: &Device,
+        _command_buffer: vk::CommandBuffer,
+        _triangles: &Draw,
+        _wireframe: bool,
+    ) -> Option<DrawStatistics> {
+        None
+    }
+
+    fn main_pass_draw<'a>(&'a self, _frame_index: usize, triangles: &Draw<'a>) -> Draw<'a> {
+        *triangles
+    }
+
+    fn resize(
+        &mut self,
+        _device: &Device,
+        _allocator: &Allocator,
+        _extent: vk::Extent2D,
+    ) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    #[cfg(feature = "hot-reload")]
+    fn shaders(&self) -> Vec<&'static str> {
+        Vec::new()
+    }
+
+    #[cfg(feature = "hot-reload")]
+    fn rebuild_pipelines(
+        &mut self,
+        _device: &Device,
+        _setup: PipelineSetup<'_>,
+    ) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn destroy(&mut self, _device: &Device) {}
+}
+
+//////////////// Geometry Passes ////////////////
+
+/// A geometry pass's pipeline, and its wireframe variant if the device supports fillModeNonSolid.
+pub struct GeometryPipelines {
+    filled: vk::Pipeline,
+    wireframe: Option<vk::Pipeline>,
+    // Shared by both.
+    layout: vk::PipelineLayout,
+}
+
+impl GeometryPipelines {
+    /// Build the pipelines drawing the triangles' buffers (laid out as vertex::Vertex and
+    /// vertex::InstanceData) with `shaders` (vertex and fragment) into the first `color_attachments`
+    /// of `targets`, in subpass 0. They use the triangles' descriptor set layout.
+    pub fn new(
+        device: &Device,
+        setup: PipelineSetup<'_>,
+        shaders: [&str; 2],
+        (targets, color_attachments): (&GeometryTargets, u32),
+    ) -> Result<Self, Box<dyn Error>> {
+        let set_layouts = [setup.triangle_layout];
+        // The builder's default vertex input is the triangles', shaders may leave some of it unused.
+        let builder = GraphicsPipelineBuilder::default()
+            .vertex_shader(shaders[0])
+            .fragment_shader(shaders[1])
+            .descriptor_set_layouts(&set_layouts)
+            .color_attachments(color_attachments);
+        let rendering = Rendering::RenderPass(targets.render_pass);
+        if !setup.fill_mode_non_solid {
+            let (filled, layout) = builder.build(device, setup.shader_cache, rendering)?;
+            return Ok(Self {
+                filled,
+                wireframe: None,
+                layout,
+            });
+        }
+        let (pipelines, layout) = builder.build_variants(
+            device,
+            setup.shader_cache,
+            rendering,
+            &[&|builder| builder.polygon_mode(vk::PolygonMode::LINE)],
+        )?;
+        Ok(Self {
+            filled: pipelines[0],
+            wireframe: Some(pipelines[1]),
+            layout,
+        })
+    }
+
+    /// `triangles` drawn with the filled pipeline, or the wireframe one if `wireframe` is set and there is one.
+    pub fn draw<'a>(&self, triangles: &Draw<'a>, wireframe: bool) -> Draw<'a> {
+        let pipeline = match self.wireframe {
+            Some(pipeline) if wireframe => pipeline,
+            _ => self.filled,
+        };
+        Draw {
+            pipeline,
+            pipeline_layout: self.layout,
+            ..*triangles
+        }
+    }
+
+    /// Destroy the pipelines. The GPU must be done with them.
+    pub fn destroy(&mut self, device: &Device) {
+        unsafe {
+            for pipeline in std::iter::once(self.filled).chain(self.wireframe) {
+                device.destroy_pipeline(pipeline, None);
+            }
+            device.destroy_pipeline_layout(self.layout, None);
+        }
+    }
+}
+
+/// One of a geometry pass's color targets.
+#[derive(Clone, Copy)]
+pub struct Target {
+    pub format: vk::Format,
+    pub clear_value: vk::ClearValue,
+    /// What it's used as besides a color attachment, e.g. STORAGE to be read back a texel at a time.
+    pub usage: vk::ImageUsageFlags,
+}
+
+/// The render pass, images and framebuffer a geometry pass draws into: color targets, e.g. read back
+/// a texel at a time as storage images, and a depth buffer that's only used for depth testing within
+/// the pass. One set is enough for every frame in flight, the render pass's dependencies keep a frame's
+/// geometry pass from writing them while the previous frame still reads them.
+pub struct GeometryTargets {
+    render_pass: vk::RenderPass,
+    targets: Vec<Target>,
+    // In attachment order, the depth buffer's last.
+    clear_values: Vec<vk::ClearValue>,
+    depth_format: vk::Format,
+    images: TargetImages,
+    extent: vk::Extent2D,
+}
+
+impl GeometryTargets {
+    /// Create a render pass with a single subpass drawing into `targets`, see `geometry_render_pass`,
+    /// and the targets at `extent` with a depth buffer of `depth_format`.
+    pub fn new(
+        device: &Device,
+        allocator: &Allocator,
+        targets: &[Target],
+        depth_format: vk::Format,
+        extent: vk::Extent2D,
+    ) -> Result<Self, Box<dyn Error>> {
+        let render_pass = geometry_render_pass(device, targets, depth_format)?;
+        Self::with_render_pass(
+            device,
+            allocator,
+            render_pass,
+            targets,
+            depth_format,
+            extent,
+        )
+    }
+
+    /// Take over `render_pass`, whose attachments are `targets` followed by a depth buffer of `depth_format`,
+    /// and create them at `extent`. The render pass is destroyed if that fails.
+    pub fn with_render_pass(
+        device: &Device,
+        allocator: &Allocator,
+        render_pass: vk::RenderPass,
+        targets: &[Target],
+        depth_format: vk::Format,
+        extent: vk::Extent2D,
+    ) -> Result<Self, Box<dyn Error>> {
+        let images = match TargetImages::new(
+            device,
+            allocator,
+            render_pass,
+            targets,
+            depth_format,
+            extent,
+        ) {
+            Ok(images) => images,
+            Err(err) => {
+                unsafe { device.destroy_render_pass(render_pass, None) };
+                return Err(err);
+            }
+        };
+        let depth_clear_value = vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue {
+                depth: 1.0,
+                stencil: 0,
+            },
+        };
+        Ok(Self {
+            render_pass,
+            targets: targets.to_vec(),
+            clear_values: targets
+                .iter()
+                .map(|target| target.clear_value)
+                .chain([depth_clear_value])
+                .collect(),
+            depth_format,
+            images,
+            extent,
+        })
+    }
+
+    /// Recreate the images and framebuffer at `extent`. The GPU must be done with the previous ones.
+    /// Keeps them if creating the new ones fails.
+    pub fn resize(
+        &mut self,
+        device: &Device,
+        allocator: &Allocator,
+        extent: vk::Extent2D,
+    ) -> Result<(), Box<dyn Error>> {
+        let images = TargetImages::new(
+            device,
+            allocator,
+            self.render_pass,
+            &self.targets,
+            self.depth_format,
+            extent,
+        )?;
+        std::mem::replace(&mut self.images, images).destroy(device);
+        self.extent = extent;
+        Ok(())
+    }
+
+    /// The color targets' views, in the order they were given.
+    pub fn views(&self) -> Vec<vk::ImageView> {
+        self.images
+            .targets
+            .iter()
+            .map(|target| target.view)
+            .collect()
+    }
+
+    /// For the pipelines drawing into the targets.
+    pub fn render_pass(&self) -> vk::RenderPass {
+        self.render_pass
+    }
+
+    /// The targets' size.
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    /// Record the pass into `command_buffer`, which is being recorded, with the draws of each of its
+    /// subpasses in turn. Afterwards fragment shaders can read the targets.
+    pub fn record(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        subpasses: &[&[Draw]],
+    ) -> DrawStatistics {
+        let begin_info = vk::RenderPassBeginInfo::default()
+            .render_pass(self.render_pass)
+            .framebuffer(self.images.framebuffer)
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: self.extent,
+            })
+            .clear_values(&self.clear_values);
+        command::record_subpasses(device, command_buffer, &begin_info, subpasses)
+    }
+
+    /// Destroy the render pass, images and framebuffer. The GPU must be done with them.
+    pub fn destroy(&mut self, device: &Device) {
+        std::mem::take(&mut self.images).destroy(device);
+        unsafe { device.destroy_render_pass(self.render_pass, None) };
+    }
+}
+
+/// The part of `GeometryTargets` that's sized like the swapchain.
+#[derive(Default)]
+struct TargetImages {
+    targets: Vec<AllocatedImage>,
+    depth: Option<AllocatedImage>,
+    framebuffer: vk::Framebuffer,
+}
+
+impl TargetImages {
+    /// Images for `targets` and a depth buffer of `depth_format`, all of `extent`, and a framebuffer
+    /// of `render_pass` with them in that order. Whatever was created is destroyed if something fails.
+    fn new(
+        device: &Device,
+        allocator: &Allocator,
+        render_pass: vk::RenderPass,
+        targets: &[Target],
+        depth_format: vk::Format,
+        extent: vk::Extent2D,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut images = Self::default();
+        match images.create(
+            device,
+            allocator,
+            render_pass,
+            targets,
+            depth_format,
+            extent,
+        ) {
+            Ok(()) => Ok(images),
+            Err(err) => {
+                images.destroy(device);
+                Err(err)
+            }
+        }
+    }
+
+    fn create(
+        &mut self,
+        device: &Device,
+        allocator: &Allocator,
+        render_pass: vk::RenderPass,
+        targets: &[Target],
+        depth_format: vk::Format,
+        extent: vk::Extent2D,
+    ) -> Result<(), Box<dyn Error>> {
+        for Target { format, usage, .. } in targets.iter() {
+            let (target_image, target_memory) = image::image(
+                device,
+                allocator,
+                extent,
+                *format,
+                1,
+                vk::SampleCountFlags::TYPE_1,
+                vk::ImageUsageFlags::COLOR_ATTACHMENT | *usage,
+            )?;
+            // Kept before its view exists, so it's destroyed with the others if creating that fails.
+            self.targets.push(AllocatedImage {
+                image: target_image,
+                memory: target_memory,
+                view: vk::ImageView::null(),
+                format: *format,
+                allocator: allocator.clone(),
+            });
+            let target = self.targets.last_mut().unwrap();
+            target.view = image::image_view(
+                device,
+                target_image,
+                *format,
+                vk::ImageAspectFlags::COLOR,
+                1,
+            )?;
+        }
+        let depth = self.depth.insert(image::depth_attachment(
+            device,
+            allocator,
+            extent,
+            depth_format,
+            vk::SampleCountFlags::TYPE_1,
+        )?);
+
+        let attachments = self
+            .targets
+            .iter()
+            .map(|target| target.view)
+            .chain([depth.view])
+            .collect::<Vec<_>>();
+        let framebuffer_create_info = vk::FramebufferCreateInfo::default()
+            .render_pass(render_pass)
+            .attachments(&attachments)
+            .width(extent.width)
+            .height(extent.height)
+            .layers(1);
+        self.framebuffer = unsafe { device.create_framebuffer(&framebuffer_create_info, None)? };
+        Ok(())
+    }
+
+    /// Destroy the images and framebuffer. The GPU must be done with them.
+    fn destroy(mut self, device: &Device) {
+        unsafe { device.destroy_framebuffer(self.framebuffer, None) };
+        self.targets
+            .drain(..)
+            .for_each(|mut target| target.destroy(device));
+        if let Some(mut depth) = self.depth.take() {
+            depth.destroy(device);
+        }
+    }
+}
+
+/// A single subpass drawing into color attachments for `targets` (cleared, stored and left in
+/// GENERAL) and a depth buffer of `depth_format` that's thrown away afterwards.
+fn geometry_render_pass(
+    device: &Device,
+    targets: &[Target],
+    depth_format: vk::Format,
+) -> Result<vk::RenderPass, Box<dyn Error>> {
+    let mut builder = RenderPassBuilder::default();
+    for target in targets.iter() {
+        builder = builder.attachment(
+            vk::AttachmentDescription::default()
+                .format(target.format)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .store_op(vk::AttachmentStoreOp::STORE)
+                .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .final_layout(vk::ImageLayout::GENERAL),
+        );
+    }
+    let depth = targets.len() as u32;
+    let color = (0..depth).collect::<Vec<_>>();
+    builder
+        .attachment(
+            vk::AttachmentDescription::default()
+                .format(depth_format)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL),
+        )
+        .subpass(Subpass::default().color(&color).depth_stencil(depth))
+        // The previous frame's resolve has to be done reading the targets before they're cleared,
+        // and its geometry pass done with the depth buffer.
+        .dependency(
+            vk::SubpassDependency::default()
+                .src_subpass(vk::SUBPASS_EXTERNAL)
+                .dst_subpass(0)
+                .src_stage_mask(
+                    vk::PipelineStageFlags::FRAGMENT_SHADER
+                        | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                )
+                .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                .dst_stage_mask(
+                    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                        | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                )
+                .dst_access_mask(
+                    vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                ),
+        )
+        // The resolve reads what was drawn.
+        .dependency(
+            vk::SubpassDependency::default()
+                .src_subpass(0)
+                .dst_subpass(vk::SUBPASS_EXTERNAL)
+                .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ),
+        )
+        .build(device)
+}
+
+/// Build the pipeline of a fullscreen draw in the main pass with `shaders` (shaders/fullscreen.vert and
+/// a fragment shader reading the geometry pass's targets through a set of `layout`).
+/// It writes depth whatever is there (the shader's gl_FragDepth), and stencil like the triangles do.
+pub fn resolve_pipeline(
+    device: &Device,
+    setup: PipelineSetup<'_>,
+    shaders: [&str; 2],
+    layout: vk::DescriptorSetLayout,
+) -> Result<(vk::Pipeline, vk::PipelineLayout), Box<dyn Error>> {
+    let set_layouts = [layout];
+    let mut builder = GraphicsPipelineBuilder::default()
+        .vertex_shader(shaders[0])
+        .fragment_shader(shaders[1])
+        .vertex_input(&[], &[])
+        .descriptor_set_layouts(&set_layouts)
+        .depth_compare_op(vk::CompareOp::ALWAYS)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .samples(setup.samples);
+    if setup.stencil {
+        let stencil_op = pipeline::stencil_write(1);
+        builder = builder.stencil(stencil_op, stencil_op);
+    }
+    builder.build(device, setup.shader_cache, setup.rendering)
+}
+
+/// The draw for a pipeline from `resolve_pipeline`: one triangle covering the screen.
+pub fn resolve_draw<'a>(
+    (pipeline, pipeline_layout): (vk::Pipeline, vk::PipelineLayout),
+    descriptor_set: &'a vk::DescriptorSet,
+) -> Draw<'a> {
+    Draw {
+        pipeline,
+        pipeline_layout,
+        descriptor_sets: std::slice::from_ref(descriptor_set),
+        push_descriptors: None,
+        push_constants: None,
+        vertex_buffer: None,
+        instance_buffer: None,
+        index_buffer: None,
+        vertex_count: 3,
+    }
+}
@@ -0,0 +1,221 @@
+use ash::{vk, Device};
+use std::error::Error;
+
+use crate::command::{self, Handover};
+use crate::memory::Allocator;
+use crate::sync::WaitError;
+use crate::util;
+
+//////////////// Buffers ////////////////
+
+/// A buffer together with the memory backing it.
+pub struct Buffer {
+    pub buffer: vk::Buffer,
+    pub memory: vk::DeviceMemory,
+    pub size: vk::DeviceSize,
+    // Frees the memory.
+    allocator: Allocator,
+}
+
+impl Buffer {
+    /// Create a buffer of `size` bytes backed by memory with `properties`.
+    /// DEVICE_LOCAL memory may be demoted to host visible memory, see `Allocator::allocate`.
+    pub fn new(
+        device: &Device,
+        allocator: &Allocator,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        properties: vk::MemoryPropertyFlags,
+    ) -> Result<Self, Box<dyn Error>> {
+        let buffer_create_info = vk::BufferCreateInfo::default()
+            .size(size)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+        let buffer = unsafe { device.create_buffer(&buffer_create_info, None)? };
+
+        let memory_requirements = unsafe { device.get_buffer_memory_requirements(buffer) };
+        let memory = match allocator.allocate(
+            device,
+            memory_requirements,
+            properties,
+            &format!("a {:?} buffer", usage),
+        ) {
+            Ok((memory, _)) => memory,
+            Err(err) => {
+                unsafe { device.destroy_buffer(buffer, None) };
+                return Err(err);
+            }
+        };
+        if let Err(err) = unsafe { device.bind_buffer_memory(buffer, memory, 0) } {
+            allocator.free(device, memory);
+            unsafe { device.destroy_buffer(buffer, None) };
+            return Err(Box::new(err));
+        }
+
+        Ok(Self {
+            buffer,
+            memory,
+            size,
+            allocator: allocator.clone(),
+        })
+    }
+
+    /// Create a device local buffer holding `data`, for the graphics queue family, see `Uploads`.
+    /// Blocks until the transfer is done.
+    // The demos' and the cursor's vertices, the triangles' buffers share one `Uploads`.
+    #[cfg(any(feature = "demos", feature = "ui"))]
+    pub fn device_local_with_data<T: Copy>(
+        device: &Device,
+        allocator: &Allocator,
+        transfer_queue: &command::TransferQueue,
+        usage: vk::BufferUsageFlags,
+        data: &[T],
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut uploads = Uploads::default();
+        let mut buffer = uploads.add(device, allocator, usage, data)?;
+        match uploads.submit(device, transfer_queue) {
+            Ok(()) => Ok(buffer),
+            Err(err) => {
+                buffer.destroy(device);
+                Err(err)
+            }
+        }
+    }
+
+    /// Copy `data` to the start of the buffer. The memory must be HOST_VISIBLE and HOST_COHERENT.
+    pub fn write<T: Copy>(&mut self, device: &Device, data: &[T]) -> Result<(), Box<dyn Error>> {
+        let size = std::mem::size_of_val(data) as vk::DeviceSize;
+        if size > self.size {
+            return Err(Box::new(util::AppError::new(&format!(
+                "Writing {} bytes into a buffer of {} bytes",
+                size, self.size
+            ))));
+        }
+
+        unsafe {
+            let mapped = device.map_memory(self.memory, 0, size, vk::MemoryMapFlags::empty())?;
+            std::ptr::copy_nonoverlapping(data.as_ptr(), mapped as *mut T, data.len());
+            device.unmap_memory(self.memory);
+        }
+
+        Ok(())
+    }
+
+    /// Destroy the buffer and free its memory. The GPU must be done with it.
+    /// Handles are nulled afterwards, so destroying twice is harmless.
+    pub fn destroy(&mut self, device: &Device) {
+        unsafe { device.destroy_buffer(self.buffer, None) };
+        self.allocator.free(device, self.memory);
+        self.buffer = vk::Buffer::null();
+        self.memory = vk::DeviceMemory::null();
+    }
+}
+
+//////////////// Uploads ////////////////
+
+/// Device local buffers created with data, uploaded together: the data is copied into host visible
+/// staging buffers right away, and from there with one `TransferQueue::submit` (handed over to the graphics
+/// family in the same batch, if the transfer queue is a dedicated one).
+#[derive(Default)]
+pub struct Uploads {
+    // Staging buffers, and the buffers they're copied into.
+    copies: Vec<(Buffer, vk::Buffer)>,
+}
+
+impl Uploads {
+    /// Create a device local buffer for `data`, which has it once the uploads are submitted.
+    /// If that fails, the caller destroys it.
+    pub fn add<T: Copy>(
+        &mut self,
+        device: &Device,
+        allocator: &Allocator,
+        usage: vk::BufferUsageFlags,
+        data: &[T],
+    ) -> Result<Buffer, Box<dyn Error>> {
+        let size = std::mem::size_of_val(data) as vk::DeviceSize;
+
+        let mut staging = Buffer::new(
+            device,
+            allocator,
+            size,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        let buffer = staging.write(device, data).and_then(|()| {
+            Buffer::new(
+                device,
+                allocator,
+                size,
+                usage | vk::BufferUsageFlags::TRANSFER_DST,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )
+        });
+        match buffer {
+            Ok(buffer) => {
+                self.copies.push((staging, buffer.buffer));
+                Ok(buffer)
+            }
+            Err(err) => {
+                staging.destroy(device);
+                Err(err)
+            }
+        }
+    }
+
+    /// Copy every added buffer's data into it on `transfer_queue`, and wait until that's done.
+    /// Then the staging buffers are destroyed, unless the GPU may still be using them (see
+    /// `command::one_time_submit`).
+    pub fn submit(
+        mut self,
+        device: &Device,
+        transfer_queue: &command::TransferQueue,
+    ) -> Result<(), Box<dyn Error>> {
+        // What the buffers are used for next isn't known, so the graphics family waits for them with everything.
+        let mut handover = Handover::default();
+        if let Some(transfer) = transfer_queue.transfer() {
+            for (staging, buffer) in self.copies.iter() {
+                let (release, acquire) = transfer.buffer(
+                    *buffer,
+                    0,
+                    staging.size,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                    vk::AccessFlags::MEMORY_READ,
+                );
+                handover.release.buffers.push(release);
+                handover.acquire.buffers.push(acquire);
+            }
+        }
+
+        let copies = &self.copies;
+        let result = transfer_queue.submit(
+            device,
+            move |command_buffer| {
+                for (staging, buffer) in copies.iter() {
+                    let regions = [vk::BufferCopy::default().size(staging.size)];
+                    unsafe {
+                        device.cmd_copy_buffer(command_buffer, staging.buffer, *buffer, &regions)
+                    };
+                }
+            },
+            handover,
+            |_| {},
+        );
+
+        let in_use = result
+            .as_ref()
+            .is_err_and(|err| err.downcast_ref::<WaitError>().is_some());
+        if !in_use {
+            for (staging, _) in self.copies.iter_mut() {
+                staging.destroy(device);
+            }
+        }
+        result
+    }
+    /// Destroy the staging buffers without uploading anything, e.g. because adding a buffer failed.
+    pub fn destroy(mut self, device: &Device) {
+        for (staging, _) in self.copies.iter_mut() {
+            staging.destroy(device);
+        }
+    }
+}
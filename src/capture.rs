@@ -0,0 +1,127 @@
+use std::error::Error;
+use std::fmt::Write as _;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::util::AppError;
+
+//////////////// Capture Sessions ////////////////
+// For driver bug reports: run with e.g.
+// VULKAN_ASH_CAPTURE=gfxreconstruct VULKAN_ASH_CAPTURE_FRAMES=10 cargo run
+// to enable the capture layer, render that many frames and exit. The capture goes to
+// VULKAN_ASH_CAPTURE_DIR (default `captures/`), next to a `.meta.txt` file describing the device and
+// settings it was made with, tagged with VULKAN_ASH_CAPTURE_TAG if set.
+
+pub const CAPTURE_ENV: &str = "VULKAN_ASH_CAPTURE";
+pub const CAPTURE_FRAMES_ENV: &str = "VULKAN_ASH_CAPTURE_FRAMES";
+pub const CAPTURE_DIR_ENV: &str = "VULKAN_ASH_CAPTURE_DIR";
+pub const CAPTURE_TAG_ENV: &str = "VULKAN_ASH_CAPTURE_TAG";
+const DEFAULT_FRAMES: u64 = 100;
+
+/// The layer recording the capture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureTool {
+    GfxReconstruct,
+    ApiDump,
+}
+
+impl CaptureTool {
+    pub fn layer_name(&self) -> &'static str {
+        match self {
+            Self::GfxReconstruct => "VK_LAYER_LUNARG_gfxreconstruct",
+            Self::ApiDump => "VK_LAYER_LUNARG_api_dump",
+        }
+    }
+
+    fn file_extension(&self) -> &'static str {
+        match self {
+            Self::GfxReconstruct => "gfxr",
+            Self::ApiDump => "txt",
+        }
+    }
+}
+
+/// A capture requested through the environment.
+#[derive(Debug, Clone)]
+pub struct CaptureSession {
+    pub tool: CaptureTool,
+    /// Frames to render before exiting.
+    pub frames: u64,
+    pub output: PathBuf,
+    pub tag: Option<String>,
+}
+
+impl CaptureSession {
+    /// The session requested through CAPTURE_ENV, if any. Points the layer at `output` through
+    /// its own environment variables, so call this before any other threads are started.
+    pub fn from_env() -> Result<Option<Self>, Box<dyn Error>> {
+        let tool = match std::env::var(CAPTURE_ENV).as_deref() {
+            Err(_) | Ok("") => return Ok(None),
+            Ok("gfxreconstruct") | Ok("gfxr") => CaptureTool::GfxReconstruct,
+            Ok("api_dump") | Ok("api-dump") => CaptureTool::ApiDump,
+            Ok(other) => {
+                return Err(Box::new(AppError::new(&format!(
+                    "Unknown {} {:?}, expected gfxreconstruct or api_dump",
+                    CAPTURE_ENV, other
+                ))))
+            }
+        };
+        let frames = match std::env::var(CAPTURE_FRAMES_ENV) {
+            Ok(frames) => frames.parse()?,
+            Err(_) => DEFAULT_FRAMES,
+        };
+        let directory =
+            PathBuf::from(std::env::var(CAPTURE_DIR_ENV).unwrap_or_else(|_| "captures".into()));
+        let tag = std::env::var(CAPTURE_TAG_ENV).ok();
+
+        std::fs::create_dir_all(&directory)?;
+        let started = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let output = directory.join(format!("capture-{}.{}", started, tool.file_extension()));
+
+        match tool {
+            CaptureTool::GfxReconstruct => {
+                std::env::set_var("GFXRECON_CAPTURE_FILE", &output);
+                // Keep the file name as is, so the metadata file matches it.
+                std::env::set_var("GFXRECON_CAPTURE_FILE_TIMESTAMP", "false");
+            }
+            CaptureTool::ApiDump => {
+                std::env::set_var("VK_APIDUMP_LOG_FILENAME", &output);
+                std::env::set_var("VK_APIDUMP_OUTPUT_FORMAT", "text");
+            }
+        }
+
+        let session = Self {
+            tool,
+            frames,
+            output,
+            tag,
+        };
+        log::info!(
+            "Capturing {} frames with {} into {}",
+            session.frames,
+            session.tool.layer_name(),
+            session.output.display()
+        );
+        Ok(Some(session))
+    }
+
+    /// Write `settings` (name, value pairs) next to the capture, along with the session itself.
+    pub fn write_metadata(&self, settings: &[(&str, String)]) -> Result<(), Box<dyn Error>> {
+        let mut metadata = String::new();
+        writeln!(metadata, "capture: {}", self.output.display())?;
+        writeln!(metadata, "layer: {}", self.tool.layer_name())?;
+        writeln!(metadata, "frames: {}", self.frames)?;
+        if let Some(tag) = &self.tag {
+            writeln!(metadata, "tag: {}", tag)?;
+        }
+        writeln!(metadata, "version: {}", env!("CARGO_PKG_VERSION"))?;
+        for (name, value) in settings.iter() {
+            writeln!(metadata, "{}: {}", name, value)?;
+        }
+
+        let path = self.output.with_extension("meta.txt");
+        std::fs::write(&path, metadata)?;
+        log::info!("Wrote capture metadata to {}", path.display());
+        Ok(())
+    }
+}
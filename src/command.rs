@@ -0,0 +1,732 @@
+use ash::khr::push_descriptor;
+use ash::{vk, Device};
+use std::error::Error;
+use std::fmt;
+use std::ops::AddAssign;
+
+use crate::descriptor::{self, PushedDescriptor};
+use crate::dynamic_rendering::{self, FrameAttachments};
+use crate::ownership::{QueueTransfer, TransferBarriers};
+use crate::render_target::Rendering;
+use crate::sync::{self, Cancel, Shutdown, WaitError};
+use crate::{util, vulkan_create};
+
+//////////////// Command Pool and Command Buffers ////////////////
+
+/// Create a command pool on the given queue family (e.g. the graphics family).
+/// Buffers allocated from it can be reset individually, so they can be re-recorded every frame.
+pub fn command_pool(
+    device: &Device,
+    queue_family_index: u32,
+) -> Result<vk::CommandPool, Box<dyn Error>> {
+    let command_pool_create_info = vk::CommandPoolCreateInfo::default()
+        .queue_family_index(queue_family_index)
+        .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER);
+
+    unsafe { Ok(device.create_command_pool(&command_pool_create_info, None)?) }
+}
+
+/// Allocate `count` primary command buffers (e.g. one per frame in flight).
+pub fn command_buffers(
+    device: &Device,
+    command_pool: vk::CommandPool,
+    count: u32,
+) -> Result<Vec<vk::CommandBuffer>, Box<dyn Error>> {
+    allocate(device, command_pool, vk::CommandBufferLevel::PRIMARY, count)
+}
+
+/// Allocate `count` secondary command buffers, for parts of a render pass recorded on their own
+/// (see `SecondaryPass`).
+pub fn secondary_command_buffers(
+    device: &Device,
+    command_pool: vk::CommandPool,
+    count: u32,
+) -> Result<Vec<vk::CommandBuffer>, Box<dyn Error>> {
+    allocate(
+        device,
+        command_pool,
+        vk::CommandBufferLevel::SECONDARY,
+        count,
+    )
+}
+
+fn allocate(
+    device: &Device,
+    command_pool: vk::CommandPool,
+    level: vk::CommandBufferLevel,
+    count: u32,
+) -> Result<Vec<vk::CommandBuffer>, Box<dyn Error>> {
+    let allocate_info = vk::CommandBufferAllocateInfo::default()
+        .command_pool(command_pool)
+        .level(level)
+        .command_buffer_count(count);
+
+    unsafe { Ok(device.allocate_command_buffers(&allocate_info)?) }
+}
+
+/// Put a command buffer back into the initial state so it can be recorded again.
+/// The GPU must be done with it.
+pub fn reset(device: &Device, command_buffer: vk::CommandBuffer) -> Result<(), Box<dyn Error>> {
+    unsafe {
+        device.reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())?;
+    }
+    Ok(())
+}
+
+/// Record `f` into a new command buffer from `command_pool`, submit it to `queue` and wait for it to finish
+/// (for at most util::WAIT_TIMEOUT, or until `cancel` says so, see sync.rs). Meant for one-off work like
+/// uploads, not for anything done every frame.
+pub fn one_time_submit<F: FnOnce(vk::CommandBuffer)>(
+    device: &Device,
+    command_pool: vk::CommandPool,
+    queue: vk::Queue,
+    cancel: Cancel,
+    f: F,
+) -> Result<(), Box<dyn Error>> {
+    one_time_submit_chain(
+        device,
+        cancel,
+        vec![OneTimeStep {
+            command_pool,
+            queue,
+            record: Box::new(f),
+        }],
+    )
+}
+
+/// One submission of `one_time_submit_chain`.
+pub struct OneTimeStep<'a> {
+    /// Of `queue`'s family.
+    pub command_pool: vk::CommandPool,
+    pub queue: vk::Queue,
+    pub record: Box<dyn FnOnce(vk::CommandBuffer) + 'a>,
+}
+
+/// Like `one_time_submit`, for work spread over queues: each step is recorded into its own command buffer
+/// and submitted to its queue, waiting for the step before through a semaphore. Only the last one is
+/// waited for, which orders it after all the others.
+pub fn one_time_submit_chain(
+    device: &Device,
+    cancel: Cancel,
+    steps: Vec<OneTimeStep>,
+) -> Result<(), Box<dyn Error>> {
+    let mut recorded = Vec::new();
+    let mut semaphores = Vec::new();
+    let mut fence = vk::Fence::null();
+    // The queue of the last step that was submitted, while the last step isn't.
+    let mut pending = None;
+
+    let result = (|| -> Result<(), Box<dyn Error>> {
+        fence = vulkan_create::fence(device, false)?;
+        let last = steps.len().saturating_sub(1);
+        for (index, step) in steps.into_iter().enumerate() {
+            let command_buffer = command_buffers(device, step.command_pool, 1)?[0];
+            recorded.push((step.command_pool, command_buffer));
+
+            let begin_info = vk::CommandBufferBeginInfo::default()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+            unsafe { device.begin_command_buffer(command_buffer, &begin_info)? };
+            (step.record)(command_buffer);
+            unsafe { device.end_command_buffer(command_buffer)? };
+
+            let wait_semaphores = semaphores.last().copied().into_iter().collect::<Vec<_>>();
+            let wait_stages = vec![vk::PipelineStageFlags::ALL_COMMANDS; wait_semaphores.len()];
+            let signal_semaphores = if index == last {
+                Vec::new()
+            } else {
+                semaphores.push(vulkan_create::semaphore(device)?);
+                semaphores[semaphores.len() - 1..].to_vec()
+            };
+            let command_buffers = [command_buffer];
+            let submit_infos = [vk::SubmitInfo::default()
+                .wait_semaphores(&wait_semaphores)
+                .wait_dst_stage_mask(&wait_stages)
+                .command_buffers(&command_buffers)
+                .signal_semaphores(&signal_semaphores)];
+            if index == last {
+                unsafe { device.queue_submit(step.queue, &submit_infos, fence)? };
+                pending = None;
+            } else {
+                unsafe { device.queue_submit(step.queue, &submit_infos, vk::Fence::null())? };
+                pending = Some(step.queue);
+            }
+        }
+        sync::wait_for_fences(device, &[fence], util::WAIT_TIMEOUT, cancel)?;
+        Ok(())
+    })();
+
+    // If a step after the first failed, the ones before may still be running: an empty submission to the
+    // last one's queue signals the fence once they're done.
+    let result = match (result, pending) {
+        (Err(err), Some(queue)) if err.downcast_ref::<WaitError>().is_none() => {
+            let waited = unsafe { device.queue_submit(queue, &[], fence) }
+                .map_err(WaitError::Vulkan)
+                .and_then(|()| sync::wait_for_fences(device, &[fence], util::WAIT_TIMEOUT, cancel));
+            match waited {
+                Ok(()) => Err(err),
+                Err(wait_err) => {
+                    log::error!("One time submission failed: {}", err);
+                    Err(Box::new(wait_err) as Box<dyn Error>)
+                }
+            }
+        }
+        (result, _) => result,
+    };
+    // After a failed wait the GPU may still be using them, leaking them is better than destroying them in use.
+    // Callers can tell from the WaitError that the same goes for what they submitted.
+    if result
+        .as_ref()
+        .is_err_and(|err| err.downcast_ref::<WaitError>().is_some())
+    {
+        return result;
+    }
+    unsafe {
+        for (command_pool, command_buffer) in recorded {
+            device.free_command_buffers(command_pool, &[command_buffer]);
+        }
+        for semaphore in semaphores {
+            device.destroy_semaphore(semaphore, None);
+        }
+        device.destroy_fence(fence, None);
+    }
+    result
+}
+
+/// Where uploads (one time transfers) are submitted: a dedicated transfer queue if the device has one
+/// (see `DeviceDetails::transfer_queue_index`), otherwise the graphics queue. What a dedicated queue
+/// uploads is handed over to the graphics family afterwards (see ownership.rs), in the same batch as
+/// what has to happen on the graphics queue after the copies (see `submit`).
+#[derive(Debug, Clone)]
+pub struct TransferQueue {
+    pub command_pool: vk::CommandPool,
+    pub queue: vk::Queue,
+    /// For a dedicated queue: the handover to the graphics family, and the graphics family's command
+    /// pool and queue to acquire with.
+    pub handover: Option<(QueueTransfer, vk::CommandPool, vk::Queue)>,
+    /// Uploads stop waiting once it's requested.
+    pub shutdown: Shutdown,
+}
+
+/// The barriers handing what an upload wrote over to the graphics family, see `TransferQueue::submit`.
+#[derive(Default)]
+pub struct Handover<'a> {
+    pub release: TransferBarriers<'a>,
+    pub acquire: TransferBarriers<'a>,
+}
+
+impl TransferQueue {
+    /// Uploads through the graphics queue, with a command pool of its family.
+    pub fn graphics(command_pool: vk::CommandPool, queue: vk::Queue, shutdown: Shutdown) -> Self {
+        Self {
+            command_pool,
+            queue,
+            handover: None,
+            shutdown,
+        }
+    }
+
+    /// Uploads through `queue` of the dedicated transfer family `queue_family_index`, with a command pool
+    /// of its own. Handed over to the family of `graphics_command_pool` and `graphics_queue`.
+    pub fn dedicated(
+        device: &Device,
+        queue_family_index: u32,
+        queue: vk::Queue,
+        graphics_family_index: u32,
+        graphics_command_pool: vk::CommandPool,
+        graphics_queue: vk::Queue,
+        shutdown: Shutdown,
+    ) -> Result<Self, Box<dyn Error>> {
+        let handover = QueueTransfer::new(queue_family_index, graphics_family_index)
+            .map(|transfer| (transfer, graphics_command_pool, graphics_queue));
+        Ok(Self {
+            command_pool: command_pool(device, queue_family_index)?,
+            queue,
+            handover,
+            shutdown,
+        })
+    }
+
+    /// The handover to the graphics family, None if uploads already run on it.
+    pub fn transfer(&self) -> Option<QueueTransfer> {
+        self.handover
+            .as_ref()
+            .map(|(transfer, _, _)| transfer.clone())
+    }
+
+    /// Record `copy` and then `finish`, submit them and wait for them to finish, like `one_time_submit`.
+    /// `copy` runs on this queue, `finish` on the graphics queue: e.g. mipmap blits and layout transitions
+    /// for sampling. With a dedicated queue they're two submissions chained by a semaphore, with `handover`
+    /// (built with `transfer`) released after `copy` and acquired before `finish`. Otherwise they share
+    /// a command buffer, with a barrier making the copies visible to everything after them in between.
+    pub fn submit<'a>(
+        &self,
+        device: &'a Device,
+        copy: impl FnOnce(vk::CommandBuffer) + 'a,
+        handover: Handover<'a>,
+        finish: impl FnOnce(vk::CommandBuffer) + 'a,
+    ) -> Result<(), Box<dyn Error>> {
+        let cancel = Cancel::On(&self.shutdown);
+        let Some((transfer, graphics_command_pool, graphics_queue)) = self.handover.clone() else {
+            return one_time_submit(
+                device,
+                self.command_pool,
+                self.queue,
+                cancel,
+                |command_buffer| {
+                    copy(command_buffer);
+                    let memory_barriers = [vk::MemoryBarrier::default()
+                        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                        .dst_access_mask(
+                            vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
+                        )];
+                    unsafe {
+                        device.cmd_pipeline_barrier(
+                            command_buffer,
+                            vk::PipelineStageFlags::TRANSFER,
+                            vk::PipelineStageFlags::ALL_COMMANDS,
+                            vk::DependencyFlags::empty(),
+                            &memory_barriers,
+                            &[],
+                            &[],
+                        )
+                    };
+                    finish(command_buffer);
+                },
+            );
+        };
+
+        let Handover { release, acquire } = handover;
+        let acquiring = transfer.clone();
+        one_time_submit_chain(
+            device,
+            cancel,
+            vec![
+                OneTimeStep {
+                    command_pool: self.command_pool,
+                    queue: self.queue,
+                    record: Box::new(move |command_buffer| {
+                        copy(command_buffer);
+                        transfer.record_release(
+                            device,
+                            command_buffer,
+                            vk::PipelineStageFlags::TRANSFER,
+                            &release,
+                        );
+                    }),
+                },
+                OneTimeStep {
+                    command_pool: graphics_command_pool,
+                    queue: graphics_queue,
+                    record: Box::new(move |command_buffer| {
+                        // What uploads are used for next isn't known, so everything waits for them.
+                        acquiring.record_acquire(
+                            device,
+                            command_buffer,
+                            vk::PipelineStageFlags::ALL_COMMANDS,
+                            &acquire,
+                        );
+                        finish(command_buffer);
+                    }),
+                },
+            ],
+        )
+    }
+
+    /// Destroy the dedicated queue's command pool (the graphics one belongs to the caller).
+    pub fn destroy(&mut self, device: &Device) {
+        if self.handover.is_some() {
+            unsafe { device.destroy_command_pool(self.command_pool, None) };
+            self.command_pool = vk::CommandPool::null();
+            self.handover = None;
+        }
+    }
+}
+
+/// What a draw binds, and how many vertices it draws.
+#[derive(Clone, Copy)]
+pub struct Draw<'a> {
+    pub pipeline: vk::Pipeline,
+    pub pipeline_layout: vk::PipelineLayout,
+    /// Bound starting at set 0.
+    pub descriptor_sets: &'a [vk::DescriptorSet],
+    /// Pushed as the set after `descriptor_sets`, see `descriptor::push_descriptor_set`.
+    pub push_descriptors: Option<(&'a push_descriptor::Device, &'a [(u32, PushedDescriptor)])>,
+    /// Pushed at offset 0 for the given stages.
+    pub push_constants: Option<(vk::ShaderStageFlags, &'a [u8])>,
+    /// Bound to binding 0. None for shaders that generate their vertices from gl_VertexIndex.
+    pub vertex_buffer: Option<vk::Buffer>,
+    /// Bound to binding 1, with how many instances to draw from it. None draws a single instance.
+    pub instance_buffer: Option<(vk::Buffer, u32)>,
+    /// u16 indices, drawn with cmd_draw_indexed. `vertex_count` counts indices then.
+    pub index_buffer: Option<vk::Buffer>,
+    pub vertex_count: u32,
+}
+
+/// What recording a frame's draws cost, to measure batching/culling improvements.
+/// Triangles assume TRIANGLE_LIST topology, which is all this crate uses.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DrawStatistics {
+    pub draw_calls: u32,
+    pub instances: u32,
+    pub triangles: u32,
+    pub pipeline_binds: u32,
+    pub descriptor_binds: u32,
+}
+
+impl AddAssign for DrawStatistics {
+    fn add_assign(&mut self, other: Self) {
+        self.draw_calls += other.draw_calls;
+        self.instances += other.instances;
+        self.triangles += other.triangles;
+        self.pipeline_binds += other.pipeline_binds;
+        self.descriptor_binds += other.descriptor_binds;
+    }
+}
+
+impl fmt::Display for DrawStatistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} draw calls, {} instances, {} triangles, {} pipeline binds, {} descriptor binds",
+            self.draw_calls,
+            self.instances,
+            self.triangles,
+            self.pipeline_binds,
+            self.descriptor_binds
+        )
+    }
+}
+
+/// How a frame's draws begin and end: a render pass, or dynamic rendering to the frame's attachments.
+#[derive(Clone, Copy)]
+pub enum PassBegin<'a> {
+    /// Also holds the clear values.
+    RenderPass(&'a vk::RenderPassBeginInfo<'a>),
+    Dynamic(&'a FrameAttachments<'a>),
+}
+
+impl PassBegin<'_> {
+    fn extent(&self) -> vk::Extent2D {
+        match self {
+            PassBegin::RenderPass(begin_info) => begin_info.render_area.extent,
+            PassBegin::Dynamic(attachments) => attachments.extent,
+        }
+    }
+
+    fn begin(&self, device: &Device, command_buffer: vk::CommandBuffer, secondaries: bool) {
+        match self {
+            PassBegin::RenderPass(begin_info) => {
+                let contents = if secondaries {
+                    vk::SubpassContents::SECONDARY_COMMAND_BUFFERS
+                } else {
+                    vk::SubpassContents::INLINE
+                };
+                unsafe { device.cmd_begin_render_pass(command_buffer, begin_info, contents) };
+            }
+            PassBegin::Dynamic(attachments) => {
+                let flags = if secondaries {
+                    vk::RenderingFlags::CONTENTS_SECONDARY_COMMAND_BUFFERS
+                } else {
+                    vk::RenderingFlags::empty()
+                };
+                dynamic_rendering::begin(device, command_buffer, attachments, flags);
+            }
+        }
+    }
+
+    fn end(&self, device: &Device, command_buffer: vk::CommandBuffer) {
+        match self {
+            PassBegin::RenderPass(_) => unsafe { device.cmd_end_render_pass(command_buffer) },
+            PassBegin::Dynamic(attachments) => {
+                dynamic_rendering::end(device, command_buffer, attachments)
+            }
+        }
+    }
+}
+
+/// Reset `command_buffer` and begin recording it, for one submission.
+pub fn begin_recording(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+) -> Result<(), Box<dyn Error>> {
+    reset(device, command_buffer)?;
+    let command_buffer_begin_info = vk::CommandBufferBeginInfo::default();
+    unsafe { device.begin_command_buffer(command_buffer, &command_buffer_begin_info)? };
+    Ok(())
+}
+
+/// A frame's primary command buffer as handed to custom code (see `VulkanApp::set_raw_commands`):
+/// begun, before the main render pass. Whatever is recorded into it runs before the frame's draws.
+pub struct RawFrame {
+    pub command_buffer: vk::CommandBuffer,
+    /// Which frame in flight this is, for per frame resources.
+    pub frame_index: usize,
+    /// The acquired swapchain image, which the render pass will draw into (and clear or discard first).
+    pub image: vk::Image,
+    pub extent: vk::Extent2D,
+}
+
+/// Record the pass begun by `pass` with `draws` inside it, in order, into `command_buffer`,
+/// which is being recorded (see `begin_recording`, or by `one_time_submit`).
+/// A pipeline already bound by the previous draw isn't bound again.
+pub fn record_render_pass(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    pass: PassBegin,
+    draws: &[Draw],
+) -> DrawStatistics {
+    pass.begin(device, command_buffer, false);
+    let statistics = record_draw_list(device, command_buffer, pass.extent(), draws);
+    pass.end(device, command_buffer);
+
+    statistics
+}
+
+/// Record the render pass begun with `begin_info` into `command_buffer`, which is being recorded,
+/// with the draws of each of its subpasses in turn (see render_pass.rs).
+pub fn record_subpasses(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    begin_info: &vk::RenderPassBeginInfo,
+    subpasses: &[&[Draw]],
+) -> DrawStatistics {
+    let pass = PassBegin::RenderPass(begin_info);
+    pass.begin(device, command_buffer, false);
+    let mut statistics = DrawStatistics::default();
+    for (index, draws) in subpasses.iter().enumerate() {
+        if index > 0 {
+            unsafe { device.cmd_next_subpass(command_buffer, vk::SubpassContents::INLINE) };
+        }
+        statistics += record_draw_list(device, command_buffer, pass.extent(), draws);
+    }
+    pass.end(device, command_buffer);
+
+    statistics
+}
+
+/// Record the pass begun by `pass`, executing `secondaries` inside it, in order, into `command_buffer`,
+/// which is being recorded (see `begin_recording`). They must have been recorded for the same kind
+/// of pass (see `SecondaryPass`).
+pub fn record_secondaries(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    pass: PassBegin,
+    secondaries: &[vk::CommandBuffer],
+) {
+    pass.begin(device, command_buffer, true);
+    if !secondaries.is_empty() {
+        unsafe { device.cmd_execute_commands(command_buffer, secondaries) };
+    }
+    pass.end(device, command_buffer);
+}
+
+/// Reset the secondary `command_buffer` and record `draws` into it, to be executed inside
+/// `rendering` at `extent`.
+pub fn record_secondary(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    rendering: Rendering,
+    extent: vk::Extent2D,
+    draws: &[Draw],
+) -> Result<DrawStatistics, Box<dyn Error>> {
+    reset(device, command_buffer)?;
+
+    // The framebuffer is left out, so the recording works with every swapchain image.
+    let color_formats;
+    let mut rendering_inheritance_info = vk::CommandBufferInheritanceRenderingInfo::default();
+    let mut inheritance_info = vk::CommandBufferInheritanceInfo::default();
+    match rendering {
+        Rendering::RenderPass(render_pass) => {
+            inheritance_info = inheritance_info.render_pass(render_pass).subpass(0);
+        }
+        Rendering::Dynamic {
+            color_format,
+            depth_format,
+            samples,
+        } => {
+            color_formats = [color_format];
+            rendering_inheritance_info = rendering_inheritance_info
+                .color_attachment_formats(&color_formats)
+                .depth_attachment_format(depth_format)
+                .stencil_attachment_format(dynamic_rendering::stencil_format(depth_format))
+                .rasterization_samples(samples);
+            inheritance_info = inheritance_info.push_next(&mut rendering_inheritance_info);
+        }
+    }
+    let begin_info = vk::CommandBufferBeginInfo::default()
+        .flags(vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE)
+        .inheritance_info(&inheritance_info);
+    unsafe { device.begin_command_buffer(command_buffer, &begin_info)? };
+    let statistics = record_draw_list(device, command_buffer, extent, draws);
+    unsafe { device.end_command_buffer(command_buffer)? };
+
+    Ok(statistics)
+}
+
+/// Set viewport and scissor to `extent` and record `draws`, inside a render pass.
+fn record_draw_list(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    extent: vk::Extent2D,
+    draws: &[Draw],
+) -> DrawStatistics {
+    let mut statistics = DrawStatistics::default();
+
+    unsafe {
+        // All pipelines have dynamic viewport and scissor, which stay set across pipeline binds.
+        // They aren't inherited by secondary command buffers, so each sets its own.
+        device.cmd_set_viewport(
+            command_buffer,
+            0,
+            &[vk::Viewport {
+                x: 0.0,
+                y: 0.0,
+                width: extent.width as f32,
+                height: extent.height as f32,
+                min_depth: 0.0,
+                max_depth: 1.0,
+            }],
+        );
+        device.cmd_set_scissor(
+            command_buffer,
+            0,
+            &[vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent,
+            }],
+        );
+
+        let mut bound_pipeline = vk::Pipeline::null();
+        for draw in draws.iter() {
+            if draw.pipeline != bound_pipeline {
+                device.cmd_bind_pipeline(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    draw.pipeline,
+                );
+                bound_pipeline = draw.pipeline;
+                statistics.pipeline_binds += 1;
+            }
+            if !draw.descriptor_sets.is_empty() {
+                statistics.descriptor_binds += 1;
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    draw.pipeline_layout,
+                    0,
+                    draw.descriptor_sets,
+                    &[],
+                );
+            }
+            if let Some((push_descriptor_loader, descriptors)) = draw.push_descriptors {
+                statistics.descriptor_binds += 1;
+                descriptor::push_descriptor_set(
+                    push_descriptor_loader,
+                    command_buffer,
+                    draw.pipeline_layout,
+                    draw.descriptor_sets.len() as u32,
+                    descriptors,
+                );
+            }
+            if let Some((stages, push_constants)) = draw.push_constants {
+                device.cmd_push_constants(
+                    command_buffer,
+                    draw.pipeline_layout,
+                    stages,
+                    0,
+                    push_constants,
+                );
+            }
+            if let Some(vertex_buffer) = draw.vertex_buffer {
+                device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer], &[0]);
+            }
+            let instance_count = match draw.instance_buffer {
+                Some((instance_buffer, instance_count)) => {
+                    device.cmd_bind_vertex_buffers(command_buffer, 1, &[instance_buffer], &[0]);
+                    instance_count
+                }
+                None => 1,
+            };
+            match draw.index_buffer {
+                Some(index_buffer) => {
+                    device.cmd_bind_index_buffer(
+                        command_buffer,
+                        index_buffer,
+                        0,
+                        vk::IndexType::UINT16,
+                    );
+                    device.cmd_draw_indexed(
+                        command_buffer,
+                        draw.vertex_count,
+                        instance_count,
+                        0,
+                        0,
+                        0,
+                    );
+                }
+                None => device.cmd_draw(command_buffer, draw.vertex_count, instance_count, 0, 0),
+            }
+            statistics.draw_calls += 1;
+            statistics.instances += instance_count;
+            statistics.triangles += draw.vertex_count / 3 * instance_count;
+        }
+    }
+
+    statistics
+}
+
+//////////////// Secondary Command Buffers ////////////////
+
+/// A part of a pass, recorded into its own secondary command buffer per frame
+/// in flight. The primary executes them with `record_secondaries`.
+/// A recording is reused until `invalidate`, so parts that rarely change aren't re-recorded
+/// every frame.
+pub struct SecondaryPass {
+    command_buffers: Vec<vk::CommandBuffer>,
+    // What each command buffer's current recording contains, None if it has to be (re-)recorded.
+    recorded: Vec<Option<DrawStatistics>>,
+}
+
+impl SecondaryPass {
+    /// Allocate `count` secondary command buffers (one per frame in flight) from `command_pool`.
+    /// They're freed with the pool.
+    pub fn new(
+        device: &Device,
+        command_pool: vk::CommandPool,
+        count: u32,
+    ) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            command_buffers: secondary_command_buffers(device, command_pool, count)?,
+            recorded: vec![None; count as usize],
+        })
+    }
+
+    /// Re-record every command buffer the next time it's used, e.g. after the extent
+    /// or a pipeline changed.
+    pub fn invalidate(&mut self) {
+        self.recorded
+            .iter_mut()
+            .for_each(|recorded| *recorded = None);
+    }
+
+    /// The command buffer for frame in flight `index`, recording `draws` into it first for
+    /// `rendering` at `extent` unless it still holds a valid recording.
+    /// The GPU must be done with it, i.e. the frame's fence has been waited on.
+    pub fn record(
+        &mut self,
+        device: &Device,
+        index: usize,
+        rendering: Rendering,
+        extent: vk::Extent2D,
+        draws: &[Draw],
+    ) -> Result<(vk::CommandBuffer, DrawStatistics), Box<dyn Error>> {
+        let command_buffer = self.command_buffers[index];
+        if let Some(statistics) = self.recorded[index] {
+            return Ok((command_buffer, statistics));
+        }
+
+        let statistics = record_secondary(device, command_buffer, rendering, extent, draws)?;
+        self.recorded[index] = Some(statistics);
+        Ok((command_buffer, statistics))
+    }
+}
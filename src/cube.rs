@@ -0,0 +1,128 @@
+use ash::{vk, Device};
+use std::error::Error;
+
+use crate::buffer::Buffer;
+use crate::command::{Draw, TransferQueue};
+use crate::memory::Allocator;
+use crate::vertex::{self, InstanceData, Vertex};
+
+//////////////// Cube ////////////////
+// `vertex::CUBE_POSITIONS` turned isometrically on the CPU and flattened into `Vertex`es, drawn with the
+// triangles' pipeline next to them. Every corner is colored by where it is, so the faces are shaded
+// RGB gradients. Back face culling alone hides the far faces, since a cube is convex.
+
+/// Where the cube goes and how big it is, like an instance of the triangle.
+const PLACEMENT: InstanceData = InstanceData {
+    offset: [0.0, -0.6],
+    scale: 0.4,
+};
+
+/// The cube's vertices, indices and its single instance.
+pub struct Cube {
+    vertex_buffer: Buffer,
+    index_buffer: Buffer,
+    instance_buffer: Buffer,
+}
+
+impl Cube {
+    /// Upload the cube through `transfer_queue`.
+    pub fn new(
+        device: &Device,
+        allocator: &Allocator,
+        transfer_queue: &TransferQueue,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut buffers: Vec<Buffer> = Vec::new();
+        let uploaded = (|| -> Result<(), Box<dyn Error>> {
+            buffers.push(Buffer::device_local_with_data(
+                device,
+                allocator,
+                transfer_queue,
+                vk::BufferUsageFlags::VERTEX_BUFFER,
+                &isometric_vertices(),
+            )?);
+            buffers.push(Buffer::device_local_with_data(
+                device,
+                allocator,
+                transfer_queue,
+                vk::BufferUsageFlags::INDEX_BUFFER,
+                &vertex::CUBE_INDICES,
+            )?);
+            buffers.push(Buffer::device_local_with_data(
+                device,
+                allocator,
+                transfer_queue,
+                vk::BufferUsageFlags::VERTEX_BUFFER,
+                &[PLACEMENT],
+            )?);
+            Ok(())
+        })();
+        if let Err(err) = uploaded {
+            for buffer in buffers.iter_mut() {
+                buffer.destroy(device);
+            }
+            return Err(err);
+        }
+
+        let mut buffers = buffers.into_iter();
+        Ok(Self {
+            vertex_buffer: buffers.next().unwrap(),
+            index_buffer: buffers.next().unwrap(),
+            instance_buffer: buffers.next().unwrap(),
+        })
+    }
+
+    /// The draw for the cube, with the pipeline and descriptors of `triangles`.
+    pub fn draw<'a>(&self, triangles: &Draw<'a>) -> Draw<'a> {
+        Draw {
+            vertex_buffer: Some(self.vertex_buffer.buffer),
+            instance_buffer: Some((self.instance_buffer.buffer, 1)),
+            index_buffer: Some(self.index_buffer.buffer),
+            vertex_count: vertex::CUBE_INDICES.len() as u32,
+            ..*triangles
+        }
+    }
+
+    /// Destroy the buffers. The GPU must be done with them.
+    pub fn destroy(&mut self, device: &Device) {
+        self.vertex_buffer.destroy(device);
+        self.index_buffer.destroy(device);
+        self.instance_buffer.destroy(device);
+    }
+}
+
+/// `vertex::CUBE_POSITIONS` turned 45 degrees around Y and tilted towards the viewer around X, so three faces
+/// show, then dropped onto the XY plane with Y pointing down like the triangle's vertices.
+/// Both turns are rotations, so the faces keep their winding.
+fn isometric_vertices() -> [Vertex; 8] {
+    let (sin_y, cos_y) = std::f32::consts::FRAC_PI_4.sin_cos();
+    let (sin_x, cos_x) = (1.0f32 / 3.0).sqrt().asin().sin_cos();
+    vertex::CUBE_POSITIONS.map(|[x, y, z]| {
+        let (turned_x, turned_z) = (x * cos_y + z * sin_y, z * cos_y - x * sin_y);
+        let tilted_y = y * cos_x - turned_z * sin_x;
+        Vertex {
+            position: [turned_x, -tilted_y],
+            color: [x + 0.5, y + 0.5, z + 0.5],
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Three faces, six triangles, face the viewer: clockwise with Y down, like `vertex::TRIANGLE`.
+    #[test]
+    fn three_faces_are_front_facing() {
+        let vertices = isometric_vertices();
+        let front_facing = vertex::CUBE_INDICES
+            .chunks_exact(3)
+            .filter(|triangle| {
+                let [a, b, c] =
+                    [0, 1, 2].map(|corner| vertices[triangle[corner] as usize].position);
+                let cross = (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0]);
+                cross > 0.0
+            })
+            .count();
+        assert_eq!(front_facing, 6);
+    }
+}
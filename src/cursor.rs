@@ -0,0 +1,150 @@
+use ash::{vk, Device};
+use std::error::Error;
+
+use crate::buffer::Buffer;
+use crate::command::{Draw, TransferQueue};
+use crate::memory::Allocator;
+use crate::pipeline::GraphicsPipelineBuilder;
+use crate::reflect::ShaderInterface;
+use crate::render_target::Rendering;
+use crate::shader_cache::ShaderCache;
+use crate::vertex;
+
+//////////////// Software Cursor ////////////////
+// Screen capture often doesn't include the OS cursor, so it can be hidden and drawn by us instead,
+// as the last draw before the frame is presented.
+
+/// Size of `push_constants`: the cursor position and the size of a pixel, both in NDC.
+const PUSH_CONSTANTS_SIZE: u32 = 16;
+
+/// The shaders the pipeline is built from.
+pub const SHADERS: [&str; 2] = ["cursor.vert.spv", "shader.frag.spv"];
+
+/// The pipeline and geometry to draw the cursor with.
+pub struct SoftwareCursor {
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    vertex_buffer: Buffer,
+}
+
+impl SoftwareCursor {
+    /// Upload the cursor's geometry through `transfer_queue` and build its pipeline for `rendering`.
+    /// It isn't depth tested, so it's drawn over everything drawn before it.
+    pub fn new(
+        device: &Device,
+        shader_cache: &ShaderCache,
+        allocator: &Allocator,
+        transfer_queue: &TransferQueue,
+        rendering: Rendering,
+        samples: vk::SampleCountFlags,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut vertex_buffer = Buffer::device_local_with_data(
+            device,
+            allocator,
+            transfer_queue,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            &vertex::CURSOR,
+        )?;
+
+        let pipeline = build_pipeline(device, shader_cache, rendering, samples);
+
+        match pipeline {
+            Ok((pipeline, pipeline_layout)) => Ok(Self {
+                pipeline,
+                pipeline_layout,
+                vertex_buffer,
+            }),
+            Err(err) => {
+                vertex_buffer.destroy(device);
+                Err(err)
+            }
+        }
+    }
+
+    /// Rebuild the pipeline from the current `SHADERS`, e.g. after they changed on disk.
+    /// Keeps the previous pipeline if building fails. The GPU must be done with it.
+    #[cfg(feature = "hot-reload")]
+    pub fn rebuild_pipeline(
+        &mut self,
+        device: &Device,
+        shader_cache: &ShaderCache,
+        rendering: Rendering,
+        samples: vk::SampleCountFlags,
+    ) -> Result<(), Box<dyn Error>> {
+        let (pipeline, pipeline_layout) = build_pipeline(device, shader_cache, rendering, samples)?;
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        self.pipeline = pipeline;
+        self.pipeline_layout = pipeline_layout;
+        Ok(())
+    }
+
+    /// Push constants placing the cursor tip at `position` (in pixels) on a target of size `extent`.
+    pub fn push_constants(position: (f64, f64), extent: vk::Extent2D) -> [u8; 16] {
+        let width = extent.width as f32;
+        let height = extent.height as f32;
+        let values = [
+            position.0 as f32 / width * 2.0 - 1.0,
+            position.1 as f32 / height * 2.0 - 1.0,
+            2.0 / width,
+            2.0 / height,
+        ];
+
+        let mut bytes = [0u8; PUSH_CONSTANTS_SIZE as usize];
+        for (chunk, value) in bytes.chunks_exact_mut(4).zip(values.iter()) {
+            chunk.copy_from_slice(&value.to_ne_bytes());
+        }
+        bytes
+    }
+
+    /// The draw for the cursor, with `push_constants` from `SoftwareCursor::push_constants`.
+    pub fn draw<'a>(&self, push_constants: &'a [u8; 16]) -> Draw<'a> {
+        Draw {
+            pipeline: self.pipeline,
+            pipeline_layout: self.pipeline_layout,
+            descriptor_sets: &[],
+            push_descriptors: None,
+            push_constants: Some((vk::ShaderStageFlags::VERTEX, push_constants)),
+            vertex_buffer: Some(self.vertex_buffer.buffer),
+            instance_buffer: None,
+            index_buffer: None,
+            vertex_count: vertex::CURSOR.len() as u32,
+        }
+    }
+
+    /// Destroy the pipeline and geometry. The GPU must be done with them.
+    pub fn destroy(&mut self, device: &Device) {
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        self.vertex_buffer.destroy(device);
+    }
+}
+
+fn build_pipeline(
+    device: &Device,
+    shader_cache: &ShaderCache,
+    rendering: Rendering,
+    samples: vk::SampleCountFlags,
+) -> Result<(vk::Pipeline, vk::PipelineLayout), Box<dyn Error>> {
+    let interface = ShaderInterface::from_shaders(shader_cache, &SHADERS)?;
+    // The cursor's geometry is vertex::Vertex.
+    let (vertex_bindings, vertex_attributes) = interface.vertex_input(&[(
+        vk::VertexInputRate::VERTEX,
+        0..2,
+        size_of::<vertex::Vertex>(),
+    )])?;
+    let push_constant_ranges = interface.push_constant_ranges();
+    GraphicsPipelineBuilder::default()
+        .vertex_shader(SHADERS[0])
+        .fragment_shader(SHADERS[1])
+        .vertex_input(&vertex_bindings, &vertex_attributes)
+        .push_constant_ranges(&push_constant_ranges)
+        .depth_test(false)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .samples(samples)
+        .build(device, shader_cache, rendering)
+}
@@ -0,0 +1,392 @@
+use ash::{vk, Device};
+use std::error::Error;
+
+use crate::backend::{self, BackendKind, BackendSetup, GeometryPipelines, GeometryTargets};
+use crate::backend::{PipelineSetup, RendererBackend, Target};
+use crate::command::{Draw, DrawStatistics};
+use crate::descriptor::{self, DescriptorManager};
+use crate::memory::Allocator;
+use crate::pipeline::GraphicsPipelineBuilder;
+use crate::reflect::ShaderInterface;
+use crate::render_pass::{RenderPassBuilder, Subpass};
+use crate::render_target::Rendering;
+use crate::shader_cache::ShaderCache;
+
+//////////////// Deferred Shading ////////////////
+// One render pass of two subpasses. The geometry subpass draws the triangles into a G-buffer: their color
+// (alpha 1 where they are) and depth. The lighting subpass is a fullscreen draw that shades every covered
+// pixel once from it, with a point light (see shaders/deferred.frag), however many triangles were drawn
+// over each other there. It reads the color as an input attachment, so on tiled GPUs it never leaves the
+// chip. The composite is a fullscreen draw in the main pass, copying the lit pixels and their depth.
+// The scene is flat and faces the camera, so no normals are stored.
+
+/// The shaders the pipelines are built from: the geometry subpass's, the lighting subpass's fragment
+/// shader and the composite's (both after the fullscreen vertex shader).
+pub const SHADERS: [&str; 5] = [
+    "shader.vert.spv",
+    "gbuffer.frag.spv",
+    "fullscreen.vert.spv",
+    "deferred.frag.spv",
+    "deferred_composite.frag.spv",
+];
+
+/// The targets' attachments, the depth buffer comes after them.
+const ALBEDO: u32 = 0;
+const DEPTH: u32 = 1;
+const LIT: u32 = 2;
+const DEPTH_BUFFER: u32 = 3;
+
+/// Size of the lighting subpass's push constants: the targets' size in pixels.
+const PUSH_CONSTANTS_SIZE: usize = 8;
+
+/// The G-buffer, the lit targets and the pipelines drawing into and compositing from them.
+pub struct Deferred {
+    targets: GeometryTargets,
+    geometry: GeometryPipelines,
+    lighting: (vk::Pipeline, vk::PipelineLayout),
+    composite: (vk::Pipeline, vk::PipelineLayout),
+    // Owns the lighting subpass's and the composite's set layouts and their sets.
+    descriptors: DescriptorManager,
+    // Kept to rebuild the pipelines: the lighting subpass's and the composite's.
+    #[cfg(feature = "hot-reload")]
+    descriptor_set_layouts: [vk::DescriptorSetLayout; 2],
+    // The targets don't change between frames in flight, so they share them.
+    lighting_set: vk::DescriptorSet,
+    composite_set: vk::DescriptorSet,
+    push_constants: [u8; PUSH_CONSTANTS_SIZE],
+}
+
+impl Deferred {
+    pub fn new(device: &Device, setup: &BackendSetup) -> Result<Self, Box<dyn Error>> {
+        let target = |format, value, usage| Target {
+            format,
+            clear_value: vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: [value; 4],
+                },
+            },
+            usage,
+        };
+        // In attachment order. The color is only read within the render pass, the lit color and depth
+        // a texel at a time by the composite.
+        let target_descriptions = [
+            target(
+                vk::Format::R8G8B8A8_UNORM,
+                0.0,
+                vk::ImageUsageFlags::INPUT_ATTACHMENT,
+            ),
+            target(vk::Format::R32_SFLOAT, 1.0, vk::ImageUsageFlags::STORAGE),
+            target(
+                vk::Format::R8G8B8A8_UNORM,
+                0.0,
+                vk::ImageUsageFlags::STORAGE,
+            ),
+        ];
+        let render_pass = render_pass(device, &target_descriptions, setup.depth_format)?;
+        let mut targets = GeometryTargets::with_render_pass(
+            device,
+            setup.allocator,
+            render_pass,
+            &target_descriptions,
+            setup.depth_format,
+            setup.extent,
+        )?;
+        let mut descriptors = DescriptorManager::default();
+        // The layouts are only kept to rebuild the pipelines with.
+        #[cfg_attr(not(feature = "hot-reload"), allow(unused_variables))]
+        let ((descriptor_set_layouts, [lighting_set, composite_set]), pipelines) =
+            match sets_and_pipelines(device, setup.pipelines, &mut descriptors, &targets) {
+                Ok(created) => created,
+                Err(err) => {
+                    descriptors.destroy(device);
+                    targets.destroy(device);
+                    return Err(err);
+                }
+            };
+        let (geometry, lighting, composite) = pipelines;
+
+        let deferred = Self {
+            push_constants: push_constants(targets.extent()),
+            targets,
+            geometry,
+            lighting,
+            composite,
+            descriptors,
+            #[cfg(feature = "hot-reload")]
+            descriptor_set_layouts,
+            lighting_set,
+            composite_set,
+        };
+        deferred.write_descriptor_sets(device);
+        Ok(deferred)
+    }
+
+    /// Destroy the pipelines. The GPU must be done with them.
+    fn destroy_pipelines(&mut self, device: &Device) {
+        self.geometry.destroy(device);
+        for (pipeline, layout) in [self.lighting, self.composite] {
+            unsafe {
+                device.destroy_pipeline(pipeline, None);
+                device.destroy_pipeline_layout(layout, None);
+            }
+        }
+    }
+
+    /// Point the sets at the current targets.
+    fn write_descriptor_sets(&self, device: &Device) {
+        let views = self.targets.views();
+        descriptor::write_input_attachment(device, self.lighting_set, 0, views[ALBEDO as usize]);
+        descriptor::write_storage_image(device, self.composite_set, 0, views[LIT as usize]);
+        descriptor::write_storage_image(device, self.composite_set, 1, views[DEPTH as usize]);
+    }
+}
+
+/// The geometry subpass's pipelines, the lighting subpass's and the composite's.
+type Pipelines = (
+    GeometryPipelines,
+    (vk::Pipeline, vk::PipelineLayout),
+    (vk::Pipeline, vk::PipelineLayout),
+);
+
+/// The lighting subpass's and the composite's set layouts and sets.
+type Sets = ([vk::DescriptorSetLayout; 2], [vk::DescriptorSet; 2]);
+
+/// The set layouts and sets (in `descriptors`), and the pipelines.
+fn sets_and_pipelines(
+    device: &Device,
+    setup: PipelineSetup<'_>,
+    descriptors: &mut DescriptorManager,
+    targets: &GeometryTargets,
+) -> Result<(Sets, Pipelines), Box<dyn Error>> {
+    let mut layouts = [vk::DescriptorSetLayout::null(); 2];
+    let mut sets = [vk::DescriptorSet::null(); 2];
+    for (index, fragment_shader) in [SHADERS[3], SHADERS[4]].into_iter().enumerate() {
+        let interface =
+            ShaderInterface::from_shaders(setup.shader_cache, &[SHADERS[2], fragment_shader])?;
+        layouts[index] = descriptors.create_layout(device, &interface.set_bindings(0))?;
+        sets[index] = descriptors.allocate(device, layouts[index], 1)?[0];
+    }
+    let pipelines = build_pipelines(device, setup, targets, layouts)?;
+    Ok(((layouts, sets), pipelines))
+}
+
+/// Build the geometry subpass's pipelines drawing into `targets`, the lighting subpass's reading them through
+/// a set of `layouts[0]`, and the composite's reading the lit targets through a set of `layouts[1]`.
+/// If one fails, the others are destroyed.
+fn build_pipelines(
+    device: &Device,
+    setup: PipelineSetup<'_>,
+    targets: &GeometryTargets,
+    layouts: [vk::DescriptorSetLayout; 2],
+) -> Result<Pipelines, Box<dyn Error>> {
+    let mut geometry =
+        GeometryPipelines::new(device, setup, [SHADERS[0], SHADERS[1]], (targets, 2))?;
+    let lighting = match lighting_pipeline(device, setup.shader_cache, targets, layouts[0]) {
+        Ok(lighting) => lighting,
+        Err(err) => {
+            geometry.destroy(device);
+            return Err(err);
+        }
+    };
+    match backend::resolve_pipeline(device, setup, [SHADERS[2], SHADERS[4]], layouts[1]) {
+        Ok(composite) => Ok((geometry, lighting, composite)),
+        Err(err) => {
+            geometry.destroy(device);
+            unsafe {
+                device.destroy_pipeline(lighting.0, None);
+                device.destroy_pipeline_layout(lighting.1, None);
+            }
+            Err(err)
+        }
+    }
+}
+
+/// The lighting subpass's pipeline: a triangle covering the targets, reading the G-buffer through a set
+/// of `layout` and writing the lit color.
+fn lighting_pipeline(
+    device: &Device,
+    shader_cache: &ShaderCache,
+    targets: &GeometryTargets,
+    layout: vk::DescriptorSetLayout,
+) -> Result<(vk::Pipeline, vk::PipelineLayout), Box<dyn Error>> {
+    let interface = ShaderInterface::from_shaders(shader_cache, &[SHADERS[2], SHADERS[3]])?;
+    let push_constant_ranges = interface.push_constant_ranges();
+    GraphicsPipelineBuilder::default()
+        .vertex_shader(SHADERS[2])
+        .fragment_shader(SHADERS[3])
+        .vertex_input(&[], &[])
+        .descriptor_set_layouts(&[layout])
+        .push_constant_ranges(&push_constant_ranges)
+        .depth_test(false)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .subpass(1)
+        .build(
+            device,
+            shader_cache,
+            Rendering::RenderPass(targets.render_pass()),
+        )
+}
+
+/// The geometry subpass draws the color and depth, the lighting subpass reads the color as an input
+/// attachment and writes the lit color. The depth and lit color are stored and left in GENERAL for the
+/// composite, the rest only lives within the render pass.
+fn render_pass(
+    device: &Device,
+    targets: &[Target],
+    depth_format: vk::Format,
+) -> Result<vk::RenderPass, Box<dyn Error>> {
+    let attachment = |format, store_op, final_layout| {
+        vk::AttachmentDescription::default()
+            .format(format)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(store_op)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(final_layout)
+    };
+    let (dont_care, store) = (
+        vk::AttachmentStoreOp::DONT_CARE,
+        vk::AttachmentStoreOp::STORE,
+    );
+    let general = vk::ImageLayout::GENERAL;
+    // The composite reads the depth and lit color a texel at a time.
+    let composite_read = vk::SubpassDependency::default()
+        .dst_subpass(vk::SUBPASS_EXTERNAL)
+        .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+        .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+        .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+        .dst_access_mask(vk::AccessFlags::SHADER_READ);
+    RenderPassBuilder::default()
+        .attachment(attachment(
+            targets[ALBEDO as usize].format,
+            dont_care,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        ))
+        .attachment(attachment(targets[DEPTH as usize].format, store, general))
+        .attachment(attachment(targets[LIT as usize].format, store, general))
+        .attachment(attachment(
+            depth_format,
+            dont_care,
+            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        ))
+        .subpass(
+            Subpass::default()
+                .color(&[ALBEDO, DEPTH])
+                .depth_stencil(DEPTH_BUFFER),
+        )
+        .subpass(
+            Subpass::default()
+                .input(&[ALBEDO])
+                .color(&[LIT])
+                .preserve(&[DEPTH]),
+        )
+        // The previous frame's composite has to be done reading the depth before it's cleared,
+        // and its geometry subpass done with the depth buffer.
+        .dependency(
+            vk::SubpassDependency::default()
+                .src_subpass(vk::SUBPASS_EXTERNAL)
+                .dst_subpass(0)
+                .src_stage_mask(
+                    vk::PipelineStageFlags::FRAGMENT_SHADER
+                        | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                )
+                .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                .dst_stage_mask(
+                    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                        | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                )
+                .dst_access_mask(
+                    vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                ),
+        )
+        // And with the lit color before the lighting subpass clears it.
+        .dependency(
+            vk::SubpassDependency::default()
+                .src_subpass(vk::SUBPASS_EXTERNAL)
+                .dst_subpass(1)
+                .src_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+                .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE),
+        )
+        .input_dependency(0, 1)
+        .dependency(composite_read.src_subpass(0))
+        .dependency(composite_read.src_subpass(1))
+        .build(device)
+}
+
+/// The lighting subpass's push constants for targets of size `extent`.
+fn push_constants(extent: vk::Extent2D) -> [u8; PUSH_CONSTANTS_SIZE] {
+    let mut bytes = [0u8; PUSH_CONSTANTS_SIZE];
+    bytes[..4].copy_from_slice(&(extent.width as f32).to_ne_bytes());
+    bytes[4..].copy_from_slice(&(extent.height as f32).to_ne_bytes());
+    bytes
+}
+
+impl RendererBackend for Deferred {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Deferred
+    }
+
+    fn record_geometry(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        triangles: &Draw,
+        wireframe: bool,
+    ) -> Option<DrawStatistics> {
+        let geometry = self.geometry.draw(triangles, wireframe);
+        let lighting = Draw {
+            push_constants: Some((vk::ShaderStageFlags::FRAGMENT, &self.push_constants)),
+            ..backend::resolve_draw(self.lighting, &self.lighting_set)
+        };
+        Some(
+            self.targets
+                .record(device, command_buffer, &[&[geometry], &[lighting]]),
+        )
+    }
+
+    fn main_pass_draw<'a>(&'a self, _frame_index: usize, _triangles: &Draw<'a>) -> Draw<'a> {
+        backend::resolve_draw(self.composite, &self.composite_set)
+    }
+
+    fn resize(
+        &mut self,
+        device: &Device,
+        allocator: &Allocator,
+        extent: vk::Extent2D,
+    ) -> Result<(), Box<dyn Error>> {
+        self.targets.resize(device, allocator, extent)?;
+        self.push_constants = push_constants(extent);
+        self.write_descriptor_sets(device);
+        Ok(())
+    }
+
+    #[cfg(feature = "hot-reload")]
+    fn shaders(&self) -> Vec<&'static str> {
+        SHADERS.to_vec()
+    }
+
+    #[cfg(feature = "hot-reload")]
+    fn rebuild_pipelines(
+        &mut self,
+        device: &Device,
+        setup: PipelineSetup<'_>,
+    ) -> Result<(), Box<dyn Error>> {
+        let (geometry, lighting, composite) =
+            build_pipelines(device, setup, &self.targets, self.descriptor_set_layouts)?;
+        self.destroy_pipelines(device);
+        self.geometry = geometry;
+        self.lighting = lighting;
+        self.composite = composite;
+        Ok(())
+    }
+
+    fn destroy(&mut self, device: &Device) {
+        self.destroy_pipelines(device);
+        self.descriptors.destroy(device);
+        self.targets.destroy(device);
+    }
+}
@@ -0,0 +1,259 @@
+use ash::{vk, Device};
+#[cfg(feature = "hot-reload")]
+use std::collections::HashSet;
+use std::error::Error;
+
+use crate::buffer::Buffer;
+use crate::command::{Draw, TransferQueue};
+use crate::cube::Cube;
+use crate::descriptor::DescriptorManager;
+#[cfg(feature = "hot-reload")]
+use crate::geometry;
+use crate::geometry::VertexMarkers;
+use crate::memory::Allocator;
+use crate::settings::Settings;
+use crate::shader_cache::ShaderCache;
+#[cfg(feature = "hot-reload")]
+use crate::subsystem;
+use crate::subsystem::{Frame, PipelineContext, Subsystem};
+use crate::sync::Cancel;
+#[cfg(feature = "hot-reload")]
+use crate::tessellation;
+use crate::tessellation::DisplacedPlane;
+use crate::util::DeviceDetails;
+use crate::voxelize;
+
+//////////////// Demos ////////////////
+// Features the triangles don't need, each shown off on its own, and only with its setting on
+// (see util::TESSELLATION_DEMO, util::VERTEX_MARKERS_DEMO, util::CUBE_DEMO and util::VOXELIZATION_DEMO):
+// the displaced plane, the vertex markers, the cube, and a voxelization of the triangle at startup.
+
+/// The demos drawing every frame.
+#[derive(Default)]
+pub struct Demos {
+    // Set if the tessellation demo is on and the device supports tessellation shaders.
+    displaced_plane: Option<DisplacedPlane>,
+    // Set if the vertex markers demo is on and the device supports geometry shaders.
+    vertex_markers: Option<VertexMarkers>,
+    // Set if the cube demo is on.
+    cube: Option<Cube>,
+    // For the next frame.
+    plane_push_constants: [u8; 8],
+    markers_push_constants: [u8; 8],
+}
+
+impl Demos {
+    /// The demos `settings` turn on that the device supports, for pipelines like `context`'s.
+    /// The plane and the cube are uploaded through `transfer_queue`, the markers use the triangles' `descriptor_set_layout`.
+    pub fn new(
+        context: &PipelineContext,
+        allocator: &Allocator,
+        transfer_queue: &TransferQueue,
+        settings: &Settings,
+        descriptor_set_layout: vk::DescriptorSetLayout,
+    ) -> Result<Self, Box<dyn Error>> {
+        let device = context.device;
+        let displaced_plane = match (
+            settings.tessellation_demo,
+            context.device_details.tessellation_shader,
+        ) {
+            (true, true) => Some(DisplacedPlane::new(
+                device,
+                context.shader_cache,
+                allocator,
+                transfer_queue,
+                context.rendering,
+                context.samples,
+            )?),
+            (true, false) => {
+                log::warn!("Tessellation demo needs the tessellationShader feature, skipping it");
+                None
+            }
+            (false, _) => None,
+        };
+
+        let vertex_markers = match (
+            settings.vertex_markers_demo,
+            context.device_details.geometry_shader,
+        ) {
+            (true, true) => VertexMarkers::new(
+                device,
+                context.shader_cache,
+                context.rendering,
+                descriptor_set_layout,
+                context.samples,
+            )
+            .map(Some),
+            (true, false) => {
+                log::warn!("Vertex markers demo needs the geometryShader feature, skipping it");
+                Ok(None)
+            }
+            (false, _) => Ok(None),
+        };
+        let vertex_markers = match vertex_markers {
+            Ok(vertex_markers) => vertex_markers,
+            Err(err) => {
+                if let Some(mut displaced_plane) = displaced_plane {
+                    displaced_plane.destroy(device);
+                }
+                return Err(err);
+            }
+        };
+
+        let mut demos = Self {
+            displaced_plane,
+            vertex_markers,
+            ..Self::default()
+        };
+        if settings.cube_demo {
+            match Cube::new(device, allocator, transfer_queue) {
+                Ok(cube) => demos.cube = Some(cube),
+                Err(err) => {
+                    demos.destroy(device);
+                    return Err(err);
+                }
+            }
+        }
+        Ok(demos)
+    }
+}
+
+impl Subsystem for Demos {
+    fn describe(&self) -> Vec<(&'static str, String)> {
+        vec![
+            (
+                "displaced plane",
+                self.displaced_plane.is_some().to_string(),
+            ),
+            ("vertex markers", self.vertex_markers.is_some().to_string()),
+            ("cube", self.cube.is_some().to_string()),
+        ]
+    }
+
+    #[cfg(feature = "hot-reload")]
+    fn shaders(&self) -> Vec<&'static str> {
+        let plane = self
+            .displaced_plane
+            .as_ref()
+            .map(|_| &tessellation::SHADERS[..]);
+        let markers = self.vertex_markers.as_ref().map(|_| &geometry::SHADERS[..]);
+        plane
+            .into_iter()
+            .chain(markers)
+            .flatten()
+            .copied()
+            .collect()
+    }
+
+    #[cfg(feature = "hot-reload")]
+    fn rebuild_pipelines(&mut self, context: &PipelineContext, changed: &HashSet<String>) {
+        if let Some(displaced_plane) = &mut self.displaced_plane {
+            if subsystem::uses_any(&tessellation::SHADERS, changed) {
+                let result = displaced_plane.rebuild_pipeline(
+                    context.device,
+                    context.shader_cache,
+                    context.rendering,
+                    context.samples,
+                );
+                subsystem::log_rebuild("displaced plane", result);
+            }
+        }
+        if let Some(vertex_markers) = &mut self.vertex_markers {
+            if subsystem::uses_any(&geometry::SHADERS, changed) {
+                let result = vertex_markers.rebuild_pipeline(
+                    context.device,
+                    context.shader_cache,
+                    context.rendering,
+                    context.samples,
+                );
+                subsystem::log_rebuild("vertex marker", result);
+            }
+        }
+    }
+
+    fn animated(&self) -> bool {
+        // The plane's wave moves.
+        self.displaced_plane.is_some()
+    }
+
+    fn prepare(&mut self, frame: &Frame) {
+        self.plane_push_constants = DisplacedPlane::push_constants(frame.time);
+        self.markers_push_constants = VertexMarkers::push_constants(frame.extent);
+    }
+
+    fn draws<'a>(
+        &'a self,
+        triangles: &Draw<'a>,
+        scene: &mut Vec<Draw<'a>>,
+        overlays: &mut Vec<Draw<'a>>,
+    ) {
+        if let Some(displaced_plane) = &self.displaced_plane {
+            scene.push(displaced_plane.draw(&self.plane_push_constants));
+        }
+        if let Some(cube) = &self.cube {
+            scene.push(cube.draw(triangles));
+        }
+        if let Some(vertex_markers) = &self.vertex_markers {
+            overlays.push(vertex_markers.draw(triangles, &self.markers_push_constants));
+        }
+    }
+
+    fn destroy(&mut self, device: &Device) {
+        if let Some(displaced_plane) = &mut self.displaced_plane {
+            displaced_plane.destroy(device);
+        }
+        if let Some(vertex_markers) = &mut self.vertex_markers {
+            vertex_markers.destroy(device);
+        }
+        if let Some(cube) = &mut self.cube {
+            cube.destroy(device);
+        }
+    }
+}
+
+/// If the voxelization demo is on (see `Settings::voxelization_demo`) and the device supports it, log how
+/// many voxels the `vertex_count` vertices of `vertex_buffer` cover, see `voxelize::count_voxels`.
+/// Blocks until the GPU is done, unless `cancel` stops it.
+pub fn log_voxel_counts(
+    (device, shader_cache): (&Device, &ShaderCache),
+    allocator: &Allocator,
+    queue: (vk::CommandPool, vk::Queue),
+    cancel: Cancel,
+    descriptors: &mut DescriptorManager,
+    vertices: (&Buffer, u32),
+    (settings, device_details): (&Settings, &DeviceDetails),
+) -> Result<(), Box<dyn Error>> {
+    if !settings.voxelization_demo {
+        return Ok(());
+    }
+    if !device_details.storage_buffer_storage_class {
+        log::warn!("Voxelization demo needs storage buffers in shaders, skipping it");
+        return Ok(());
+    }
+    if !device_details.fragment_stores_and_atomics {
+        log::warn!("Voxelization demo needs fragmentStoresAndAtomics, skipping it");
+        return Ok(());
+    }
+
+    let counts = voxelize::count_voxels(
+        device,
+        allocator,
+        queue,
+        cancel,
+        (descriptors, shader_cache),
+        vertices,
+        device_details,
+    )?;
+    match counts {
+        (regular, Some(conservative)) => log::info!(
+            "Voxelized triangle: {} voxels, {} with conservative rasterization",
+            regular,
+            conservative
+        ),
+        (regular, None) => log::info!(
+            "Voxelized triangle: {} voxels (conservative rasterization isn't supported)",
+            regular
+        ),
+    }
+    Ok(())
+}
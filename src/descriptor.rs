@@ -0,0 +1,359 @@
+use ash::khr::push_descriptor;
+use ash::{vk, Device};
+use std::collections::HashMap;
+use std::error::Error;
+
+use crate::buffer::Buffer;
+#[cfg(feature = "asset-import")]
+use crate::texture::Texture;
+use crate::util;
+
+//////////////// Descriptors ////////////////
+// Layouts are created through the DescriptorManager, which remembers how many descriptors of each type
+// they declare. Pools are sized from that, so adding a binding to a shader only means declaring it
+// in its layout. When a pool runs out, another (bigger) one is created.
+// With VK_KHR_push_descriptor, a set can instead be pushed into the command buffer right before a draw
+// (see `push_descriptor_set`), for bindings that change every draw: no sets to allocate or keep per frame.
+
+/// How many sets of every declared layout the first pool has room for. Each new pool doubles it.
+const INITIAL_SETS_PER_LAYOUT: u32 = util::MAX_FRAMES_IN_FLIGHT as u32;
+
+/// Owns descriptor set layouts and the pools sets are allocated from.
+pub struct DescriptorManager {
+    layouts: Vec<vk::DescriptorSetLayout>,
+    // Descriptors of each type one set of every declared layout needs, added up.
+    declared: HashMap<vk::DescriptorType, u32>,
+    pools: Vec<vk::DescriptorPool>,
+    // Pools before this one are full.
+    current_pool: usize,
+    sets_per_layout: u32,
+}
+
+impl Default for DescriptorManager {
+    fn default() -> Self {
+        Self {
+            layouts: Vec::new(),
+            declared: HashMap::new(),
+            pools: Vec::new(),
+            current_pool: 0,
+            sets_per_layout: INITIAL_SETS_PER_LAYOUT,
+        }
+    }
+}
+
+impl DescriptorManager {
+    /// Create a set layout with `bindings` and declare its descriptors for pool sizing.
+    /// The layout lives as long as the manager.
+    pub fn create_layout(
+        &mut self,
+        device: &Device,
+        bindings: &[vk::DescriptorSetLayoutBinding],
+    ) -> Result<vk::DescriptorSetLayout, Box<dyn Error>> {
+        let layout_create_info = vk::DescriptorSetLayoutCreateInfo::default().bindings(bindings);
+        let layout = unsafe { device.create_descriptor_set_layout(&layout_create_info, None)? };
+
+        for binding in bindings.iter() {
+            *self.declared.entry(binding.descriptor_type).or_insert(0) += binding.descriptor_count;
+        }
+        self.layouts.push(layout);
+
+        Ok(layout)
+    }
+
+    /// Create a set layout with `bindings` whose descriptors are pushed with `push_descriptor_set`
+    /// instead of allocated. Needs PUSH_DESCRIPTOR_EXTENSION (see `DeviceDetails::push_descriptor`).
+    /// The layout lives as long as the manager.
+    pub fn create_push_layout(
+        &mut self,
+        device: &Device,
+        bindings: &[vk::DescriptorSetLayoutBinding],
+    ) -> Result<vk::DescriptorSetLayout, Box<dyn Error>> {
+        let layout_create_info = vk::DescriptorSetLayoutCreateInfo::default()
+            .flags(vk::DescriptorSetLayoutCreateFlags::PUSH_DESCRIPTOR_KHR)
+            .bindings(bindings);
+        let layout = unsafe { device.create_descriptor_set_layout(&layout_create_info, None)? };
+        // Sets of it can't be allocated, so it takes no room in the pools.
+        self.layouts.push(layout);
+
+        Ok(layout)
+    }
+
+    /// Allocate `count` sets of `layout` (created by this manager), growing into a new pool if needed.
+    pub fn allocate(
+        &mut self,
+        device: &Device,
+        layout: vk::DescriptorSetLayout,
+        count: usize,
+    ) -> Result<Vec<vk::DescriptorSet>, Box<dyn Error>> {
+        let layouts = vec![layout; count];
+
+        loop {
+            let fresh_pool = self.current_pool == self.pools.len();
+            if fresh_pool {
+                let pool = self.create_pool(device, count as u32)?;
+                self.pools.push(pool);
+            }
+
+            let allocate_info = vk::DescriptorSetAllocateInfo::default()
+                .descriptor_pool(self.pools[self.current_pool])
+                .set_layouts(&layouts);
+
+            match unsafe { device.allocate_descriptor_sets(&allocate_info) } {
+                Ok(sets) => return Ok(sets),
+                // This pool is full, move on to the next (new) one.
+                // A new pool is sized to fit the request, so if even that fails, give up.
+                Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY | vk::Result::ERROR_FRAGMENTED_POOL)
+                    if !fresh_pool =>
+                {
+                    self.current_pool += 1;
+                }
+                Err(err) => return Err(Box::new(err)),
+            }
+        }
+    }
+
+    /// One set of `layout` per frame in flight.
+    pub fn allocate_per_frame(
+        &mut self,
+        device: &Device,
+        layout: vk::DescriptorSetLayout,
+    ) -> Result<Vec<vk::DescriptorSet>, Box<dyn Error>> {
+        self.allocate(device, layout, util::MAX_FRAMES_IN_FLIGHT)
+    }
+
+    /// Destroy all pools (freeing their sets) and layouts. The GPU must be done with them.
+    pub fn destroy(&mut self, device: &Device) {
+        unsafe {
+            self.pools
+                .drain(..)
+                .for_each(|pool| device.destroy_descriptor_pool(pool, None));
+            self.layouts
+                .drain(..)
+                .for_each(|layout| device.destroy_descriptor_set_layout(layout, None));
+        }
+        self.current_pool = 0;
+    }
+
+    /// A pool with room for `sets_per_layout` (but at least `min_sets_per_layout`) sets of every declared layout.
+    /// The next pool will be twice as big.
+    fn create_pool(
+        &mut self,
+        device: &Device,
+        min_sets_per_layout: u32,
+    ) -> Result<vk::DescriptorPool, Box<dyn Error>> {
+        self.sets_per_layout = self.sets_per_layout.max(min_sets_per_layout);
+        let pool_sizes = self
+            .declared
+            .iter()
+            .map(|(ty, count)| {
+                vk::DescriptorPoolSize::default()
+                    .ty(*ty)
+                    .descriptor_count(count * self.sets_per_layout)
+            })
+            .collect::<Vec<_>>();
+        let max_sets = self.layouts.len().max(1) as u32 * self.sets_per_layout;
+
+        log::debug!(
+            "Creating descriptor pool for {} sets: {:?}",
+            max_sets,
+            pool_sizes
+        );
+
+        let pool_create_info = vk::DescriptorPoolCreateInfo::default()
+            .pool_sizes(&pool_sizes)
+            .max_sets(max_sets);
+        let pool = unsafe { device.create_descriptor_pool(&pool_create_info, None)? };
+
+        self.sets_per_layout *= 2;
+        Ok(pool)
+    }
+}
+
+/// Point binding `binding` of `set` at the whole of `buffer`, as a uniform buffer.
+pub fn write_uniform_buffer(
+    device: &Device,
+    set: vk::DescriptorSet,
+    binding: u32,
+    buffer: &Buffer,
+) {
+    write_buffer(
+        device,
+        set,
+        binding,
+        vk::DescriptorType::UNIFORM_BUFFER,
+        buffer,
+    );
+}
+
+/// Point binding `binding` of `set` at the whole of `buffer`, as a storage buffer.
+pub fn write_storage_buffer(
+    device: &Device,
+    set: vk::DescriptorSet,
+    binding: u32,
+    buffer: &Buffer,
+) {
+    write_buffer(
+        device,
+        set,
+        binding,
+        vk::DescriptorType::STORAGE_BUFFER,
+        buffer,
+    );
+}
+
+fn write_buffer(
+    device: &Device,
+    set: vk::DescriptorSet,
+    binding: u32,
+    descriptor_type: vk::DescriptorType,
+    buffer: &Buffer,
+) {
+    let buffer_infos = [vk::DescriptorBufferInfo::default()
+        .buffer(buffer.buffer)
+        .offset(0)
+        .range(buffer.size)];
+    let writes = [vk::WriteDescriptorSet::default()
+        .dst_set(set)
+        .dst_binding(binding)
+        .dst_array_element(0)
+        .descriptor_type(descriptor_type)
+        .buffer_info(&buffer_infos)];
+
+    unsafe { device.update_descriptor_sets(&writes, &[]) };
+}
+
+/// Point binding `image_binding` of `set` at `texture`'s view as a sampled image, and `sampler_binding`
+/// at its sampler, for shaders that combine them themselves (e.g. `samplerCube(image, sampler)`).
+#[cfg(feature = "asset-import")]
+pub fn write_separate_texture(
+    device: &Device,
+    set: vk::DescriptorSet,
+    image_binding: u32,
+    sampler_binding: u32,
+    texture: &Texture,
+) {
+    let image_infos = [vk::DescriptorImageInfo::default()
+        .image_view(texture.image.view)
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+    let sampler_infos = [vk::DescriptorImageInfo::default().sampler(texture.sampler)];
+    let writes = [
+        vk::WriteDescriptorSet::default()
+            .dst_set(set)
+            .dst_binding(image_binding)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+            .image_info(&image_infos),
+        vk::WriteDescriptorSet::default()
+            .dst_set(set)
+            .dst_binding(sampler_binding)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::SAMPLER)
+            .image_info(&sampler_infos),
+    ];
+
+    unsafe { device.update_descriptor_sets(&writes, &[]) };
+}
+
+/// Point binding `binding` of `set` at the image `view`, as a storage image in GENERAL layout,
+/// e.g. a render target shaders read a texel at a time with imageLoad.
+pub fn write_storage_image(
+    device: &Device,
+    set: vk::DescriptorSet,
+    binding: u32,
+    view: vk::ImageView,
+) {
+    let image_infos = [vk::DescriptorImageInfo::default()
+        .image_view(view)
+        .image_layout(vk::ImageLayout::GENERAL)];
+    let writes = [vk::WriteDescriptorSet::default()
+        .dst_set(set)
+        .dst_binding(binding)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+        .image_info(&image_infos)];
+
+    unsafe { device.update_descriptor_sets(&writes, &[]) };
+}
+
+/// Point binding `binding` of `set` at the color attachment `view`, as an input attachment read in
+/// SHADER_READ_ONLY_OPTIMAL layout by a later subpass (see render_pass.rs).
+pub fn write_input_attachment(
+    device: &Device,
+    set: vk::DescriptorSet,
+    binding: u32,
+    view: vk::ImageView,
+) {
+    let image_infos = [vk::DescriptorImageInfo::default()
+        .image_view(view)
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+    let writes = [vk::WriteDescriptorSet::default()
+        .dst_set(set)
+        .dst_binding(binding)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::INPUT_ATTACHMENT)
+        .image_info(&image_infos)];
+
+    unsafe { device.update_descriptor_sets(&writes, &[]) };
+}
+
+/// One descriptor of a pushed set, see `push_descriptor_set`.
+#[derive(Clone, Copy, Debug)]
+pub enum PushedDescriptor {
+    UniformBuffer(vk::DescriptorBufferInfo),
+}
+
+impl PushedDescriptor {
+    /// The whole of `buffer`, as a uniform buffer.
+    pub fn uniform_buffer(buffer: &Buffer) -> Self {
+        Self::UniformBuffer(
+            vk::DescriptorBufferInfo::default()
+                .buffer(buffer.buffer)
+                .offset(0)
+                .range(buffer.size),
+        )
+    }
+}
+
+/// Record `descriptors` (each with its binding) into `command_buffer` as set `set` of `layout`,
+/// a pipeline layout whose set `set` was created with `DescriptorManager::create_push_layout`.
+/// They're copied while recording, nothing has to outlive this call.
+pub fn push_descriptor_set(
+    push_descriptor_loader: &push_descriptor::Device,
+    command_buffer: vk::CommandBuffer,
+    layout: vk::PipelineLayout,
+    set: u32,
+    descriptors: &[(u32, PushedDescriptor)],
+) {
+    // The writes point into these, so they're collected first.
+    let infos = descriptors
+        .iter()
+        .map(|(_, descriptor)| match descriptor {
+            PushedDescriptor::UniformBuffer(info) => [*info],
+        })
+        .collect::<Vec<_>>();
+    let writes = descriptors
+        .iter()
+        .zip(infos.iter())
+        .map(|((binding, descriptor), buffer_info)| {
+            // dst_set is ignored when pushing.
+            let write = vk::WriteDescriptorSet::default()
+                .dst_binding(*binding)
+                .dst_array_element(0);
+            match descriptor {
+                PushedDescriptor::UniformBuffer(_) => write
+                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                    .buffer_info(buffer_info),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    unsafe {
+        push_descriptor_loader.cmd_push_descriptor_set(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            layout,
+            set,
+            &writes,
+        )
+    };
+}
@@ -0,0 +1,169 @@
+use ash::{vk, Device};
+
+use crate::image::{self, AllocatedImage};
+use crate::render_target::RenderTargets;
+
+//////////////// Dynamic Rendering ////////////////
+// Vulkan 1.3's cmd_begin_rendering takes the attachments' image views directly, so there is no
+// render pass to create and no framebuffers to recreate with the swapchain.
+// What the render pass did implicitly is recorded here instead: the layout transitions, and waiting
+// for the previous frame's depth writes before the (shared) depth buffer is cleared.
+
+/// The attachments of one frame, for `begin` and `end`.
+pub struct FrameAttachments<'a> {
+    pub extent: vk::Extent2D,
+    /// The swapchain image and its view in the swapchain format. Presented afterwards.
+    pub swapchain_image: (vk::Image, vk::ImageView),
+    /// The multisampled color target, resolved into the swapchain image. None without MSAA.
+    pub multisampled: Option<&'a AllocatedImage>,
+    pub depth: &'a AllocatedImage,
+    pub targets: &'a RenderTargets,
+}
+
+/// The stencil attachment format for a depth attachment of `depth_format`:
+/// the same format if it has a stencil component, UNDEFINED if it doesn't.
+pub fn stencil_format(depth_format: vk::Format) -> vk::Format {
+    if image::has_stencil_component(depth_format) {
+        depth_format
+    } else {
+        vk::Format::UNDEFINED
+    }
+}
+
+/// Transition `attachments` for rendering and begin rendering to them, with `flags`
+/// (e.g. CONTENTS_SECONDARY_COMMAND_BUFFERS).
+pub fn begin(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    attachments: &FrameAttachments,
+    flags: vk::RenderingFlags,
+) {
+    let color_range = subresource_range(vk::ImageAspectFlags::COLOR);
+    let depth_aspect = if image::has_stencil_component(attachments.depth.format) {
+        vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+    } else {
+        vk::ImageAspectFlags::DEPTH
+    };
+
+    // The acquire semaphore is waited on at COLOR_ATTACHMENT_OUTPUT, so the color transitions
+    // wait there too. Attachments start out UNDEFINED, like they did with the render pass.
+    let color_barrier = |image| {
+        vk::ImageMemoryBarrier::default()
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(color_range)
+    };
+    let mut barriers = vec![
+        color_barrier(attachments.swapchain_image.0),
+        vk::ImageMemoryBarrier::default()
+            .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .dst_access_mask(
+                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            )
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(attachments.depth.image)
+            .subresource_range(subresource_range(depth_aspect)),
+    ];
+    if let Some(multisampled) = attachments.multisampled {
+        barriers.push(color_barrier(multisampled.image));
+    }
+
+    let targets = attachments.targets;
+    let swapchain_view = attachments.swapchain_image.1;
+    let color_attachment = vk::RenderingAttachmentInfo::default()
+        .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        .load_op(targets.color.effective_load_op())
+        .clear_value(targets.color.clear_value);
+    let color_attachments = [match attachments.multisampled {
+        // The samples are only needed until they're resolved at the end of rendering.
+        Some(multisampled) => color_attachment
+            .image_view(multisampled.view)
+            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .resolve_mode(vk::ResolveModeFlags::AVERAGE)
+            .resolve_image_view(swapchain_view)
+            .resolve_image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+        None => color_attachment
+            .image_view(swapchain_view)
+            .store_op(targets.color.store_op),
+    }];
+    let depth_attachment = vk::RenderingAttachmentInfo::default()
+        .image_view(attachments.depth.view)
+        .image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+        .load_op(targets.depth.effective_load_op())
+        .store_op(targets.depth.store_op)
+        .clear_value(targets.depth.clear_value);
+
+    let mut rendering_info = vk::RenderingInfo::default()
+        .flags(flags)
+        .render_area(vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: attachments.extent,
+        })
+        .layer_count(1)
+        .color_attachments(&color_attachments)
+        .depth_attachment(&depth_attachment);
+    // Stencil follows depth if the depth buffer has a stencil component.
+    if depth_aspect.contains(vk::ImageAspectFlags::STENCIL) {
+        rendering_info = rendering_info.stencil_attachment(&depth_attachment);
+    }
+
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &barriers,
+        );
+        device.cmd_begin_rendering(command_buffer, &rendering_info);
+    }
+}
+
+/// End rendering to `attachments` and transition the swapchain image for presentation.
+pub fn end(device: &Device, command_buffer: vk::CommandBuffer, attachments: &FrameAttachments) {
+    let barriers = [vk::ImageMemoryBarrier::default()
+        .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+        .dst_access_mask(vk::AccessFlags::empty())
+        .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        .new_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(attachments.swapchain_image.0)
+        .subresource_range(subresource_range(vk::ImageAspectFlags::COLOR))];
+
+    unsafe {
+        device.cmd_end_rendering(command_buffer);
+        // Presentation waits on the render finished semaphore, which covers everything else.
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &barriers,
+        );
+    }
+}
+
+fn subresource_range(aspect_mask: vk::ImageAspectFlags) -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange::default()
+        .aspect_mask(aspect_mask)
+        .base_mip_level(0)
+        .level_count(1)
+        .base_array_layer(0)
+        .layer_count(1)
+}
@@ -0,0 +1,139 @@
+use ash::{vk, Device, Instance};
+use std::collections::HashSet;
+use std::error::Error;
+
+use crate::command::RawFrame;
+use crate::util::AppError;
+
+//////////////// GPU Frame Timer ////////////////
+// Custom commands recorded through the escape hatches (see `VulkanApp::set_raw_commands`): a timestamp at
+// the top of every frame's command buffer, one query per frame in flight. A frame's query is read back the
+// next time its frame in flight is recorded, once its fence was waited for, and the time between
+// consecutive frames is logged every LOG_INTERVAL frames, with the extent and how many swapchain images
+// the frames went to (fewer than the swapchain has means presents are blocking on the same ones).
+
+/// How many frames the logged interval is averaged over.
+const LOG_INTERVAL: u32 = 300;
+
+/// Owns its query pool, which is destroyed with it: drop it before the device, after the GPU is done.
+pub struct GpuFrameTimer {
+    device: Device,
+    query_pool: vk::QueryPool,
+    // Nanoseconds per tick, and the bits of a timestamp that are valid.
+    period: f64,
+    valid_mask: u64,
+    // Whether each frame in flight's query was written.
+    written: Vec<bool>,
+    // The last timestamp read back, and the intervals since the last log.
+    previous: Option<u64>,
+    ticks: u64,
+    intervals: u32,
+    // The swapchain images and extent of the frames since the last log.
+    images: HashSet<vk::Image>,
+    extent: vk::Extent2D,
+}
+
+impl GpuFrameTimer {
+    /// For frames recorded into command buffers of the queue family `queue_family_index`.
+    ///
+    /// # Safety
+    /// `device` has to be created from `physical_device` of `instance`.
+    pub unsafe fn new(
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        queue_family_index: u32,
+        frames_in_flight: usize,
+    ) -> Result<Self, Box<dyn Error>> {
+        let valid_bits = instance.get_physical_device_queue_family_properties(physical_device)
+            [queue_family_index as usize]
+            .timestamp_valid_bits;
+        if valid_bits == 0 {
+            return Err(Box::new(AppError::new(
+                "The graphics queue doesn't support timestamps",
+            )));
+        }
+        let period = instance
+            .get_physical_device_properties(physical_device)
+            .limits
+            .timestamp_period;
+
+        let query_pool_create_info = vk::QueryPoolCreateInfo::default()
+            .query_type(vk::QueryType::TIMESTAMP)
+            .query_count(frames_in_flight as u32);
+        let query_pool = device.create_query_pool(&query_pool_create_info, None)?;
+
+        Ok(Self {
+            device: device.clone(),
+            query_pool,
+            period: period as f64,
+            valid_mask: u64::MAX >> (64 - valid_bits),
+            written: vec![false; frames_in_flight],
+            previous: None,
+            ticks: 0,
+            intervals: 0,
+            images: HashSet::new(),
+            extent: vk::Extent2D::default(),
+        })
+    }
+
+    /// Read back the timestamp `frame`'s frame in flight wrote last time, then write its next one.
+    pub fn record(&mut self, device: &Device, frame: &RawFrame) {
+        let query = frame.frame_index as u32;
+        if self.written[frame.frame_index] {
+            let mut timestamp = [0u64];
+            let read = unsafe {
+                device.get_query_pool_results(
+                    self.query_pool,
+                    query,
+                    &mut timestamp,
+                    vk::QueryResultFlags::TYPE_64,
+                )
+            };
+            match read {
+                Ok(()) => self.add(timestamp[0] & self.valid_mask),
+                Err(err) => log::debug!("Failed to read frame timestamp: {}", err),
+            }
+        }
+
+        unsafe {
+            device.cmd_reset_query_pool(frame.command_buffer, self.query_pool, query, 1);
+            device.cmd_write_timestamp(
+                frame.command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                self.query_pool,
+                query,
+            );
+        }
+        self.written[frame.frame_index] = true;
+        self.images.insert(frame.image);
+        self.extent = frame.extent;
+    }
+
+    /// Count the interval since the previous timestamp, and log the average every LOG_INTERVAL frames.
+    fn add(&mut self, timestamp: u64) {
+        if let Some(previous) = self.previous.replace(timestamp) {
+            self.ticks += timestamp.wrapping_sub(previous) & self.valid_mask;
+            self.intervals += 1;
+        }
+        if self.intervals == LOG_INTERVAL {
+            let nanoseconds = self.ticks as f64 * self.period / self.intervals as f64;
+            log::info!(
+                "GPU frame interval: {:.2} ms at {}x{}, over {} swapchain images",
+                nanoseconds / 1_000_000.0,
+                self.extent.width,
+                self.extent.height,
+                self.images.len()
+            );
+            self.ticks = 0;
+            self.intervals = 0;
+            self.images.clear();
+        }
+    }
+}
+
+impl Drop for GpuFrameTimer {
+    fn drop(&mut self) {
+        unsafe { self.device.destroy_query_pool(self.query_pool, None) };
+    }
+}
@@ -0,0 +1,166 @@
+use ash::{vk, Device};
+use std::error::Error;
+
+use crate::command::Draw;
+use crate::pipeline::GraphicsPipelineBuilder;
+use crate::reflect::ShaderInterface;
+use crate::render_target::Rendering;
+use crate::shader_cache::ShaderCache;
+use crate::vertex;
+
+//////////////// Vertex Markers ////////////////
+// Draws the triangles again through a geometry shader that turns each of their corners into a small
+// square, on top of the scene. Nothing extra is uploaded: it's the triangles' own buffers and
+// descriptors, only the pipeline differs. Needs the geometryShader feature.
+
+/// Size of the squares, in pixels.
+const MARKER_SIZE: f32 = 6.0;
+
+/// Size of `push_constants`: half the marker size in clip space, horizontally and vertically.
+const PUSH_CONSTANTS_SIZE: u32 = 8;
+
+/// The shaders the pipeline is built from: the triangles' vertex shader, geometry, fragment.
+pub const SHADERS: [&str; 3] = ["shader.vert.spv", "markers.geom.spv", "shader.frag.spv"];
+
+/// The pipeline to draw the markers with.
+pub struct VertexMarkers {
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    push_constant_stages: vk::ShaderStageFlags,
+    // The triangles', owned by their descriptor manager. Kept to rebuild the pipeline.
+    #[cfg(feature = "hot-reload")]
+    descriptor_set_layout: vk::DescriptorSetLayout,
+}
+
+impl VertexMarkers {
+    /// Build the pipeline for `rendering`, using the triangles' `descriptor_set_layout`.
+    pub fn new(
+        device: &Device,
+        shader_cache: &ShaderCache,
+        rendering: Rendering,
+        descriptor_set_layout: vk::DescriptorSetLayout,
+        samples: vk::SampleCountFlags,
+    ) -> Result<Self, Box<dyn Error>> {
+        let (pipeline, pipeline_layout, push_constant_stages) = build_pipeline(
+            device,
+            shader_cache,
+            rendering,
+            descriptor_set_layout,
+            samples,
+        )?;
+        Ok(Self {
+            pipeline,
+            pipeline_layout,
+            push_constant_stages,
+            #[cfg(feature = "hot-reload")]
+            descriptor_set_layout,
+        })
+    }
+
+    /// Rebuild the pipeline from the current `SHADERS`, e.g. after they changed on disk.
+    /// Keeps the previous pipeline if building fails. The GPU must be done with it.
+    #[cfg(feature = "hot-reload")]
+    pub fn rebuild_pipeline(
+        &mut self,
+        device: &Device,
+        shader_cache: &ShaderCache,
+        rendering: Rendering,
+        samples: vk::SampleCountFlags,
+    ) -> Result<(), Box<dyn Error>> {
+        let (pipeline, pipeline_layout, push_constant_stages) = build_pipeline(
+            device,
+            shader_cache,
+            rendering,
+            self.descriptor_set_layout,
+            samples,
+        )?;
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        self.pipeline = pipeline;
+        self.pipeline_layout = pipeline_layout;
+        self.push_constant_stages = push_constant_stages;
+        Ok(())
+    }
+
+    /// Push constants for markers of MARKER_SIZE pixels on a swapchain of `extent`.
+    pub fn push_constants(extent: vk::Extent2D) -> [u8; 8] {
+        // Clip space is 2 across, so half a marker is MARKER_SIZE / extent of it.
+        let half_size = [
+            MARKER_SIZE / extent.width.max(1) as f32,
+            MARKER_SIZE / extent.height.max(1) as f32,
+        ];
+        let mut bytes = [0u8; PUSH_CONSTANTS_SIZE as usize];
+        for (chunk, value) in bytes.chunks_exact_mut(4).zip(half_size.iter()) {
+            chunk.copy_from_slice(&value.to_ne_bytes());
+        }
+        bytes
+    }
+
+    /// The draw for the markers of `triangles` (the triangles' own draw), with `push_constants`
+    /// from `VertexMarkers::push_constants`.
+    pub fn draw<'a>(&self, triangles: &Draw<'a>, push_constants: &'a [u8; 8]) -> Draw<'a> {
+        Draw {
+            pipeline: self.pipeline,
+            pipeline_layout: self.pipeline_layout,
+            descriptor_sets: triangles.descriptor_sets,
+            push_descriptors: triangles.push_descriptors,
+            push_constants: Some((self.push_constant_stages, push_constants)),
+            vertex_buffer: triangles.vertex_buffer,
+            instance_buffer: triangles.instance_buffer,
+            index_buffer: triangles.index_buffer,
+            vertex_count: triangles.vertex_count,
+        }
+    }
+
+    /// Destroy the pipeline. The GPU must be done with it.
+    pub fn destroy(&mut self, device: &Device) {
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
+
+fn build_pipeline(
+    device: &Device,
+    shader_cache: &ShaderCache,
+    rendering: Rendering,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    samples: vk::SampleCountFlags,
+) -> Result<(vk::Pipeline, vk::PipelineLayout, vk::ShaderStageFlags), Box<dyn Error>> {
+    let interface = ShaderInterface::from_shaders(shader_cache, &SHADERS)?;
+    // The same vertex input as the triangle pipeline.
+    let (vertex_bindings, vertex_attributes) = interface.vertex_input(&[
+        (
+            vk::VertexInputRate::VERTEX,
+            0..2,
+            size_of::<vertex::Vertex>(),
+        ),
+        (
+            vk::VertexInputRate::INSTANCE,
+            2..4,
+            size_of::<vertex::InstanceData>(),
+        ),
+    ])?;
+    let push_constant_ranges = interface.push_constant_ranges();
+    let push_constant_stages = push_constant_ranges
+        .first()
+        .map_or(vk::ShaderStageFlags::empty(), |range| range.stage_flags);
+    let descriptor_set_layouts = [descriptor_set_layout];
+
+    let (pipeline, pipeline_layout) = GraphicsPipelineBuilder::default()
+        .vertex_shader(SHADERS[0])
+        .geometry_shader(SHADERS[1])
+        .fragment_shader(SHADERS[2])
+        .vertex_input(&vertex_bindings, &vertex_attributes)
+        .descriptor_set_layouts(&descriptor_set_layouts)
+        .push_constant_ranges(&push_constant_ranges)
+        // Always on top of the triangles they mark.
+        .depth_test(false)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .samples(samples)
+        .build(device, shader_cache, rendering)?;
+    Ok((pipeline, pipeline_layout, push_constant_stages))
+}
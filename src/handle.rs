@@ -0,0 +1,82 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+//////////////// Typed Handles ////////////////
+// Raw vk handles are just numbers, so nothing stops a framebuffer from an old swapchain
+// (or an image view from another device) being used by mistake; the driver usually just crashes.
+// The handles that go stale when the swapchain is recreated (its images, their views and the
+// framebuffers) are wrapped with the context and swapchain generation they belong to, and checked
+// against the current scope whenever they are used (in debug builds). Other handles aren't tied to
+// a swapchain generation and stay raw.
+
+static NEXT_CONTEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Identifies the Vulkan context (instance + device) and swapchain generation handles are valid in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HandleScope {
+    context: u64,
+    generation: u64,
+}
+
+impl HandleScope {
+    /// A scope for a newly created context (unique for the lifetime of the process).
+    pub fn new_context() -> Self {
+        Self {
+            context: NEXT_CONTEXT_ID.fetch_add(1, Ordering::Relaxed),
+            generation: 0,
+        }
+    }
+
+    /// Move on to the next swapchain generation (e.g. after recreating the swapchain).
+    /// Handles tagged with an older generation are no longer valid.
+    pub fn next_generation(&mut self) {
+        self.generation += 1;
+    }
+
+    /// Tag a raw handle as belonging to this scope.
+    pub fn tag<T: Copy>(&self, raw: T) -> Handle<T> {
+        Handle { raw, scope: *self }
+    }
+}
+
+/// A raw vk handle tagged with the scope it was created in.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Handle<T: Copy> {
+    raw: T,
+    scope: HandleScope,
+}
+
+impl<T: Copy + fmt::Debug> Handle<T> {
+    /// Get the raw handle for use in the `current` scope.
+    ///
+    /// # Panics
+    ///
+    /// In debug builds, panic if the handle was created by another context or an older swapchain generation.
+    pub fn get(&self, current: &HandleScope) -> T {
+        debug_assert!(
+            self.scope.context == current.context,
+            "{:?} belongs to context {} but was used in context {}",
+            self.raw,
+            self.scope.context,
+            current.context
+        );
+        debug_assert!(
+            self.scope.generation == current.generation,
+            "{:?} belongs to swapchain generation {} but was used in generation {}",
+            self.raw,
+            self.scope.generation,
+            current.generation
+        );
+        self.raw
+    }
+}
+
+impl<T: Copy + fmt::Debug> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?} (context {}, generation {})",
+            self.raw, self.scope.context, self.scope.generation
+        )
+    }
+}
@@ -0,0 +1,112 @@
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant};
+
+use crate::shader;
+use crate::shader_cache::ShaderCache;
+
+//////////////// Shader Hot Reload ////////////////
+// The shader directory is watched while the app runs. notify reports changes on its own thread,
+// they're queued up and picked up by the graphics thread between frames, which rebuilds the
+// pipelines that use the changed shaders (see VulkanApp::reload_shaders).
+// Editors tend to write a file in several steps, so one save shows up as a few changes. A shader is
+// only reported once it stopped changing for DEBOUNCE, so it's rebuilt once, from the finished file.
+// If it's read while still half written anyway, building with it fails, the previous pipeline is kept,
+// and the next write triggers another reload.
+
+/// How long a shader has to be left alone after a change before it's reloaded.
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// Watches the shader directory for changed shaders.
+pub struct ShaderWatcher {
+    // Stops watching when dropped.
+    _watcher: notify::RecommendedWatcher,
+    changed: Receiver<(String, Instant)>,
+    // Changed shaders not reported yet, with when they last changed.
+    pending: HashMap<String, Instant>,
+}
+
+impl ShaderWatcher {
+    /// Start watching `shader::shader_dir()`. Headers are looked up in `shader_cache`, which loads
+    /// the shaders including them.
+    pub fn new(shader_cache: &ShaderCache) -> Result<Self, Box<dyn Error>> {
+        let shader_cache = shader_cache.clone();
+        let (sender, changed) = mpsc::channel();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(err) => {
+                        log::warn!("Watching shaders failed: {}", err);
+                        return;
+                    }
+                };
+                if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                    return;
+                }
+                for name in event
+                    .paths
+                    .iter()
+                    .flat_map(|path| shader_names(path, &shader_cache))
+                {
+                    // Only fails once the ShaderWatcher (and with it this watcher) is gone.
+                    let _ = sender.send((name, Instant::now()));
+                }
+            })?;
+
+        let dir = shader::shader_dir();
+        watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+        log::info!("Watching {} for shader changes", dir.display());
+
+        Ok(Self {
+            _watcher: watcher,
+            changed,
+            pending: HashMap::new(),
+        })
+    }
+
+    /// The shaders (file names as pipelines load them, e.g. `shader.vert.spv`) that changed since
+    /// the last call, and then didn't for DEBOUNCE. The others are reported by a later call.
+    pub fn changed(&mut self) -> HashSet<String> {
+        self.pending.extend(self.changed.try_iter());
+        let now = Instant::now();
+        let settled: HashSet<String> = self
+            .pending
+            .iter()
+            .filter(|(_, changed)| now.duration_since(**changed) >= DEBOUNCE)
+            .map(|(name, _)| name.clone())
+            .collect();
+        self.pending.retain(|name, _| !settled.contains(name));
+        settled
+    }
+}
+
+/// The names pipelines load the shaders affected by a change to `path` by.
+/// With the shaderc or hlsl feature GLSL or HLSL sources are compiled on load, so their changes count too,
+/// and so do changes to the headers GLSL sources include.
+#[cfg_attr(not(feature = "shaderc"), allow(unused_variables))]
+fn shader_names(path: &Path, shader_cache: &ShaderCache) -> Vec<String> {
+    let (Some(name), Some(extension)) = (
+        path.file_name().and_then(|name| name.to_str()),
+        path.extension().and_then(|extension| extension.to_str()),
+    ) else {
+        return Vec::new();
+    };
+    match extension {
+        "spv" => vec![name.to_string()],
+        "vert" | "frag" | "comp" | "tesc" | "tese" | "geom" if cfg!(feature = "shaderc") => {
+            vec![format!("{}.spv", name)]
+        }
+        #[cfg(feature = "shaderc")]
+        "glsl" => shader_cache.including(path),
+        "hlsl" if cfg!(feature = "hlsl") => name
+            .strip_suffix(".hlsl")
+            .map(|stem| format!("{}.spv", stem))
+            .into_iter()
+            .collect(),
+        _ => Vec::new(),
+    }
+}
@@ -0,0 +1,577 @@
+use ash::{vk, Device, Instance};
+use std::error::Error;
+
+use crate::memory::Allocator;
+use crate::util::AppError;
+
+//////////////// Images ////////////////
+
+/// An image together with the memory backing it and a view of it.
+pub struct AllocatedImage {
+    pub image: vk::Image,
+    pub memory: vk::DeviceMemory,
+    pub view: vk::ImageView,
+    pub format: vk::Format,
+    // Frees the memory.
+    pub allocator: Allocator,
+}
+
+impl AllocatedImage {
+    /// Destroy the view and image and free the memory. The GPU must be done with it.
+    /// Handles are nulled afterwards, so destroying twice is harmless.
+    pub fn destroy(&mut self, device: &Device) {
+        unsafe {
+            device.destroy_image_view(self.view, None);
+            device.destroy_image(self.image, None);
+        }
+        self.allocator.free(device, self.memory);
+        self.view = vk::ImageView::null();
+        self.image = vk::Image::null();
+        self.memory = vk::DeviceMemory::null();
+    }
+}
+
+/// Views of one swapchain image.
+/// `view` is in the swapchain's own format (the framebuffers use it). With a MUTABLE_FORMAT swapchain
+/// there is also an `alternate` view reinterpreting the image as its UNORM/SRGB counterpart,
+/// e.g. UNORM for storage writes from compute while blending happens in SRGB.
+#[derive(Debug, Clone, Copy)]
+pub struct SwapchainImageViews {
+    pub format: vk::Format,
+    pub view: vk::ImageView,
+    pub alternate: Option<(vk::Format, vk::ImageView)>,
+}
+
+impl SwapchainImageViews {
+    /// Formats this image can be viewed as.
+    pub fn formats(&self) -> Vec<vk::Format> {
+        std::iter::once(self.format)
+            .chain(self.alternate.map(|(format, _)| format))
+            .collect()
+    }
+
+    /// Destroy all views. The GPU must be done with them.
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            device.destroy_image_view(self.view, None);
+            if let Some((_, view)) = self.alternate {
+                device.destroy_image_view(view, None);
+            }
+        }
+    }
+}
+
+/// Create a 2D image view covering the whole image, all `mip_levels` of it.
+pub fn image_view(
+    device: &Device,
+    image: vk::Image,
+    format: vk::Format,
+    aspect_mask: vk::ImageAspectFlags,
+    mip_levels: u32,
+) -> Result<vk::ImageView, Box<dyn Error>> {
+    view(
+        device,
+        image,
+        vk::ImageViewType::TYPE_2D,
+        format,
+        vk::ImageSubresourceRange {
+            aspect_mask,
+            base_mip_level: 0,
+            level_count: mip_levels,
+            base_array_layer: 0,
+            layer_count: 1,
+        },
+    )
+}
+
+#[cfg(feature = "asset-import")]
+/// Create a CUBE view of the color aspect of a `cube_image`.
+pub fn cube_view(
+    device: &Device,
+    image: vk::Image,
+    format: vk::Format,
+) -> Result<vk::ImageView, Box<dyn Error>> {
+    view(
+        device,
+        image,
+        vk::ImageViewType::CUBE,
+        format,
+        vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 6,
+        },
+    )
+}
+
+fn view(
+    device: &Device,
+    image: vk::Image,
+    view_type: vk::ImageViewType,
+    format: vk::Format,
+    subresource_range: vk::ImageSubresourceRange,
+) -> Result<vk::ImageView, Box<dyn Error>> {
+    let image_view_create_info = vk::ImageViewCreateInfo::default()
+        .image(image)
+        .view_type(view_type)
+        .format(format)
+        .components(vk::ComponentMapping {
+            r: vk::ComponentSwizzle::IDENTITY,
+            g: vk::ComponentSwizzle::IDENTITY,
+            b: vk::ComponentSwizzle::IDENTITY,
+            a: vk::ComponentSwizzle::IDENTITY,
+        })
+        .subresource_range(subresource_range);
+
+    unsafe { Ok(device.create_image_view(&image_view_create_info, None)?) }
+}
+
+/// Create a 2D, optimally tiled image with `mip_levels` mips, backed by device local memory
+/// (or host visible memory if that runs out, see `Allocator::allocate`).
+pub fn image(
+    device: &Device,
+    allocator: &Allocator,
+    extent: vk::Extent2D,
+    format: vk::Format,
+    mip_levels: u32,
+    samples: vk::SampleCountFlags,
+    usage: vk::ImageUsageFlags,
+) -> Result<(vk::Image, vk::DeviceMemory), Box<dyn Error>> {
+    let image_create_info = vk::ImageCreateInfo::default()
+        .image_type(vk::ImageType::TYPE_2D)
+        .extent(vk::Extent3D {
+            width: extent.width,
+            height: extent.height,
+            depth: 1,
+        })
+        .mip_levels(mip_levels)
+        .array_layers(1)
+        .format(format)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .usage(usage)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .samples(samples);
+
+    create_image(device, allocator, &image_create_info)
+}
+
+#[cfg(feature = "asset-import")]
+/// Create a single mip cubemap image: 6 square layers of `size` (+X, -X, +Y, -Y, +Z, -Z),
+/// optimally tiled and backed by device local memory like `image`.
+pub fn cube_image(
+    device: &Device,
+    allocator: &Allocator,
+    size: u32,
+    format: vk::Format,
+    usage: vk::ImageUsageFlags,
+) -> Result<(vk::Image, vk::DeviceMemory), Box<dyn Error>> {
+    let image_create_info = vk::ImageCreateInfo::default()
+        .flags(vk::ImageCreateFlags::CUBE_COMPATIBLE)
+        .image_type(vk::ImageType::TYPE_2D)
+        .extent(vk::Extent3D {
+            width: size,
+            height: size,
+            depth: 1,
+        })
+        .mip_levels(1)
+        .array_layers(6)
+        .format(format)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .usage(usage)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .samples(vk::SampleCountFlags::TYPE_1);
+
+    create_image(device, allocator, &image_create_info)
+}
+
+fn create_image(
+    device: &Device,
+    allocator: &Allocator,
+    image_create_info: &vk::ImageCreateInfo,
+) -> Result<(vk::Image, vk::DeviceMemory), Box<dyn Error>> {
+    let image = unsafe { device.create_image(image_create_info, None)? };
+
+    let extent = image_create_info.extent;
+    let memory_requirements = unsafe { device.get_image_memory_requirements(image) };
+    let memory = match allocator.allocate(
+        device,
+        memory_requirements,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        &format!(
+            "a {}x{}x{} {:?} image",
+            extent.width, extent.height, image_create_info.array_layers, image_create_info.format
+        ),
+    ) {
+        Ok((memory, _)) => memory,
+        Err(err) => {
+            unsafe { device.destroy_image(image, None) };
+            return Err(err);
+        }
+    };
+    if let Err(err) = unsafe { device.bind_image_memory(image, memory, 0) } {
+        allocator.free(device, memory);
+        unsafe { device.destroy_image(image, None) };
+        return Err(Box::new(err));
+    }
+
+    Ok((image, memory))
+}
+
+/// Whether `format` has a depth component (depth only or combined depth/stencil).
+pub fn has_depth_component(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::D16_UNORM | vk::Format::X8_D24_UNORM_PACK32 | vk::Format::D32_SFLOAT
+    ) || has_stencil_component(format)
+}
+
+/// Whether `format` is a combined depth/stencil format.
+pub fn has_stencil_component(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::D32_SFLOAT_S8_UINT
+            | vk::Format::D24_UNORM_S8_UINT
+            | vk::Format::D16_UNORM_S8_UINT
+    )
+}
+
+/// Pick the first depth format usable as an optimally tiled depth attachment on this device.
+/// With `require_stencil` only combined depth/stencil formats are considered.
+pub fn find_depth_format(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+    require_stencil: bool,
+) -> Result<vk::Format, Box<dyn Error>> {
+    let candidates = [
+        vk::Format::D32_SFLOAT,
+        vk::Format::D32_SFLOAT_S8_UINT,
+        vk::Format::D24_UNORM_S8_UINT,
+    ];
+
+    candidates
+        .iter()
+        .filter(|format| !require_stencil || has_stencil_component(**format))
+        .find(|format| {
+            let properties = unsafe {
+                instance.get_physical_device_format_properties(physical_device, **format)
+            };
+            properties
+                .optimal_tiling_features
+                .contains(vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+        })
+        .copied()
+        .ok_or_else(|| {
+            let message = if require_stencil {
+                "No supported depth/stencil format found!"
+            } else {
+                "No supported depth format found!"
+            };
+            Box::new(AppError::new(message)) as Box<dyn Error>
+        })
+}
+
+/// Highest sample count up to `requested` that both color and depth attachments support on this device.
+pub fn usable_sample_count(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+    requested: vk::SampleCountFlags,
+) -> vk::SampleCountFlags {
+    let limits = unsafe { instance.get_physical_device_properties(physical_device) }.limits;
+    let supported = limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts;
+
+    [
+        vk::SampleCountFlags::TYPE_64,
+        vk::SampleCountFlags::TYPE_32,
+        vk::SampleCountFlags::TYPE_16,
+        vk::SampleCountFlags::TYPE_8,
+        vk::SampleCountFlags::TYPE_4,
+        vk::SampleCountFlags::TYPE_2,
+    ]
+    .into_iter()
+    .find(|count| count.as_raw() <= requested.as_raw() && supported.contains(*count))
+    .unwrap_or(vk::SampleCountFlags::TYPE_1)
+}
+
+/// Create the multisampled color target (image, memory and view) that gets resolved into the swapchain image.
+/// It's only used within the render pass, so it's transient.
+pub fn color_attachment(
+    device: &Device,
+    allocator: &Allocator,
+    extent: vk::Extent2D,
+    format: vk::Format,
+    samples: vk::SampleCountFlags,
+) -> Result<AllocatedImage, Box<dyn Error>> {
+    let (image, memory) = image(
+        device,
+        allocator,
+        extent,
+        format,
+        1,
+        samples,
+        vk::ImageUsageFlags::TRANSIENT_ATTACHMENT | vk::ImageUsageFlags::COLOR_ATTACHMENT,
+    )?;
+    let view = image_view(device, image, format, vk::ImageAspectFlags::COLOR, 1)?;
+
+    Ok(AllocatedImage {
+        image,
+        memory,
+        view,
+        format,
+        allocator: allocator.clone(),
+    })
+}
+
+/// Create the depth buffer (image, memory and view) matching the swapchain extent.
+/// The view covers the stencil aspect too if `format` has one.
+pub fn depth_attachment(
+    device: &Device,
+    allocator: &Allocator,
+    extent: vk::Extent2D,
+    format: vk::Format,
+    samples: vk::SampleCountFlags,
+) -> Result<AllocatedImage, Box<dyn Error>> {
+    let (image, memory) = image(
+        device,
+        allocator,
+        extent,
+        format,
+        1,
+        samples,
+        vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+    )?;
+    let aspect_mask = if has_stencil_component(format) {
+        vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+    } else {
+        vk::ImageAspectFlags::DEPTH
+    };
+    let view = image_view(device, image, format, aspect_mask, 1)?;
+
+    Ok(AllocatedImage {
+        image,
+        memory,
+        view,
+        format,
+        allocator: allocator.clone(),
+    })
+}
+
+#[cfg(feature = "asset-import")]
+/// Record a barrier moving the color aspect of the first `mip_levels` mips and `layer_count` layers
+/// of `image` from `old_layout` to `new_layout`.
+/// Supports the transitions of a texture upload: UNDEFINED to TRANSFER_DST_OPTIMAL before the copy,
+/// then TRANSFER_DST_OPTIMAL to SHADER_READ_ONLY_OPTIMAL for sampling in fragment shaders.
+pub fn transition_layout(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    mip_levels: u32,
+    layer_count: u32,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+) -> Result<(), Box<dyn Error>> {
+    let (src_access_mask, dst_access_mask, src_stage, dst_stage) = match (old_layout, new_layout) {
+        (vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL) => (
+            vk::AccessFlags::empty(),
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            vk::PipelineStageFlags::TRANSFER,
+        ),
+        (vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL) => (
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::AccessFlags::SHADER_READ,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+        ),
+        _ => {
+            return Err(Box::new(AppError::new(&format!(
+                "Unsupported layout transition from {:?} to {:?}",
+                old_layout, new_layout
+            ))))
+        }
+    };
+
+    let barriers = [vk::ImageMemoryBarrier::default()
+        .old_layout(old_layout)
+        .new_layout(new_layout)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: mip_levels,
+            base_array_layer: 0,
+            layer_count,
+        })
+        .src_access_mask(src_access_mask)
+        .dst_access_mask(dst_access_mask)];
+
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            src_stage,
+            dst_stage,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &barriers,
+        )
+    };
+    Ok(())
+}
+
+#[cfg(feature = "asset-import")]
+/// Number of mips in a full chain for `extent`, down to 1x1.
+pub fn mip_levels(extent: vk::Extent2D) -> u32 {
+    u32::BITS - extent.width.max(extent.height).max(1).leading_zeros()
+}
+
+/// Whether the device can generate mips for optimally tiled images of `format` by blitting with linear filtering.
+#[cfg(feature = "asset-import")]
+pub fn supports_mipmap_generation(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+    format: vk::Format,
+) -> bool {
+    let properties =
+        unsafe { instance.get_physical_device_format_properties(physical_device, format) };
+    properties.optimal_tiling_features.contains(
+        vk::FormatFeatureFlags::BLIT_SRC
+            | vk::FormatFeatureFlags::BLIT_DST
+            | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR,
+    )
+}
+
+#[cfg(feature = "asset-import")]
+/// Record blits filling mips 1.. of `image` (of `extent`) from mip 0, each from the one before it.
+/// All mips must be TRANSFER_DST_OPTIMAL, with mip 0 already written. Afterwards they're all
+/// SHADER_READ_ONLY_OPTIMAL. Check `supports_mipmap_generation` for the image's format first.
+pub fn generate_mipmaps(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    extent: vk::Extent2D,
+    mip_levels: u32,
+) {
+    let barrier = |level: u32,
+                   old_layout: vk::ImageLayout,
+                   new_layout: vk::ImageLayout,
+                   src_access_mask: vk::AccessFlags,
+                   dst_access_mask: vk::AccessFlags| {
+        vk::ImageMemoryBarrier::default()
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: level,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .src_access_mask(src_access_mask)
+            .dst_access_mask(dst_access_mask)
+    };
+    let pipeline_barrier = |src_stage, dst_stage, barrier: vk::ImageMemoryBarrier| unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            src_stage,
+            dst_stage,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[barrier],
+        )
+    };
+    let subresource = |level: u32| vk::ImageSubresourceLayers {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        mip_level: level,
+        base_array_layer: 0,
+        layer_count: 1,
+    };
+
+    let mut width = extent.width as i32;
+    let mut height = extent.height as i32;
+    for level in 1..mip_levels {
+        // The previous mip has been written (by the upload or the last blit), read from it now.
+        pipeline_barrier(
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::TRANSFER,
+            barrier(
+                level - 1,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::AccessFlags::TRANSFER_READ,
+            ),
+        );
+
+        let next_width = (width / 2).max(1);
+        let next_height = (height / 2).max(1);
+        let regions = [vk::ImageBlit::default()
+            .src_subresource(subresource(level - 1))
+            .src_offsets([
+                vk::Offset3D::default(),
+                vk::Offset3D {
+                    x: width,
+                    y: height,
+                    z: 1,
+                },
+            ])
+            .dst_subresource(subresource(level))
+            .dst_offsets([
+                vk::Offset3D::default(),
+                vk::Offset3D {
+                    x: next_width,
+                    y: next_height,
+                    z: 1,
+                },
+            ])];
+        unsafe {
+            device.cmd_blit_image(
+                command_buffer,
+                image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &regions,
+                vk::Filter::LINEAR,
+            )
+        };
+
+        // Done reading the previous mip.
+        pipeline_barrier(
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            barrier(
+                level - 1,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::AccessFlags::TRANSFER_READ,
+                vk::AccessFlags::SHADER_READ,
+            ),
+        );
+
+        width = next_width;
+        height = next_height;
+    }
+
+    // The last mip was only written to.
+    pipeline_barrier(
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::FRAGMENT_SHADER,
+        barrier(
+            mip_levels - 1,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::AccessFlags::SHADER_READ,
+        ),
+    );
+}
@@ -0,0 +1,72 @@
+use std::cell::Cell;
+use std::fmt;
+use std::io::Write;
+
+//////////////// Log Context ////////////////
+// Every log message gets the frame and pass the thread logging it is working on, e.g.
+// `[... ERROR vulkan_ash_tutorial::util] [frame 1234, scene] Validation Error: ...`, so messages in a
+// long capture can be matched to where they came from. That includes validation messages: the layers
+// call back on the thread making the Vulkan call. The render thread sets the context (and hands it to
+// its recording threads), messages logged anywhere else (e.g. the event loop) go without.
+
+thread_local! {
+    static CONTEXT: Cell<Context> = const { Cell::new(Context { frame: None, pass: None }) };
+}
+
+/// What the current thread is working on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Context {
+    /// Counts the frames the app ran, from 0.
+    pub frame: Option<u64>,
+    /// The part of the frame, e.g. "acquire" or "scene".
+    pub pass: Option<&'static str>,
+}
+
+impl fmt::Display for Context {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.frame, self.pass) {
+            (Some(frame), Some(pass)) => write!(f, "[frame {}, {}] ", frame, pass),
+            (Some(frame), None) => write!(f, "[frame {}] ", frame),
+            (None, Some(pass)) => write!(f, "[{}] ", pass),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// Set up env_logger (configured with RUST_LOG as usual) to write the context before every message.
+pub fn init_logger() {
+    env_logger::Builder::from_default_env()
+        .format(|buf, record| {
+            let level_style = buf.default_level_style(record.level());
+            writeln!(
+                buf,
+                "[{} {level_style}{:<5}{level_style:#} {}] {}{}",
+                buf.timestamp(),
+                record.level(),
+                record.target(),
+                current(),
+                record.args()
+            )
+        })
+        .init();
+}
+
+/// The current thread's context.
+pub fn current() -> Context {
+    CONTEXT.get()
+}
+
+/// Replace the current thread's context, e.g. with the one of the thread that handed it work.
+pub fn set(context: Context) {
+    CONTEXT.set(context);
+}
+
+/// Start frame `frame` on the current thread, outside any pass.
+pub fn set_frame(frame: Option<u64>) {
+    set(Context { frame, pass: None });
+}
+
+/// Move the current thread on to `pass` of its frame.
+pub fn set_pass(pass: Option<&'static str>) {
+    set(Context { pass, ..current() });
+}
@@ -1,13 +1,20 @@
 use ash::ext::debug_utils;
-use ash::khr::{surface, swapchain};
+use ash::khr::{push_descriptor, surface, swapchain};
 
 use ash::vk::SurfaceKHR;
 use ash::{vk, Device, Entry, Instance};
-use std::borrow::{Borrow, BorrowMut};
-use std::collections::HashMap;
+use handle::{Handle, HandleScope};
+use std::any::Any;
+use std::borrow::BorrowMut;
+use std::cell::OnceCell;
+#[cfg(feature = "hot-reload")]
+use std::collections::HashSet;
+use std::collections::{HashMap, VecDeque};
+use std::panic::{self, AssertUnwindSafe};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{error::Error, result::Result};
+use util::{DeviceDetails, SwapChainSupportDetails};
 use winit::dpi::PhysicalSize;
 use winit::platform::x11::WindowAttributesExtX11;
 use winit::raw_window_handle::HasDisplayHandle;
@@ -16,15 +23,80 @@ use winit::platform::startup_notify::{
     self, EventLoopExtStartupNotify, WindowAttributesExtStartupNotify,
 };
 use winit::{
-    application::ApplicationHandler, event_loop::ActiveEventLoop, event_loop::EventLoop,
-    window::Window, window::WindowId,
+    application::ApplicationHandler,
+    event::{ElementState, KeyEvent, WindowEvent},
+    event_loop::ActiveEventLoop,
+    event_loop::EventLoop,
+    keyboard::{KeyCode, PhysicalKey},
+    window::Window,
+    window::WindowId,
 };
 
 use std::sync::Arc;
-use std::sync::Mutex;
 
 // mod debug;
+mod assets;
+mod audit;
+mod backend;
+mod buffer;
+#[cfg(feature = "capture")]
+mod capture;
+mod command;
+#[cfg(feature = "demos")]
+mod cube;
+#[cfg(feature = "ui")]
+mod cursor;
+mod deferred;
+#[cfg(feature = "demos")]
+mod demos;
+mod descriptor;
+mod dynamic_rendering;
+mod frame_timer;
+#[cfg(feature = "demos")]
+mod geometry;
+mod handle;
+#[cfg(feature = "hot-reload")]
+mod hot_reload;
+mod image;
+mod log_context;
+mod memory;
+mod ownership;
+#[cfg(feature = "parallel-recording")]
+mod parallel;
+mod pipeline;
+#[cfg(feature = "post-processing")]
+mod post;
+#[cfg(feature = "asset-import")]
+mod precision;
+mod present;
+mod reflect;
+mod render_pass;
+mod render_target;
+#[cfg(feature = "asset-import")]
+mod scenery;
+mod settings;
+mod shader;
+mod shader_cache;
+#[cfg(feature = "asset-import")]
+mod skybox;
+mod spirv;
+mod subsystem;
+mod sync;
+#[cfg(feature = "demos")]
+mod tessellation;
+#[cfg(feature = "asset-import")]
+mod texture;
+#[cfg(feature = "asset-import")]
+mod textured_quad;
+mod triple_buffer;
+#[cfg(feature = "ui")]
+mod ui;
+mod uniform;
 mod util;
+mod vertex;
+mod visibility;
+#[cfg(feature = "demos")]
+mod voxelize;
 mod vulkan_create;
 
 // Need to use underscores: "If the binary name contains hyphens, you will need to replace them with underscores:"
@@ -41,12 +113,60 @@ mod vulkan_create;
 
 #[derive(Debug)]
 enum EventLoopProxyEvent {
-    RequestWindowHandle,
+    // The graphics thread has finished (and cleaned up), so the event loop can stop.
+    Exit,
+    // The graphics thread panicked (with this message), after cleaning up what it could.
+    Fatal(String),
 }
 
+/// What the event loop tells the graphics thread: input, window state and settings.
+/// The event loop publishes a snapshot after every change, the graphics thread reads the latest one
+/// once per frame (see triple_buffer.rs), so neither ever waits for the other.
+#[derive(Clone)]
+struct UiState {
+    // Set once the window is created, the graphics thread waits for it.
+    window: Option<Arc<Window>>,
+    // Cleared when the window is closed, telling the graphics thread to stop rendering.
+    running: bool,
+    // Counts resizes. When it changes, the graphics thread recreates the swapchain.
+    resizes: u64,
+    // Toggled with the W key, telling the graphics thread to draw in wireframe.
+    wireframe: bool,
+    // Counts presses of the B key. When it changes, the graphics thread switches to the next renderer backend.
+    backend_switches: u64,
+    // Cursor position in the window (physical pixels), None while it's outside. Used for the software cursor.
+    #[cfg(feature = "ui")]
+    cursor_position: Option<(f64, f64)>,
+}
+
+impl Default for UiState {
+    fn default() -> Self {
+        Self {
+            window: None,
+            running: true,
+            resizes: 0,
+            wireframe: false,
+            backend_switches: 0,
+            #[cfg(feature = "ui")]
+            cursor_position: None,
+        }
+    }
+}
+
+/// How often the graphics thread checks whether the window has been created yet.
+const WINDOW_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
 struct Application {
     windows: HashMap<WindowId, Arc<Window>>,
-    shared_window: Arc<Mutex<Option<Arc<Window>>>>,
+    // The event loop's copy, published to the graphics thread through `ui_writer` when it changes.
+    ui_state: UiState,
+    ui_writer: triple_buffer::Writer<UiState>,
+    // Set when the graphics thread panicked, the process then exits with an error.
+    failed: bool,
+    // Hide the OS cursor, see util::SOFTWARE_CURSOR.
+    software_cursor: bool,
+    // Shared with the graphics thread's waits for the GPU.
+    shutdown: sync::Shutdown,
 }
 
 impl ApplicationHandler<EventLoopProxyEvent> for Application {
@@ -66,6 +186,59 @@ impl ApplicationHandler<EventLoopProxyEvent> for Application {
             Some(window) => window,
             None => return,
         };
+
+        match event {
+            WindowEvent::CloseRequested => {
+                // Don't exit yet, the graphics thread still has to release the surface.
+                log::debug!("Close requested.");
+                self.ui_state.running = false;
+                // The UI state is read between frames, this also stops waits in the middle of one.
+                self.shutdown.request();
+            }
+            WindowEvent::Resized(size) => {
+                log::debug!("Resized to {:?}", size);
+                self.ui_state.resizes += 1;
+            }
+            #[cfg(feature = "ui")]
+            WindowEvent::CursorMoved { position, .. } => {
+                self.ui_state.cursor_position = Some((position.x, position.y));
+            }
+            #[cfg(feature = "ui")]
+            WindowEvent::CursorLeft { .. } => {
+                self.ui_state.cursor_position = None;
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::KeyW),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                self.ui_state.wireframe = !self.ui_state.wireframe;
+                log::debug!(
+                    "Wireframe {}.",
+                    if self.ui_state.wireframe { "on" } else { "off" }
+                );
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::KeyB),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                log::debug!("Switching renderer backend.");
+                self.ui_state.backend_switches += 1;
+            }
+            _ => return,
+        }
+        self.ui_writer.publish(&self.ui_state);
     }
 
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
@@ -75,23 +248,23 @@ impl ApplicationHandler<EventLoopProxyEvent> for Application {
             Some(window) => log::debug!("{:?}", window),
             None => {
                 log::debug!("create window");
-                let (window_id, window) = Self::create_window(event_loop).unwrap();
+                let (window_id, window) =
+                    Self::create_window(event_loop, self.software_cursor).unwrap();
+                self.ui_state.window = Some(Arc::clone(&window));
+                self.ui_writer.publish(&self.ui_state);
                 self.windows.insert(window_id, window);
             }
         };
     }
 
-    fn user_event(&mut self, _event_loop: &ActiveEventLoop, event: EventLoopProxyEvent) {
+    fn user_event(&mut self, event_loop: &ActiveEventLoop, event: EventLoopProxyEvent) {
         match event {
-            EventLoopProxyEvent::RequestWindowHandle => match self.windows.iter().next() {
-                Some(window) => {
-                    let mut shared_window = self.shared_window.lock().unwrap();
-                    *shared_window = Some(Arc::clone(&window.1))
-                }
-                None => {
-                    log::debug!("No Window Created.")
-                }
-            },
+            EventLoopProxyEvent::Exit => event_loop.exit(),
+            EventLoopProxyEvent::Fatal(message) => {
+                log::error!("Graphics thread panicked, exiting: {}", message);
+                self.failed = true;
+                event_loop.exit();
+            }
         }
     }
 }
@@ -99,6 +272,7 @@ impl ApplicationHandler<EventLoopProxyEvent> for Application {
 impl Application {
     fn create_window(
         event_loop: &ActiveEventLoop,
+        software_cursor: bool,
     ) -> Result<(WindowId, Arc<Window>), Box<dyn Error>> {
         let mut window_attributes = Window::default_attributes().with_title("Vulkan Ash Tutorial");
         window_attributes = window_attributes.with_base_size(PhysicalSize {
@@ -114,6 +288,10 @@ impl Application {
         }
 
         let window = event_loop.create_window(window_attributes)?;
+        if cfg!(feature = "ui") && software_cursor {
+            // Drawn by the graphics thread instead.
+            window.set_cursor_visible(false);
+        }
 
         Ok((window.id(), Arc::new(window)))
     }
@@ -135,59 +313,338 @@ impl Application {
 //     }
 // }
 
+/// Custom commands recorded into every frame, see `VulkanApp::set_raw_commands`.
+type RawCommands = Box<dyn FnMut(&Device, &command::RawFrame)>;
+
+/// How the main pass's draws are recorded, see util::SECONDARY_COMMAND_BUFFERS and util::RECORDING_THREADS.
+enum Recording {
+    /// Into the frame's primary command buffer.
+    Primary,
+    /// Into secondary command buffers, one for the scene (reused until it changes) and one for the overlays.
+    Secondary {
+        scene: command::SecondaryPass,
+        overlays: command::SecondaryPass,
+    },
+    /// Into secondary command buffers, on several threads.
+    #[cfg(feature = "parallel-recording")]
+    Parallel(parallel::ParallelRecorder),
+}
+
+impl Recording {
+    /// The way `settings` ask for, several threads taking precedence over secondary command buffers.
+    #[cfg_attr(not(feature = "parallel-recording"), allow(unused_variables))]
+    fn new(
+        device: &Device,
+        device_details: &util::DeviceDetails,
+        command_pool: vk::CommandPool,
+        settings: &settings::Settings,
+    ) -> Result<Self, Box<dyn Error>> {
+        #[cfg(feature = "parallel-recording")]
+        if settings.recording_threads != 0 {
+            return Ok(Self::Parallel(parallel::ParallelRecorder::new(
+                device,
+                device_details.graphics_queue_index,
+                settings.recording_threads,
+                util::MAX_FRAMES_IN_FLIGHT,
+            )?));
+        }
+
+        if settings.secondary_command_buffers {
+            let count = util::MAX_FRAMES_IN_FLIGHT as u32;
+            let scene = command::SecondaryPass::new(device, command_pool, count)?;
+            let overlays = command::SecondaryPass::new(device, command_pool, count)?;
+            return Ok(Self::Secondary { scene, overlays });
+        }
+        Ok(Self::Primary)
+    }
+}
+
 ////////////////   ////////////////
 struct VulkanApp {
     _entry: Entry,
     instance: Instance,
-    debug_utils_loader: debug_utils::Instance,
-    debug_callback: vk::DebugUtilsMessengerEXT,
+    // Only present when validation is enabled.
+    debug_messenger: Option<vk::DebugUtilsMessengerEXT>,
+    // The debug_utils function table, loaded the first time something uses it (the messenger).
+    debug_utils: OnceCell<debug_utils::Instance>,
     surface: surface::Instance,
     surface_khr: SurfaceKHR,
     device: Device,
-    _physical_device: vk::PhysicalDevice,
-    _graphics_queue: vk::Queue,
-    _present_queue: vk::Queue,
+    physical_device: vk::PhysicalDevice,
+    device_details: DeviceDetails,
+    // Every buffer and image allocates through it, see memory.rs.
+    allocator: memory::Allocator,
+    // Every pipeline's shader modules come from it, see shader_cache.rs.
+    shader_cache: shader_cache::ShaderCache,
+    // Kept to size the swapchain when the surface doesn't dictate an extent.
+    window: Arc<Window>,
+    graphics_queue: vk::Queue,
+    present_queue: vk::Queue,
+    // Buffer uploads go through it, a dedicated transfer queue if the device has one.
+    transfer_queue: command::TransferQueue,
     swapchain: swapchain::Device,
     swapchain_khr: vk::SwapchainKHR,
-    _images: Vec<vk::Image>,
-    _swapchain_image_format: vk::Format,
-    _swapchain_extent: vk::Extent2D,
-    swapchain_image_views: Vec<vk::ImageView>,
+    swapchain_image_format: vk::Format,
+    swapchain_extent: vk::Extent2D,
+    // Context + swapchain generation the handles below were created in.
+    scope: HandleScope,
+    images: Vec<Handle<vk::Image>>,
+    // Which formats each image can be viewed as depends on MUTABLE_FORMAT support, see image::SwapchainImageViews.
+    swapchain_image_views: Vec<Handle<image::SwapchainImageViews>>,
+    swapchain_framebuffers: Vec<Handle<vk::Framebuffer>>,
+    // Sized like the swapchain, so it is recreated with it.
+    // Multisampled color target resolved into the swapchain image, None without MSAA.
+    color_attachment: Option<image::AllocatedImage>,
+    depth_attachment: image::AllocatedImage,
+    msaa_samples: vk::SampleCountFlags,
+    // Null with dynamic rendering.
+    render_pass: vk::RenderPass,
+    // What pipelines are built for: `render_pass`, or dynamic rendering.
+    rendering: render_target::Rendering,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    // Same as `pipeline` (and with its layout) but with PolygonMode::LINE, None if the device doesn't support it.
+    wireframe_pipeline: Option<vk::Pipeline>,
+    wireframe: bool,
+    command_pool: vk::CommandPool,
+    vertex_buffer: buffer::Buffer,
+    index_buffer: buffer::Buffer,
+    index_count: u32,
+    // Copies of the triangle, see vertex::InstanceData.
+    instance_buffer: buffer::Buffer,
+    instance_count: u32,
+    // One uniform buffer and descriptor set per frame in flight.
+    uniform_buffers: uniform::UniformBuffers<uniform::UniformBufferObject>,
+    descriptors: descriptor::DescriptorManager,
+    // Owned by `descriptors`, kept to rebuild the triangle pipelines.
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    // Empty if the uniform buffer is pushed with `push_descriptor_loader` (util::PUSH_DESCRIPTORS) instead.
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    push_descriptor_loader: Option<push_descriptor::Device>,
+    // How the triangles are shaded, see backend.rs. Switched with `set_backend`.
+    backend: Box<dyn backend::RendererBackend>,
+    // What the enabled cargo features add, see subsystem.rs.
+    subsystems: subsystem::Subsystems,
+    // Into what the main pass's draws are recorded.
+    recording: Recording,
+    // Set if util::SHADER_HOT_RELOAD is and the shader directory could be watched.
+    #[cfg(feature = "hot-reload")]
+    shader_watcher: Option<hot_reload::ShaderWatcher>,
+    // Of the most recently recorded frame.
+    draw_statistics: command::DrawStatistics,
+    // Why startup picked the device, swapchain settings and so on, see util::StartupDecisions.
+    startup_decisions: util::StartupDecisions,
+    // Set with `set_raw_commands`.
+    raw_commands: Option<RawCommands>,
+    // The triangle's rotation is based on the time since this.
+    started: Instant,
+    // One per frame in flight.
+    command_buffers: Vec<vk::CommandBuffer>,
+    frames: sync::FramesInFlight,
+    // Checks submissions are in order (debug builds only).
+    audit: audit::SubmissionAudit,
+    present_timing: present::PresentTiming,
+    // Set when acquire/present report the swapchain no longer matches the surface.
+    swapchain_out_of_date: bool,
+    // Set when drawing failed with VK_ERROR_DEVICE_LOST, nothing needs to be waited for after that.
+    device_lost: bool,
+    // Load/store ops the render pass was created with, and the clear values used when recording.
+    targets: render_target::RenderTargets,
+    // What the app was started with, see settings::Settings.
+    settings: settings::Settings,
 }
 
 impl VulkanApp {
-    fn new(window: &Arc<Window>) -> Result<Self, Box<dyn Error>> {
+    /// With a `capture_layer` (see capture::CaptureSession) it's enabled too. Waits for the GPU give up
+    /// once `shutdown` is requested.
+    fn new(
+        window: &Arc<Window>,
+        capture_layer: Option<&str>,
+        settings: &settings::Settings,
+        shutdown: &sync::Shutdown,
+    ) -> Result<Self, Box<dyn Error>> {
         log::debug!("Creating Application");
-        let entry = unsafe { Entry::load()? };
+        let mut timings = util::StartupTimings::start();
+        let mut decisions = util::StartupDecisions::default();
+
+        let entry = timings.time("entry", || unsafe { Entry::load() })?;
 
         //////////////// Refactor ////////////////
-        util::check_validation_layer_support(&entry)?;
+        // Missing layers are only fatal with strict validation layers, otherwise we go on without
+        // them (and without the debug messenger reporting through them).
+        let validation = util::ENABLE_VALIDATION_LAYERS
+            && match util::check_validation_layer_support(&entry) {
+                Ok(()) => true,
+                Err(err) if !settings.strict_validation_layers => {
+                    log::warn!("{}\nContinuing without validation.", err);
+                    false
+                }
+                Err(err) => return Err(err),
+            };
+
+        if log::log_enabled!(log::Level::Debug) {
+            for layer in util::available_instance_layers(&entry)? {
+                log::debug!("Available layer {}: {}", layer.name, layer.description);
+            }
+        }
+
+        let mut layers = if validation {
+            util::REQUIRED_LAYERS.map(String::from).to_vec()
+        } else {
+            Vec::new()
+        };
+        for layer in util::extra_instance_layers(&entry)? {
+            if !layers.contains(&layer) {
+                layers.push(layer);
+            }
+        }
+        if let Some(layer) = capture_layer {
+            if !util::available_instance_layers(&entry)?
+                .iter()
+                .any(|available| available.name == layer)
+            {
+                return Err(Box::new(util::AppError::new(&format!(
+                    "Capture layer {} isn't installed",
+                    layer
+                ))));
+            }
+            if !layers.iter().any(|enabled| enabled == layer) {
+                layers.push(layer.to_string());
+            }
+        }
+        log::info!("Enabling instance layers: {:?}", layers);
+        let (_layer_names, layer_names_ptrs) = util::get_layer_names_and_pointers(&layers);
+
+        let extension_names =
+            util::get_extension_names(Some(window.display_handle()?.as_raw()), validation)?;
+
+        let api_version = util::instance_api_version(&entry)?;
+        log::info!(
+            "Creating a Vulkan {}.{} instance.",
+            vk::api_version_major(api_version),
+            vk::api_version_minor(api_version)
+        );
+        let instance = timings.time("instance", || {
+            vulkan_create::instance(&entry, api_version, layer_names_ptrs, extension_names)
+        })?;
+
+        let debug_utils = OnceCell::new();
+        let debug_messenger = if validation {
+            let loader = debug_utils.get_or_init(|| debug_utils::Instance::new(&entry, &instance));
+            Some(vulkan_create::debug_messenger(loader)?)
+        } else {
+            None
+        };
+
+        let (surface_khr, surface_loader) = timings.time("surface", || {
+            vulkan_create::surface(&entry, &instance, window)
+        })?;
 
-        let (_layer_names, layer_names_ptrs) = util::get_layer_names_and_pointers();
+        let (physical_device, mut device_details) = timings.time(
+            "physical device selection",
+            || -> Result<_, Box<dyn Error>> {
+                let enumerated = util::physical_devices(&instance)?;
+                let mut devices = enumerated.clone();
 
-        let extension_names = util::get_extension_names(Some(window.display_handle()?.as_raw()))?;
+                // This needs a little work, nothing enforces you to run these two commands.
+                devices = util::devices_extension_support(&instance, devices, &mut decisions)?;
+                devices = util::devices_swapchain_adequate(
+                    &instance,
+                    &surface_loader,
+                    surface_khr,
+                    devices,
+                    &mut decisions,
+                )?;
 
-        let instance = vulkan_create::instance(&entry, layer_names_ptrs, extension_names)?;
+                // This should only be able to take in some form of suitable device,
+                // filtered by util::devices_extension_support and util::devices_swapchain_adequate.
+                let physical_devices = util::devices_queue_family_support(
+                    &instance,
+                    &surface_loader,
+                    surface_khr,
+                    devices,
+                    &mut decisions,
+                )?;
 
-        let (debug_callback, debug_utils_loader) =
-            vulkan_create::debug_messenger(&entry, &instance)?;
+                log::debug!("Found Physical Devices: {:?}", physical_devices);
 
-        let (surface_khr, surface_loader) = vulkan_create::surface(&entry, &instance, window)?;
+                util::pick_physical_device(
+                    &instance,
+                    &enumerated,
+                    &physical_devices,
+                    &settings.device_selection,
+                    &mut decisions,
+                )
+            },
+        )?;
 
-        let mut devices = util::physical_devices(&instance)?;
+        let features = unsafe { instance.get_physical_device_features(physical_device) };
+        device_details.fill_mode_non_solid = features.fill_mode_non_solid == vk::TRUE;
+        device_details.depth_bounds = features.depth_bounds == vk::TRUE;
+        device_details.depth_clamp = features.depth_clamp == vk::TRUE;
+        device_details.fragment_stores_and_atomics =
+            features.fragment_stores_and_atomics == vk::TRUE;
+        device_details.tessellation_shader = features.tessellation_shader == vk::TRUE;
+        device_details.geometry_shader = features.geometry_shader == vk::TRUE;
+        device_details.sampler_anisotropy = (features.sampler_anisotropy == vk::TRUE).then(|| {
+            unsafe { instance.get_physical_device_properties(physical_device) }
+                .limits
+                .max_sampler_anisotropy
+        });
+        device_details.mutable_swapchain_format = util::device_supports_extensions(
+            &instance,
+            physical_device,
+            &util::MUTABLE_SWAPCHAIN_FORMAT_EXTENSIONS,
+        )?;
 
-        // This needs a little work, nothing enforces you to run these two commands.
-        devices = util::devices_extension_support(&instance, devices)?;
-        devices = util::devices_swapchain_adequate(&surface_loader, surface_khr, devices)?;
+        device_details.display_timing = util::device_supports_extensions(
+            &instance,
+            physical_device,
+            &[util::DISPLAY_TIMING_EXTENSION],
+        )?;
 
-        // This should only be able to take in some form of suitable device,
-        // filtered by util::devices_extension_support and util::devices_swapchain_adequate.
-        let physical_devices =
-            util::devices_queue_family_support(&instance, &surface_loader, surface_khr, devices)?;
+        device_details.conservative_rasterization = util::device_supports_extensions(
+            &instance,
+            physical_device,
+            &[util::CONSERVATIVE_RASTERIZATION_EXTENSION],
+        )?;
 
-        log::debug!("Found Physical Devices: {:?}", physical_devices);
+        device_details.push_descriptor = util::PUSH_DESCRIPTORS
+            && util::device_supports_extensions(
+                &instance,
+                physical_device,
+                &[util::PUSH_DESCRIPTOR_EXTENSION],
+            )?;
 
-        let (physical_device, device_details) = util::pick_physical_device(&physical_devices)?;
+        device_details.dynamic_rendering = util::DYNAMIC_RENDERING
+            && util::device_supports_dynamic_rendering(&instance, api_version, physical_device);
+        (device_details.shader_float16, device_details.storage_16bit) =
+            util::device_supports_half_precision(&instance, api_version, physical_device);
+        device_details.vulkan_1_1 = api_version >= vk::API_VERSION_1_1
+            && unsafe { instance.get_physical_device_properties(physical_device) }.api_version
+                >= vk::API_VERSION_1_1;
+        device_details.storage_buffer_storage_class = device_details.vulkan_1_1
+            || util::device_supports_extensions(
+                &instance,
+                physical_device,
+                &[util::STORAGE_BUFFER_STORAGE_CLASS_EXTENSION],
+            )?;
+        // Budgets are queried with vkGetPhysicalDeviceMemoryProperties2.
+        device_details.memory_budget = device_details.vulkan_1_1
+            && util::device_supports_extensions(
+                &instance,
+                physical_device,
+                &[util::MEMORY_BUDGET_EXTENSION],
+            )?;
+        device_details.compute_post_process = cfg!(feature = "post-processing")
+            && settings.compute_post_process
+            && util::device_supports_compute_post_process(
+                &instance,
+                physical_device,
+                device_details.graphics_queue_index,
+                &surface_loader,
+                surface_khr,
+            )?;
 
         log::debug!(
             "Selected Physical Device {:?} ({:?})",
@@ -195,135 +652,1797 @@ impl VulkanApp {
             device_details
         );
 
-        let (device, graphics_queue, present_queue) =
+        let (device, queues) = timings.time("logical device", || {
             vulkan_create::logical_device_with_graphics_queue(
                 &instance,
                 physical_device,
                 &device_details,
-            )?;
+            )
+        })?;
+        let (graphics_queue, present_queue) = (queues.graphics, queues.present);
 
         let (swapchain_loader, swapchain_khr, format, extent, images) =
-            vulkan_create::swapchain_and_images(
-                &instance,
-                physical_device,
-                &device_details,
+            timings.time("swapchain", || {
+                vulkan_create::swapchain_and_images(
+                    &instance,
+                    physical_device,
+                    &device_details,
+                    &device,
+                    &surface_loader,
+                    surface_khr,
+                    util::window_extent(window),
+                )
+            })?;
+
+        let allocator =
+            memory::Allocator::new(&instance, physical_device, device_details.memory_budget);
+        let shader_cache = shader_cache::ShaderCache::new(&device)?;
+        let depth_format = image::find_depth_format(&instance, physical_device, settings.stencil)?;
+
+        let msaa_samples =
+            image::usable_sample_count(&instance, physical_device, settings.msaa_samples);
+        log::info!("Using {:?} sample(s) per pixel.", msaa_samples);
+
+        SwapChainSupportDetails::new(physical_device, &surface_loader, surface_khr)?
+            .record_decisions(util::window_extent(window), &mut decisions)?;
+        decisions.record(
+            "depth format",
+            format!("{:?}", depth_format),
+            if settings.stencil {
+                "the first supported with a stencil component (the stencil setting)"
+            } else {
+                "the first supported"
+            },
+        );
+        decisions.record(
+            "msaa samples",
+            format!("{:?}", msaa_samples),
+            format!(
+                "{:?} asked for, lowered to what color and depth attachments support",
+                settings.msaa_samples
+            ),
+        );
+        decisions.record(
+            "rendering",
+            if device_details.dynamic_rendering {
+                "dynamic rendering"
+            } else {
+                "render pass"
+            },
+            match (util::DYNAMIC_RENDERING, device_details.dynamic_rendering) {
+                (false, _) => "DYNAMIC_RENDERING is off",
+                (true, true) => "the device supports Vulkan 1.3 dynamic rendering",
+                (true, false) => "the device doesn't support Vulkan 1.3 dynamic rendering",
+            },
+        );
+
+        let targets = render_target::RenderTargets::default();
+        // Without dynamic rendering there is a render pass, which the framebuffers are created for.
+        let (render_pass, rendering) = if device_details.dynamic_rendering {
+            log::info!("Using dynamic rendering.");
+            let rendering = render_target::Rendering::Dynamic {
+                color_format: format,
+                depth_format,
+                samples: msaa_samples,
+            };
+            (vk::RenderPass::null(), rendering)
+        } else {
+            let render_pass = timings.time("render pass", || {
+                vulkan_create::render_pass(&device, format, depth_format, msaa_samples, &targets)
+            })?;
+            (
+                render_pass,
+                render_target::Rendering::RenderPass(render_pass),
+            )
+        };
+
+        let mut descriptors = descriptor::DescriptorManager::default();
+        // Binding 0: the vertex shader's uniform buffer.
+        let triangle_interface =
+            reflect::ShaderInterface::from_shaders(&shader_cache, &TRIANGLE_SHADERS)?;
+        let descriptor_set_layout = if device_details.push_descriptor {
+            descriptors.create_push_layout(&device, &triangle_interface.set_bindings(0))?
+        } else {
+            descriptors.create_layout(&device, &triangle_interface.set_bindings(0))?
+        };
+        let descriptor_set_layouts = [descriptor_set_layout];
+
+        // The pipeline only depends on the render pass (or formats), so build it on another thread
+        // while the swapchain image views and color/depth buffers are created.
+        // Box<dyn Error> isn't Send, so errors cross the thread boundary as Strings.
+        let (pipeline_result, swapchain_image_views, color_attachment, depth_attachment) =
+            thread::scope(|scope| {
+                let pipeline_handle = scope.spawn(|| {
+                    let started = Instant::now();
+                    let result = triangle_pipelines(
+                        &device,
+                        &shader_cache,
+                        &device_details,
+                        rendering,
+                        &descriptor_set_layouts,
+                        msaa_samples,
+                        settings.stencil,
+                    )
+                    .map_err(|err| err.to_string());
+                    (result, started.elapsed())
+                });
+
+                let swapchain_image_views = timings.time("swapchain image views", || {
+                    vulkan_create::swapchain_image_views(&device, &device_details, &images, format)
+                });
+
+                let color_attachment = timings.time("color buffer", || {
+                    multisampled_color_attachment(&device, &allocator, extent, format, msaa_samples)
+                });
+
+                let depth_attachment = timings.time("depth buffer", || {
+                    image::depth_attachment(&device, &allocator, extent, depth_format, msaa_samples)
+                });
+
+                (
+                    pipeline_handle.join(),
+                    swapchain_image_views,
+                    color_attachment,
+                    depth_attachment,
+                )
+            });
+
+        let (pipeline_result, pipeline_duration) =
+            pipeline_result.map_err(|_| "Pipeline creation thread panicked.")?;
+        timings.record("graphics pipeline", pipeline_duration);
+
+        let swapchain_image_views = swapchain_image_views?;
+        if let Some(views) = swapchain_image_views.first() {
+            log::info!("Swapchain images can be viewed as {:?}.", views.formats());
+        }
+        let color_attachment = color_attachment?;
+        let depth_attachment = depth_attachment?;
+        let ((pipeline, pipeline_layout), wireframe_pipeline) = pipeline_result?;
+
+        let swapchain_framebuffers = match rendering {
+            render_target::Rendering::RenderPass(render_pass) => {
+                timings.time("framebuffers", || {
+                    vulkan_create::framebuffers(
+                        &device,
+                        &views_in_swapchain_format(&swapchain_image_views),
+                        color_attachment.as_ref().map(|color| color.view),
+                        depth_attachment.view,
+                        render_pass,
+                        extent,
+                    )
+                })?
+            }
+            render_target::Rendering::Dynamic { .. } => Vec::new(),
+        };
+
+        let command_pool = command::command_pool(&device, device_details.graphics_queue_index)?;
+        let command_buffers =
+            command::command_buffers(&device, command_pool, util::MAX_FRAMES_IN_FLIGHT as u32)?;
+
+        #[cfg(feature = "post-processing")]
+        let post_process = if device_details.compute_post_process {
+            // On the dedicated compute queue if there is one, see post.rs.
+            let async_compute = match (device_details.post_process_compute_family(), queues.compute)
+            {
+                (Some(family_index), Some(queue)) => Some(post::AsyncCompute::new(
+                    &device,
+                    family_index,
+                    queue,
+                    device_details.graphics_queue_index,
+                    command_pool,
+                    util::MAX_FRAMES_IN_FLIGHT,
+                )?),
+                _ => None,
+            };
+            Some(post::ComputePostProcess::new(
                 &device,
-                &surface_loader,
-                surface_khr,
-            )?;
+                &shader_cache,
+                &allocator,
+                &mut descriptors,
+                (&views_in_swapchain_format(&swapchain_image_views), format),
+                extent,
+                async_compute,
+            )?)
+        } else {
+            None
+        };
+
+        let scope = HandleScope::new_context();
+        let swapchain_image_views = swapchain_image_views
+            .into_iter()
+            .map(|v| scope.tag(v))
+            .collect();
+        let swapchain_framebuffers = swapchain_framebuffers
+            .into_iter()
+            .map(|f| scope.tag(f))
+            .collect::<Vec<_>>();
 
-        let swapchain_image_views = vulkan_create::swapchain_image_views(&device, &images, format)?;
+        let transfer_queue = match (device_details.transfer_queue_index, queues.transfer) {
+            (Some(family_index), Some(queue)) => command::TransferQueue::dedicated(
+                &device,
+                family_index,
+                queue,
+                device_details.graphics_queue_index,
+                command_pool,
+                graphics_queue,
+                shutdown.clone(),
+            )?,
+            _ => command::TransferQueue::graphics(command_pool, graphics_queue, shutdown.clone()),
+        };
+
+        // The visibility buffer backend reads the indices as u32 words, two at a time.
+        let mut indices = vertex::TRIANGLE_INDICES.to_vec();
+        if indices.len() % 2 == 1 {
+            indices.push(0);
+        }
+        let instances = vertex::InstanceData::row(util::TRIANGLE_INSTANCES);
+        let (vertex_buffer, index_buffer, instance_buffer) =
+            timings.time("scene buffers", || -> Result<_, Box<dyn Error>> {
+                let usage = vk::BufferUsageFlags::STORAGE_BUFFER;
+                let mut uploads = buffer::Uploads::default();
+                let mut buffers: Vec<buffer::Buffer> = Vec::new();
+                let added = (|| -> Result<(), Box<dyn Error>> {
+                    buffers.push(uploads.add(
+                        &device,
+                        &allocator,
+                        usage | vk::BufferUsageFlags::VERTEX_BUFFER,
+                        &vertex::TRIANGLE,
+                    )?);
+                    buffers.push(uploads.add(
+                        &device,
+                        &allocator,
+                        usage | vk::BufferUsageFlags::INDEX_BUFFER,
+                        &indices,
+                    )?);
+                    buffers.push(uploads.add(
+                        &device,
+                        &allocator,
+                        usage | vk::BufferUsageFlags::VERTEX_BUFFER,
+                        &instances,
+                    )?);
+                    Ok(())
+                })();
+                let submitted = match added {
+                    Ok(()) => uploads.submit(&device, &transfer_queue),
+                    Err(err) => {
+                        uploads.destroy(&device);
+                        Err(err)
+                    }
+                };
+                if let Err(err) = submitted {
+                    for buffer in buffers.iter_mut() {
+                        buffer.destroy(&device);
+                    }
+                    return Err(err);
+                }
+                let mut buffers = buffers.into_iter();
+                Ok((
+                    buffers.next().unwrap(),
+                    buffers.next().unwrap(),
+                    buffers.next().unwrap(),
+                ))
+            })?;
 
-        Ok(Self {
+        let uniform_buffers =
+            uniform::UniformBuffers::new(&device, &allocator, util::MAX_FRAMES_IN_FLIGHT)?;
+        // Pushed sets aren't allocated, the uniform buffer is pushed with every draw instead.
+        let (descriptor_sets, push_descriptor_loader) = if device_details.push_descriptor {
+            (
+                Vec::new(),
+                Some(push_descriptor::Device::new(&instance, &device)),
+            )
+        } else {
+            (
+                descriptors.allocate_per_frame(&device, descriptor_set_layout)?,
+                None,
+            )
+        };
+        for (index, set) in descriptor_sets.iter().enumerate() {
+            descriptor::write_uniform_buffer(&device, *set, 0, uniform_buffers.buffer(index));
+        }
+
+        let backend_setup = backend::BackendSetup {
+            device_details: &device_details,
+            allocator: &allocator,
+            pipelines: backend::PipelineSetup {
+                shader_cache: &shader_cache,
+                rendering,
+                samples: msaa_samples,
+                triangle_layout: descriptor_set_layout,
+                fill_mode_non_solid: device_details.fill_mode_non_solid,
+                stencil: settings.stencil,
+            },
+            depth_format,
+            extent,
+            uniform_buffers: &uniform_buffers,
+            scene: (&vertex_buffer, &index_buffer, &instance_buffer),
+        };
+        // Not being able to use the one asked for shouldn't stop the app from running.
+        let backend_kind = settings.backend;
+        let backend = match backend::create(backend_kind, &device, &backend_setup) {
+            Ok(backend) => {
+                let reason =
+                    "RENDERER_BACKEND, or as asked on the command line or in the environment";
+                decisions.record(
+                    "renderer backend",
+                    backend_kind.to_string(),
+                    if backend_kind.multisampled() || msaa_samples == vk::SampleCountFlags::TYPE_1 {
+                        reason.to_string()
+                    } else {
+                        format!("{}, its scene isn't multisampled", reason)
+                    },
+                );
+                backend
+            }
+            Err(err) => {
+                log::warn!("{}, falling back to the forward renderer backend", err);
+                decisions.record(
+                    "renderer backend",
+                    backend::BackendKind::Forward.to_string(),
+                    format!("{} failed: {}", backend_kind, err),
+                );
+                Box::new(backend::Forward)
+            }
+        };
+
+        #[cfg(feature = "demos")]
+        demos::log_voxel_counts(
+            (&device, &shader_cache),
+            &allocator,
+            (command_pool, graphics_queue),
+            sync::Cancel::On(shutdown),
+            &mut descriptors,
+            (&vertex_buffer, vertex::TRIANGLE.len() as u32),
+            (settings, &device_details),
+        )?;
+
+        let frames = sync::FramesInFlight::new(
+            &device,
+            util::MAX_FRAMES_IN_FLIGHT,
+            swapchain_framebuffers.len(),
+            shutdown.clone(),
+        )?;
+
+        let mut audit = audit::SubmissionAudit::default();
+        for frame in frames.frames().iter() {
+            audit.fence_created(frame.in_flight, true);
+        }
+
+        // The UI goes on top of the post processed frame, so it isn't processed along.
+        #[cfg(all(feature = "ui", feature = "post-processing"))]
+        let final_pass = post_process
+            .as_ref()
+            .map(post::ComputePostProcess::final_pass);
+        #[cfg(all(feature = "ui", not(feature = "post-processing")))]
+        let final_pass = None;
+        #[cfg(any(feature = "demos", feature = "ui"))]
+        let pipeline_context = subsystem::PipelineContext {
+            device: &device,
+            shader_cache: &shader_cache,
+            #[cfg(any(
+                feature = "demos",
+                all(feature = "hot-reload", feature = "asset-import")
+            ))]
+            device_details: &device_details,
+            rendering,
+            samples: msaa_samples,
+        };
+        let subsystems = subsystem::Subsystems {
+            #[cfg(feature = "demos")]
+            demos: demos::Demos::new(
+                &pipeline_context,
+                &allocator,
+                &transfer_queue,
+                settings,
+                descriptor_set_layout,
+            )?,
+            #[cfg(feature = "asset-import")]
+            scenery: Default::default(),
+            #[cfg(feature = "ui")]
+            ui: ui::Ui::new(
+                &pipeline_context,
+                &allocator,
+                &transfer_queue,
+                settings.software_cursor,
+                final_pass,
+            )?,
+            #[cfg(feature = "post-processing")]
+            post_process,
+        };
+
+        let recording = Recording::new(&device, &device_details, command_pool, settings)?;
+
+        #[cfg(feature = "hot-reload")]
+        let shader_watcher = if util::SHADER_HOT_RELOAD {
+            // Not being able to watch shouldn't stop the app from running.
+            hot_reload::ShaderWatcher::new(&shader_cache)
+                .inspect_err(|err| log::warn!("Shader hot reload is off: {}", err))
+                .ok()
+        } else {
+            None
+        };
+
+        let present_timing =
+            present::PresentTiming::new(&instance, &device, device_details.display_timing);
+
+        timings.report();
+        decisions.report(settings.startup_decisions_log_level);
+        let mut app = Self {
             _entry: entry,
-            instance: instance,
-            debug_utils_loader: debug_utils_loader,
-            debug_callback: debug_callback,
+            instance,
+            debug_messenger,
+            debug_utils,
             surface: surface_loader,
-            surface_khr: surface_khr,
-            device: device,
-            _physical_device: physical_device,
-            _graphics_queue: graphics_queue,
-            _present_queue: present_queue,
+            surface_khr,
+            device,
+            physical_device,
+            device_details,
+            allocator,
+            shader_cache,
+            window: Arc::clone(window),
+            graphics_queue,
+            present_queue,
+            transfer_queue,
             swapchain: swapchain_loader,
-            swapchain_khr: swapchain_khr,
-            _images: images,
-            _swapchain_image_format: format,
-            _swapchain_extent: extent,
-            swapchain_image_views: swapchain_image_views,
-        })
+            swapchain_khr,
+            swapchain_image_format: format,
+            swapchain_extent: extent,
+            images: images.into_iter().map(|i| scope.tag(i)).collect(),
+            scope,
+            swapchain_image_views,
+            swapchain_framebuffers,
+            color_attachment,
+            depth_attachment,
+            msaa_samples,
+            render_pass,
+            rendering,
+            pipeline_layout,
+            pipeline,
+            wireframe_pipeline,
+            wireframe: false,
+            command_pool,
+            vertex_buffer,
+            index_buffer,
+            index_count: vertex::TRIANGLE_INDICES.len() as u32,
+            instance_buffer,
+            instance_count: instances.len() as u32,
+            uniform_buffers,
+            descriptors,
+            descriptor_set_layout,
+            descriptor_sets,
+            push_descriptor_loader,
+            backend,
+            subsystems,
+            recording,
+            #[cfg(feature = "hot-reload")]
+            shader_watcher,
+            draw_statistics: command::DrawStatistics::default(),
+            startup_decisions: decisions,
+            raw_commands: None,
+            started: Instant::now(),
+            command_buffers,
+            frames,
+            audit,
+            present_timing,
+            swapchain_out_of_date: false,
+            device_lost: false,
+            targets,
+            settings: settings.clone(),
+        };
+        app.log_bandwidth_estimate();
+        app.set_clear_color(vk::ClearValue {
+            color: vk::ClearColorValue {
+                float32: settings.clear_color,
+            },
+        });
+        #[cfg(feature = "asset-import")]
+        if let Some(path) = &settings.skybox {
+            if let Err(err) = app.load_skybox(path) {
+                log::warn!("Can't draw a skybox: {}", err);
+            }
+        }
+        #[cfg(feature = "asset-import")]
+        if let Some(path) = &settings.texture {
+            if let Err(err) = app.load_textured_quad(path) {
+                log::warn!("Can't draw the textured quad: {}", err);
+            }
+        }
+        if settings.gpu_frame_timer {
+            if let Err(err) = app.start_gpu_frame_timer() {
+                log::warn!("Can't time frames on the GPU: {}", err);
+            }
+        }
+
+        let (demoted_count, demoted_size) = app.allocator.demoted();
+        if demoted_count > 0 {
+            log::warn!(
+                "{} allocations ({} bytes) live in host visible memory instead of device local memory",
+                demoted_count,
+                demoted_size
+            );
+        }
+        for heap in app.allocator.heap_budgets().unwrap_or_default() {
+            log::info!(
+                "Memory heap {}: {} of its {} byte budget in use",
+                heap.heap_index,
+                heap.usage,
+                heap.budget
+            );
+        }
+
+        Ok(app)
     }
 
-    fn run(&mut self) {
+    /// Draw frames until `running` is cleared (e.g. the window was closed) or drawing fails.
+    /// Recreates the swapchain when needed, and pauses while the window is minimized.
+    /// Stops by itself after `frame_limit` frames, if given.
+    fn run(&mut self, ui: &mut triple_buffer::Reader<UiState>, frame_limit: Option<u64>) {
         log::info!("Running application");
+
+        let mut frames_drawn = 0;
+        let mut statistics_logged = Instant::now();
+        let mut resizes = ui.read().resizes;
+        let mut backend_switches = ui.read().backend_switches;
+        loop {
+            let ui_state = ui.read();
+            if !ui_state.running {
+                break;
+            }
+            if frame_limit.is_some_and(|limit| frames_drawn >= limit) {
+                log::info!("Drew {} frames, stopping", frames_drawn);
+                break;
+            }
+            // Whatever happens until the frame is drawn is logged as part of it.
+            log_context::set_frame(Some(frames_drawn));
+
+            if ui_state.resizes != resizes {
+                resizes = ui_state.resizes;
+                self.swapchain_out_of_date = true;
+            }
+
+            #[cfg(feature = "ui")]
+            {
+                self.subsystems.ui.cursor_position = ui_state.cursor_position;
+            }
+
+            let wireframe = ui_state.wireframe;
+            if wireframe != self.wireframe {
+                self.set_wireframe(wireframe);
+            }
+
+            if ui_state.backend_switches != backend_switches {
+                backend_switches = ui_state.backend_switches;
+                if let Err(err) = self.set_backend(self.backend.kind().next()) {
+                    if !is_cancelled(err.as_ref()) {
+                        log::error!("Failed to switch renderer backend: {}", err);
+                    }
+                    break;
+                }
+            }
+
+            #[cfg(feature = "hot-reload")]
+            if let Some(shader_watcher) = &mut self.shader_watcher {
+                let changed = shader_watcher.changed();
+                if !changed.is_empty() {
+                    if let Err(err) = self.reload_shaders(&changed) {
+                        if !is_cancelled(err.as_ref()) {
+                            log::error!("Failed to reload shaders: {}", err);
+                        }
+                        break;
+                    }
+                }
+            }
+
+            if self.swapchain_out_of_date {
+                match self.recreate_swapchain() {
+                    Ok(true) => self.swapchain_out_of_date = false,
+                    Ok(false) => {
+                        // Zero extent (minimized), nothing to draw into. Check again later.
+                        thread::sleep(Duration::from_millis(100));
+                        continue;
+                    }
+                    Err(err) => {
+                        if !is_cancelled(err.as_ref()) {
+                            log::error!("Failed to recreate swapchain: {}", err);
+                        }
+                        break;
+                    }
+                }
+            }
+
+            let drawn = match self.draw_frame() {
+                Ok(drawn) => drawn,
+                Err(err) => {
+                    if is_cancelled(err.as_ref()) {
+                        break;
+                    }
+                    log::error!("Failed to draw frame: {}", err);
+                    if is_device_lost(err.as_ref()) {
+                        self.device_lost = true;
+                        self.log_device_lost_diagnostics();
+                    } else if err.downcast_ref::<sync::GpuHang>().is_some() {
+                        // Not lost, the GPU may still be working on it (see util::GPU_HANG_STOPS_DRAWING).
+                        self.log_device_lost_diagnostics();
+                    }
+                    break;
+                }
+            };
+            if drawn {
+                frames_drawn += 1;
+            }
+
+            if statistics_logged.elapsed() >= util::STATISTICS_LOG_INTERVAL {
+                statistics_logged = Instant::now();
+                self.log_statistics();
+            }
+        }
+        log_context::set_frame(None);
+
+        log::info!("Stopped running application");
+    }
+
+    /// Acquire a swapchain image, record and submit the triangle draw into it, and present it.
+    /// Whether a frame was presented, not if no image could be acquired or the swapchain went out of date.
+    fn draw_frame(&mut self) -> Result<bool, Box<dyn Error>> {
+        // Wait until the GPU is done with this frame in flight's command buffer and semaphores.
+        log_context::set_pass(Some("wait"));
+        self.frames.wait(
+            &self.device,
+            util::GPU_WATCHDOG_TIMEOUT,
+            util::GPU_HANG_STOPS_DRAWING,
+        )?;
+        self.audit.fence_waited(self.frames.current().in_flight);
+
+        log_context::set_pass(Some("acquire"));
+        let image = match present::acquire(
+            &self.swapchain,
+            self.swapchain_khr,
+            self.frames.current().image_available,
+        ) {
+            Ok(image) => image,
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                // Nothing was acquired (or signaled), so just skip this frame.
+                self.swapchain_out_of_date = true;
+                return Ok(false);
+            }
+            Err(vk::Result::TIMEOUT | vk::Result::NOT_READY) => {
+                // No image yet, try again after checking whether to stop.
+                return Ok(false);
+            }
+            Err(err) => return Err(Box::new(err)),
+        };
+        self.audit.acquired(self.frames.current().image_available);
+        if image.is_suboptimal() {
+            // Still usable, finish this frame and recreate afterwards.
+            self.swapchain_out_of_date = true;
+        }
+
+        log_context::set_pass(Some("record"));
+        self.update_uniforms();
+        let passes = self.record_frame(&image)?;
+
+        // The frame's submissions, in order: the queue, the command buffer, the semaphore it waits for (and
+        // the stage that waits) and the one it signals. The last one signals the frame's fence.
+        let render_finished = self.frames.render_finished(image.index());
+        #[cfg_attr(not(feature = "post-processing"), allow(unused_mut))]
+        let mut submissions = vec![(
+            self.graphics_queue,
+            self.command_buffers[self.frames.current_index()],
+            (
+                self.frames.current().image_available,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            ),
+            render_finished,
+        )];
+        #[cfg(feature = "post-processing")]
+        if let Some(post_process) = &self.subsystems.post_process {
+            // Processed on the compute queue in between, see post.rs.
+            let async_frame = post_process.record_async(
+                &self.device,
+                self.frames.current_index(),
+                (image.index(), self.images[image.index()].get(&self.scope)),
+                &self.subsystems.final_draws(),
+            )?;
+            if let Some(async_frame) = async_frame {
+                self.draw_statistics += async_frame.draw_statistics;
+                for command_buffer in [async_frame.dispatch, async_frame.blit] {
+                    self.audit.command_buffer_recording(command_buffer);
+                    self.audit.command_buffer_recorded(command_buffer);
+                }
+                submissions[0].3 = async_frame.rendered;
+                submissions.push((
+                    async_frame.queue,
+                    async_frame.dispatch,
+                    (async_frame.rendered, vk::PipelineStageFlags::COMPUTE_SHADER),
+                    async_frame.processed,
+                ));
+                submissions.push((
+                    self.graphics_queue,
+                    async_frame.blit,
+                    (async_frame.processed, vk::PipelineStageFlags::TRANSFER),
+                    render_finished,
+                ));
+            }
+        }
+
+        // Only now, so the fence isn't left unsignaled if recording fails (see `FramesInFlight::wait_all`).
+        log_context::set_pass(Some("submit"));
+        self.frames.reset(&self.device)?;
+        self.audit.fence_reset(self.frames.current().in_flight);
+
+        let last = submissions.len() - 1;
+        for (index, (queue, command_buffer, (wait_semaphore, wait_stage), signal_semaphore)) in
+            submissions.into_iter().enumerate()
+        {
+            let wait_semaphores = [wait_semaphore];
+            let wait_stages = [wait_stage];
+            let command_buffers = [command_buffer];
+            let signal_semaphores = [signal_semaphore];
+            let submit_infos = [vk::SubmitInfo::default()
+                .wait_semaphores(&wait_semaphores)
+                .wait_dst_stage_mask(&wait_stages)
+                .command_buffers(&command_buffers)
+                .signal_semaphores(&signal_semaphores)];
+            let fence = if index == last {
+                self.frames.current().in_flight
+            } else {
+                vk::Fence::null()
+            };
+
+            self.audit.submitting(
+                &command_buffers,
+                &wait_semaphores,
+                &signal_semaphores,
+                fence,
+            );
+            unsafe { self.device.queue_submit(queue, &submit_infos, fence)? };
+        }
+        self.frames.submitted(passes);
+
+        log_context::set_pass(Some("present"));
+        let signal_semaphores = [render_finished];
+        self.audit.presenting(&signal_semaphores);
+        let presented = match present::present(
+            &self.swapchain,
+            self.present_queue,
+            image,
+            &signal_semaphores,
+            &mut self.present_timing,
+        ) {
+            Ok(suboptimal) => {
+                self.swapchain_out_of_date |= suboptimal;
+                true
+            }
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                self.swapchain_out_of_date = true;
+                false
+            }
+            Err(err) => return Err(Box::new(err)),
+        };
+
+        if let Err(err) = self.present_timing.poll(self.swapchain_khr) {
+            // Only feedback, not worth failing the frame over (e.g. the swapchain just went out of date).
+            log::debug!("Failed to get present timing: {}", err);
+        }
+
+        self.frames.advance();
+        log_context::set_pass(None);
+
+        Ok(presented)
+    }
+
+    /// Record the triangle draw for an acquired swapchain image into the current frame's command buffer,
+    /// followed by the compute post process if there is one.
+    /// Taking the `AcquiredImage` means we can only record into an image we currently own.
+    /// Returns labels of the passes recorded, for the GPU watchdog to report.
+    fn record_frame(
+        &mut self,
+        image: &present::AcquiredImage,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let command_buffer = self.command_buffers[self.frames.current_index()];
+        if self
+            .subsystems
+            .all()
+            .iter()
+            .any(|subsystem| subsystem.animated())
+        {
+            self.scene_changed();
+        }
+        let frame = subsystem::Frame {
+            #[cfg(feature = "demos")]
+            time: self.started.elapsed().as_secs_f32(),
+            #[cfg(any(feature = "demos", feature = "asset-import", feature = "ui"))]
+            extent: self.swapchain_extent,
+        };
+        for subsystem in self.subsystems.all_mut() {
+            subsystem.prepare(&frame);
+        }
+
+        self.audit.command_buffer_recording(command_buffer);
+        let clear_values = self.targets.clear_values();
+        let render_pass_begin_info;
+        let frame_attachments;
+        let pass = match self.rendering {
+            render_target::Rendering::RenderPass(render_pass) => {
+                render_pass_begin_info = vk::RenderPassBeginInfo::default()
+                    .render_pass(render_pass)
+                    .framebuffer(self.swapchain_framebuffers[image.index()].get(&self.scope))
+                    .render_area(vk::Rect2D {
+                        offset: vk::Offset2D { x: 0, y: 0 },
+                        extent: self.swapchain_extent,
+                    })
+                    .clear_values(&clear_values);
+                command::PassBegin::RenderPass(&render_pass_begin_info)
+            }
+            render_target::Rendering::Dynamic { .. } => {
+                frame_attachments = dynamic_rendering::FrameAttachments {
+                    extent: self.swapchain_extent,
+                    swapchain_image: (
+                        self.images[image.index()].get(&self.scope),
+                        self.swapchain_image_views[image.index()]
+                            .get(&self.scope)
+                            .view,
+                    ),
+                    multisampled: self.color_attachment.as_ref(),
+                    depth: &self.depth_attachment,
+                    targets: &self.targets,
+                };
+                command::PassBegin::Dynamic(&frame_attachments)
+            }
+        };
+
+        let (pipeline, pipeline_layout) = self.current_pipeline();
+        let frame_index = self.frames.current_index();
+        let pushed_uniform_buffer = [(
+            0,
+            descriptor::PushedDescriptor::uniform_buffer(self.uniform_buffers.buffer(frame_index)),
+        )];
+        let triangles = command::Draw {
+            pipeline,
+            pipeline_layout,
+            descriptor_sets: match &self.push_descriptor_loader {
+                Some(_) => &[],
+                None => std::slice::from_ref(&self.descriptor_sets[frame_index]),
+            },
+            push_descriptors: self
+                .push_descriptor_loader
+                .as_ref()
+                .map(|loader| (loader, &pushed_uniform_buffer[..])),
+            push_constants: None,
+            vertex_buffer: Some(self.vertex_buffer.buffer),
+            instance_buffer: Some((self.instance_buffer.buffer, self.instance_count)),
+            index_buffer: Some(self.index_buffer.buffer),
+            vertex_count: self.index_count,
+        };
+        // The triangles themselves, or the renderer backend's resolve of its geometry pass.
+        let mut scene_draws = vec![self.backend.main_pass_draw(frame_index, &triangles)];
+
+        // Overlays go after the whole scene, so the skybox doesn't cover the parts of them next to the triangles.
+        let mut overlay_draws = Vec::new();
+        for subsystem in self.subsystems.all() {
+            subsystem.draws(&triangles, &mut scene_draws, &mut overlay_draws);
+        }
+
+        let mut labels = vec![format!("scene ({} draws)", scene_draws.len())];
+        if !overlay_draws.is_empty() {
+            labels.push(format!("overlays ({} draws)", overlay_draws.len()));
+        }
+        #[cfg(feature = "post-processing")]
+        let final_draws = match &self.subsystems.post_process {
+            Some(_) => self.subsystems.final_draws(),
+            None => Vec::new(),
+        };
+        #[cfg(feature = "post-processing")]
+        if self.subsystems.post_process.is_some() {
+            labels.push("compute post process".to_string());
+            if !final_draws.is_empty() {
+                labels.push(format!("final pass ({} draws)", final_draws.len()));
+            }
+        }
+
+        command::begin_recording(&self.device, command_buffer)?;
+        if let Some(raw_commands) = &mut self.raw_commands {
+            log_context::set_pass(Some("raw commands"));
+            raw_commands(
+                &self.device,
+                &command::RawFrame {
+                    command_buffer,
+                    frame_index: self.frames.current_index(),
+                    image: self.images[image.index()].get(&self.scope),
+                    extent: self.swapchain_extent,
+                },
+            );
+            labels.insert(0, "raw commands".to_string());
+        }
+        // Before the main pass, which reads what it drew.
+        log_context::set_pass(Some("geometry pass"));
+        let geometry_statistics =
+            self.backend
+                .record_geometry(&self.device, command_buffer, &triangles, self.wireframe);
+        if geometry_statistics.is_some() {
+            labels.insert(
+                usize::from(self.raw_commands.is_some()),
+                format!("{} geometry pass", self.backend.kind()),
+            );
+        }
+        self.draw_statistics = match &mut self.recording {
+            #[cfg(feature = "parallel-recording")]
+            Recording::Parallel(parallel_recorder) => {
+                log_context::set_pass(Some("scene and overlays"));
+                scene_draws.append(&mut overlay_draws);
+                let (secondaries, statistics) = parallel_recorder.record(
+                    &self.device,
+                    self.frames.current_index(),
+                    self.rendering,
+                    self.swapchain_extent,
+                    &scene_draws,
+                )?;
+                command::record_secondaries(&self.device, command_buffer, pass, &secondaries);
+                statistics
+            }
+            Recording::Secondary {
+                scene: scene_pass,
+                overlays: overlay_pass,
+            } => {
+                let index = self.frames.current_index();
+                log_context::set_pass(Some("scene"));
+                let (scene, mut statistics) = scene_pass.record(
+                    &self.device,
+                    index,
+                    self.rendering,
+                    self.swapchain_extent,
+                    &scene_draws,
+                )?;
+                // The cursor moves all the time.
+                log_context::set_pass(Some("overlays"));
+                overlay_pass.invalidate();
+                let (overlay, overlay_statistics) = overlay_pass.record(
+                    &self.device,
+                    index,
+                    self.rendering,
+                    self.swapchain_extent,
+                    &overlay_draws,
+                )?;
+                statistics += overlay_statistics;
+
+                command::record_secondaries(&self.device, command_buffer, pass, &[scene, overlay]);
+                statistics
+            }
+            Recording::Primary => {
+                log_context::set_pass(Some("scene and overlays"));
+                scene_draws.append(&mut overlay_draws);
+                command::record_render_pass(&self.device, command_buffer, pass, &scene_draws)
+            }
+        };
+        if let Some(geometry_statistics) = geometry_statistics {
+            self.draw_statistics += geometry_statistics;
+        }
+        #[cfg(feature = "post-processing")]
+        if let Some(post_process) = &self.subsystems.post_process {
+            log_context::set_pass(Some("compute post process"));
+            if post_process.is_async() {
+                post_process.record_handoff(
+                    &self.device,
+                    command_buffer,
+                    self.images[image.index()].get(&self.scope),
+                );
+            } else {
+                self.draw_statistics += post_process.record(
+                    &self.device,
+                    command_buffer,
+                    image.index(),
+                    self.images[image.index()].get(&self.scope),
+                    &final_draws,
+                );
+            }
+        }
+        unsafe { self.device.end_command_buffer(command_buffer)? };
+        log::trace!("Recorded frame: {}", self.draw_statistics);
+        self.audit.command_buffer_recorded(command_buffer);
+
+        Ok(labels)
+    }
+
+    /// Log what the GPU was last given to work on and what the app was set up with,
+    /// after the device was lost (or hung).
+    fn log_device_lost_diagnostics(&self) {
+        let what = if self.device_lost { "lost" } else { "hung" };
+        log::error!("Device {} {}", self.device_details.name, what);
+        for (frame, passes) in self.frames.submitted_passes().iter().enumerate() {
+            log::error!("  frame {}'s last submission: {}", frame, passes.join(", "));
+        }
+        for (name, value) in self.settings_summary() {
+            log::error!("  {}: {}", name, value);
+        }
+        // A machine picking something unexpected at startup may be why.
+        for decision in self.startup_decisions.decisions() {
+            log::error!("  startup {}", decision);
+        }
+    }
+
+    /// Wait (for at most util::WAIT_TIMEOUT, or until shutdown is requested) until the GPU is done with
+    /// every frame in flight, then for the device to go idle, which is quick after that.
+    /// device_wait_idle can't time out itself, but only frame submissions are ever left running.
+    fn wait_idle(&self) -> Result<(), Box<dyn Error>> {
+        self.frames.wait_all(
+            &self.device,
+            util::WAIT_TIMEOUT,
+            sync::Cancel::On(self.frames.shutdown()),
+        )?;
+        unsafe { self.device.device_wait_idle()? };
+        Ok(())
+    }
+
+    /// Log how much memory traffic the render pass is estimated to cause at the current extent.
+    fn log_bandwidth_estimate(&self) {
+        let estimate = self.targets.estimated_bandwidth(
+            self.swapchain_extent,
+            self.swapchain_image_format,
+            self.depth_attachment.format,
+            self.msaa_samples,
+        );
+        log::info!("Main render pass bandwidth estimate: {}", estimate);
+    }
+
+    /// Draw in wireframe (or filled again), starting with the next recorded frame.
+    /// Only has an effect if the device supports the fillModeNonSolid feature.
+    fn set_wireframe(&mut self, wireframe: bool) {
+        if wireframe && self.wireframe_pipeline.is_none() {
+            log::warn!("Wireframe isn't supported by this device (no fillModeNonSolid).");
+        }
+        if wireframe != self.wireframe {
+            self.scene_changed();
+        }
+        self.wireframe = wireframe;
+    }
+
+    /// Shade the scene with the renderer backend `kind` (see backend.rs), starting with the next recorded frame.
+    /// If the device doesn't support it or creating it fails, that's logged and the forward backend is used.
+    /// Only fails if waiting for the GPU does.
+    fn set_backend(&mut self, kind: backend::BackendKind) -> Result<(), Box<dyn Error>> {
+        // Frames in flight may still use the current backend.
+        self.wait_idle()?;
+        self.backend.destroy(&self.device);
+        let setup = backend::BackendSetup {
+            device_details: &self.device_details,
+            allocator: &self.allocator,
+            pipelines: self.backend_pipeline_setup(&self.shader_cache),
+            depth_format: self.depth_attachment.format,
+            extent: self.swapchain_extent,
+            uniform_buffers: &self.uniform_buffers,
+            scene: (
+                &self.vertex_buffer,
+                &self.index_buffer,
+                &self.instance_buffer,
+            ),
+        };
+        self.backend = match backend::create(kind, &self.device, &setup) {
+            Ok(backend) => {
+                log::info!("Using the {} renderer backend.", kind);
+                backend
+            }
+            Err(err) => {
+                log::warn!("{}, falling back to the forward renderer backend", err);
+                Box::new(backend::Forward)
+            }
+        };
+        self.scene_changed();
+        Ok(())
+    }
+
+    /// What the renderer backend's pipelines are built for, with modules from `shader_cache` (a clone of
+    /// the app's when the backend is borrowed mutably alongside).
+    fn backend_pipeline_setup<'a>(
+        &self,
+        shader_cache: &'a shader_cache::ShaderCache,
+    ) -> backend::PipelineSetup<'a> {
+        backend::PipelineSetup {
+            shader_cache,
+            rendering: self.rendering,
+            samples: self.msaa_samples,
+            triangle_layout: self.descriptor_set_layout,
+            fill_mode_non_solid: self.device_details.fill_mode_non_solid,
+            stencil: self.settings.stencil,
+        }
+    }
+
+    /// Rebuild the pipelines using any of the `changed` shaders (file names, see
+    /// `hot_reload::ShaderWatcher::changed`), between frames. A pipeline that fails to build
+    /// (e.g. the shader doesn't compile) is logged and the previous one kept.
+    /// Only fails if waiting for the GPU does.
+    #[cfg(feature = "hot-reload")]
+    fn reload_shaders(&mut self, changed: &HashSet<String>) -> Result<(), Box<dyn Error>> {
+        // Their SPIR-V is loaded again by whatever builds with them next.
+        self.shader_cache.reload(changed);
+        let triangle = subsystem::uses_any(&TRIANGLE_SHADERS, changed);
+        let backend = subsystem::uses_any(&self.backend.shaders(), changed);
+        let subsystems = self
+            .subsystems
+            .all()
+            .iter()
+            .any(|subsystem| subsystem::uses_any(&subsystem.shaders(), changed));
+        if !(triangle || backend || subsystems) {
+            return Ok(());
+        }
+
+        // Frames in flight may still use the current pipelines.
+        self.wait_idle()?;
+
+        if triangle {
+            match triangle_pipelines(
+                &self.device,
+                &self.shader_cache,
+                &self.device_details,
+                self.rendering,
+                &[self.descriptor_set_layout],
+                self.msaa_samples,
+                self.settings.stencil,
+            ) {
+                Ok(((pipeline, pipeline_layout), wireframe_pipeline)) => {
+                    self.destroy_triangle_pipelines();
+                    self.pipeline = pipeline;
+                    self.pipeline_layout = pipeline_layout;
+                    self.wireframe_pipeline = wireframe_pipeline;
+                    log::info!("Reloaded triangle shaders");
+                }
+                Err(err) => log::error!("Failed to reload triangle shaders: {}", err),
+            }
+        }
+        #[cfg(any(
+            feature = "demos",
+            feature = "asset-import",
+            feature = "ui",
+            feature = "post-processing"
+        ))]
+        {
+            let context = subsystem::PipelineContext {
+                device: &self.device,
+                shader_cache: &self.shader_cache,
+                #[cfg(any(feature = "demos", feature = "asset-import"))]
+                device_details: &self.device_details,
+                #[cfg(any(feature = "demos", feature = "asset-import", feature = "ui"))]
+                rendering: self.rendering,
+                #[cfg(any(feature = "demos", feature = "asset-import", feature = "ui"))]
+                samples: self.msaa_samples,
+            };
+            for subsystem in self.subsystems.all_mut() {
+                subsystem.rebuild_pipelines(&context, changed);
+            }
+        }
+        if backend {
+            let shader_cache = self.shader_cache.clone();
+            let setup = self.backend_pipeline_setup(&shader_cache);
+            match self.backend.rebuild_pipelines(&self.device, setup) {
+                Ok(()) => log::info!("Reloaded {} renderer backend shaders", self.backend.kind()),
+                Err(err) => log::error!(
+                    "Failed to reload {} renderer backend shaders: {}",
+                    self.backend.kind(),
+                    err
+                ),
+            }
+        }
+
+        // Recorded secondaries reference the old pipelines.
+        self.scene_changed();
+        if let Recording::Secondary { overlays, .. } = &mut self.recording {
+            overlays.invalidate();
+        }
+        Ok(())
+    }
+
+    /// Destroy the filled and wireframe triangle pipelines. The GPU must be done with them.
+    fn destroy_triangle_pipelines(&mut self) {
+        unsafe {
+            self.device.destroy_pipeline(self.pipeline, None);
+            if let Some(wireframe_pipeline) = self.wireframe_pipeline {
+                self.device.destroy_pipeline(wireframe_pipeline, None);
+            }
+            self.device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+
+    /// Something drawn in the scene changed, so its secondary command buffers have to be re-recorded.
+    fn scene_changed(&mut self) {
+        if let Recording::Secondary { scene, .. } = &mut self.recording {
+            scene.invalidate();
+        }
+    }
+
+    /// The pipeline to draw with, depending on whether wireframe is on.
+    fn current_pipeline(&self) -> (vk::Pipeline, vk::PipelineLayout) {
+        match self.wireframe_pipeline {
+            Some(wireframe_pipeline) if self.wireframe => {
+                (wireframe_pipeline, self.pipeline_layout)
+            }
+            _ => (self.pipeline, self.pipeline_layout),
+        }
+    }
+
+    /// Write this frame's uniforms: the triangle slowly spins, corrected for the window's aspect ratio.
+    /// The current frame's fence must have been waited on.
+    fn update_uniforms(&mut self) {
+        let angle = self.started.elapsed().as_secs_f32() * std::f32::consts::FRAC_PI_4;
+        let ubo = uniform::UniformBufferObject {
+            model: uniform::rotation_z(angle),
+            view: uniform::IDENTITY,
+            proj: uniform::aspect_correction(self.swapchain_extent),
+        };
+        self.uniform_buffers
+            .update(self.frames.current_index(), &ubo);
+    }
+
+    /// Set the background color the swapchain image is cleared to, starting with the next recorded frame.
+    fn set_clear_color(&mut self, clear_value: vk::ClearValue) {
+        self.targets.color.clear_value = clear_value;
+    }
+
+    /// Draw calls, triangles, binds etc. of the most recently recorded frame.
+    pub fn draw_statistics(&self) -> command::DrawStatistics {
+        self.draw_statistics
+    }
+
+    /// Feedback about recent presents, oldest first (see `present::PresentStatistics`).
+    pub fn present_statistics(&self) -> &VecDeque<present::PresentStatistics> {
+        self.present_timing.history()
     }
+
+    /// Log `draw_statistics`, and how far apart the `present_statistics` were on average,
+    /// see util::STATISTICS_LOG_INTERVAL.
+    fn log_statistics(&self) {
+        log::info!("Last frame: {}", self.draw_statistics());
+
+        let history = self.present_statistics();
+        let (Some(first), Some(last)) = (history.front(), history.back()) else {
+            return;
+        };
+        if history.len() < 2 {
+            return;
+        }
+        let interval = (last.queued_at - first.queued_at) / (history.len() - 1) as u32;
+        let margins = history
+            .iter()
+            .filter_map(|statistics| statistics.present_margin)
+            .collect::<Vec<_>>();
+        if margins.is_empty() {
+            log::info!(
+                "Last {} presents: {:?} apart on average",
+                history.len(),
+                interval
+            );
+        } else {
+            let margin = margins.iter().sum::<u64>() / margins.len() as u64;
+            log::info!(
+                "Last {} presents: {:?} apart on average, ready {:?} early",
+                history.len(),
+                interval,
+                Duration::from_nanos(margin)
+            );
+        }
+    }
+
+    //////////////// Escape Hatches ////////////////
+    // The raw Vulkan objects behind the app, for recording custom commands without forking it
+    // (like the GPU frame timer, see frame_timer.rs).
+    // The app keeps owning all of them: don't destroy them, and only use them the way each contract says.
+
+    /// The instance.
+    ///
+    /// # Safety
+    /// Don't destroy it, or anything the app created from it (surface, debug messenger).
+    unsafe fn raw_instance(&self) -> &Instance {
+        &self.instance
+    }
+
+    /// The logical device and the physical device it was created from.
+    ///
+    /// # Safety
+    /// Objects created with it must be destroyed with it before the app is dropped, after the GPU is done
+    /// with them. The app's own objects mustn't be destroyed, and only the features and extensions in
+    /// its `DeviceDetails` are enabled.
+    unsafe fn raw_device(&self) -> (&Device, vk::PhysicalDevice) {
+        (&self.device, self.physical_device)
+    }
+
+    /// The graphics and present queues, each with its queue family index (they can be the same queue).
+    ///
+    /// # Safety
+    /// Queues aren't externally synchronized by the app: only submit to them from the graphics thread
+    /// between frames (or from the `set_raw_commands` callback), never concurrently with the frame loop.
+    unsafe fn raw_queues(&self) -> ((vk::Queue, u32), (vk::Queue, u32)) {
+        (
+            (
+                self.graphics_queue,
+                self.device_details.graphics_queue_index,
+            ),
+            (self.present_queue, self.device_details.present_queue_index),
+        )
+    }
+
+    /// The swapchain, its loader and its images.
+    ///
+    /// # Safety
+    /// Only valid until the swapchain is recreated (on resize), don't keep them across frames.
+    /// Don't acquire or present images: the frame loop does, and owns the images in between.
+    unsafe fn raw_swapchain(&self) -> (&swapchain::Device, vk::SwapchainKHR, Vec<vk::Image>) {
+        let images = self
+            .images
+            .iter()
+            .map(|image| image.get(&self.scope))
+            .collect();
+        (&self.swapchain, self.swapchain_khr, images)
+    }
+
+    /// Call `raw_commands` (None removes it) while recording every frame, with the frame's primary
+    /// command buffer begun, before the main render pass (see `command::RawFrame`). It must leave the
+    /// command buffer outside any render pass, and barrier its own results if the frame's draws use them.
+    /// Resources it uses have to stay alive until the frame's submission finishes (MAX_FRAMES_IN_FLIGHT
+    /// frames later, or `raw_device`'s device_wait_idle). It's dropped before the device is destroyed.
+    fn set_raw_commands(&mut self, raw_commands: Option<RawCommands>) {
+        self.raw_commands = raw_commands;
+    }
+
+    /// Log how far apart the GPU starts frames, with custom commands (see frame_timer.rs).
+    fn start_gpu_frame_timer(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut timer = unsafe {
+            let (device, physical_device) = self.raw_device();
+            let ((_, graphics_queue_index), _) = self.raw_queues();
+            frame_timer::GpuFrameTimer::new(
+                self.raw_instance(),
+                device,
+                physical_device,
+                graphics_queue_index,
+                util::MAX_FRAMES_IN_FLIGHT,
+            )?
+        };
+        let (_, _, images) = unsafe { self.raw_swapchain() };
+        log::info!(
+            "Timing frames on the GPU, the swapchain has {} images",
+            images.len()
+        );
+        self.set_raw_commands(Some(Box::new(move |device, frame| {
+            timer.record(device, frame)
+        })));
+        Ok(())
+    }
+
+    /// What's being drawn and how, as (name, value) pairs, e.g. for capture metadata or when the device is
+    /// lost. The optional subsystems describe themselves.
+    fn settings_summary(&self) -> Vec<(&'static str, String)> {
+        let mut summary = vec![
+            (
+                "scene",
+                format!("{} triangle instances", self.instance_count),
+            ),
+            ("device", self.device_details.name.clone()),
+            (
+                "extent",
+                format!(
+                    "{}x{}",
+                    self.swapchain_extent.width, self.swapchain_extent.height
+                ),
+            ),
+            (
+                "swapchain format",
+                format!("{:?}", self.swapchain_image_format),
+            ),
+            ("msaa samples", format!("{:?}", self.msaa_samples)),
+            ("wireframe", self.wireframe.to_string()),
+            ("renderer backend", self.backend.kind().to_string()),
+        ];
+        for subsystem in self.subsystems.all() {
+            summary.extend(subsystem.describe());
+        }
+        summary
+    }
+
+    /// Recreate the swapchain and everything tied to it (e.g. after a resize).
+    /// Returns false without touching anything if the surface currently has a zero extent (minimized window),
+    /// since a swapchain can't be created for it.
+    fn recreate_swapchain(&mut self) -> Result<bool, Box<dyn Error>> {
+        let window_extent = util::window_extent(&self.window);
+        let (extent, _) =
+            SwapChainSupportDetails::new(self.physical_device, &self.surface, self.surface_khr)?
+                .choose_swapchain_extent(window_extent);
+
+        if extent.width == 0 || extent.height == 0 {
+            return Ok(false);
+        }
+
+        log::debug!("Recreating swapchain.");
+        self.wait_idle()?;
+        self.audit.device_idle();
+        self.cleanup_swapchain();
+
+        let (swapchain_loader, swapchain_khr, format, extent, images) =
+            vulkan_create::swapchain_and_images(
+                &self.instance,
+                self.physical_device,
+                &self.device_details,
+                &self.device,
+                &self.surface,
+                self.surface_khr,
+                window_extent,
+            )?;
+
+        let swapchain_image_views = vulkan_create::swapchain_image_views(
+            &self.device,
+            &self.device_details,
+            &images,
+            format,
+        )?;
+        let color_attachment = multisampled_color_attachment(
+            &self.device,
+            &self.allocator,
+            extent,
+            format,
+            self.msaa_samples,
+        )?;
+        let depth_attachment = image::depth_attachment(
+            &self.device,
+            &self.allocator,
+            extent,
+            self.depth_attachment.format,
+            self.msaa_samples,
+        )?;
+        let swapchain_framebuffers = match self.rendering {
+            render_target::Rendering::RenderPass(render_pass) => vulkan_create::framebuffers(
+                &self.device,
+                &views_in_swapchain_format(&swapchain_image_views),
+                color_attachment.as_ref().map(|color| color.view),
+                depth_attachment.view,
+                render_pass,
+                extent,
+            )?,
+            render_target::Rendering::Dynamic { .. } => Vec::new(),
+        };
+        #[cfg(feature = "post-processing")]
+        if let Some(post_process) = &mut self.subsystems.post_process {
+            post_process.resize(
+                &self.device,
+                &self.allocator,
+                &mut self.descriptors,
+                &views_in_swapchain_format(&swapchain_image_views),
+                extent,
+            )?;
+        }
+
+        // The backend keeps its previous targets if this fails, but they no longer fit the swapchain.
+        if let Err(err) = self.backend.resize(&self.device, &self.allocator, extent) {
+            log::warn!(
+                "Failed to resize the {} renderer backend ({}), falling back to the forward renderer backend",
+                self.backend.kind(),
+                err
+            );
+            self.backend.destroy(&self.device);
+            self.backend = Box::new(backend::Forward);
+        }
+
+        self.audit
+            .forget_semaphores(self.frames.render_finished_semaphores());
+        self.frames
+            .recreate_render_finished(&self.device, images.len())?;
+
+        self.swapchain = swapchain_loader;
+        self.swapchain_khr = swapchain_khr;
+        self.images = images.into_iter().map(|i| self.scope.tag(i)).collect();
+        self.swapchain_image_format = format;
+        self.swapchain_extent = extent;
+        self.color_attachment = color_attachment;
+        self.depth_attachment = depth_attachment;
+        self.swapchain_image_views = swapchain_image_views
+            .into_iter()
+            .map(|v| self.scope.tag(v))
+            .collect();
+        self.swapchain_framebuffers = swapchain_framebuffers
+            .into_iter()
+            .map(|f| self.scope.tag(f))
+            .collect();
+        // Viewport, scissor and skybox push constants depend on the extent.
+        self.scene_changed();
+        self.log_bandwidth_estimate();
+
+        Ok(true)
+    }
+
+    /// Destroy everything that is tied to the current swapchain (framebuffers, image views, the swapchain itself).
+    /// Framebuffers reference the image views, so they have to go first.
+    /// Afterwards the swapchain generation moves on, so stale handles get caught (in debug builds).
+    fn cleanup_swapchain(&mut self) {
+        unsafe {
+            self.swapchain_framebuffers
+                .drain(..)
+                .for_each(|f| self.device.destroy_framebuffer(f.get(&self.scope), None));
+            self.swapchain_image_views
+                .drain(..)
+                .for_each(|v| v.get(&self.scope).destroy(&self.device));
+            if let Some(color_attachment) = &mut self.color_attachment {
+                color_attachment.destroy(&self.device);
+            }
+            self.depth_attachment.destroy(&self.device);
+            self.swapchain.destroy_swapchain(self.swapchain_khr, None);
+        }
+        self.present_timing.swapchain_destroyed();
+        // Destroying a null handle is a no-op, so cleaning up twice (e.g. failed recreation, then Drop) is fine.
+        self.swapchain_khr = vk::SwapchainKHR::null();
+        self.scope.next_generation();
+    }
+}
+
+/// The view of each swapchain image in the swapchain's own format, e.g. for framebuffers.
+fn views_in_swapchain_format(image_views: &[image::SwapchainImageViews]) -> Vec<vk::ImageView> {
+    image_views.iter().map(|views| views.view).collect()
+}
+
+/// The shaders the triangle pipelines are built from (the builder's defaults).
+const TRIANGLE_SHADERS: [&str; 2] = ["shader.vert.spv", "shader.frag.spv"];
+
+/// The triangle's pipeline, and its wireframe variant (sharing its layout) if the device supports
+/// fillModeNonSolid.
+type TrianglePipelines = ((vk::Pipeline, vk::PipelineLayout), Option<vk::Pipeline>);
+
+/// Build the triangle pipelines for `rendering`, with push constants and vertex input reflected from
+/// the shaders. `descriptor_set_layouts` must match them, they aren't rebuilt when the shaders change.
+/// With `stencil` they write to the stencil buffer.
+fn triangle_pipelines(
+    device: &Device,
+    shader_cache: &shader_cache::ShaderCache,
+    device_details: &DeviceDetails,
+    rendering: render_target::Rendering,
+    descriptor_set_layouts: &[vk::DescriptorSetLayout],
+    samples: vk::SampleCountFlags,
+    stencil: bool,
+) -> Result<TrianglePipelines, Box<dyn Error>> {
+    let interface = reflect::ShaderInterface::from_shaders(shader_cache, &TRIANGLE_SHADERS)?;
+    // Per vertex (vertex::Vertex) and per instance (vertex::InstanceData) attributes.
+    let (vertex_bindings, vertex_attributes) = interface.vertex_input(&[
+        (
+            vk::VertexInputRate::VERTEX,
+            0..2,
+            size_of::<vertex::Vertex>(),
+        ),
+        (
+            vk::VertexInputRate::INSTANCE,
+            2..4,
+            size_of::<vertex::InstanceData>(),
+        ),
+    ])?;
+    let push_constant_ranges = interface.push_constant_ranges();
+    let mut pipeline_builder = pipeline::GraphicsPipelineBuilder::default()
+        .vertex_shader(TRIANGLE_SHADERS[0])
+        .fragment_shader(TRIANGLE_SHADERS[1])
+        .vertex_input(&vertex_bindings, &vertex_attributes)
+        .descriptor_set_layouts(descriptor_set_layouts)
+        .push_constant_ranges(&push_constant_ranges)
+        .samples(samples);
+    if stencil {
+        let stencil_op = pipeline::stencil_write(1);
+        pipeline_builder = pipeline_builder.stencil(stencil_op, stencil_op);
+    }
+    // The wireframe variant needs the fillModeNonSolid feature.
+    if !device_details.fill_mode_non_solid {
+        return Ok((
+            pipeline_builder.build(device, shader_cache, rendering)?,
+            None,
+        ));
+    }
+    // Filled is the base, wireframe only differs in polygon mode.
+    let (pipelines, layout) = pipeline_builder.build_variants(
+        device,
+        shader_cache,
+        rendering,
+        &[&|builder| builder.polygon_mode(vk::PolygonMode::LINE)],
+    )?;
+    Ok(((pipelines[0], layout), Some(pipelines[1])))
+}
+
+/// The multisampled color target for `samples`, or None if there is only one sample
+/// (then the swapchain image is rendered to directly).
+fn multisampled_color_attachment(
+    device: &Device,
+    allocator: &memory::Allocator,
+    extent: vk::Extent2D,
+    format: vk::Format,
+    samples: vk::SampleCountFlags,
+) -> Result<Option<image::AllocatedImage>, Box<dyn Error>> {
+    if samples == vk::SampleCountFlags::TYPE_1 {
+        return Ok(None);
+    }
+    Ok(Some(image::color_attachment(
+        device, allocator, extent, format, samples,
+    )?))
 }
 
 impl Drop for VulkanApp {
     fn drop(&mut self) {
         log::debug!("Dropping application.");
+        // Nothing can be destroyed while the GPU may still be using it.
+        // Unless the device is lost, then it may never become idle, and destroying is allowed anyway.
+        // Shutting down is why we're here, so that doesn't cancel the wait, only the timeout ends it.
+        // If the GPU doesn't finish, everything is leaked instead, the process is about to exit anyway.
+        if !self.device_lost {
+            let idle = self
+                .frames
+                .wait_all(&self.device, util::WAIT_TIMEOUT, sync::Cancel::Never)
+                .map_err(|err| Box::new(err) as Box<dyn Error>)
+                .and_then(|()| unsafe { self.device.device_wait_idle() }.map_err(Box::from));
+            if let Err(err) = idle {
+                if !is_device_lost(err.as_ref()) {
+                    log::error!("Leaking everything, the GPU may still be using it: {}", err);
+                    return;
+                }
+            }
+        }
+        // It may own objects of the device, see `set_raw_commands`.
+        self.raw_commands = None;
+        self.cleanup_swapchain();
+        self.frames.destroy(&self.device);
+        self.vertex_buffer.destroy(&self.device);
+        self.index_buffer.destroy(&self.device);
+        self.instance_buffer.destroy(&self.device);
+        self.uniform_buffers.destroy(&self.device);
+        self.backend.destroy(&self.device);
+        self.descriptors.destroy(&self.device);
+        for subsystem in self.subsystems.all_mut() {
+            subsystem.destroy(&self.device);
+        }
+        #[cfg(feature = "parallel-recording")]
+        if let Recording::Parallel(parallel_recorder) = &mut self.recording {
+            parallel_recorder.destroy(&self.device);
+        }
+        self.transfer_queue.destroy(&self.device);
         unsafe {
-            self.swapchain_image_views
-                .iter()
-                .for_each(|v| self.device.destroy_image_view(*v, None));
-            self.swapchain.destroy_swapchain(self.swapchain_khr, None);
+            // Freeing the pool frees its command buffers.
+            self.device.destroy_command_pool(self.command_pool, None);
+        }
+        self.destroy_triangle_pipelines();
+        self.shader_cache.destroy(&self.device);
+        unsafe {
+            self.device.destroy_render_pass(self.render_pass, None);
             self.device.destroy_device(None);
             self.surface.destroy_surface(self.surface_khr, None);
-            self.debug_utils_loader
-                .destroy_debug_utils_messenger(self.debug_callback, None);
+            if let (Some(debug_callback), Some(debug_utils_loader)) =
+                (self.debug_messenger, self.debug_utils.get())
+            {
+                debug_utils_loader.destroy_debug_utils_messenger(debug_callback, None);
+            }
             self.instance.destroy_instance(None);
         }
     }
 }
 
 fn main() {
-    env_logger::init();
+    log_context::init_logger();
+
+    // Before any threads are started, it sets environment variables for the capture layer.
+    #[cfg(feature = "capture")]
+    let capture = match capture::CaptureSession::from_env() {
+        Ok(capture) => capture,
+        Err(err) => {
+            log::error!("Can't start capture session: {}", err);
+            return;
+        }
+    };
+    #[cfg(feature = "capture")]
+    let capture_layer = capture.as_ref().map(|capture| capture.tool.layer_name());
+    #[cfg(not(feature = "capture"))]
+    let capture_layer = None;
+
+    let settings = match settings::Settings::from_args_and_env(std::env::args().skip(1)) {
+        Ok(settings) => settings,
+        Err(err) => {
+            log::error!("Invalid settings: {}", err);
+            return;
+        }
+    };
+    let software_cursor = settings.software_cursor;
 
     // They don't want you to run event_loop outside the main thread.
     let event_loop = EventLoop::<EventLoopProxyEvent>::with_user_event()
         .build()
         .unwrap();
 
-    let shared_window: Arc<Mutex<Option<Arc<Window>>>> = Arc::new(Mutex::new(None));
-
-    let window_shared_to_graphics_thread = Arc::clone(&shared_window);
+    let (ui_writer, mut ui_reader) = triple_buffer::triple_buffer(UiState::default());
 
     let event_loop_proxy = event_loop.create_proxy();
 
-    let _graphics_thread = thread::spawn(move || {
-        let mut vulkan_app: Option<VulkanApp> = None;
+    let shutdown = sync::Shutdown::default();
+    let graphics_shutdown = shutdown.clone();
+    let graphics_thread = thread::spawn(move || {
+        let shutdown = graphics_shutdown;
+        // A panic unwinds through here, dropping (and cleaning up) the Vulkan App on the way,
+        // and is reported to the event loop, which would otherwise keep running without us.
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            // The window is created once the event loop starts.
+            let window = loop {
+                let ui_state = ui_reader.read();
+                if !ui_state.running {
+                    break None;
+                }
+                if let Some(window) = &ui_state.window {
+                    break Some(Arc::clone(window));
+                }
+                thread::sleep(WINDOW_POLL_INTERVAL);
+            };
+
+            let mut vulkan_app = window.and_then(|window| {
+                log::debug!("Create Vulkan App for window {:?}.", window);
+                VulkanApp::new(&window, capture_layer, &settings, &shutdown)
+                    .inspect_err(|err| {
+                        log::error!(
+                            "Encountered some error trying to create Vulkan App: {}",
+                            err
+                        )
+                    })
+                    .ok()
+            });
 
-        loop {
-            log::debug!("Check for window.");
-            let shared_window = window_shared_to_graphics_thread.lock().unwrap();
-            match (*shared_window).borrow() {
-                Some(window) => {
-                    log::debug!("Found window {:?}", window);
-                    match vulkan_app {
-                        Some(_) => break,
-                        None => {
-                            log::debug!("Create Vulkan App.");
-                            vulkan_app = match VulkanApp::new(window) {
-                                Ok(app) => Some(app),
-                                Err(err) => {
-                                    log::error!(
-                                        "Encountered some error trying to create Vulkan App: {}",
-                                        err
-                                    );
-                                    None
-                                }
-                            }
+            log::debug!("Run Vulkan App.");
+
+            match vulkan_app {
+                Some(ref mut app) => {
+                    #[cfg(feature = "capture")]
+                    if let Some(capture) = &capture {
+                        if let Err(err) = capture.write_metadata(&app.settings_summary()) {
+                            log::error!("Failed to write capture metadata: {}", err);
                         }
                     }
+                    #[cfg(feature = "capture")]
+                    let frame_limit = capture.as_ref().map(|capture| capture.frames);
+                    #[cfg(not(feature = "capture"))]
+                    let frame_limit = None;
+                    app.run(&mut ui_reader, frame_limit);
                 }
-                None => {
-                    log::debug!("Request Window.");
-                    let _ = event_loop_proxy.send_event(EventLoopProxyEvent::RequestWindowHandle);
-                }
+                None => log::error!("No Vulkan App to run."),
             }
 
-            thread::sleep(Duration::from_secs(1));
-        }
-
-        log::debug!("Run Vulkan App.");
+            // Drop (and clean up) the Vulkan App before the window goes away with the event loop.
+            drop(vulkan_app);
+        }));
 
-        match vulkan_app {
-            Some(ref mut app) => {
-                app.run();
-                // Deliberately drop Vulkan App (to test Drop implementation)
-                // loop {
-                //     log::debug!("Vulkan App Running");
-                //     thread::sleep(Duration::from_secs(1));
-                // }
-            }
-            None => log::error!("Vulkan App not missing?"),
-        }
+        let event = match result {
+            Ok(()) => EventLoopProxyEvent::Exit,
+            Err(payload) => EventLoopProxyEvent::Fatal(panic_message(payload.as_ref())),
+        };
+        let _ = event_loop_proxy.send_event(event);
     });
 
     let mut app = Application {
         windows: Default::default(),
-        shared_window: shared_window,
+        ui_state: UiState::default(),
+        ui_writer,
+        failed: false,
+        software_cursor,
+        shutdown,
     };
 
     event_loop.run_app(app.borrow_mut()).unwrap();
+
+    graphics_thread.join().unwrap();
+    if app.failed {
+        std::process::exit(1);
+    }
+}
+
+/// Whether `err` means the device is lost, as reported by Vulkan (directly or by a wait).
+/// A `sync::GpuHang` isn't: the GPU may still finish, or still be using what it was given.
+fn is_device_lost(err: &(dyn Error + 'static)) -> bool {
+    err.downcast_ref::<vk::Result>() == Some(&vk::Result::ERROR_DEVICE_LOST)
+        || err
+            .downcast_ref::<sync::WaitError>()
+            .is_some_and(sync::WaitError::is_device_lost)
+}
+
+/// Whether `err` is a wait that stopped because shutdown was requested, which isn't a failure.
+fn is_cancelled(err: &(dyn Error + 'static)) -> bool {
+    err.downcast_ref::<sync::WaitError>() == Some(&sync::WaitError::Cancelled)
+}
+
+/// The message a panic was started with, if it was a string (as with `panic!` and `unwrap`).
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
 }
@@ -0,0 +1,175 @@
+use ash::{vk, Device, Instance};
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+use crate::util::{self, AppError};
+
+//////////////// Device Memory ////////////////
+// Every buffer and image allocates through the app's Allocator. If DEVICE_LOCAL memory was asked for but
+// the device is out of it (or has no such type for the resource), host visible memory is used instead,
+// which is slower for the GPU to read but keeps large scenes running. With MEMORY_BUDGET_EXTENSION the
+// driver also tells how much of each heap the app should use, and allocations that would take a device
+// local heap over that are demoted right away instead of pushing other apps' memory out.
+// Demoted allocations are tracked until they're freed, so they can be reported.
+
+/// Allocates device memory, see the comment above. Clones share the demoted allocations, so resources
+/// keep one to free their memory with.
+#[derive(Clone)]
+pub struct Allocator {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    properties: vk::PhysicalDeviceMemoryProperties,
+    // Set if MEMORY_BUDGET_EXTENSION is enabled, to query the heaps' budgets with.
+    budget: Option<(Instance, vk::PhysicalDevice)>,
+    // Demoted allocations that haven't been freed yet, and their sizes.
+    demoted: Mutex<HashMap<vk::DeviceMemory, vk::DeviceSize>>,
+}
+
+/// How much of a memory heap is in use by this app, and how much it should use (see the comment above).
+#[derive(Debug, Clone, Copy)]
+pub struct HeapBudget {
+    pub heap_index: usize,
+    pub usage: vk::DeviceSize,
+    pub budget: vk::DeviceSize,
+}
+
+impl Allocator {
+    /// For devices created from `physical_device`, with `memory_budget` if MEMORY_BUDGET_EXTENSION is enabled
+    /// on them (which needs Vulkan 1.1 to query).
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        memory_budget: bool,
+    ) -> Self {
+        let properties = unsafe { instance.get_physical_device_memory_properties(physical_device) };
+        Self {
+            shared: Arc::new(Shared {
+                properties,
+                budget: memory_budget.then(|| (instance.clone(), physical_device)),
+                demoted: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// Allocate memory for `requirements` with `properties`, falling back to host visible memory
+    /// if `properties` includes DEVICE_LOCAL and that can't be had (or is over budget).
+    /// `what` names the resource for the warning. Returns the memory and the properties it actually has.
+    pub fn allocate(
+        &self,
+        device: &Device,
+        requirements: vk::MemoryRequirements,
+        properties: vk::MemoryPropertyFlags,
+        what: &str,
+    ) -> Result<(vk::DeviceMemory, vk::MemoryPropertyFlags), Box<dyn Error>> {
+        let exact = match self.over_budget(requirements, properties) {
+            Some(heap) => Err(Box::new(AppError::new(&format!(
+                "it would take heap {} over its budget",
+                heap.heap_index
+            ))) as Box<dyn Error>),
+            None => self.allocate_exactly(device, requirements, properties),
+        };
+        match exact {
+            Ok(memory) => Ok((memory, properties)),
+            Err(err) if properties.contains(vk::MemoryPropertyFlags::DEVICE_LOCAL) => {
+                let fallback = (properties & !vk::MemoryPropertyFlags::DEVICE_LOCAL)
+                    | vk::MemoryPropertyFlags::HOST_VISIBLE;
+                let memory = self.allocate_exactly(device, requirements, fallback)?;
+
+                log::warn!(
+                    "Couldn't allocate {} bytes of device local memory for {} ({}), using host visible memory instead",
+                    requirements.size,
+                    what,
+                    err
+                );
+                self.shared
+                    .demoted
+                    .lock()
+                    .unwrap()
+                    .insert(memory, requirements.size);
+
+                Ok((memory, fallback))
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Free memory from `allocate`. The GPU must be done with it.
+    pub fn free(&self, device: &Device, memory: vk::DeviceMemory) {
+        self.shared.demoted.lock().unwrap().remove(&memory);
+        unsafe { device.free_memory(memory, None) };
+    }
+
+    /// How many allocations are currently demoted to host visible memory, and their total size.
+    pub fn demoted(&self) -> (usize, vk::DeviceSize) {
+        let demoted = self.shared.demoted.lock().unwrap();
+        (demoted.len(), demoted.values().sum())
+    }
+
+    /// Every heap's current usage and budget, None without MEMORY_BUDGET_EXTENSION.
+    pub fn heap_budgets(&self) -> Option<Vec<HeapBudget>> {
+        let (instance, physical_device) = self.shared.budget.as_ref()?;
+        let mut budget_properties = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+        let mut properties =
+            vk::PhysicalDeviceMemoryProperties2::default().push_next(&mut budget_properties);
+        unsafe {
+            instance.get_physical_device_memory_properties2(*physical_device, &mut properties)
+        };
+
+        let heap_count = self.shared.properties.memory_heap_count as usize;
+        Some(
+            (0..heap_count)
+                .map(|heap_index| HeapBudget {
+                    heap_index,
+                    usage: budget_properties.heap_usage[heap_index],
+                    budget: budget_properties.heap_budget[heap_index],
+                })
+                .collect(),
+        )
+    }
+
+    /// The heap a DEVICE_LOCAL allocation for `requirements` would come from, if that would take it
+    /// over its budget.
+    fn over_budget(
+        &self,
+        requirements: vk::MemoryRequirements,
+        properties: vk::MemoryPropertyFlags,
+    ) -> Option<HeapBudget> {
+        if !properties.contains(vk::MemoryPropertyFlags::DEVICE_LOCAL) {
+            return None;
+        }
+        let memory_type_index = util::find_memory_type(
+            &self.shared.properties,
+            requirements.memory_type_bits,
+            properties,
+        )
+        .ok()?;
+        let heap_index =
+            self.shared.properties.memory_types[memory_type_index as usize].heap_index as usize;
+        self.heap_budgets()?
+            .into_iter()
+            .find(|heap| heap.heap_index == heap_index)
+            .filter(|heap| heap.usage + requirements.size > heap.budget)
+    }
+
+    fn allocate_exactly(
+        &self,
+        device: &Device,
+        requirements: vk::MemoryRequirements,
+        properties: vk::MemoryPropertyFlags,
+    ) -> Result<vk::DeviceMemory, Box<dyn Error>> {
+        let memory_type_index = util::find_memory_type(
+            &self.shared.properties,
+            requirements.memory_type_bits,
+            properties,
+        )?;
+
+        let allocate_info = vk::MemoryAllocateInfo::default()
+            .allocation_size(requirements.size)
+            .memory_type_index(memory_type_index);
+
+        unsafe { Ok(device.allocate_memory(&allocate_info, None)?) }
+    }
+}
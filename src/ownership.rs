@@ -0,0 +1,353 @@
+use ash::{vk, Device};
+#[cfg(debug_assertions)]
+use std::collections::HashMap;
+#[cfg(debug_assertions)]
+use std::sync::{Arc, Mutex};
+
+//////////////// Queue Family Ownership Transfers ////////////////
+// An EXCLUSIVE resource used on another queue family has to be handed over: the old family records
+// a release barrier, the new one an identical acquire barrier (same families, same layouts), and a
+// semaphore orders the two submissions. The release's destination access and the acquire's source
+// access are ignored, so they're left empty. A layout change happens once, between the two.
+// Within one family (or with QUEUE_FAMILY_IGNORED) there's nothing to transfer, a normal barrier does.
+//
+// In debug builds every transfer tracks what its barriers did to each resource: which family owns it,
+// its layout, the access it was last left with, and a release still waiting for its acquire. Each
+// barrier is checked against that: an image is released from the layout it's in, by the family that
+// owns it, making the last writes available, and acquired with exactly the release's barrier.
+// A plain barrier from UNDEFINED discards an image's contents, and with them the ownership, so the
+// ones on handed over images go through `track_image`. Buffers have no layout to discard their
+// contents with, an upload overwriting one may be released by a family that doesn't own it.
+
+/// A handover of resources from the `src` queue family to the `dst` one.
+/// Clones share what the barriers are tracked against.
+#[derive(Clone, Debug)]
+pub struct QueueTransfer {
+    pub src: u32,
+    pub dst: u32,
+    #[cfg(debug_assertions)]
+    tracked: Arc<Mutex<HashMap<Resource, Tracked>>>,
+}
+
+/// The barriers one side of a `QueueTransfer` records.
+#[derive(Default)]
+pub struct TransferBarriers<'a> {
+    pub images: Vec<vk::ImageMemoryBarrier<'a>>,
+    pub buffers: Vec<vk::BufferMemoryBarrier<'a>>,
+}
+
+impl QueueTransfer {
+    /// None if `src` and `dst` are the same family, then no transfer is needed.
+    pub fn new(src: u32, dst: u32) -> Option<Self> {
+        (src != dst).then_some(Self {
+            src,
+            dst,
+            #[cfg(debug_assertions)]
+            tracked: Arc::default(),
+        })
+    }
+
+    /// The release (for the `src` family) and acquire (for the `dst` family) barriers for `image`,
+    /// moving `range` from `old_layout` to `new_layout`. `src_access` is what the `src` family did
+    /// to it last, `dst_access` what the `dst` family does with it first.
+    #[cfg(any(feature = "asset-import", feature = "post-processing"))]
+    pub fn image(
+        &self,
+        image: vk::Image,
+        range: vk::ImageSubresourceRange,
+        (old_layout, new_layout): (vk::ImageLayout, vk::ImageLayout),
+        src_access: vk::AccessFlags,
+        dst_access: vk::AccessFlags,
+    ) -> (
+        vk::ImageMemoryBarrier<'static>,
+        vk::ImageMemoryBarrier<'static>,
+    ) {
+        let barrier = vk::ImageMemoryBarrier::default()
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .src_queue_family_index(self.src)
+            .dst_queue_family_index(self.dst)
+            .image(image)
+            .subresource_range(range);
+        (
+            barrier.src_access_mask(src_access),
+            barrier.dst_access_mask(dst_access),
+        )
+    }
+
+    /// The release and acquire barriers for `size` bytes of `buffer` from `offset`, like `image`.
+    pub fn buffer(
+        &self,
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+        size: vk::DeviceSize,
+        src_access: vk::AccessFlags,
+        dst_access: vk::AccessFlags,
+    ) -> (
+        vk::BufferMemoryBarrier<'static>,
+        vk::BufferMemoryBarrier<'static>,
+    ) {
+        let barrier = vk::BufferMemoryBarrier::default()
+            .src_queue_family_index(self.src)
+            .dst_queue_family_index(self.dst)
+            .buffer(buffer)
+            .offset(offset)
+            .size(size);
+        (
+            barrier.src_access_mask(src_access),
+            barrier.dst_access_mask(dst_access),
+        )
+    }
+
+    /// Record `release` into `command_buffer`, which is submitted to the `src` family, after the
+    /// `src_stage` work using the resources.
+    pub fn record_release(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        src_stage: vk::PipelineStageFlags,
+        release: &TransferBarriers,
+    ) {
+        self.check(release);
+        #[cfg(debug_assertions)]
+        self.track(release, false);
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                src_stage,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::DependencyFlags::empty(),
+                &[],
+                &release.buffers,
+                &release.images,
+            )
+        };
+    }
+
+    /// Record `acquire` into `command_buffer`, which is submitted to the `dst` family after the
+    /// release (waiting on a semaphore it signals), before the `dst_stage` work using the resources.
+    pub fn record_acquire(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        dst_stage: vk::PipelineStageFlags,
+        acquire: &TransferBarriers,
+    ) {
+        self.check(acquire);
+        #[cfg(debug_assertions)]
+        self.track(acquire, true);
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                &acquire.buffers,
+                &acquire.images,
+            )
+        };
+    }
+
+    /// In debug builds, check the plain `barrier` the `family` (`src` or `dst`) records for an image this
+    /// transfer hands over, and track it, see the comment at the top.
+    #[cfg(any(feature = "asset-import", feature = "post-processing"))]
+    #[cfg_attr(not(debug_assertions), allow(unused_variables))]
+    pub fn track_image(&self, family: u32, barrier: &vk::ImageMemoryBarrier) {
+        #[cfg(debug_assertions)]
+        {
+            let mut tracked = self.tracked.lock().unwrap();
+            let resource = Resource::Image(barrier.image);
+            if barrier.old_layout != vk::ImageLayout::UNDEFINED {
+                if let Some(last) = tracked.get(&resource) {
+                    assert!(
+                        last.pending.is_none(),
+                        "{:?} is used before it was acquired",
+                        resource
+                    );
+                    assert!(
+                        last.family == family,
+                        "{:?} is owned by family {}, not {}",
+                        resource,
+                        last.family,
+                        family
+                    );
+                    last.check(resource, barrier.old_layout, barrier.src_access_mask);
+                }
+            }
+            tracked.insert(
+                resource,
+                Tracked {
+                    family,
+                    layout: barrier.new_layout,
+                    access: barrier.dst_access_mask,
+                    pending: None,
+                },
+            );
+        }
+    }
+
+    /// In debug builds, that `barriers` all belong to this transfer.
+    fn check(&self, barriers: &TransferBarriers) {
+        let families = barriers
+            .images
+            .iter()
+            .map(|barrier| {
+                (
+                    barrier.src_queue_family_index,
+                    barrier.dst_queue_family_index,
+                )
+            })
+            .chain(barriers.buffers.iter().map(|barrier| {
+                (
+                    barrier.src_queue_family_index,
+                    barrier.dst_queue_family_index,
+                )
+            }));
+        for (src, dst) in families {
+            debug_assert!(
+                (src, dst) == (self.src, self.dst),
+                "barrier transfers from family {} to {}, not {} to {}",
+                src,
+                dst,
+                self.src,
+                self.dst
+            );
+        }
+    }
+}
+
+/// A resource barriers are tracked for.
+#[cfg(debug_assertions)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Resource {
+    Image(vk::Image),
+    Buffer(vk::Buffer),
+}
+
+/// Where a resource was left by the last barrier tracked for it.
+#[cfg(debug_assertions)]
+#[derive(Clone, Copy, Debug)]
+struct Tracked {
+    // Owns it, or released it if `pending`.
+    family: u32,
+    // UNDEFINED for buffers.
+    layout: vk::ImageLayout,
+    // What the barrier left it for, writes have to be made available by the next one.
+    access: vk::AccessFlags,
+    // A release's layouts and range (subresource range or offset and size), until it's acquired.
+    pending: Option<((vk::ImageLayout, vk::ImageLayout), [u64; 5])>,
+}
+
+/// The access flags that write.
+#[cfg(debug_assertions)]
+const WRITES: vk::AccessFlags = vk::AccessFlags::from_raw(
+    vk::AccessFlags::SHADER_WRITE.as_raw()
+        | vk::AccessFlags::COLOR_ATTACHMENT_WRITE.as_raw()
+        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE.as_raw()
+        | vk::AccessFlags::TRANSFER_WRITE.as_raw()
+        | vk::AccessFlags::HOST_WRITE.as_raw()
+        | vk::AccessFlags::MEMORY_WRITE.as_raw(),
+);
+
+#[cfg(debug_assertions)]
+impl Tracked {
+    /// That a barrier from `old_layout` making `src_access` available follows this.
+    fn check(&self, resource: Resource, old_layout: vk::ImageLayout, src_access: vk::AccessFlags) {
+        assert!(
+            self.layout == old_layout,
+            "{:?} is in {:?}, not {:?}",
+            resource,
+            self.layout,
+            old_layout
+        );
+        assert!(
+            src_access.contains(self.access & WRITES),
+            "{:?} was last written with {:?}, which {:?} doesn't make available",
+            resource,
+            self.access & WRITES,
+            src_access
+        );
+    }
+}
+
+#[cfg(debug_assertions)]
+impl QueueTransfer {
+    /// Check the release (or `acquire`) `barriers` against what's tracked, and track them.
+    fn track(&self, barriers: &TransferBarriers, acquire: bool) {
+        let images = barriers.images.iter().map(|barrier| {
+            let range = barrier.subresource_range;
+            (
+                Resource::Image(barrier.image),
+                (barrier.old_layout, barrier.new_layout),
+                [
+                    range.aspect_mask.as_raw() as u64,
+                    range.base_mip_level as u64,
+                    range.level_count as u64,
+                    range.base_array_layer as u64,
+                    range.layer_count as u64,
+                ],
+                (barrier.src_access_mask, barrier.dst_access_mask),
+            )
+        });
+        let buffers = barriers.buffers.iter().map(|barrier| {
+            (
+                Resource::Buffer(barrier.buffer),
+                (vk::ImageLayout::UNDEFINED, vk::ImageLayout::UNDEFINED),
+                [barrier.offset, barrier.size, 0, 0, 0],
+                (barrier.src_access_mask, barrier.dst_access_mask),
+            )
+        });
+
+        let mut tracked = self.tracked.lock().unwrap();
+        for (resource, layouts, range, (src_access, dst_access)) in images.chain(buffers) {
+            let last = tracked.get(&resource).copied();
+            if acquire {
+                assert!(
+                    last.and_then(|last| last.pending) == Some((layouts, range)),
+                    "{:?} is acquired without a matching release",
+                    resource
+                );
+                tracked.insert(
+                    resource,
+                    Tracked {
+                        family: self.dst,
+                        layout: layouts.1,
+                        access: dst_access,
+                        pending: None,
+                    },
+                );
+                continue;
+            }
+
+            if let Some(last) = last {
+                assert!(
+                    last.pending.is_none(),
+                    "{:?} is released again before it was acquired",
+                    resource
+                );
+                let image = matches!(resource, Resource::Image(_));
+                // From UNDEFINED an image's contents (and ownership) are discarded.
+                if !image || layouts.0 != vk::ImageLayout::UNDEFINED {
+                    assert!(
+                        !image || last.family == self.src,
+                        "{:?} is owned by family {}, not {}",
+                        resource,
+                        last.family,
+                        self.src
+                    );
+                    last.check(resource, layouts.0, src_access);
+                }
+            }
+            tracked.insert(
+                resource,
+                Tracked {
+                    family: self.src,
+                    layout: layouts.0,
+                    access: src_access,
+                    pending: Some((layouts, range)),
+                },
+            );
+        }
+    }
+}
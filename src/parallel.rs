@@ -0,0 +1,128 @@
+use ash::{vk, Device};
+use std::error::Error;
+use std::thread;
+
+use crate::command::{self, Draw, DrawStatistics};
+use crate::log_context;
+use crate::render_target::Rendering;
+use crate::util;
+
+//////////////// Multi-threaded Recording ////////////////
+// A command pool (and everything allocated from it) may only be used by one thread at a time,
+// so every worker thread has its own pool, with one secondary command buffer per frame in flight.
+// A frame's draw list is split into one contiguous chunk per worker. The chunks are recorded at the
+// same time, and the primary executes the secondaries in order, so draw order is kept.
+// Workers are scoped threads started for each frame, since draws borrow from the frame.
+
+/// Everything one worker thread records with.
+struct Worker {
+    command_pool: vk::CommandPool,
+    // One per frame in flight.
+    command_buffers: Vec<vk::CommandBuffer>,
+}
+
+/// Records draw lists into secondary command buffers on several threads.
+pub struct ParallelRecorder {
+    workers: Vec<Worker>,
+}
+
+impl ParallelRecorder {
+    /// Create `threads` workers with a command pool on `queue_family_index` each,
+    /// and `frames_in_flight` secondary command buffers per worker.
+    pub fn new(
+        device: &Device,
+        queue_family_index: u32,
+        threads: usize,
+        frames_in_flight: usize,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut workers = Vec::with_capacity(threads);
+        let created = (|| {
+            for _ in 0..threads {
+                let command_pool = command::command_pool(device, queue_family_index)?;
+                // Pushed before allocating, so the pool is destroyed with the others if that fails.
+                workers.push(Worker {
+                    command_pool,
+                    command_buffers: Vec::new(),
+                });
+                let command_buffers = command::secondary_command_buffers(
+                    device,
+                    command_pool,
+                    frames_in_flight as u32,
+                )?;
+                workers.last_mut().unwrap().command_buffers = command_buffers;
+            }
+            Ok::<_, Box<dyn Error>>(())
+        })();
+
+        let mut recorder = Self { workers };
+        if let Err(err) = created {
+            recorder.destroy(device);
+            return Err(err);
+        }
+        Ok(recorder)
+    }
+
+    /// Record `draws` for frame in flight `index`, split across the workers, to be executed inside
+    /// `rendering` at `extent`. Returns the secondaries to execute, in order,
+    /// and what they draw. Blocks until every worker is done.
+    /// The GPU must be done with the frame's previous recordings, i.e. its fence has been waited on.
+    pub fn record(
+        &self,
+        device: &Device,
+        index: usize,
+        rendering: Rendering,
+        extent: vk::Extent2D,
+        draws: &[Draw],
+    ) -> Result<(Vec<vk::CommandBuffer>, DrawStatistics), Box<dyn Error>> {
+        if draws.is_empty() {
+            return Ok((Vec::new(), DrawStatistics::default()));
+        }
+        let chunk_size = draws.len().div_ceil(self.workers.len());
+
+        let log_context = log_context::current();
+        let recorded = thread::scope(|scope| {
+            let handles: Vec<_> = draws
+                .chunks(chunk_size)
+                .zip(self.workers.iter())
+                .map(|(chunk, worker)| {
+                    let command_buffer = worker.command_buffers[index];
+                    scope.spawn(move || {
+                        log_context::set(log_context);
+                        command::record_secondary(device, command_buffer, rendering, extent, chunk)
+                            .map(|statistics| (command_buffer, statistics))
+                            // Box<dyn Error> can't be sent back to this thread.
+                            .map_err(|err| err.to_string())
+                    })
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .map(|handle| match handle.join() {
+                    Ok(result) => result,
+                    Err(_) => Err("recording thread panicked".to_string()),
+                })
+                .collect::<Result<Vec<_>, String>>()
+        });
+
+        let recorded = recorded.map_err(|err| {
+            util::AppError::new(&format!("Failed to record draws in parallel: {}", err))
+        })?;
+        let mut statistics = DrawStatistics::default();
+        let command_buffers = recorded
+            .into_iter()
+            .map(|(command_buffer, chunk_statistics)| {
+                statistics += chunk_statistics;
+                command_buffer
+            })
+            .collect();
+        Ok((command_buffers, statistics))
+    }
+
+    /// Destroy every worker's command pool (freeing its command buffers). The GPU must be done with them.
+    pub fn destroy(&mut self, device: &Device) {
+        for worker in self.workers.drain(..) {
+            unsafe { device.destroy_command_pool(worker.command_pool, None) };
+        }
+    }
+}
@@ -0,0 +1,578 @@
+use ash::{vk, Device};
+use std::error::Error;
+
+use crate::dynamic_rendering;
+use crate::render_target::Rendering;
+use crate::shader_cache::ShaderCache;
+#[cfg(feature = "demos")]
+use crate::util;
+use crate::util::AppError;
+#[cfg(any(feature = "demos", feature = "asset-import"))]
+use crate::util::DeviceDetails;
+use crate::vertex::{InstanceData, Vertex};
+
+//////////////// Graphics Pipeline ////////////////
+
+/// Fixed function state and shaders of a graphics pipeline.
+/// Starts out as the hardcoded triangle pipeline; change what you need, then `build` it.
+#[derive(Clone)]
+pub struct GraphicsPipelineBuilder<'a> {
+    // Files in the shader directory, see shader.rs.
+    vertex_shader: &'a str,
+    fragment_shader: &'a str,
+    // Tessellation control and evaluation shaders, and the control points per patch.
+    tessellation: Option<(&'a str, &'a str, u32)>,
+    geometry_shader: Option<&'a str>,
+    vertex_bindings: Vec<vk::VertexInputBindingDescription>,
+    vertex_attributes: Vec<vk::VertexInputAttributeDescription>,
+    descriptor_set_layouts: &'a [vk::DescriptorSetLayout],
+    push_constant_ranges: &'a [vk::PushConstantRange],
+    depth_test: bool,
+    depth_write: bool,
+    depth_compare_op: vk::CompareOp,
+    // Min and max depth the attachment's existing depth must be within, `None` disables the test.
+    depth_bounds: Option<(f32, f32)>,
+    depth_clamp: bool,
+    cull_mode: vk::CullModeFlags,
+    // Front and back face stencil state, `None` disables the stencil test.
+    stencil: Option<(vk::StencilOpState, vk::StencilOpState)>,
+    samples: vk::SampleCountFlags,
+    polygon_mode: vk::PolygonMode,
+    conservative_rasterization: Option<vk::ConservativeRasterizationModeEXT>,
+    // What the state above needs that the device doesn't have enabled, `build` fails if there is any.
+    missing_features: Vec<String>,
+    // Color attachments of the subpass, all written without blending.
+    color_attachments: u32,
+    // Of the render pass, ignored with dynamic rendering.
+    subpass: u32,
+    // Whether other pipelines can be derivatives of this one, and the one this is a derivative of.
+    allow_derivatives: bool,
+    base_pipeline: Option<vk::Pipeline>,
+}
+
+impl Default for GraphicsPipelineBuilder<'_> {
+    /// Shaders are compiled ahead of time into the shader directory (e.g. `glslc shader.vert -o shader.vert.spv`)
+    /// and loaded when the pipeline is built.
+    fn default() -> Self {
+        Self {
+            vertex_shader: "shader.vert.spv",
+            fragment_shader: "shader.frag.spv",
+            tessellation: None,
+            geometry_shader: None,
+            vertex_bindings: [
+                &Vertex::binding_descriptions()[..],
+                &InstanceData::binding_descriptions(),
+            ]
+            .concat(),
+            vertex_attributes: [
+                &Vertex::attribute_descriptions()[..],
+                &InstanceData::attribute_descriptions(),
+            ]
+            .concat(),
+            descriptor_set_layouts: &[],
+            push_constant_ranges: &[],
+            depth_test: true,
+            depth_write: true,
+            depth_compare_op: vk::CompareOp::LESS,
+            depth_bounds: None,
+            depth_clamp: false,
+            cull_mode: vk::CullModeFlags::BACK,
+            stencil: None,
+            samples: vk::SampleCountFlags::TYPE_1,
+            polygon_mode: vk::PolygonMode::FILL,
+            conservative_rasterization: None,
+            missing_features: Vec::new(),
+            color_attachments: 1,
+            subpass: 0,
+            allow_derivatives: false,
+            base_pipeline: None,
+        }
+    }
+}
+
+impl<'a> GraphicsPipelineBuilder<'a> {
+    /// Use another vertex shader, the SPIR-V file `name` in the shader directory.
+    pub fn vertex_shader(mut self, name: &'a str) -> Self {
+        self.vertex_shader = name;
+        self
+    }
+
+    /// Use another fragment shader, the SPIR-V file `name` in the shader directory.
+    pub fn fragment_shader(mut self, name: &'a str) -> Self {
+        self.fragment_shader = name;
+        self
+    }
+
+    /// Add tessellation control and evaluation shaders (SPIR-V files in the shader directory),
+    /// drawing patches of `control_points` vertices instead of triangles.
+    /// Needs the tessellationShader feature (see `DeviceDetails::tessellation_shader`).
+    // Only the displaced plane uses it.
+    #[cfg(feature = "demos")]
+    pub fn tessellation(
+        mut self,
+        control: &'a str,
+        evaluation: &'a str,
+        control_points: u32,
+    ) -> Self {
+        self.tessellation = Some((control, evaluation, control_points));
+        self
+    }
+
+    /// Add a geometry shader (a SPIR-V file in the shader directory) between the vertex (or tessellation)
+    /// and fragment shaders, e.g. to expand points into quads or draw normals as lines.
+    /// Needs the geometryShader feature (see `DeviceDetails::geometry_shader`).
+    // Only the vertex markers use it.
+    #[cfg(feature = "demos")]
+    pub fn geometry_shader(mut self, name: &'a str) -> Self {
+        self.geometry_shader = Some(name);
+        self
+    }
+
+    /// Vertex buffer bindings and attributes, `Vertex`'s and `InstanceData`'s by default.
+    /// Leave both empty for shaders that generate their vertices from gl_VertexIndex.
+    pub fn vertex_input(
+        mut self,
+        bindings: &[vk::VertexInputBindingDescription],
+        attributes: &[vk::VertexInputAttributeDescription],
+    ) -> Self {
+        self.vertex_bindings = bindings.to_vec();
+        self.vertex_attributes = attributes.to_vec();
+        self
+    }
+
+    /// Layouts of the descriptor sets the shaders use, set 0 first.
+    pub fn descriptor_set_layouts(mut self, layouts: &'a [vk::DescriptorSetLayout]) -> Self {
+        self.descriptor_set_layouts = layouts;
+        self
+    }
+
+    /// Push constant ranges the shaders use.
+    pub fn push_constant_ranges(mut self, ranges: &'a [vk::PushConstantRange]) -> Self {
+        self.push_constant_ranges = ranges;
+        self
+    }
+
+    /// Depth test (LESS) and depth writes, on by default.
+    /// Turn off for overlays that should always be drawn on top.
+    pub fn depth_test(mut self, enabled: bool) -> Self {
+        self.depth_test = enabled;
+        self
+    }
+
+    /// Whether depth tested fragments also write depth, on by default.
+    #[cfg(feature = "asset-import")]
+    pub fn depth_write(mut self, enabled: bool) -> Self {
+        self.depth_write = enabled;
+        self
+    }
+
+    /// How fragments are depth tested, LESS by default.
+    /// E.g. LESS_OR_EQUAL for something drawn at the far plane after the depth buffer was cleared to it.
+    pub fn depth_compare_op(mut self, compare_op: vk::CompareOp) -> Self {
+        self.depth_compare_op = compare_op;
+        self
+    }
+
+    /// Discard fragments where the depth already in the attachment is outside `min..=max`,
+    /// e.g. to only draw a skybox where the depth buffer is still cleared to the far plane.
+    /// Needs the depthBounds feature, `build` fails if `device_details` says it isn't enabled.
+    // The skybox is the only user.
+    #[cfg(feature = "asset-import")]
+    pub fn depth_bounds(mut self, device_details: &DeviceDetails, min: f32, max: f32) -> Self {
+        self.depth_bounds = Some((min, max));
+        if !device_details.depth_bounds {
+            self.missing_features.push("depthBounds".to_string());
+        }
+        self
+    }
+
+    /// Clamp fragment depth to the viewport's depth range instead of clipping against the near and far planes,
+    /// e.g. for a skybox on the far plane.
+    /// Needs the depthClamp feature, `build` fails if `device_details` says it isn't enabled.
+    // The skybox is the only user.
+    #[cfg(feature = "asset-import")]
+    pub fn depth_clamp(mut self, device_details: &DeviceDetails) -> Self {
+        self.depth_clamp = true;
+        if !device_details.depth_clamp {
+            self.missing_features.push("depthClamp".to_string());
+        }
+        self
+    }
+
+    /// Which faces to cull, BACK by default (front faces are clockwise).
+    pub fn cull_mode(mut self, cull_mode: vk::CullModeFlags) -> Self {
+        self.cull_mode = cull_mode;
+        self
+    }
+
+    /// Enable the stencil test with separate state for front and back facing primitives.
+    /// The render pass's depth attachment must have a stencil component (see `image::find_depth_format`).
+    pub fn stencil(mut self, front: vk::StencilOpState, back: vk::StencilOpState) -> Self {
+        self.stencil = Some((front, back));
+        self
+    }
+
+    /// Rasterization sample count, must match the render pass attachments.
+    pub fn samples(mut self, samples: vk::SampleCountFlags) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    /// FILL, or LINE/POINT for debugging geometry (needs the fillModeNonSolid feature).
+    pub fn polygon_mode(mut self, polygon_mode: vk::PolygonMode) -> Self {
+        self.polygon_mode = polygon_mode;
+        self
+    }
+
+    /// Rasterize with OVERESTIMATE (every pixel a primitive touches at all) or UNDERESTIMATE
+    /// (only pixels fully covered) instead of sampling pixel centers.
+    /// Needs CONSERVATIVE_RASTERIZATION_EXTENSION, `build` fails if `device_details` says it isn't enabled.
+    // Only the voxelization uses it.
+    #[cfg(feature = "demos")]
+    pub fn conservative_rasterization(
+        mut self,
+        mode: vk::ConservativeRasterizationModeEXT,
+        device_details: &DeviceDetails,
+    ) -> Self {
+        self.conservative_rasterization = Some(mode);
+        if !device_details.conservative_rasterization {
+            self.missing_features.push(
+                util::CONSERVATIVE_RASTERIZATION_EXTENSION
+                    .to_string_lossy()
+                    .into_owned(),
+            );
+        }
+        self
+    }
+
+    /// Whether the pipeline's subpass has a color attachment to write to, true by default.
+    /// Turn off for passes that only have side effects, like storage buffer writes.
+    #[cfg(feature = "demos")]
+    pub fn color_attachment(mut self, enabled: bool) -> Self {
+        self.color_attachments = enabled as u32;
+        self
+    }
+
+    /// How many color attachments the pipeline's subpass has, e.g. the targets of a G-buffer (see backend.rs).
+    /// More than one needs a render pass, dynamic rendering only has the swapchain's format.
+    pub fn color_attachments(mut self, count: u32) -> Self {
+        self.color_attachments = count;
+        self
+    }
+
+    /// The subpass of the render pass the pipeline is used in, 0 by default (see render_pass.rs).
+    pub fn subpass(mut self, index: u32) -> Self {
+        self.subpass = index;
+        self
+    }
+
+    /// Let pipelines be created as derivatives of this one (see `derivative_of`), e.g. when it's the base
+    /// of a set of variants.
+    pub fn allow_derivatives(mut self, enabled: bool) -> Self {
+        self.allow_derivatives = enabled;
+        self
+    }
+
+    /// Create the pipeline as a derivative of `base`, which was built with `allow_derivatives`.
+    /// Variants that differ little from their base (e.g. only the polygon mode) can be cheaper to create
+    /// and switch between on some drivers. Others ignore it, so the result is the same either way.
+    pub fn derivative_of(mut self, base: vk::Pipeline) -> Self {
+        self.base_pipeline = Some(base);
+        self
+    }
+
+    /// Create this pipeline as the base, and one derivative of it per entry of `variants`, built from
+    /// what it makes of (a copy of) this builder, e.g. `|builder| builder.polygon_mode(vk::PolygonMode::LINE)`.
+    /// Variants can't change the layout (descriptor set layouts and push constants), they all share this one's.
+    /// Returns the base first, then the variants in order, and the layout. If any fails, the ones already
+    /// built are destroyed.
+    pub fn build_variants(
+        &self,
+        device: &Device,
+        shader_cache: &ShaderCache,
+        rendering: Rendering,
+        variants: &[&dyn Fn(Self) -> Self],
+    ) -> Result<(Vec<vk::Pipeline>, vk::PipelineLayout), Box<dyn Error>> {
+        let layout = self.layout(device)?;
+        let mut pipelines = Vec::with_capacity(variants.len() + 1);
+        let built = (|| {
+            let base = self.clone().allow_derivatives(true).build_with_layout(
+                device,
+                shader_cache,
+                rendering,
+                layout,
+            )?;
+            pipelines.push(base);
+            for variant in variants.iter() {
+                let builder = variant(self.clone()).derivative_of(base);
+                pipelines.push(builder.build_with_layout(
+                    device,
+                    shader_cache,
+                    rendering,
+                    layout,
+                )?);
+            }
+            Ok::<_, Box<dyn Error>>(())
+        })();
+        match built {
+            Ok(()) => Ok((pipelines, layout)),
+            Err(err) => {
+                unsafe {
+                    for pipeline in pipelines {
+                        device.destroy_pipeline(pipeline, None);
+                    }
+                    device.destroy_pipeline_layout(layout, None);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Create the pipeline (and its layout) for `rendering`, with shader modules from `shader_cache`.
+    /// Viewport and scissor are dynamic, so the pipeline survives swapchain recreation.
+    pub fn build(
+        &self,
+        device: &Device,
+        shader_cache: &ShaderCache,
+        rendering: Rendering,
+    ) -> Result<(vk::Pipeline, vk::PipelineLayout), Box<dyn Error>> {
+        let layout = self.layout(device)?;
+        match self.build_with_layout(device, shader_cache, rendering, layout) {
+            Ok(pipeline) => Ok((pipeline, layout)),
+            Err(err) => {
+                unsafe { device.destroy_pipeline_layout(layout, None) };
+                Err(err)
+            }
+        }
+    }
+
+    /// Create the pipeline layout for the descriptor set layouts and push constants.
+    fn layout(&self, device: &Device) -> Result<vk::PipelineLayout, Box<dyn Error>> {
+        let layout_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(self.descriptor_set_layouts)
+            .push_constant_ranges(self.push_constant_ranges);
+        Ok(unsafe { device.create_pipeline_layout(&layout_info, None)? })
+    }
+
+    /// `build`, with `layout`, which is left to the caller.
+    fn build_with_layout(
+        &self,
+        device: &Device,
+        shader_cache: &ShaderCache,
+        rendering: Rendering,
+        layout: vk::PipelineLayout,
+    ) -> Result<vk::Pipeline, Box<dyn Error>> {
+        if !self.missing_features.is_empty() {
+            return Err(Box::new(AppError::new(&format!(
+                "The pipeline needs {}, which isn't enabled",
+                self.missing_features.join(", ")
+            ))));
+        }
+
+        // Owned by the cache, which also has the pipeline cache.
+        let vertex_shader_module = shader_cache.module(device, self.vertex_shader)?;
+        let fragment_shader_module = shader_cache.module(device, self.fragment_shader)?;
+        let tessellation_shader_modules = match self.tessellation {
+            Some((control, evaluation, _)) => Some((
+                shader_cache.module(device, control)?,
+                shader_cache.module(device, evaluation)?,
+            )),
+            None => None,
+        };
+        let geometry_shader_module = match self.geometry_shader {
+            Some(name) => Some(shader_cache.module(device, name)?),
+            None => None,
+        };
+        let pipeline_cache = shader_cache.pipeline_cache();
+
+        let entry_point_name = c"main";
+        let mut shader_stage_infos = vec![
+            vk::PipelineShaderStageCreateInfo::default()
+                .stage(vk::ShaderStageFlags::VERTEX)
+                .module(vertex_shader_module)
+                .name(entry_point_name),
+            vk::PipelineShaderStageCreateInfo::default()
+                .stage(vk::ShaderStageFlags::FRAGMENT)
+                .module(fragment_shader_module)
+                .name(entry_point_name),
+        ];
+        if let Some((control_module, evaluation_module)) = tessellation_shader_modules {
+            shader_stage_infos.extend([
+                vk::PipelineShaderStageCreateInfo::default()
+                    .stage(vk::ShaderStageFlags::TESSELLATION_CONTROL)
+                    .module(control_module)
+                    .name(entry_point_name),
+                vk::PipelineShaderStageCreateInfo::default()
+                    .stage(vk::ShaderStageFlags::TESSELLATION_EVALUATION)
+                    .module(evaluation_module)
+                    .name(entry_point_name),
+            ]);
+        }
+        if let Some(geometry_module) = geometry_shader_module {
+            shader_stage_infos.push(
+                vk::PipelineShaderStageCreateInfo::default()
+                    .stage(vk::ShaderStageFlags::GEOMETRY)
+                    .module(geometry_module)
+                    .name(entry_point_name),
+            );
+        }
+
+        let vertex_input_info = vk::PipelineVertexInputStateCreateInfo::default()
+            .vertex_binding_descriptions(&self.vertex_bindings)
+            .vertex_attribute_descriptions(&self.vertex_attributes);
+
+        let topology = match self.tessellation {
+            Some(_) => vk::PrimitiveTopology::PATCH_LIST,
+            None => vk::PrimitiveTopology::TRIANGLE_LIST,
+        };
+        let input_assembly_info = vk::PipelineInputAssemblyStateCreateInfo::default()
+            .topology(topology)
+            .primitive_restart_enable(false);
+
+        let tessellation_info = vk::PipelineTessellationStateCreateInfo::default()
+            .patch_control_points(
+                self.tessellation
+                    .map_or(0, |(_, _, control_points)| control_points),
+            );
+
+        // Set with cmd_set_viewport/cmd_set_scissor while recording.
+        let viewport_info = vk::PipelineViewportStateCreateInfo::default()
+            .viewport_count(1)
+            .scissor_count(1);
+
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_state_info =
+            vk::PipelineDynamicStateCreateInfo::default().dynamic_states(&dynamic_states);
+
+        let mut conservative_info =
+            vk::PipelineRasterizationConservativeStateCreateInfoEXT::default()
+                .conservative_rasterization_mode(
+                    self.conservative_rasterization
+                        .unwrap_or(vk::ConservativeRasterizationModeEXT::DISABLED),
+                )
+                .extra_primitive_overestimation_size(0.0);
+        let mut rasterizer_info = vk::PipelineRasterizationStateCreateInfo::default()
+            .depth_clamp_enable(self.depth_clamp)
+            .rasterizer_discard_enable(false)
+            .polygon_mode(self.polygon_mode)
+            .line_width(1.0)
+            .cull_mode(self.cull_mode)
+            .front_face(vk::FrontFace::CLOCKWISE)
+            .depth_bias_enable(false);
+        if self.conservative_rasterization.is_some() {
+            rasterizer_info = rasterizer_info.push_next(&mut conservative_info);
+        }
+
+        let multisampling_info = vk::PipelineMultisampleStateCreateInfo::default()
+            .sample_shading_enable(false)
+            .rasterization_samples(self.samples)
+            .min_sample_shading(1.0)
+            .alpha_to_coverage_enable(false)
+            .alpha_to_one_enable(false);
+
+        let mut depth_stencil_info = vk::PipelineDepthStencilStateCreateInfo::default()
+            .depth_test_enable(self.depth_test)
+            .depth_write_enable(self.depth_test && self.depth_write)
+            .depth_compare_op(self.depth_compare_op)
+            .depth_bounds_test_enable(false)
+            .stencil_test_enable(false);
+        if let Some((min, max)) = self.depth_bounds {
+            depth_stencil_info = depth_stencil_info
+                .depth_bounds_test_enable(true)
+                .min_depth_bounds(min)
+                .max_depth_bounds(max);
+        }
+        if let Some((front, back)) = self.stencil {
+            depth_stencil_info = depth_stencil_info
+                .stencil_test_enable(true)
+                .front(front)
+                .back(back);
+        }
+
+        let color_blend_attachments = vec![
+            vk::PipelineColorBlendAttachmentState::default()
+                .color_write_mask(vk::ColorComponentFlags::RGBA)
+                .blend_enable(false);
+            self.color_attachments as usize
+        ];
+
+        let color_blending_info = vk::PipelineColorBlendStateCreateInfo::default()
+            .logic_op_enable(false)
+            .logic_op(vk::LogicOp::COPY)
+            .attachments(&color_blend_attachments)
+            .blend_constants([0.0, 0.0, 0.0, 0.0]);
+
+        let (render_pass, dynamic_formats) = match rendering {
+            Rendering::RenderPass(render_pass) => (render_pass, None),
+            Rendering::Dynamic { .. } if self.color_attachments > 1 => {
+                return Err(Box::new(AppError::new(
+                    "Dynamic rendering has a single color attachment, use a render pass for more",
+                )));
+            }
+            Rendering::Dynamic {
+                color_format,
+                depth_format,
+                ..
+            } => (vk::RenderPass::null(), Some(([color_format], depth_format))),
+        };
+        let mut rendering_info = vk::PipelineRenderingCreateInfo::default();
+        if let Some((color_formats, depth_format)) = &dynamic_formats {
+            rendering_info = rendering_info
+                .color_attachment_formats(&color_formats[..self.color_attachments as usize])
+                .depth_attachment_format(*depth_format)
+                .stencil_attachment_format(dynamic_rendering::stencil_format(*depth_format));
+        }
+
+        let mut pipeline_info = vk::GraphicsPipelineCreateInfo::default()
+            .stages(&shader_stage_infos)
+            .vertex_input_state(&vertex_input_info)
+            .input_assembly_state(&input_assembly_info)
+            .viewport_state(&viewport_info)
+            .rasterization_state(&rasterizer_info)
+            .multisample_state(&multisampling_info)
+            .depth_stencil_state(&depth_stencil_info)
+            .color_blend_state(&color_blending_info)
+            .dynamic_state(&dynamic_state_info)
+            .layout(layout)
+            .render_pass(render_pass)
+            .subpass(self.subpass);
+        let mut flags = vk::PipelineCreateFlags::empty();
+        if self.allow_derivatives {
+            flags |= vk::PipelineCreateFlags::ALLOW_DERIVATIVES;
+        }
+        if let Some(base_pipeline) = self.base_pipeline {
+            flags |= vk::PipelineCreateFlags::DERIVATIVE;
+            pipeline_info = pipeline_info
+                .base_pipeline_handle(base_pipeline)
+                .base_pipeline_index(-1);
+        }
+        pipeline_info = pipeline_info.flags(flags);
+        if self.tessellation.is_some() {
+            pipeline_info = pipeline_info.tessellation_state(&tessellation_info);
+        }
+        if dynamic_formats.is_some() {
+            pipeline_info = pipeline_info.push_next(&mut rendering_info);
+        }
+        let pipeline_infos = [pipeline_info];
+
+        let pipelines = unsafe {
+            device
+                .create_graphics_pipelines(pipeline_cache, &pipeline_infos, None)
+                .map_err(|(_, err)| err)?
+        };
+        Ok(pipelines[0])
+    }
+}
+
+/// Stencil state that always passes and writes `reference` wherever a fragment is drawn,
+/// e.g. to mark an object's silhouette for an outline pass that tests against it later.
+pub fn stencil_write(reference: u32) -> vk::StencilOpState {
+    vk::StencilOpState::default()
+        .fail_op(vk::StencilOp::KEEP)
+        .pass_op(vk::StencilOp::REPLACE)
+        .depth_fail_op(vk::StencilOp::KEEP)
+        .compare_op(vk::CompareOp::ALWAYS)
+        .compare_mask(0xff)
+        .write_mask(0xff)
+        .reference(reference)
+}
@@ -0,0 +1,845 @@
+use ash::{vk, Device};
+#[cfg(feature = "hot-reload")]
+use std::collections::HashSet;
+use std::error::Error;
+
+use crate::command::{self, Draw, DrawStatistics};
+use crate::descriptor::DescriptorManager;
+use crate::image::{self, AllocatedImage};
+use crate::memory::Allocator;
+use crate::ownership::{QueueTransfer, TransferBarriers};
+use crate::reflect::ShaderInterface;
+use crate::render_pass::{RenderPassBuilder, Subpass};
+#[cfg(feature = "ui")]
+use crate::render_target::Rendering;
+use crate::shader_cache::ShaderCache;
+use crate::subsystem::Subsystem;
+#[cfg(feature = "hot-reload")]
+use crate::subsystem::{self, PipelineContext};
+use crate::util::AppError;
+use crate::vulkan_create;
+
+//////////////// Compute Post Processing ////////////////
+// After the render pass, a compute shader reads the finished swapchain image and writes the processed
+// frame into a storage image of its own. That's blitted back into the swapchain image before presenting.
+// Swapchain images usually can't be storage images (and SRGB ones never can), hence the detour.
+// The frame is read with texelFetch, so its sampler is never used for filtering.
+// Needs SAMPLED and TRANSFER_DST swapchain images, see util::device_supports_compute_post_process.
+// With a dedicated compute family (see `AsyncCompute`) the dispatch runs on its queue, and a frame is
+// three submissions chained by semaphores: the graphics queue renders, the compute queue processes, and
+// the graphics queue blits (a blit needs a graphics queue). The swapchain images are then CONCURRENT
+// between the families (see vulkan_create::swapchain_and_images), the targets are handed over.
+// What shouldn't be processed (e.g. the software cursor) is drawn after the blit, in a render pass of
+// its own over the swapchain image (see `final_pass`), whether the main pass is a render pass or not.
+
+/// The shader the pipeline is built from.
+pub const SHADERS: [&str; 1] = ["post.comp.spv"];
+
+/// The workgroup size of shaders/post.comp, in both dimensions.
+const WORKGROUP_SIZE: u32 = 8;
+
+/// Format of the images the shader writes into. Can hold any swapchain format's colors.
+const TARGET_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+/// The compute pipeline, and a target image and descriptor set for every swapchain image.
+pub struct ComputePostProcess {
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    // Owned by the DescriptorManager, kept to allocate sets for more swapchain images and rebuild the pipeline.
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    sampler: vk::Sampler,
+    // One per swapchain image. There may be more sets than images after the swapchain shrank.
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    targets: Vec<AllocatedImage>,
+    extent: vk::Extent2D,
+    async_compute: Option<AsyncCompute>,
+    // Draws into the processed swapchain image, see `final_pass`. One framebuffer per swapchain image.
+    final_pass: vk::RenderPass,
+    framebuffers: Vec<vk::Framebuffer>,
+}
+
+/// A dedicated compute queue the dispatch runs on, with what each frame in flight submits to it.
+pub struct AsyncCompute {
+    queue: vk::Queue,
+    // Hands the targets over to the graphics family for the blit.
+    transfer: QueueTransfer,
+    command_pool: vk::CommandPool,
+    // Per frame in flight: the dispatch, and the blit after it (from `graphics_command_pool`).
+    command_buffers: Vec<vk::CommandBuffer>,
+    graphics_command_pool: vk::CommandPool,
+    blit_command_buffers: Vec<vk::CommandBuffer>,
+    // Per frame in flight: signaled once the frame is rendered, and once it's processed.
+    rendered: Vec<vk::Semaphore>,
+    processed: Vec<vk::Semaphore>,
+}
+
+/// A frame's submissions with `AsyncCompute`: after the frame's command buffer (see
+/// `ComputePostProcess::record_handoff`) signals `rendered`, `dispatch` waits for it on `queue` and
+/// signals `processed`, which `blit` waits for on the graphics queue.
+pub struct AsyncFrame {
+    pub queue: vk::Queue,
+    pub rendered: vk::Semaphore,
+    pub dispatch: vk::CommandBuffer,
+    pub processed: vk::Semaphore,
+    pub blit: vk::CommandBuffer,
+    /// Of the draws recorded into `blit`.
+    pub draw_statistics: DrawStatistics,
+}
+
+impl AsyncCompute {
+    /// For `frames_in_flight` frames, dispatching on `queue` of `compute_family_index`. The blits are
+    /// recorded into command buffers of `graphics_command_pool`, of `graphics_family_index`.
+    pub fn new(
+        device: &Device,
+        compute_family_index: u32,
+        queue: vk::Queue,
+        graphics_family_index: u32,
+        graphics_command_pool: vk::CommandPool,
+        frames_in_flight: usize,
+    ) -> Result<Self, Box<dyn Error>> {
+        let transfer = QueueTransfer::new(compute_family_index, graphics_family_index)
+            .ok_or_else(|| AppError::new("The compute family has to be a dedicated one"))?;
+        let mut async_compute = Self {
+            queue,
+            transfer,
+            command_pool: command::command_pool(device, compute_family_index)?,
+            command_buffers: Vec::new(),
+            graphics_command_pool,
+            blit_command_buffers: Vec::new(),
+            rendered: Vec::new(),
+            processed: Vec::new(),
+        };
+        let created = (|| -> Result<(), Box<dyn Error>> {
+            let count = frames_in_flight as u32;
+            async_compute.command_buffers =
+                command::command_buffers(device, async_compute.command_pool, count)?;
+            async_compute.blit_command_buffers =
+                command::command_buffers(device, graphics_command_pool, count)?;
+            for _ in 0..frames_in_flight {
+                async_compute
+                    .rendered
+                    .push(vulkan_create::semaphore(device)?);
+                async_compute
+                    .processed
+                    .push(vulkan_create::semaphore(device)?);
+            }
+            Ok(())
+        })();
+        match created {
+            Ok(()) => Ok(async_compute),
+            Err(err) => {
+                async_compute.destroy(device);
+                Err(err)
+            }
+        }
+    }
+
+    /// Destroy the command pool and semaphores, and free the blit command buffers.
+    /// The GPU must be done with them.
+    pub fn destroy(&mut self, device: &Device) {
+        unsafe {
+            if !self.blit_command_buffers.is_empty() {
+                device.free_command_buffers(self.graphics_command_pool, &self.blit_command_buffers);
+            }
+            // Freeing the pool frees its command buffers.
+            device.destroy_command_pool(self.command_pool, None);
+            self.rendered
+                .drain(..)
+                .chain(self.processed.drain(..))
+                .for_each(|semaphore| device.destroy_semaphore(semaphore, None));
+        }
+        self.blit_command_buffers.clear();
+        self.command_buffers.clear();
+    }
+}
+
+impl ComputePostProcess {
+    /// Build the pipeline, and targets and descriptor sets for the swapchain images `views` (in the
+    /// swapchain's own `format`) of size `extent`. The set's layout lives in `descriptors`, the shader
+    /// module in `shader_cache`. With `async_compute` it's dispatched on the compute queue, see
+    /// `record_async`. It's destroyed along.
+    pub fn new(
+        device: &Device,
+        shader_cache: &ShaderCache,
+        allocator: &Allocator,
+        descriptors: &mut DescriptorManager,
+        (views, format): (&[vk::ImageView], vk::Format),
+        extent: vk::Extent2D,
+        async_compute: Option<AsyncCompute>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut async_compute = async_compute;
+        let pipeline =
+            ShaderInterface::from_shaders(shader_cache, &SHADERS).and_then(|interface| {
+                let layout = descriptors.create_layout(device, &interface.set_bindings(0))?;
+                Ok((
+                    layout,
+                    build_pipeline(device, shader_cache, &interface, layout)?,
+                ))
+            });
+        let (descriptor_set_layout, (pipeline, pipeline_layout)) = match pipeline {
+            Ok(pipeline) => pipeline,
+            Err(err) => {
+                if let Some(async_compute) = &mut async_compute {
+                    async_compute.destroy(device);
+                }
+                return Err(err);
+            }
+        };
+
+        let sampler_create_info = vk::SamplerCreateInfo::default()
+            .mag_filter(vk::Filter::NEAREST)
+            .min_filter(vk::Filter::NEAREST)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE);
+        let sampler = match unsafe { device.create_sampler(&sampler_create_info, None) } {
+            Ok(sampler) => sampler,
+            Err(err) => {
+                unsafe {
+                    device.destroy_pipeline(pipeline, None);
+                    device.destroy_pipeline_layout(pipeline_layout, None);
+                }
+                if let Some(async_compute) = &mut async_compute {
+                    async_compute.destroy(device);
+                }
+                return Err(Box::new(err));
+            }
+        };
+
+        let mut post_process = Self {
+            pipeline,
+            pipeline_layout,
+            descriptor_set_layout,
+            sampler,
+            descriptor_sets: Vec::new(),
+            targets: Vec::new(),
+            extent,
+            async_compute,
+            final_pass: vk::RenderPass::null(),
+            framebuffers: Vec::new(),
+        };
+        let created = build_final_pass(device, format).and_then(|final_pass| {
+            post_process.final_pass = final_pass;
+            post_process.resize(device, allocator, descriptors, views, extent)
+        });
+        if let Err(err) = created {
+            post_process.destroy(device);
+            return Err(err);
+        }
+        Ok(post_process)
+    }
+
+    /// Recreate the targets for new swapchain images `views` of size `extent`, e.g. after a resize.
+    /// The GPU must be done with the previous ones.
+    pub fn resize(
+        &mut self,
+        device: &Device,
+        allocator: &Allocator,
+        descriptors: &mut DescriptorManager,
+        views: &[vk::ImageView],
+        extent: vk::Extent2D,
+    ) -> Result<(), Box<dyn Error>> {
+        self.destroy_targets(device);
+        self.extent = extent;
+
+        if views.len() > self.descriptor_sets.len() {
+            let missing = views.len() - self.descriptor_sets.len();
+            let sets = descriptors.allocate(device, self.descriptor_set_layout, missing)?;
+            self.descriptor_sets.extend(sets);
+        }
+
+        for (view, set) in views.iter().zip(self.descriptor_sets.iter()) {
+            let (target_image, target_memory) = image::image(
+                device,
+                allocator,
+                extent,
+                TARGET_FORMAT,
+                1,
+                vk::SampleCountFlags::TYPE_1,
+                vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_SRC,
+            )?;
+            let mut target = AllocatedImage {
+                image: target_image,
+                memory: target_memory,
+                view: vk::ImageView::null(),
+                format: TARGET_FORMAT,
+                allocator: allocator.clone(),
+            };
+            match image::image_view(
+                device,
+                target_image,
+                TARGET_FORMAT,
+                vk::ImageAspectFlags::COLOR,
+                1,
+            ) {
+                Ok(target_view) => target.view = target_view,
+                Err(err) => {
+                    target.destroy(device);
+                    return Err(err);
+                }
+            }
+
+            self.write_descriptor_set(device, *set, *view, target.view);
+            self.targets.push(target);
+
+            let attachments = [*view];
+            let framebuffer_create_info = vk::FramebufferCreateInfo::default()
+                .render_pass(self.final_pass)
+                .attachments(&attachments)
+                .width(extent.width)
+                .height(extent.height)
+                .layers(1);
+            let framebuffer = unsafe { device.create_framebuffer(&framebuffer_create_info, None)? };
+            self.framebuffers.push(framebuffer);
+        }
+        Ok(())
+    }
+
+    /// Rebuild the pipeline from the current `SHADERS`, e.g. after they changed on disk.
+    /// Their descriptor set has to stay the same.
+    /// Keeps the previous pipeline if building fails. The GPU must be done with it.
+    #[cfg(feature = "hot-reload")]
+    pub fn rebuild_pipeline(
+        &mut self,
+        device: &Device,
+        shader_cache: &ShaderCache,
+    ) -> Result<(), Box<dyn Error>> {
+        let interface = ShaderInterface::from_shaders(shader_cache, &SHADERS)?;
+        let (pipeline, pipeline_layout) =
+            build_pipeline(device, shader_cache, &interface, self.descriptor_set_layout)?;
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        self.pipeline = pipeline;
+        self.pipeline_layout = pipeline_layout;
+        Ok(())
+    }
+
+    /// Whether the dispatch runs on a dedicated compute queue, see `record_async`.
+    pub fn is_async(&self) -> bool {
+        self.async_compute.is_some()
+    }
+
+    /// What pipelines drawing on top of the processed frame are built for, with a single sample and
+    /// no depth buffer. Their draws are passed to `record` or `record_async`.
+    // Only the UI draws there.
+    #[cfg(feature = "ui")]
+    pub fn final_pass(&self) -> Rendering {
+        Rendering::RenderPass(self.final_pass)
+    }
+
+    /// Record processing the swapchain image `swapchain_image` (number `image_index`) into `command_buffer`,
+    /// after the pass that rendered it left it in PRESENT_SRC_KHR, then `draws` on top of it (see
+    /// `final_pass`). It is left in PRESENT_SRC_KHR again. Without `AsyncCompute` only.
+    pub fn record(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+        swapchain_image: vk::Image,
+        draws: &[Draw],
+    ) -> DrawStatistics {
+        let target = self.targets[image_index].image;
+
+        // The frame becomes readable once rendered, the target's old contents don't matter.
+        let barriers = [
+            barrier(
+                swapchain_image,
+                (
+                    vk::ImageLayout::PRESENT_SRC_KHR,
+                    vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                ),
+                (
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    vk::AccessFlags::SHADER_READ,
+                ),
+            ),
+            barrier(
+                target,
+                (vk::ImageLayout::UNDEFINED, vk::AccessFlags::empty()),
+                (vk::ImageLayout::GENERAL, vk::AccessFlags::SHADER_WRITE),
+            ),
+        ];
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &barriers,
+            );
+        }
+        self.record_dispatch(device, command_buffer, image_index);
+
+        // The swapchain image is overwritten by the blit, after the shader is done reading it.
+        let target_barrier = barrier(
+            target,
+            (vk::ImageLayout::GENERAL, vk::AccessFlags::SHADER_WRITE),
+            (
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::AccessFlags::TRANSFER_READ,
+            ),
+        );
+        self.record_blit(
+            device,
+            command_buffer,
+            (vk::PipelineStageFlags::COMPUTE_SHADER, Some(target_barrier)),
+            (image_index, swapchain_image),
+            draws,
+        )
+    }
+
+    /// With `AsyncCompute`: record into the frame's `command_buffer`, after the pass that rendered the
+    /// swapchain image `swapchain_image`, making it readable by the compute queue. Its submission has to
+    /// signal the frame's `AsyncFrame::rendered`.
+    pub fn record_handoff(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        swapchain_image: vk::Image,
+    ) {
+        // The swapchain image is CONCURRENT, the semaphore makes the pass's writes visible to the compute queue.
+        let barriers = [barrier(
+            swapchain_image,
+            (
+                vk::ImageLayout::PRESENT_SRC_KHR,
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            ),
+            (
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::AccessFlags::empty(),
+            ),
+        )];
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &barriers,
+            );
+        }
+    }
+
+    /// With `AsyncCompute`: record the dispatch processing the swapchain image `swapchain_image`
+    /// (number `image_index`, see `record_handoff`) for frame in flight `frame_index`, and the blit back
+    /// into it followed by `draws` (see `final_pass`). Returns what to submit, see `AsyncFrame`.
+    /// The GPU must be done with the frame's last ones.
+    pub fn record_async(
+        &self,
+        device: &Device,
+        frame_index: usize,
+        (image_index, swapchain_image): (usize, vk::Image),
+        draws: &[Draw],
+    ) -> Result<Option<AsyncFrame>, Box<dyn Error>> {
+        let Some(async_compute) = &self.async_compute else {
+            return Ok(None);
+        };
+        let target = self.targets[image_index].image;
+        let dispatch = async_compute.command_buffers[frame_index];
+        let blit = async_compute.blit_command_buffers[frame_index];
+
+        // After the semaphore wait (at COMPUTE_SHADER), which orders it after the previous blit from it.
+        let target_barriers = [barrier(
+            target,
+            (vk::ImageLayout::UNDEFINED, vk::AccessFlags::empty()),
+            (vk::ImageLayout::GENERAL, vk::AccessFlags::SHADER_WRITE),
+        )];
+        let transfer = &async_compute.transfer;
+        transfer.track_image(transfer.src, &target_barriers[0]);
+        let (release, acquire) = transfer.image(
+            target,
+            color_range(),
+            (
+                vk::ImageLayout::GENERAL,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            ),
+            vk::AccessFlags::SHADER_WRITE,
+            vk::AccessFlags::TRANSFER_READ,
+        );
+
+        command::begin_recording(device, dispatch)?;
+        unsafe {
+            device.cmd_pipeline_barrier(
+                dispatch,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &target_barriers,
+            );
+        }
+        self.record_dispatch(device, dispatch, image_index);
+        transfer.record_release(
+            device,
+            dispatch,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            &TransferBarriers {
+                images: vec![release],
+                buffers: Vec::new(),
+            },
+        );
+        unsafe { device.end_command_buffer(dispatch)? };
+
+        command::begin_recording(device, blit)?;
+        transfer.record_acquire(
+            device,
+            blit,
+            vk::PipelineStageFlags::TRANSFER,
+            &TransferBarriers {
+                images: vec![acquire],
+                buffers: Vec::new(),
+            },
+        );
+        let draw_statistics = self.record_blit(
+            device,
+            blit,
+            (vk::PipelineStageFlags::TRANSFER, None),
+            (image_index, swapchain_image),
+            draws,
+        );
+        unsafe { device.end_command_buffer(blit)? };
+
+        Ok(Some(AsyncFrame {
+            queue: async_compute.queue,
+            rendered: async_compute.rendered[frame_index],
+            dispatch,
+            processed: async_compute.processed[frame_index],
+            blit,
+            draw_statistics,
+        }))
+    }
+
+    /// Bind the pipeline and dispatch it over swapchain image `image_index`'s target, which is in GENERAL.
+    fn record_dispatch(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+    ) {
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                0,
+                &[self.descriptor_sets[image_index]],
+                &[],
+            );
+            device.cmd_dispatch(
+                command_buffer,
+                self.extent.width.div_ceil(WORKGROUP_SIZE),
+                self.extent.height.div_ceil(WORKGROUP_SIZE),
+                1,
+            );
+        }
+    }
+
+    /// Blit swapchain image `image_index`'s target into `swapchain_image` (in SHADER_READ_ONLY_OPTIMAL),
+    /// and record `draws` on top of it in the final pass. It's left in PRESENT_SRC_KHR. `target_barrier`
+    /// moves the target to TRANSFER_SRC_OPTIMAL, after `src_stage`, None if it's there already.
+    fn record_blit(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        (src_stage, target_barrier): (vk::PipelineStageFlags, Option<vk::ImageMemoryBarrier>),
+        (image_index, swapchain_image): (usize, vk::Image),
+        draws: &[Draw],
+    ) -> DrawStatistics {
+        let target = self.targets[image_index].image;
+        let swapchain_barrier = barrier(
+            swapchain_image,
+            (
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::AccessFlags::empty(),
+            ),
+            (
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::AccessFlags::TRANSFER_WRITE,
+            ),
+        );
+        let barriers: Vec<_> = target_barrier
+            .into_iter()
+            .chain([swapchain_barrier])
+            .collect();
+        let corner = vk::Offset3D {
+            x: self.extent.width as i32,
+            y: self.extent.height as i32,
+            z: 1,
+        };
+        let subresource = vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let regions = [vk::ImageBlit {
+            src_subresource: subresource,
+            src_offsets: [vk::Offset3D::default(), corner],
+            dst_subresource: subresource,
+            dst_offsets: [vk::Offset3D::default(), corner],
+        }];
+        // Presentation waits on the render finished semaphore, which covers everything else.
+        // With draws, the final pass leaves it in PRESENT_SRC_KHR.
+        let (next_layout, next_access, next_stage) = if draws.is_empty() {
+            (
+                vk::ImageLayout::PRESENT_SRC_KHR,
+                vk::AccessFlags::empty(),
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            )
+        } else {
+            (
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            )
+        };
+        let present_barriers = [barrier(
+            swapchain_image,
+            (
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::AccessFlags::TRANSFER_WRITE,
+            ),
+            (next_layout, next_access),
+        )];
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                src_stage,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &barriers,
+            );
+            // Same size, so NEAREST only converts the format.
+            device.cmd_blit_image(
+                command_buffer,
+                target,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                swapchain_image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &regions,
+                vk::Filter::NEAREST,
+            );
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                next_stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &present_barriers,
+            );
+        }
+        if draws.is_empty() {
+            return DrawStatistics::default();
+        }
+
+        let render_pass_begin_info = vk::RenderPassBeginInfo::default()
+            .render_pass(self.final_pass)
+            .framebuffer(self.framebuffers[image_index])
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: self.extent,
+            });
+        command::record_render_pass(
+            device,
+            command_buffer,
+            command::PassBegin::RenderPass(&render_pass_begin_info),
+            draws,
+        )
+    }
+
+    /// Destroy the pipeline, sampler and targets. The descriptor sets are freed with the DescriptorManager.
+    /// The GPU must be done with them.
+    pub fn destroy(&mut self, device: &Device) {
+        self.destroy_targets(device);
+        if let Some(async_compute) = &mut self.async_compute {
+            async_compute.destroy(device);
+        }
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            device.destroy_sampler(self.sampler, None);
+            device.destroy_render_pass(self.final_pass, None);
+        }
+    }
+
+    fn destroy_targets(&mut self, device: &Device) {
+        self.targets
+            .drain(..)
+            .for_each(|mut target| target.destroy(device));
+        self.framebuffers
+            .drain(..)
+            .for_each(|framebuffer| unsafe { device.destroy_framebuffer(framebuffer, None) });
+    }
+
+    /// Point `set` at the swapchain image `view`, the sampler and `target_view`, as shaders/post.comp
+    /// declares them.
+    fn write_descriptor_set(
+        &self,
+        device: &Device,
+        set: vk::DescriptorSet,
+        view: vk::ImageView,
+        target_view: vk::ImageView,
+    ) {
+        let frame_infos = [vk::DescriptorImageInfo::default()
+            .image_view(view)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+        let sampler_infos = [vk::DescriptorImageInfo::default().sampler(self.sampler)];
+        let target_infos = [vk::DescriptorImageInfo::default()
+            .image_view(target_view)
+            .image_layout(vk::ImageLayout::GENERAL)];
+        let writes = [
+            vk::WriteDescriptorSet::default()
+                .dst_set(set)
+                .dst_binding(0)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .image_info(&frame_infos),
+            vk::WriteDescriptorSet::default()
+                .dst_set(set)
+                .dst_binding(1)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::SAMPLER)
+                .image_info(&sampler_infos),
+            vk::WriteDescriptorSet::default()
+                .dst_set(set)
+                .dst_binding(2)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                .image_info(&target_infos),
+        ];
+
+        unsafe { device.update_descriptor_sets(&writes, &[]) };
+    }
+}
+
+impl Subsystem for ComputePostProcess {
+    fn describe(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("compute post process", true.to_string()),
+            ("async compute", self.is_async().to_string()),
+        ]
+    }
+
+    #[cfg(feature = "hot-reload")]
+    fn shaders(&self) -> Vec<&'static str> {
+        SHADERS.to_vec()
+    }
+
+    #[cfg(feature = "hot-reload")]
+    fn rebuild_pipelines(&mut self, context: &PipelineContext, changed: &HashSet<String>) {
+        if subsystem::uses_any(&SHADERS, changed) {
+            let result = self.rebuild_pipeline(context.device, context.shader_cache);
+            subsystem::log_rebuild("post process", result);
+        }
+    }
+
+    fn destroy(&mut self, device: &Device) {
+        ComputePostProcess::destroy(self, device);
+    }
+}
+
+fn build_pipeline(
+    device: &Device,
+    shader_cache: &ShaderCache,
+    interface: &ShaderInterface,
+    layout: vk::DescriptorSetLayout,
+) -> Result<(vk::Pipeline, vk::PipelineLayout), Box<dyn Error>> {
+    let push_constant_ranges = interface.push_constant_ranges();
+    let set_layouts = [layout];
+    let pipeline_layout_create_info = vk::PipelineLayoutCreateInfo::default()
+        .set_layouts(&set_layouts)
+        .push_constant_ranges(&push_constant_ranges);
+    let pipeline_layout =
+        unsafe { device.create_pipeline_layout(&pipeline_layout_create_info, None)? };
+
+    // Owned by the cache.
+    let pipeline = shader_cache.module(device, SHADERS[0]).and_then(|module| {
+        let stage = vk::PipelineShaderStageCreateInfo::default()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(module)
+            .name(c"main");
+        let pipeline_create_info = [vk::ComputePipelineCreateInfo::default()
+            .stage(stage)
+            .layout(pipeline_layout)];
+        unsafe {
+            device.create_compute_pipelines(
+                shader_cache.pipeline_cache(),
+                &pipeline_create_info,
+                None,
+            )
+        }
+        .map(|pipelines| pipelines[0])
+        .map_err(|(_, err)| Box::new(err) as Box<dyn Error>)
+    });
+
+    match pipeline {
+        Ok(pipeline) => Ok((pipeline, pipeline_layout)),
+        Err(err) => {
+            unsafe { device.destroy_pipeline_layout(pipeline_layout, None) };
+            Err(err)
+        }
+    }
+}
+
+/// The render pass drawing over a processed swapchain image of `format`, see `final_pass`. The image
+/// comes in COLOR_ATTACHMENT_OPTIMAL after the blit (see `record_blit`), and is kept for presentation.
+fn build_final_pass(device: &Device, format: vk::Format) -> Result<vk::RenderPass, Box<dyn Error>> {
+    RenderPassBuilder::default()
+        .attachment(
+            vk::AttachmentDescription::default()
+                .format(format)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .load_op(vk::AttachmentLoadOp::LOAD)
+                .store_op(vk::AttachmentStoreOp::STORE)
+                .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .initial_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .final_layout(vk::ImageLayout::PRESENT_SRC_KHR),
+        )
+        .subpass(Subpass::default().color(&[0]))
+        .build(device)
+}
+
+/// A barrier moving the color aspect of `image` from `old` to `new` (layout and access each).
+fn barrier(
+    image: vk::Image,
+    (old_layout, src_access_mask): (vk::ImageLayout, vk::AccessFlags),
+    (new_layout, dst_access_mask): (vk::ImageLayout, vk::AccessFlags),
+) -> vk::ImageMemoryBarrier<'static> {
+    vk::ImageMemoryBarrier::default()
+        .old_layout(old_layout)
+        .new_layout(new_layout)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(color_range())
+        .src_access_mask(src_access_mask)
+        .dst_access_mask(dst_access_mask)
+}
+
+/// The color aspect of an image's only mip level and layer.
+fn color_range() -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        base_mip_level: 0,
+        level_count: 1,
+        base_array_layer: 0,
+        layer_count: 1,
+    }
+}
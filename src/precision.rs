@@ -0,0 +1,103 @@
+use ash::vk;
+use half::f16;
+
+//////////////// Reduced Precision ////////////////
+// Colors, normals, UVs and the like rarely need 32-bit floats, and halves take half the bandwidth.
+// Vertex attributes can always be stored as halves, vertex fetch widens them to floats, so the shaders
+// still declare float, vec2 etc. (see the textured quad).
+// Uniform and storage buffers could only hold halves with the 16-bit storage features
+// (DeviceDetails::storage_16bit), nothing packs data for them yet.
+
+/// How floats are stored in a buffer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Precision {
+    Half,
+    Single,
+}
+
+impl Precision {
+    /// Bytes per float.
+    pub fn size(self) -> usize {
+        match self {
+            Precision::Half => 2,
+            Precision::Single => 4,
+        }
+    }
+
+    /// Append `values` to `bytes` at this precision, in native byte order.
+    pub fn pack(self, values: &[f32], bytes: &mut Vec<u8>) {
+        bytes.reserve(values.len() * self.size());
+        for value in values {
+            match self {
+                Precision::Half => bytes.extend(f16::from_f32(*value).to_ne_bytes()),
+                Precision::Single => bytes.extend(value.to_ne_bytes()),
+            }
+        }
+    }
+
+    /// The vertex attribute format for `components` (1 to 4) floats at this precision,
+    /// and how many components are stored. Not every device can fetch three halves,
+    /// so those are padded to four.
+    pub fn vertex_format(self, components: usize) -> Option<(vk::Format, usize)> {
+        let format = match (self, components) {
+            (Precision::Half, 1) => vk::Format::R16_SFLOAT,
+            (Precision::Half, 2) => vk::Format::R16G16_SFLOAT,
+            (Precision::Half, 3 | 4) => vk::Format::R16G16B16A16_SFLOAT,
+            (Precision::Single, 1) => vk::Format::R32_SFLOAT,
+            (Precision::Single, 2) => vk::Format::R32G32_SFLOAT,
+            (Precision::Single, 3) => vk::Format::R32G32B32_SFLOAT,
+            (Precision::Single, 4) => vk::Format::R32G32B32A32_SFLOAT,
+            _ => return None,
+        };
+        let stored = match format {
+            vk::Format::R16G16B16A16_SFLOAT => 4,
+            _ => components,
+        };
+        Some((format, stored))
+    }
+
+    /// Append the vertex attribute `values` (1 to 4 floats) to `bytes`, padded like `vertex_format` says.
+    pub fn pack_attribute(self, values: &[f32], bytes: &mut Vec<u8>) {
+        let stored = self
+            .vertex_format(values.len())
+            .map_or(values.len(), |(_, stored)| stored);
+        self.pack(values, bytes);
+        for _ in values.len()..stored {
+            self.pack(&[0.0], bytes);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pack_half() {
+        let mut bytes = Vec::new();
+        Precision::Half.pack(&[1.0, -0.5], &mut bytes);
+        assert_eq!(bytes.len(), 4);
+        assert_eq!(f16::from_ne_bytes([bytes[0], bytes[1]]).to_f32(), 1.0);
+        assert_eq!(f16::from_ne_bytes([bytes[2], bytes[3]]).to_f32(), -0.5);
+    }
+
+    #[test]
+    fn three_halves_are_padded() {
+        assert_eq!(
+            Precision::Half.vertex_format(3),
+            Some((vk::Format::R16G16B16A16_SFLOAT, 4))
+        );
+        let mut bytes = Vec::new();
+        Precision::Half.pack_attribute(&[1.0, 1.0, 1.0], &mut bytes);
+        assert_eq!(bytes.len(), 4 * Precision::Half.size());
+        assert_eq!(f16::from_ne_bytes([bytes[6], bytes[7]]).to_f32(), 0.0);
+    }
+
+    #[test]
+    fn single_isnt_padded() {
+        let mut bytes = Vec::new();
+        Precision::Single.pack_attribute(&[1.0, 2.0, 3.0], &mut bytes);
+        assert_eq!(bytes.len(), 12);
+        assert_eq!(Precision::Single.vertex_format(5), None);
+    }
+}
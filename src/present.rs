@@ -0,0 +1,229 @@
+use ash::{google::display_timing, khr::swapchain, vk, Device, Instance};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+//////////////// Acquire / Present ////////////////
+
+/// A swapchain image this application currently owns.
+///
+/// Only `acquire` can create one, and `present` consumes it, so code holding an `AcquiredImage`
+/// can't be recording into an image that has already been handed back to the presentation engine.
+/// It is deliberately not `Clone`/`Copy`.
+#[must_use = "an acquired swapchain image has to be presented"]
+#[derive(Debug)]
+pub struct AcquiredImage {
+    swapchain_khr: vk::SwapchainKHR,
+    index: u32,
+    suboptimal: bool,
+}
+
+impl AcquiredImage {
+    /// Index into the swapchain images (and everything created per image, e.g. framebuffers).
+    pub fn index(&self) -> usize {
+        self.index as usize
+    }
+
+    /// The swapchain no longer matches the surface exactly, but can still be presented to.
+    pub fn is_suboptimal(&self) -> bool {
+        self.suboptimal
+    }
+}
+
+/// How long `acquire` waits for an image. Short, so the draw loop gets to check whether it should stop.
+const ACQUIRE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Acquire the next swapchain image, signaling `image_available` once it can be written to.
+/// Fails with TIMEOUT if none is available within ACQUIRE_TIMEOUT (nothing is signaled then).
+pub fn acquire(
+    swapchain: &swapchain::Device,
+    swapchain_khr: vk::SwapchainKHR,
+    image_available: vk::Semaphore,
+) -> Result<AcquiredImage, vk::Result> {
+    let (index, suboptimal) = unsafe {
+        swapchain.acquire_next_image(
+            swapchain_khr,
+            ACQUIRE_TIMEOUT.as_nanos() as u64,
+            image_available,
+            vk::Fence::null(),
+        )?
+    };
+
+    Ok(AcquiredImage {
+        swapchain_khr,
+        index,
+        suboptimal,
+    })
+}
+
+/// Hand `image` back to the presentation engine once `wait_semaphores` are signaled.
+/// The present is recorded in `timing`. Returns true if the swapchain is suboptimal.
+pub fn present(
+    swapchain: &swapchain::Device,
+    present_queue: vk::Queue,
+    image: AcquiredImage,
+    wait_semaphores: &[vk::Semaphore],
+    timing: &mut PresentTiming,
+) -> Result<bool, vk::Result> {
+    let swapchains = [image.swapchain_khr];
+    let image_indices = [image.index];
+
+    let present_id = timing.next_present_id();
+    // No desired present time, we only want the feedback.
+    let present_times = [vk::PresentTimeGOOGLE::default().present_id(present_id)];
+    let mut present_times_info = vk::PresentTimesInfoGOOGLE::default().times(&present_times);
+
+    let mut present_info = vk::PresentInfoKHR::default()
+        .wait_semaphores(wait_semaphores)
+        .swapchains(&swapchains)
+        .image_indices(&image_indices);
+    if timing.display_timing.is_some() {
+        present_info = present_info.push_next(&mut present_times_info);
+    }
+
+    let result = unsafe { swapchain.queue_present(present_queue, &present_info) };
+    if result.is_ok() {
+        timing.presented(present_id);
+    }
+    result
+}
+
+//////////////// Present Timing ////////////////
+
+/// Feedback about one present, e.g. for frame pacing experiments.
+/// Display timing fields are None unless the device supports VK_GOOGLE_display_timing.
+/// Times from the extension are in nanoseconds, in the presentation engine's clock (CLOCK_MONOTONIC on Linux).
+#[derive(Debug, Clone, Copy)]
+pub struct PresentStatistics {
+    pub present_id: u32,
+    /// When `vkQueuePresentKHR` returned on the CPU.
+    pub queued_at: Instant,
+    /// When the image actually started being displayed.
+    pub actual_present_time: Option<u64>,
+    /// How early the image was ready, compared to the latest it could have been presented at that time.
+    pub present_margin: Option<u64>,
+    /// Duration of one display refresh cycle.
+    pub refresh_duration: Option<u64>,
+}
+
+/// Collects `PresentStatistics` for every present, keeping the most recent `HISTORY_LENGTH`.
+/// With VK_GOOGLE_display_timing, statistics only complete once the presentation engine reports back
+/// (a few frames later). Without it they complete right away, with only CPU timing.
+pub struct PresentTiming {
+    display_timing: Option<display_timing::Device>,
+    next_present_id: u32,
+    refresh_duration: Option<u64>,
+    // Presented, but no timing reported yet.
+    pending: VecDeque<PresentStatistics>,
+    history: VecDeque<PresentStatistics>,
+}
+
+impl PresentTiming {
+    const HISTORY_LENGTH: usize = 64;
+
+    /// `display_timing_enabled` if VK_GOOGLE_display_timing was enabled on `device`.
+    pub fn new(instance: &Instance, device: &Device, display_timing_enabled: bool) -> Self {
+        Self {
+            display_timing: display_timing_enabled
+                .then(|| display_timing::Device::new(instance, device)),
+            // Present ids have to be non-zero.
+            next_present_id: 1,
+            refresh_duration: None,
+            pending: VecDeque::new(),
+            history: VecDeque::new(),
+        }
+    }
+
+    /// Completed statistics, oldest first.
+    pub fn history(&self) -> &VecDeque<PresentStatistics> {
+        &self.history
+    }
+
+    fn next_present_id(&mut self) -> u32 {
+        let present_id = self.next_present_id;
+        self.next_present_id = self.next_present_id.checked_add(1).unwrap_or(1);
+        present_id
+    }
+
+    fn presented(&mut self, present_id: u32) {
+        let statistics = PresentStatistics {
+            present_id,
+            queued_at: Instant::now(),
+            actual_present_time: None,
+            present_margin: None,
+            refresh_duration: self.refresh_duration,
+        };
+        if self.display_timing.is_some() {
+            self.pending.push_back(statistics);
+            // Never reported (e.g. the image was never displayed), give up on the oldest.
+            while self.pending.len() > Self::HISTORY_LENGTH {
+                let statistics = self.pending.pop_front().unwrap();
+                self.complete(statistics);
+            }
+        } else {
+            self.complete(statistics);
+        }
+    }
+
+    /// Pick up timing the presentation engine has reported for `swapchain_khr` since the last poll.
+    pub fn poll(&mut self, swapchain_khr: vk::SwapchainKHR) -> Result<(), vk::Result> {
+        let Some(display_timing) = &self.display_timing else {
+            return Ok(());
+        };
+
+        if self.refresh_duration.is_none() {
+            self.refresh_duration = Some(
+                unsafe { display_timing.get_refresh_cycle_duration(swapchain_khr)? }
+                    .refresh_duration,
+            );
+        }
+
+        let timings = unsafe { display_timing.get_past_presentation_timing(swapchain_khr)? };
+        for timing in timings.iter() {
+            if !self
+                .pending
+                .iter()
+                .any(|statistics| statistics.present_id == timing.present_id)
+            {
+                // Already given up on.
+                continue;
+            }
+            // Reports come in present order, so anything pending before this one was never reported.
+            while let Some(mut statistics) = self.pending.pop_front() {
+                if statistics.present_id == timing.present_id {
+                    statistics.actual_present_time = Some(timing.actual_present_time);
+                    statistics.present_margin = Some(timing.present_margin);
+                    statistics.refresh_duration = self.refresh_duration;
+                    self.complete(statistics);
+                    break;
+                }
+                self.complete(statistics);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The swapchain is being destroyed, so pending presents will never be reported.
+    /// The refresh duration is queried again for the new swapchain.
+    pub fn swapchain_destroyed(&mut self) {
+        while let Some(statistics) = self.pending.pop_front() {
+            self.complete(statistics);
+        }
+        self.refresh_duration = None;
+    }
+
+    fn complete(&mut self, statistics: PresentStatistics) {
+        log::trace!(
+            "Present {} (queued {:?} ago): displayed at {:?}, margin {:?}, refresh {:?}",
+            statistics.present_id,
+            statistics.queued_at.elapsed(),
+            statistics.actual_present_time,
+            statistics.present_margin,
+            statistics.refresh_duration
+        );
+        self.history.push_back(statistics);
+        while self.history.len() > Self::HISTORY_LENGTH {
+            self.history.pop_front();
+        }
+    }
+}
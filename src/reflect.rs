@@ -0,0 +1,463 @@
+use ash::vk;
+use rspirv::dr::{Instruction, Operand};
+use rspirv::spirv::{Decoration, Dim, ExecutionModel, Op, StorageClass, Word};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::ops::Range;
+
+use crate::shader_cache::ShaderCache;
+use crate::util::AppError;
+
+//////////////// SPIR-V Reflection ////////////////
+// Descriptor set layouts, push constant ranges and vertex inputs are read from the shaders themselves,
+// so the Rust side doesn't have to repeat what the GLSL declares (and break when one changes without the other).
+// Only what these shaders need is understood. Anything else (e.g. runtime descriptor arrays,
+// 64-bit vertex inputs) is an error, rather than a layout that's silently wrong.
+// A shader doesn't know how its vertex inputs are laid out in buffers, so `vertex_input` packs them
+// tightly in location order, which is what a #[repr(C)] vertex struct with the same fields looks like,
+// and fails if that doesn't add up to the size of the struct the buffer actually holds.
+
+/// What the stages of one pipeline use: descriptors, push constants and vertex inputs.
+#[derive(Default)]
+pub struct ShaderInterface {
+    // (set, binding) → descriptor type, count and the stages using it.
+    bindings: BTreeMap<(u32, u32), (vk::DescriptorType, u32, vk::ShaderStageFlags)>,
+    // One range covering every stage's push constant block.
+    push_constants: Option<vk::PushConstantRange>,
+    // Location → format and size of the vertex shader's inputs.
+    vertex_inputs: BTreeMap<u32, (vk::Format, u32)>,
+}
+
+impl ShaderInterface {
+    /// Reflect the SPIR-V shaders `names` (see `ShaderCache::code`), the stages of one pipeline,
+    /// and merge what they use.
+    pub fn from_shaders(
+        shader_cache: &ShaderCache,
+        names: &[&str],
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut interface = Self::default();
+        for name in names {
+            let code = shader_cache.code(name)?;
+            interface.add(&code).map_err(|err| {
+                AppError::new(&format!("Failed to reflect shader {}: {}", name, err))
+            })?;
+        }
+        Ok(interface)
+    }
+
+    /// The bindings of descriptor set `set`, for `DescriptorManager::create_layout`.
+    pub fn set_bindings(&self, set: u32) -> Vec<vk::DescriptorSetLayoutBinding<'static>> {
+        self.bindings
+            .range((set, 0)..=(set, u32::MAX))
+            .map(|(&(_, binding), &(descriptor_type, count, stages))| {
+                vk::DescriptorSetLayoutBinding::default()
+                    .binding(binding)
+                    .descriptor_type(descriptor_type)
+                    .descriptor_count(count)
+                    .stage_flags(stages)
+            })
+            .collect()
+    }
+
+    /// The push constant ranges, for `GraphicsPipelineBuilder::push_constant_ranges`.
+    /// Empty if no stage has push constants.
+    pub fn push_constant_ranges(&self) -> Vec<vk::PushConstantRange> {
+        self.push_constants.into_iter().collect()
+    }
+
+    /// Vertex buffer bindings and attributes for the vertex shader's inputs, for
+    /// `GraphicsPipelineBuilder::vertex_input`. `bindings` are bindings 0, 1, ...: their input rate,
+    /// the locations they hold, each packed tightly in location order, and the size of what the buffer
+    /// holds per vertex or instance (e.g. `size_of::<Vertex>()`).
+    /// Fails if the shader's inputs and the locations don't match up, or the locations don't fill the size.
+    pub fn vertex_input(
+        &self,
+        bindings: &[(vk::VertexInputRate, Range<u32>, usize)],
+    ) -> Result<
+        (
+            Vec<vk::VertexInputBindingDescription>,
+            Vec<vk::VertexInputAttributeDescription>,
+        ),
+        Box<dyn Error>,
+    > {
+        let mut binding_descriptions = Vec::with_capacity(bindings.len());
+        let mut attribute_descriptions = Vec::with_capacity(self.vertex_inputs.len());
+        for (binding, (input_rate, locations, stride)) in bindings.iter().enumerate() {
+            let mut offset = 0;
+            for location in locations.clone() {
+                let (format, size) = self.vertex_inputs.get(&location).ok_or_else(|| {
+                    AppError::new(&format!(
+                        "Vertex binding {} has location {}, which isn't a vertex shader input",
+                        binding, location
+                    ))
+                })?;
+                attribute_descriptions.push(
+                    vk::VertexInputAttributeDescription::default()
+                        .binding(binding as u32)
+                        .location(location)
+                        .format(*format)
+                        .offset(offset),
+                );
+                offset += size;
+            }
+            if offset as usize != *stride {
+                return Err(Box::new(AppError::new(&format!(
+                    "Vertex binding {} holds {} bytes each, but the shader's inputs at locations {:?} take {}",
+                    binding, stride, locations, offset
+                ))));
+            }
+            binding_descriptions.push(
+                vk::VertexInputBindingDescription::default()
+                    .binding(binding as u32)
+                    .stride(offset)
+                    .input_rate(*input_rate),
+            );
+        }
+
+        if let Some(location) = self.vertex_inputs.keys().find(|location| {
+            !bindings
+                .iter()
+                .any(|(_, range, _)| range.contains(location))
+        }) {
+            return Err(Box::new(AppError::new(&format!(
+                "Vertex shader input at location {} isn't in any vertex binding",
+                location
+            ))));
+        }
+        Ok((binding_descriptions, attribute_descriptions))
+    }
+
+    /// Add what the SPIR-V module `code` uses.
+    fn add(&mut self, code: &[u32]) -> Result<(), Box<dyn Error>> {
+        let module = rspirv::dr::load_words(code)?;
+        let module = Module::new(&module);
+
+        let stage = match module.execution_model()? {
+            ExecutionModel::Vertex => vk::ShaderStageFlags::VERTEX,
+            ExecutionModel::TessellationControl => vk::ShaderStageFlags::TESSELLATION_CONTROL,
+            ExecutionModel::TessellationEvaluation => vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+            ExecutionModel::Geometry => vk::ShaderStageFlags::GEOMETRY,
+            ExecutionModel::Fragment => vk::ShaderStageFlags::FRAGMENT,
+            ExecutionModel::GLCompute => vk::ShaderStageFlags::COMPUTE,
+            model => return Err(error(&format!("unsupported execution model {:?}", model))),
+        };
+
+        for (variable, storage_class, pointee) in module.variables()? {
+            match storage_class {
+                StorageClass::UniformConstant
+                | StorageClass::Uniform
+                | StorageClass::StorageBuffer => {
+                    let Some(binding) = module.decoration(variable, Decoration::Binding) else {
+                        continue;
+                    };
+                    let set = module
+                        .decoration(variable, Decoration::DescriptorSet)
+                        .unwrap_or(0);
+                    let (descriptor_type, count) = module.descriptor(storage_class, pointee)?;
+                    let entry = self.bindings.entry((set, binding)).or_insert((
+                        descriptor_type,
+                        count,
+                        vk::ShaderStageFlags::empty(),
+                    ));
+                    if (entry.0, entry.1) != (descriptor_type, count) {
+                        return Err(error(&format!(
+                            "set {} binding {} is {} {:?} here but {} {:?} in another stage",
+                            set, binding, count, descriptor_type, entry.1, entry.0
+                        )));
+                    }
+                    entry.2 |= stage;
+                }
+                StorageClass::PushConstant => {
+                    let size = module.size(pointee, None)?;
+                    let range = self
+                        .push_constants
+                        .get_or_insert(vk::PushConstantRange::default().offset(0).size(0));
+                    range.stage_flags |= stage;
+                    range.size = range.size.max(size);
+                }
+                StorageClass::Input if stage == vk::ShaderStageFlags::VERTEX => {
+                    // Built-ins (gl_VertexIndex, ...) don't come from vertex buffers.
+                    let Some(location) = module.decoration(variable, Decoration::Location) else {
+                        continue;
+                    };
+                    let format = module.vertex_format(pointee)?;
+                    self.vertex_inputs.insert(location, format);
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A global variable: its id, storage class and the id of the type it points to.
+type Variable = (Word, StorageClass, Word);
+
+/// A parsed module's definitions and decorations, looked up by id.
+struct Module<'a> {
+    entry_points: &'a [Instruction],
+    globals: &'a [Instruction],
+    definitions: HashMap<Word, &'a Instruction>,
+    // (target, decoration) → its first literal operand, or 0 if it has none.
+    decorations: HashMap<(Word, Decoration), u32>,
+    // (struct, member, decoration) → its first literal operand, or 0 if it has none.
+    member_decorations: HashMap<(Word, u32, Decoration), u32>,
+}
+
+impl<'a> Module<'a> {
+    fn new(module: &'a rspirv::dr::Module) -> Self {
+        let definitions = module
+            .types_global_values
+            .iter()
+            .filter_map(|instruction| Some((instruction.result_id?, instruction)))
+            .collect();
+
+        let mut decorations = HashMap::new();
+        let mut member_decorations = HashMap::new();
+        for annotation in &module.annotations {
+            match (annotation.class.opcode, annotation.operands.as_slice()) {
+                (
+                    Op::Decorate,
+                    [Operand::IdRef(target), Operand::Decoration(decoration), rest @ ..],
+                ) => {
+                    decorations.insert((*target, *decoration), first_literal(rest));
+                }
+                (
+                    Op::MemberDecorate,
+                    [Operand::IdRef(target), Operand::LiteralBit32(member), Operand::Decoration(decoration), rest @ ..],
+                ) => {
+                    member_decorations.insert((*target, *member, *decoration), first_literal(rest));
+                }
+                _ => {}
+            }
+        }
+
+        Self {
+            entry_points: &module.entry_points,
+            globals: &module.types_global_values,
+            definitions,
+            decorations,
+            member_decorations,
+        }
+    }
+
+    /// The stage of the (first) entry point.
+    fn execution_model(&self) -> Result<ExecutionModel, Box<dyn Error>> {
+        match self
+            .entry_points
+            .first()
+            .and_then(|entry_point| entry_point.operands.first())
+        {
+            Some(Operand::ExecutionModel(model)) => Ok(*model),
+            _ => Err(error("no entry point")),
+        }
+    }
+
+    /// Every global variable.
+    fn variables(&self) -> Result<Vec<Variable>, Box<dyn Error>> {
+        self.globals
+            .iter()
+            .filter(|instruction| instruction.class.opcode == Op::Variable)
+            .map(|variable| {
+                let id = variable
+                    .result_id
+                    .ok_or_else(|| error("variable without id"))?;
+                let pointer = self.definition(variable.result_type.unwrap_or(0))?;
+                match pointer.operands.as_slice() {
+                    [Operand::StorageClass(storage_class), Operand::IdRef(pointee)] => {
+                        Ok((id, *storage_class, *pointee))
+                    }
+                    _ => Err(error(&format!("variable %{} isn't a pointer", id))),
+                }
+            })
+            .collect()
+    }
+
+    fn definition(&self, id: Word) -> Result<&'a Instruction, Box<dyn Error>> {
+        self.definitions
+            .get(&id)
+            .copied()
+            .ok_or_else(|| error(&format!("%{} isn't defined", id)))
+    }
+
+    fn decoration(&self, id: Word, decoration: Decoration) -> Option<u32> {
+        self.decorations.get(&(id, decoration)).copied()
+    }
+
+    /// The value of the integer constant `id`, e.g. an array length.
+    fn constant(&self, id: Word) -> Result<u32, Box<dyn Error>> {
+        let constant = self.definition(id)?;
+        match (constant.class.opcode, constant.operands.as_slice()) {
+            (Op::Constant, [Operand::LiteralBit32(value)]) => Ok(*value),
+            _ => Err(error(&format!("%{} isn't a 32-bit constant", id))),
+        }
+    }
+
+    /// The descriptor type and count a variable of `storage_class` pointing to `type_id` is bound as.
+    fn descriptor(
+        &self,
+        storage_class: StorageClass,
+        type_id: Word,
+    ) -> Result<(vk::DescriptorType, u32), Box<dyn Error>> {
+        let mut definition = self.definition(type_id)?;
+        let mut count = 1;
+        match (definition.class.opcode, definition.operands.as_slice()) {
+            (Op::TypeArray, [Operand::IdRef(element), Operand::IdRef(length)]) => {
+                count = self.constant(*length)?;
+                definition = self.definition(*element)?;
+            }
+            (Op::TypeRuntimeArray, _) => {
+                return Err(error("runtime descriptor arrays aren't supported"));
+            }
+            _ => {}
+        }
+
+        let descriptor_type = match (storage_class, definition.class.opcode) {
+            (StorageClass::UniformConstant, Op::TypeSampler) => vk::DescriptorType::SAMPLER,
+            (StorageClass::UniformConstant, Op::TypeSampledImage) => {
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER
+            }
+            (StorageClass::UniformConstant, Op::TypeImage) => {
+                let (dim, sampled) = match definition.operands.as_slice() {
+                    [_, Operand::Dim(dim), _, _, _, Operand::LiteralBit32(sampled), ..] => {
+                        (*dim, *sampled)
+                    }
+                    _ => return Err(error("malformed image type")),
+                };
+                // Sampled is 1 for images used with a sampler, 2 for storage images.
+                match (dim, sampled) {
+                    (Dim::DimSubpassData, _) => vk::DescriptorType::INPUT_ATTACHMENT,
+                    (Dim::DimBuffer, 1) => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+                    (Dim::DimBuffer, _) => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+                    (_, 1) => vk::DescriptorType::SAMPLED_IMAGE,
+                    _ => vk::DescriptorType::STORAGE_IMAGE,
+                }
+            }
+            (StorageClass::UniformConstant, Op::TypeAccelerationStructureKHR) => {
+                vk::DescriptorType::ACCELERATION_STRUCTURE_KHR
+            }
+            // Before SPIR-V 1.3 storage buffers were Uniform blocks decorated BufferBlock.
+            (StorageClass::Uniform, Op::TypeStruct) => {
+                let id = definition.result_id.unwrap_or(0);
+                if self.decoration(id, Decoration::BufferBlock).is_some() {
+                    vk::DescriptorType::STORAGE_BUFFER
+                } else {
+                    vk::DescriptorType::UNIFORM_BUFFER
+                }
+            }
+            (StorageClass::StorageBuffer, Op::TypeStruct) => vk::DescriptorType::STORAGE_BUFFER,
+            (storage_class, opcode) => {
+                return Err(error(&format!(
+                    "unsupported resource: {:?} in {:?}",
+                    opcode, storage_class
+                )))
+            }
+        };
+        Ok((descriptor_type, count))
+    }
+
+    /// Size in bytes of `type_id` with its explicit layout (offsets and strides), e.g. a push constant block.
+    /// `matrix_stride` is the MatrixStride of the struct member a matrix is.
+    fn size(&self, type_id: Word, matrix_stride: Option<u32>) -> Result<u32, Box<dyn Error>> {
+        let definition = self.definition(type_id)?;
+        match (definition.class.opcode, definition.operands.as_slice()) {
+            (Op::TypeInt, [Operand::LiteralBit32(width), _])
+            | (Op::TypeFloat, [Operand::LiteralBit32(width), ..]) => Ok(width / 8),
+            (Op::TypeVector, [Operand::IdRef(component), Operand::LiteralBit32(count)]) => {
+                Ok(count * self.size(*component, None)?)
+            }
+            (Op::TypeMatrix, [Operand::IdRef(column), Operand::LiteralBit32(columns)]) => {
+                let stride = match matrix_stride {
+                    Some(stride) => stride,
+                    None => self.size(*column, None)?,
+                };
+                Ok(columns * stride)
+            }
+            (Op::TypeArray, [Operand::IdRef(element), Operand::IdRef(length)]) => {
+                let stride = match self.decoration(type_id, Decoration::ArrayStride) {
+                    Some(stride) => stride,
+                    None => self.size(*element, matrix_stride)?,
+                };
+                Ok(self.constant(*length)? * stride)
+            }
+            (Op::TypeStruct, members) => {
+                let mut size = 0;
+                for (index, member) in members.iter().enumerate() {
+                    let Operand::IdRef(member) = member else {
+                        return Err(error("malformed struct type"));
+                    };
+                    let index = index as u32;
+                    let offset = self
+                        .member_decorations
+                        .get(&(type_id, index, Decoration::Offset))
+                        .ok_or_else(|| error("struct member without an offset"))?;
+                    let stride = self
+                        .member_decorations
+                        .get(&(type_id, index, Decoration::MatrixStride))
+                        .copied();
+                    size = size.max(offset + self.size(*member, stride)?);
+                }
+                Ok(size)
+            }
+            (opcode, _) => Err(error(&format!("can't size a {:?}", opcode))),
+        }
+    }
+
+    /// Format and size of a vertex shader input of type `type_id`.
+    fn vertex_format(&self, type_id: Word) -> Result<(vk::Format, u32), Box<dyn Error>> {
+        let definition = self.definition(type_id)?;
+        let (scalar, count) = match (definition.class.opcode, definition.operands.as_slice()) {
+            (Op::TypeVector, [Operand::IdRef(component), Operand::LiteralBit32(count)]) => {
+                (self.definition(*component)?, *count)
+            }
+            _ => (definition, 1),
+        };
+
+        const FLOAT: [vk::Format; 4] = [
+            vk::Format::R32_SFLOAT,
+            vk::Format::R32G32_SFLOAT,
+            vk::Format::R32G32B32_SFLOAT,
+            vk::Format::R32G32B32A32_SFLOAT,
+        ];
+        const SINT: [vk::Format; 4] = [
+            vk::Format::R32_SINT,
+            vk::Format::R32G32_SINT,
+            vk::Format::R32G32B32_SINT,
+            vk::Format::R32G32B32A32_SINT,
+        ];
+        const UINT: [vk::Format; 4] = [
+            vk::Format::R32_UINT,
+            vk::Format::R32G32_UINT,
+            vk::Format::R32G32B32_UINT,
+            vk::Format::R32G32B32A32_UINT,
+        ];
+        let formats = match (scalar.class.opcode, scalar.operands.as_slice()) {
+            (Op::TypeFloat, [Operand::LiteralBit32(32), ..]) => FLOAT,
+            (Op::TypeInt, [Operand::LiteralBit32(32), Operand::LiteralBit32(1)]) => SINT,
+            (Op::TypeInt, [Operand::LiteralBit32(32), Operand::LiteralBit32(0)]) => UINT,
+            _ => {
+                return Err(error(
+                    "only 32-bit scalar and vector vertex inputs are supported",
+                ))
+            }
+        };
+        let format = formats.get(count as usize - 1).ok_or_else(|| {
+            error(&format!(
+                "{}-component vertex inputs aren't supported",
+                count
+            ))
+        })?;
+        Ok((*format, count * 4))
+    }
+}
+
+fn first_literal(operands: &[Operand]) -> u32 {
+    match operands.first() {
+        Some(Operand::LiteralBit32(value)) => *value,
+        _ => 0,
+    }
+}
+
+fn error(message: &str) -> Box<dyn Error> {
+    Box::new(AppError::new(message))
+}
@@ -0,0 +1,205 @@
+use ash::{vk, Device};
+use std::error::Error;
+
+use crate::image;
+use crate::util::AppError;
+
+//////////////// Render Pass ////////////////
+// A render pass is a list of attachments, the subpasses using them (by index) and the dependencies
+// between subpasses. Later subpasses can read what earlier ones wrote at the same pixel as input
+// attachments (subpassInput in GLSL), which tiled GPUs keep on chip, e.g. a G-buffer written in one
+// subpass and lit in the next without ever reaching memory (see deferred.rs). Pipelines are built for one
+// subpass each (see `GraphicsPipelineBuilder::subpass`), and recording moves on with cmd_next_subpass
+// (see `command::record_subpasses`).
+
+/// The attachments (indices into the render pass's) one subpass uses, and how.
+#[derive(Clone, Default)]
+pub struct Subpass {
+    color: Vec<u32>,
+    resolve: Vec<u32>,
+    input: Vec<u32>,
+    depth_stencil: Option<u32>,
+    preserve: Vec<u32>,
+}
+
+impl Subpass {
+    /// Attachments the fragment shader writes, in `layout(location = ...)` order.
+    pub fn color(mut self, attachments: &[u32]) -> Self {
+        self.color = attachments.to_vec();
+        self
+    }
+
+    /// Single sample attachments the color attachments are resolved into at the end of the subpass,
+    /// one per color attachment (vk::ATTACHMENT_UNUSED to skip one).
+    pub fn resolve(mut self, attachments: &[u32]) -> Self {
+        self.resolve = attachments.to_vec();
+        self
+    }
+
+    /// Attachments earlier subpasses wrote that the fragment shader reads, in
+    /// `layout(input_attachment_index = ...)` order.
+    pub fn input(mut self, attachments: &[u32]) -> Self {
+        self.input = attachments.to_vec();
+        self
+    }
+
+    /// The depth (stencil) attachment, none by default.
+    pub fn depth_stencil(mut self, attachment: u32) -> Self {
+        self.depth_stencil = Some(attachment);
+        self
+    }
+
+    /// Attachments this subpass doesn't use whose contents later subpasses still need.
+    pub fn preserve(mut self, attachments: &[u32]) -> Self {
+        self.preserve = attachments.to_vec();
+        self
+    }
+}
+
+/// Attachments, subpasses and dependencies of a render pass. Add them in order, then `build` it.
+#[derive(Default)]
+pub struct RenderPassBuilder {
+    attachments: Vec<vk::AttachmentDescription>,
+    subpasses: Vec<Subpass>,
+    dependencies: Vec<vk::SubpassDependency>,
+}
+
+impl RenderPassBuilder {
+    /// Add an attachment, subpasses refer to it by the number of attachments added before it.
+    pub fn attachment(mut self, description: vk::AttachmentDescription) -> Self {
+        self.attachments.push(description);
+        self
+    }
+
+    /// Add a subpass, numbered like attachments are.
+    pub fn subpass(mut self, subpass: Subpass) -> Self {
+        self.subpasses.push(subpass);
+        self
+    }
+
+    /// Add a dependency between subpasses (or with vk::SUBPASS_EXTERNAL).
+    pub fn dependency(mut self, dependency: vk::SubpassDependency) -> Self {
+        self.dependencies.push(dependency);
+        self
+    }
+
+    /// Make subpass `dst` wait for the color and depth writes of subpass `src` before its fragment
+    /// shader reads them as input attachments. Only the same pixel is read, so it's BY_REGION,
+    /// which lets tilers keep the attachments on chip.
+    pub fn input_dependency(self, src: u32, dst: u32) -> Self {
+        self.dependency(
+            vk::SubpassDependency::default()
+                .src_subpass(src)
+                .dst_subpass(dst)
+                .src_stage_mask(
+                    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                        | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                )
+                .src_access_mask(
+                    vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                )
+                .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+                .dst_access_mask(vk::AccessFlags::INPUT_ATTACHMENT_READ)
+                .dependency_flags(vk::DependencyFlags::BY_REGION),
+        )
+    }
+
+    /// Create the render pass. Attachments are used in COLOR_ATTACHMENT_OPTIMAL or
+    /// DEPTH_STENCIL_ATTACHMENT_OPTIMAL while written, and read as input attachments in
+    /// SHADER_READ_ONLY_OPTIMAL or DEPTH_STENCIL_READ_ONLY_OPTIMAL (depth formats).
+    pub fn build(&self, device: &Device) -> Result<vk::RenderPass, Box<dyn Error>> {
+        let reference = |attachment: u32, layout: vk::ImageLayout| {
+            vk::AttachmentReference::default()
+                .attachment(attachment)
+                .layout(layout)
+        };
+        let color_layout = vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL;
+        let depth_layout = vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL;
+
+        // Descriptions point into these, so they're all built first.
+        let mut references = Vec::with_capacity(self.subpasses.len());
+        for (index, subpass) in self.subpasses.iter().enumerate() {
+            let attachment_count = self.attachments.len() as u32;
+            let used = subpass
+                .color
+                .iter()
+                .chain(&subpass.resolve)
+                .chain(&subpass.input);
+            if let Some(attachment) = used
+                .chain(&subpass.depth_stencil)
+                .chain(&subpass.preserve)
+                .find(|attachment| {
+                    **attachment >= attachment_count && **attachment != vk::ATTACHMENT_UNUSED
+                })
+            {
+                return Err(Box::new(AppError::new(&format!(
+                    "Subpass {} uses attachment {}, but there are only {}",
+                    index, attachment, attachment_count
+                ))));
+            }
+            if !subpass.resolve.is_empty() && subpass.resolve.len() != subpass.color.len() {
+                return Err(Box::new(AppError::new(&format!(
+                    "Subpass {} resolves {} attachments, but has {} color attachments",
+                    index,
+                    subpass.resolve.len(),
+                    subpass.color.len()
+                ))));
+            }
+
+            let input_layout = |attachment: u32| match self.attachments.get(attachment as usize) {
+                Some(description) if image::has_depth_component(description.format) => {
+                    vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL
+                }
+                _ => vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            };
+            references.push((
+                subpass
+                    .color
+                    .iter()
+                    .map(|attachment| reference(*attachment, color_layout))
+                    .collect::<Vec<_>>(),
+                subpass
+                    .resolve
+                    .iter()
+                    .map(|attachment| reference(*attachment, color_layout))
+                    .collect::<Vec<_>>(),
+                subpass
+                    .input
+                    .iter()
+                    .map(|attachment| reference(*attachment, input_layout(*attachment)))
+                    .collect::<Vec<_>>(),
+                subpass
+                    .depth_stencil
+                    .map(|attachment| reference(attachment, depth_layout)),
+            ));
+        }
+
+        let subpass_descs = self
+            .subpasses
+            .iter()
+            .zip(references.iter())
+            .map(|(subpass, (color, resolve, input, depth_stencil))| {
+                let mut subpass_desc = vk::SubpassDescription::default()
+                    .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+                    .color_attachments(color)
+                    .input_attachments(input)
+                    .preserve_attachments(&subpass.preserve);
+                if !resolve.is_empty() {
+                    subpass_desc = subpass_desc.resolve_attachments(resolve);
+                }
+                if let Some(depth_stencil) = depth_stencil {
+                    subpass_desc = subpass_desc.depth_stencil_attachment(depth_stencil);
+                }
+                subpass_desc
+            })
+            .collect::<Vec<_>>();
+
+        let render_pass_create_info = vk::RenderPassCreateInfo::default()
+            .attachments(&self.attachments)
+            .subpasses(&subpass_descs)
+            .dependencies(&self.dependencies);
+
+        unsafe { Ok(device.create_render_pass(&render_pass_create_info, None)?) }
+    }
+}
@@ -0,0 +1,173 @@
+use ash::vk;
+use std::fmt;
+
+use crate::util;
+
+//////////////// Render Targets ////////////////
+
+/// How one attachment of the render pass is loaded and stored, and what it's cleared to.
+#[derive(Clone, Copy)]
+pub struct AttachmentTarget {
+    pub load_op: vk::AttachmentLoadOp,
+    pub store_op: vk::AttachmentStoreOp,
+    /// Used when `load_op` is CLEAR. Can be changed between frames without recreating the render pass.
+    pub clear_value: vk::ClearValue,
+    /// The pass writes every pixel of the target (e.g. a fullscreen pass), so its previous contents
+    /// don't matter: the load op becomes DONT_CARE, which saves a clear (or a load on tiled GPUs).
+    pub fully_overwritten: bool,
+}
+
+impl AttachmentTarget {
+    /// Load op the render pass should use. LOAD isn't supported: attachments start out UNDEFINED.
+    pub fn effective_load_op(&self) -> vk::AttachmentLoadOp {
+        if self.fully_overwritten {
+            vk::AttachmentLoadOp::DONT_CARE
+        } else {
+            self.load_op
+        }
+    }
+}
+
+/// The targets the render pass draws into: the swapchain image and the depth (stencil) buffer.
+/// Load/store ops are baked into the render pass, clear values are read when recording.
+#[derive(Clone, Copy)]
+pub struct RenderTargets {
+    pub color: AttachmentTarget,
+    pub depth: AttachmentTarget,
+}
+
+impl Default for RenderTargets {
+    fn default() -> Self {
+        Self {
+            // Cleared every frame and kept for presentation.
+            color: AttachmentTarget {
+                load_op: vk::AttachmentLoadOp::CLEAR,
+                store_op: vk::AttachmentStoreOp::STORE,
+                clear_value: vk::ClearValue {
+                    color: vk::ClearColorValue {
+                        float32: util::CLEAR_COLOR,
+                    },
+                },
+                fully_overwritten: false,
+            },
+            // Depth (and stencil) are only needed during the pass, so their contents don't have to be kept.
+            depth: AttachmentTarget {
+                load_op: vk::AttachmentLoadOp::CLEAR,
+                store_op: vk::AttachmentStoreOp::DONT_CARE,
+                clear_value: vk::ClearValue {
+                    depth_stencil: vk::ClearDepthStencilValue {
+                        depth: 1.0,
+                        stencil: 0,
+                    },
+                },
+                fully_overwritten: false,
+            },
+        }
+    }
+}
+
+/// What pipelines are built for and secondary command buffers are recorded for.
+#[derive(Debug, Clone, Copy)]
+pub enum Rendering {
+    /// Subpass 0 of a render pass.
+    RenderPass(vk::RenderPass),
+    /// Dynamic rendering (see dynamic_rendering.rs) into a color attachment of `color_format` and a
+    /// depth (stencil) attachment of `depth_format`, both with `samples`.
+    Dynamic {
+        color_format: vk::Format,
+        depth_format: vk::Format,
+        samples: vk::SampleCountFlags,
+    },
+}
+
+/// Estimated memory traffic of one render pass per frame, from its attachments' load/store ops.
+/// Assumes a tiling GPU: CLEAR/DONT_CARE loads and DONT_CARE stores stay on chip and cost nothing.
+/// Immediate mode GPUs also pay for depth testing and blending, so treat this as a lower bound.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BandwidthEstimate {
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+}
+
+impl BandwidthEstimate {
+    fn add(&mut self, target: &AttachmentTarget, bytes: u64) {
+        if target.effective_load_op() == vk::AttachmentLoadOp::LOAD {
+            self.read_bytes += bytes;
+        }
+        if target.store_op == vk::AttachmentStoreOp::STORE {
+            self.write_bytes += bytes;
+        }
+    }
+}
+
+impl fmt::Display for BandwidthEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const MIB: f64 = 1024.0 * 1024.0;
+        write!(
+            f,
+            "{:.2} MiB read, {:.2} MiB written per frame",
+            self.read_bytes as f64 / MIB,
+            self.write_bytes as f64 / MIB
+        )
+    }
+}
+
+impl RenderTargets {
+    /// Clear values in attachment order, for `vk::RenderPassBeginInfo`.
+    pub fn clear_values(&self) -> [vk::ClearValue; 2] {
+        [self.color.clear_value, self.depth.clear_value]
+    }
+
+    /// Estimate the bandwidth of the render pass built from these targets (see `vulkan_create::render_pass`).
+    /// With multisampling the color samples are resolved on chip, only the resolved image is stored.
+    pub fn estimated_bandwidth(
+        &self,
+        extent: vk::Extent2D,
+        color_format: vk::Format,
+        depth_format: vk::Format,
+        samples: vk::SampleCountFlags,
+    ) -> BandwidthEstimate {
+        let pixels = extent.width as u64 * extent.height as u64;
+        let sample_count = samples.as_raw() as u64;
+        let mut estimate = BandwidthEstimate::default();
+
+        let color_bytes = pixels * bytes_per_pixel(color_format);
+        if sample_count > 1 {
+            // Multisampled color is never stored, the resolve attachment takes the color store op.
+            let multisampled = AttachmentTarget {
+                store_op: vk::AttachmentStoreOp::DONT_CARE,
+                ..self.color
+            };
+            estimate.add(&multisampled, color_bytes * sample_count);
+            estimate.add(
+                &AttachmentTarget {
+                    load_op: vk::AttachmentLoadOp::DONT_CARE,
+                    ..self.color
+                },
+                color_bytes,
+            );
+        } else {
+            estimate.add(&self.color, color_bytes);
+        }
+
+        estimate.add(
+            &self.depth,
+            pixels * bytes_per_pixel(depth_format) * sample_count,
+        );
+
+        estimate
+    }
+}
+
+/// Size of one texel of the attachment formats this crate uses. Unknown formats count as 4 bytes.
+fn bytes_per_pixel(format: vk::Format) -> u64 {
+    match format {
+        vk::Format::D16_UNORM => 2,
+        vk::Format::D16_UNORM_S8_UINT => 3,
+        // Packed depth/stencil is usually stored as 8 bytes.
+        vk::Format::D32_SFLOAT_S8_UINT => 8,
+        vk::Format::R16G16B16A16_SFLOAT => 8,
+        vk::Format::R32G32B32A32_SFLOAT => 16,
+        _ => 4,
+    }
+}
@@ -0,0 +1,321 @@
+use ash::Device;
+#[cfg(feature = "hot-reload")]
+use std::collections::HashSet;
+use std::error::Error;
+use std::path::Path;
+
+use crate::command::Draw;
+use crate::skybox::{self, Skybox};
+#[cfg(feature = "hot-reload")]
+use crate::subsystem::{self, PipelineContext};
+use crate::subsystem::{Frame, Subsystem};
+#[cfg(feature = "hot-reload")]
+use crate::textured_quad;
+use crate::textured_quad::TexturedQuad;
+use crate::{assets, image, sync, texture, util, VulkanApp};
+
+//////////////// Scenery ////////////////
+// What's drawn from image files (see util::SKYBOX and util::TEXTURE): a skybox behind the scene and a
+// textured quad over it. Missing files are drawn with the placeholder checkerboard (see assets.rs).
+
+/// The files a skybox directory holds, one per cubemap face in layer order (+X, -X, +Y, -Y, +Z, -Z).
+const CUBEMAP_FACES: [&str; 6] = ["px.png", "nx.png", "py.png", "ny.png", "pz.png", "nz.png"];
+
+/// The skybox and textured quad, once loaded.
+pub struct Scenery {
+    skybox: Option<Skybox>,
+    textured_quad: Option<TexturedQuad>,
+    // For the next frame.
+    skybox_push_constants: [u8; 64],
+    quad_push_constants: [u8; 16],
+}
+
+impl Default for Scenery {
+    fn default() -> Self {
+        Self {
+            skybox: None,
+            textured_quad: None,
+            skybox_push_constants: [0; 64],
+            quad_push_constants: [0; 16],
+        }
+    }
+}
+
+impl Subsystem for Scenery {
+    fn describe(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("skybox", self.skybox.is_some().to_string()),
+            (
+                "textured quad",
+                self.textured_quad
+                    .as_ref()
+                    .map_or_else(|| false.to_string(), TexturedQuad::describe),
+            ),
+        ]
+    }
+
+    #[cfg(feature = "hot-reload")]
+    fn shaders(&self) -> Vec<&'static str> {
+        let skybox = self.skybox.as_ref().map(|_| &skybox::SHADERS[..]);
+        let quad = self
+            .textured_quad
+            .as_ref()
+            .map(|_| &textured_quad::SHADERS[..]);
+        skybox.into_iter().chain(quad).flatten().copied().collect()
+    }
+
+    #[cfg(feature = "hot-reload")]
+    fn rebuild_pipelines(&mut self, context: &PipelineContext, changed: &HashSet<String>) {
+        if let Some(skybox) = &mut self.skybox {
+            if subsystem::uses_any(&skybox::SHADERS, changed) {
+                let result = skybox.rebuild_pipeline(
+                    context.device,
+                    context.shader_cache,
+                    context.device_details,
+                    context.rendering,
+                    context.samples,
+                );
+                subsystem::log_rebuild("skybox", result);
+            }
+        }
+        if let Some(textured_quad) = &mut self.textured_quad {
+            if subsystem::uses_any(&textured_quad::SHADERS, changed) {
+                let result = textured_quad.rebuild_pipeline(
+                    context.device,
+                    context.shader_cache,
+                    context.rendering,
+                    context.samples,
+                );
+                subsystem::log_rebuild("textured quad", result);
+            }
+        }
+    }
+
+    fn prepare(&mut self, frame: &Frame) {
+        self.skybox_push_constants = Skybox::push_constants(&skybox::fixed_view(frame.extent));
+        self.quad_push_constants = TexturedQuad::push_constants(frame.extent);
+    }
+
+    fn draws<'a>(
+        &'a self,
+        _triangles: &Draw<'a>,
+        scene: &mut Vec<Draw<'a>>,
+        overlays: &mut Vec<Draw<'a>>,
+    ) {
+        // After the rest of the scene, so only the parts of it that show are shaded.
+        if let Some(skybox) = &self.skybox {
+            scene.push(skybox.draw(&self.skybox_push_constants));
+        }
+        if let Some(textured_quad) = &self.textured_quad {
+            overlays.push(textured_quad.draw(&self.quad_push_constants));
+        }
+    }
+
+    fn destroy(&mut self, device: &Device) {
+        if let Some(skybox) = &mut self.skybox {
+            skybox.destroy(device);
+        }
+        if let Some(textured_quad) = &mut self.textured_quad {
+            textured_quad.destroy(device);
+        }
+    }
+}
+
+impl VulkanApp {
+    /// Load a PNG or JPEG file into a texture sampled as described by `sampler`,
+    /// with a full mip chain if the device can blit one. Anisotropy is clamped to what the device supports
+    /// (none if it doesn't). If the file doesn't exist, it's the placeholder checkerboard (see assets.rs).
+    /// The caller destroys the texture (before the app is dropped).
+    fn load_texture(
+        &self,
+        path: &Path,
+        sampler: &texture::SamplerDesc,
+    ) -> Result<texture::Texture, Box<dyn Error>> {
+        if !path.exists() {
+            log::warn!(
+                "Texture {} doesn't exist, using a placeholder",
+                path.display()
+            );
+            return self.placeholder_texture();
+        }
+
+        let mipmapped = image::supports_mipmap_generation(
+            &self.instance,
+            self.physical_device,
+            texture::TEXTURE_FORMAT,
+        );
+        if !mipmapped {
+            log::info!(
+                "Can't blit {:?} with linear filtering, {} gets a single mip",
+                texture::TEXTURE_FORMAT,
+                path.display()
+            );
+        }
+
+        let sampler = texture::SamplerDesc {
+            max_anisotropy: sampler.max_anisotropy.and_then(|requested| {
+                self.device_details
+                    .sampler_anisotropy
+                    .map(|supported| requested.min(supported))
+            }),
+            ..*sampler
+        };
+
+        texture::Texture::from_file(
+            &self.device,
+            &self.allocator,
+            &self.transfer_queue,
+            path,
+            &texture::TextureOptions { mipmapped, sampler },
+        )
+    }
+
+    /// Load the first of `variants` (KTX2 files of the same texture, e.g. BC7 and ASTC encoded)
+    /// whose format this device can sample, so it doesn't need to be uploaded as RGBA8.
+    /// Variants that don't exist are skipped, if none does it's the placeholder checkerboard (see assets.rs).
+    /// The caller destroys the texture (before the app is dropped).
+    fn load_compressed_texture(
+        &self,
+        variants: &[&Path],
+        sampler: &texture::SamplerDesc,
+    ) -> Result<texture::Texture, Box<dyn Error>> {
+        let existing = variants
+            .iter()
+            .filter(|path| path.exists())
+            .collect::<Vec<_>>();
+        if existing.is_empty() {
+            log::warn!("None of {:?} exists, using a placeholder", variants);
+            return self.placeholder_texture();
+        }
+
+        for path in existing {
+            let file = texture::Ktx2File::read(path)?;
+            let supported = match file.format()? {
+                Some(format) => texture::compressed_format_supported(
+                    &self.instance,
+                    self.physical_device,
+                    format,
+                ),
+                None => false,
+            };
+            if supported {
+                return texture::Texture::from_ktx2(
+                    &self.device,
+                    &self.allocator,
+                    &self.transfer_queue,
+                    &file,
+                    sampler,
+                );
+            }
+            log::debug!(
+                "Skipping {}, its format isn't supported",
+                file.path().display()
+            );
+        }
+
+        Err(Box::new(util::AppError::new(&format!(
+            "None of {:?} is in a format this device can sample",
+            variants
+        ))))
+    }
+
+    /// The texture standing in for missing ones.
+    fn placeholder_texture(&self) -> Result<texture::Texture, Box<dyn Error>> {
+        assets::placeholder_texture(&self.device, &self.allocator, &self.transfer_queue)
+    }
+
+    /// Draw the cubemap at `path` behind the scene from now on, replacing any previous one: a directory with
+    /// one image per face (see `CUBEMAP_FACES` and `texture::Texture::cubemap_from_files`), or else a
+    /// horizontal cross image (see `texture::Texture::cubemap_from_cross`).
+    /// If it can't be loaded, it's the placeholder checkerboard on every face (see assets.rs).
+    pub fn load_skybox(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let cubemap = if path.is_dir() {
+            texture::Texture::cubemap_from_files(
+                &self.device,
+                &self.allocator,
+                &self.transfer_queue,
+                &CUBEMAP_FACES.map(|face| path.join(face)),
+                &texture::SamplerDesc::default(),
+            )
+        } else {
+            texture::Texture::cubemap_from_cross(
+                &self.device,
+                &self.allocator,
+                &self.transfer_queue,
+                path,
+                &texture::SamplerDesc::default(),
+            )
+        };
+        let cubemap = match cubemap {
+            Ok(cubemap) => cubemap,
+            // After a failed wait the GPU may still be uploading, see `command::one_time_submit`.
+            Err(err) if err.downcast_ref::<sync::WaitError>().is_some() => return Err(err),
+            Err(err) => {
+                log::warn!(
+                    "Can't load skybox {}, using a placeholder: {}",
+                    path.display(),
+                    err
+                );
+                assets::placeholder_cubemap(&self.device, &self.allocator, &self.transfer_queue)?
+            }
+        };
+        self.set_skybox(cubemap)
+    }
+
+    /// Draw `cubemap` (see `texture::Texture::cubemap_from_files`) behind the scene from now on,
+    /// replacing the previous one, if any, which keeps its pipeline and descriptor set.
+    fn set_skybox(&mut self, cubemap: texture::Texture) -> Result<(), Box<dyn Error>> {
+        if self.subsystems.scenery.skybox.is_some() {
+            // The previous cubemap and the descriptor set may still be in use by frames in flight.
+            if let Err(err) = self.wait_idle() {
+                let mut cubemap = cubemap;
+                cubemap.destroy(&self.device);
+                return Err(err);
+            }
+        }
+        match &mut self.subsystems.scenery.skybox {
+            Some(skybox) => skybox.set_cubemap(&self.device, cubemap),
+            None => {
+                self.subsystems.scenery.skybox = Some(Skybox::new(
+                    &self.device,
+                    &self.shader_cache,
+                    &self.device_details,
+                    &mut self.descriptors,
+                    self.rendering,
+                    self.msaa_samples,
+                    cubemap,
+                )?)
+            }
+        }
+        self.scene_changed();
+        Ok(())
+    }
+
+    /// Draw the texture at `path` on a quad over the scene: a KTX2 file through `load_compressed_texture`,
+    /// anything else through `load_texture`, with trilinear and anisotropic filtering.
+    pub fn load_textured_quad(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let sampler = texture::SamplerDesc {
+            max_anisotropy: Some(16.0),
+            ..texture::SamplerDesc::default()
+        };
+        let texture = if path
+            .extension()
+            .is_some_and(|extension| extension == "ktx2")
+        {
+            self.load_compressed_texture(&[path], &sampler)?
+        } else {
+            self.load_texture(path, &sampler)?
+        };
+        self.subsystems.scenery.textured_quad = Some(TexturedQuad::new(
+            &self.device,
+            &self.shader_cache,
+            &self.allocator,
+            &self.transfer_queue,
+            &mut self.descriptors,
+            (self.rendering, self.msaa_samples),
+            texture,
+        )?);
+        self.scene_changed();
+        Ok(())
+    }
+}
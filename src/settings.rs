@@ -0,0 +1,364 @@
+use ash::vk;
+use std::cell::RefCell;
+use std::error::Error;
+#[cfg(feature = "asset-import")]
+use std::path::PathBuf;
+
+use crate::backend::BackendKind;
+use crate::util::{self, AppError, DeviceSelection};
+
+//////////////// Settings ////////////////
+// The switches in util.rs, given at runtime. Each one is `--name value` (or `--name=value`) on the command
+// line, or else VULKAN_ASH_NAME in the environment (dashes becoming underscores), or else its constant, e.g.
+// cargo run -- --msaa-samples 1 --software-cursor on, or VULKAN_ASH_TESSELLATION_DEMO=on cargo run.
+// Switches take on/off, true/false, yes/no or 1/0. Any other option is an error listing the known ones.
+
+/// What the app is run with, see the comment above.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub device_selection: DeviceSelection,
+    /// See util::RENDERER_BACKEND.
+    pub backend: BackendKind,
+    /// See util::STRICT_VALIDATION_LAYERS.
+    pub strict_validation_layers: bool,
+    /// See util::ENABLE_STENCIL.
+    pub stencil: bool,
+    /// See util::MSAA_SAMPLES.
+    pub msaa_samples: vk::SampleCountFlags,
+    /// See util::SOFTWARE_CURSOR.
+    pub software_cursor: bool,
+    /// See util::SECONDARY_COMMAND_BUFFERS.
+    pub secondary_command_buffers: bool,
+    /// See util::RECORDING_THREADS.
+    #[cfg(feature = "parallel-recording")]
+    pub recording_threads: usize,
+    /// See util::COMPUTE_POST_PROCESS.
+    pub compute_post_process: bool,
+    /// See util::VOXELIZATION_DEMO.
+    #[cfg(feature = "demos")]
+    pub voxelization_demo: bool,
+    /// See util::TESSELLATION_DEMO.
+    #[cfg(feature = "demos")]
+    pub tessellation_demo: bool,
+    /// See util::VERTEX_MARKERS_DEMO.
+    #[cfg(feature = "demos")]
+    pub vertex_markers_demo: bool,
+    /// See util::CUBE_DEMO.
+    #[cfg(feature = "demos")]
+    pub cube_demo: bool,
+    /// See util::CLEAR_COLOR.
+    pub clear_color: [f32; 4],
+    /// See util::GPU_FRAME_TIMER.
+    pub gpu_frame_timer: bool,
+    /// See util::STARTUP_DECISIONS_LOG_LEVEL.
+    pub startup_decisions_log_level: log::Level,
+    /// See util::SKYBOX.
+    #[cfg(feature = "asset-import")]
+    pub skybox: Option<PathBuf>,
+    /// See util::TEXTURE.
+    #[cfg(feature = "asset-import")]
+    pub texture: Option<PathBuf>,
+}
+
+impl Settings {
+    /// From `args` (without the program name) and the environment, see the comment above.
+    pub fn from_args_and_env<I: IntoIterator<Item = String>>(
+        args: I,
+    ) -> Result<Self, Box<dyn Error>> {
+        let args = Args::new(args);
+        let settings = Self {
+            device_selection: DeviceSelection::from_args_and_env(&args)?,
+            backend: BackendKind::from_args_and_env(&args)?,
+            strict_validation_layers: setting(
+                &args,
+                "strict-validation-layers",
+                parse_switch,
+                util::STRICT_VALIDATION_LAYERS,
+            )?,
+            stencil: setting(&args, "enable-stencil", parse_switch, util::ENABLE_STENCIL)?,
+            msaa_samples: setting(
+                &args,
+                "msaa-samples",
+                parse_sample_count,
+                util::MSAA_SAMPLES,
+            )?,
+            software_cursor: setting(
+                &args,
+                "software-cursor",
+                parse_switch,
+                util::SOFTWARE_CURSOR,
+            )?,
+            secondary_command_buffers: setting(
+                &args,
+                "secondary-command-buffers",
+                parse_switch,
+                util::SECONDARY_COMMAND_BUFFERS,
+            )?,
+            #[cfg(feature = "parallel-recording")]
+            recording_threads: setting(
+                &args,
+                "recording-threads",
+                |value| value.parse().ok(),
+                util::RECORDING_THREADS,
+            )?,
+            compute_post_process: setting(
+                &args,
+                "compute-post-process",
+                parse_switch,
+                util::COMPUTE_POST_PROCESS,
+            )?,
+            #[cfg(feature = "demos")]
+            voxelization_demo: setting(
+                &args,
+                "voxelization-demo",
+                parse_switch,
+                util::VOXELIZATION_DEMO,
+            )?,
+            #[cfg(feature = "demos")]
+            tessellation_demo: setting(
+                &args,
+                "tessellation-demo",
+                parse_switch,
+                util::TESSELLATION_DEMO,
+            )?,
+            #[cfg(feature = "demos")]
+            vertex_markers_demo: setting(
+                &args,
+                "vertex-markers-demo",
+                parse_switch,
+                util::VERTEX_MARKERS_DEMO,
+            )?,
+            #[cfg(feature = "demos")]
+            cube_demo: setting(&args, "cube-demo", parse_switch, util::CUBE_DEMO)?,
+            clear_color: setting(&args, "clear-color", parse_color, util::CLEAR_COLOR)?,
+            gpu_frame_timer: setting(
+                &args,
+                "gpu-frame-timer",
+                parse_switch,
+                util::GPU_FRAME_TIMER,
+            )?,
+            startup_decisions_log_level: setting(
+                &args,
+                "startup-decisions-log-level",
+                |value| value.parse().ok(),
+                util::STARTUP_DECISIONS_LOG_LEVEL,
+            )?,
+            #[cfg(feature = "asset-import")]
+            skybox: setting(&args, "skybox", parse_path, util::SKYBOX.map(PathBuf::from))?,
+            #[cfg(feature = "asset-import")]
+            texture: setting(
+                &args,
+                "texture",
+                parse_path,
+                util::TEXTURE.map(PathBuf::from),
+            )?,
+        };
+        args.reject_unknown()?;
+        Ok(settings)
+    }
+}
+
+/// The setting `name` from `args` or the environment, see the comment above, parsed with `parse`.
+fn setting<T>(
+    args: &Args,
+    name: &str,
+    parse: impl Fn(&str) -> Option<T>,
+    default: T,
+) -> Result<T, AppError> {
+    let arg = format!("--{}", name);
+    let env = format!("VULKAN_ASH_{}", name.to_uppercase().replace('-', "_"));
+    let (value, source) = match args.value(&arg)? {
+        Some(value) => (value, arg),
+        None => match std::env::var(&env) {
+            Ok(value) => (value.trim().to_string(), env),
+            Err(_) => return Ok(default),
+        },
+    };
+    parse(&value).ok_or_else(|| AppError::new(&format!("{} can't be {:?}", source, value)))
+}
+
+/// A switch's value, see the comment above.
+fn parse_switch(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "on" | "true" | "yes" | "1" => Some(true),
+        "off" | "false" | "no" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// A path to a file, which can't be empty.
+#[cfg(feature = "asset-import")]
+fn parse_path(value: &str) -> Option<Option<PathBuf>> {
+    (!value.is_empty()).then(|| Some(PathBuf::from(value)))
+}
+
+/// A color as comma separated components from 0 to 1: red, green, blue and optionally alpha (else opaque).
+fn parse_color(value: &str) -> Option<[f32; 4]> {
+    let components = value
+        .split(',')
+        .map(|component| component.trim().parse::<f32>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    if !components
+        .iter()
+        .all(|component| (0.0..=1.0).contains(component))
+    {
+        return None;
+    }
+    match components[..] {
+        [r, g, b] => Some([r, g, b, 1.0]),
+        [r, g, b, a] => Some([r, g, b, a]),
+        _ => None,
+    }
+}
+
+/// A sample count: 1, 2, 4, 8, 16, 32 or 64.
+fn parse_sample_count(value: &str) -> Option<vk::SampleCountFlags> {
+    let count = value.parse::<u32>().ok()?;
+    (count.is_power_of_two() && count <= 64).then(|| vk::SampleCountFlags::from_raw(count))
+}
+
+//////////////// Command Line ////////////////
+
+/// The command line (without the program name), and the options looked up in it so far.
+/// Whatever nothing looked up is unknown, see `reject_unknown`.
+pub struct Args {
+    args: Vec<String>,
+    known: RefCell<Vec<String>>,
+}
+
+impl Args {
+    pub fn new<I: IntoIterator<Item = String>>(args: I) -> Self {
+        Self {
+            args: args.into_iter().collect(),
+            known: RefCell::default(),
+        }
+    }
+
+    /// The value of the option `name`, given as `name value` or `name=value`.
+    pub fn value(&self, name: &str) -> Result<Option<String>, AppError> {
+        self.known.borrow_mut().push(name.to_string());
+        let mut args = self.args.iter();
+        while let Some(arg) = args.next() {
+            let value = match arg.strip_prefix(name) {
+                Some("") => args
+                    .next()
+                    .ok_or_else(|| AppError::new(&format!("{} needs a value", name)))?,
+                Some(value) if value.starts_with('=') => &value[1..],
+                _ => continue,
+            };
+            return Ok(Some(value.trim().to_string()));
+        }
+        Ok(None)
+    }
+
+    /// Fail on the first argument that isn't one of the options looked up so far (or its value),
+    /// listing those.
+    pub fn reject_unknown(&self) -> Result<(), AppError> {
+        let known = self.known.borrow();
+        let mut args = self.args.iter();
+        while let Some(arg) = args.next() {
+            let name = arg.split_once('=').map_or(arg.as_str(), |(name, _)| name);
+            if !known.iter().any(|option| option == name) {
+                let mut options = known.clone();
+                options.sort();
+                return Err(AppError::new(&format!(
+                    "Unknown option {:?}, the options are {}",
+                    arg,
+                    options.join(", ")
+                )));
+            }
+            if !arg.contains('=') {
+                // Its value.
+                args.next();
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::{DeviceOverride, DevicePreference};
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn values_with_space_or_equals() {
+        let args = Args::new(strings(&["--msaa-samples", "8", "--backend=deferred"]));
+        assert_eq!(args.value("--msaa-samples").unwrap().as_deref(), Some("8"));
+        assert_eq!(
+            args.value("--backend").unwrap().as_deref(),
+            Some("deferred")
+        );
+        assert_eq!(args.value("--gpu").unwrap(), None);
+        assert!(Args::new(strings(&["--gpu"])).value("--gpu").is_err());
+    }
+
+    #[test]
+    fn parses_known_options() {
+        let settings = Settings::from_args_and_env(strings(&[
+            "--msaa-samples",
+            "8",
+            "--enable-stencil=on",
+            "--clear-color",
+            "0.1,0.2,0.3",
+            "--backend",
+            "deferred",
+            "--gpu-preference=low-power",
+        ]))
+        .unwrap();
+        assert_eq!(settings.msaa_samples, vk::SampleCountFlags::TYPE_8);
+        assert!(settings.stencil);
+        assert_eq!(settings.clear_color, [0.1, 0.2, 0.3, 1.0]);
+        assert_eq!(settings.backend, BackendKind::Deferred);
+        assert_eq!(
+            settings.device_selection.preference,
+            DevicePreference::LowPower
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_options() {
+        let err = Settings::from_args_and_env(strings(&["--msaa-sample", "8"]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("\"--msaa-sample\""), "{}", err);
+        assert!(err.contains("--msaa-samples,"), "{}", err);
+        assert!(Settings::from_args_and_env(strings(&["8"])).is_err());
+        // A known option's value isn't an option itself.
+        assert!(Settings::from_args_and_env(strings(&["--gpu", "--msaa-sample"])).is_ok());
+    }
+
+    #[test]
+    fn gpu_names_and_indices_dont_collide() {
+        let selection =
+            |given: &[&str]| DeviceSelection::from_args_and_env(&Args::new(strings(given)));
+        assert_eq!(
+            selection(&["--gpu", "1"]).unwrap().device_override,
+            Some(DeviceOverride::Name("1".to_string()))
+        );
+        assert_eq!(
+            selection(&["--gpu-index=1"]).unwrap().device_override,
+            Some(DeviceOverride::Index(1))
+        );
+        assert!(selection(&["--gpu-index", "nvidia"]).is_err());
+        assert!(selection(&["--gpu", "nvidia", "--gpu-index", "1"]).is_err());
+        assert!(selection(&["--gpu="]).is_err());
+    }
+
+    #[test]
+    fn parses_values() {
+        assert_eq!(parse_switch("ON"), Some(true));
+        assert_eq!(parse_switch("0"), Some(false));
+        assert_eq!(parse_switch("maybe"), None);
+        assert_eq!(parse_color("0,0.5,1,0.25"), Some([0.0, 0.5, 1.0, 0.25]));
+        assert_eq!(parse_color("0,0.5"), None);
+        assert_eq!(parse_color("0,0.5,2"), None);
+        assert_eq!(parse_sample_count("4"), Some(vk::SampleCountFlags::TYPE_4));
+        assert_eq!(parse_sample_count("3"), None);
+        assert_eq!(parse_sample_count("128"), None);
+    }
+}
@@ -0,0 +1,304 @@
+use std::borrow::Cow;
+#[cfg(feature = "shaderc")]
+use std::cell::RefCell;
+#[cfg(feature = "shaderc")]
+use std::collections::HashSet;
+use std::error::Error;
+use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+
+use crate::assets;
+use crate::util::AppError;
+
+//////////////// Shader Loading ////////////////
+// SPIR-V is read from the shader directory when pipelines are built, so shaders recompiled into it
+// (e.g. `glslc shader.vert -o shader.vert.spv`) are picked up without rebuilding.
+// The directory is `shaders/` next to the executable unless SHADER_DIR_ENV points somewhere else. Run from
+// the crate's own target directory (e.g. `cargo run`), it's the crate's `shaders/`, so the sources being
+// edited are used. Shaders that aren't there are loaded from the copies built into the binary (see
+// assets.rs), so it runs without the directory, e.g. when only the binary was installed.
+// With the shaderc feature the GLSL sources next to them are compiled instead, when they exist,
+// so editing a shader only takes a restart.
+// GLSL sources can #include shared headers (e.g. `common.glsl`): `"..."` relative to the including file,
+// `<...>` from the shader directory. Which shader included which header (by canonical path, so headers
+// with the same name in different directories are told apart) is remembered by the ShaderCache that
+// loaded it, so hot reload can rebuild every shader using a header that changed (as long as it's in the
+// shader directory).
+// The hlsl feature does the same for HLSL sources (`shader.vert.hlsl` for `shader.vert.spv`), through DXC.
+// Their entry point and shader model are HLSL_ENTRY_POINT and HLSL_SHADER_MODEL, unless the
+// environment variables below say otherwise.
+
+pub const SHADER_DIR_ENV: &str = "VULKAN_ASH_SHADER_DIR";
+/// Where the crate was built from, see `shader_dir`.
+const CRATE_DIR: &str = env!("CARGO_MANIFEST_DIR");
+/// Name of the shader directory, next to the executable or in the crate.
+const SHADER_DIR_NAME: &str = "shaders";
+
+#[cfg(feature = "hlsl")]
+pub const HLSL_ENTRY_POINT_ENV: &str = "VULKAN_ASH_HLSL_ENTRY_POINT";
+/// The function HLSL shaders start at.
+#[cfg(feature = "hlsl")]
+const HLSL_ENTRY_POINT: &str = "main";
+#[cfg(feature = "hlsl")]
+pub const HLSL_SHADER_MODEL_ENV: &str = "VULKAN_ASH_HLSL_SHADER_MODEL";
+/// The shader model HLSL shaders are compiled for, e.g. `6_0` for the `vs_6_0` and `ps_6_0` profiles.
+#[cfg(feature = "hlsl")]
+const HLSL_SHADER_MODEL: &str = "6_0";
+
+/// The first word of every SPIR-V module.
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+/// Where shaders are loaded from, see the comment at the top.
+pub fn shader_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os(SHADER_DIR_ENV) {
+        return PathBuf::from(dir);
+    }
+    match std::env::current_exe() {
+        Ok(exe) if exe.starts_with(CRATE_DIR) => Path::new(CRATE_DIR).join(SHADER_DIR_NAME),
+        Ok(exe) => exe.with_file_name(SHADER_DIR_NAME),
+        // Relative to the working directory then.
+        Err(_) => PathBuf::from(SHADER_DIR_NAME),
+    }
+}
+
+/// Read the SPIR-V file `name` from the shader directory as words in native byte order,
+/// or its embedded copy if the file doesn't exist.
+/// With the shaderc feature, its GLSL source (`name` without `.spv`) is compiled instead if it exists.
+/// With the hlsl feature, so is its HLSL source (`name` with `.hlsl` instead of `.spv`).
+// The shader cache uses load_with_headers instead when it keeps the headers for hot reload.
+#[cfg(not(all(feature = "shaderc", feature = "hot-reload")))]
+pub fn load(name: &str) -> Result<Vec<u32>, Box<dyn Error>> {
+    #[cfg(feature = "shaderc")]
+    return load_with_headers(name).map(|(code, _)| code);
+    #[cfg(not(feature = "shaderc"))]
+    load_compiled(name)
+}
+
+/// Like `load`, with the headers (canonical paths) its GLSL source included, directly or not.
+/// Empty if it wasn't compiled from GLSL.
+#[cfg(feature = "shaderc")]
+pub fn load_with_headers(name: &str) -> Result<(Vec<u32>, HashSet<PathBuf>), Box<dyn Error>> {
+    if let Some(source_name) = name.strip_suffix(".spv") {
+        let source_path = shader_dir().join(source_name);
+        if source_path.exists() {
+            return compile_glsl(&source_path);
+        }
+    }
+    Ok((load_compiled(name)?, HashSet::new()))
+}
+
+/// `load`, without looking for a GLSL source.
+fn load_compiled(name: &str) -> Result<Vec<u32>, Box<dyn Error>> {
+    #[cfg(feature = "hlsl")]
+    if let Some(source_name) = name.strip_suffix(".spv") {
+        let source_path = shader_dir().join(format!("{}.hlsl", source_name));
+        if source_path.exists() {
+            return compile_hlsl(&source_path);
+        }
+    }
+
+    let path = shader_dir().join(name);
+    let bytes = match std::fs::read(&path) {
+        Ok(bytes) => Cow::Owned(bytes),
+        Err(err) => match assets::embedded_shader(name) {
+            Some(bytes) if err.kind() == io::ErrorKind::NotFound => {
+                log::debug!(
+                    "{} doesn't exist, using the shader built into the binary",
+                    path.display()
+                );
+                Cow::Borrowed(bytes)
+            }
+            _ => {
+                return Err(Box::new(AppError::new(&format!(
+                    "Failed to read shader {}: {} (set {} to the directory the .spv files are in)",
+                    path.display(),
+                    err,
+                    SHADER_DIR_ENV
+                ))))
+            }
+        },
+    };
+
+    if bytes.is_empty() || bytes.len() % 4 != 0 {
+        return Err(Box::new(AppError::new(&format!(
+            "{} isn't SPIR-V: {} bytes isn't a whole number of 32-bit words",
+            path.display(),
+            bytes.len()
+        ))));
+    }
+    // Swaps the words if the module was written with the other byte order.
+    let words = ash::util::read_spv(&mut Cursor::new(&bytes))?;
+    if words[0] != SPIRV_MAGIC {
+        return Err(Box::new(AppError::new(&format!(
+            "{} isn't SPIR-V: it starts with {:#010x} instead of the magic number {:#010x}",
+            path.display(),
+            words[0],
+            SPIRV_MAGIC
+        ))));
+    }
+
+    log::debug!("Loaded shader {} ({} words)", path.display(), words.len());
+    Ok(words)
+}
+
+/// Compile the GLSL source at `path` for Vulkan, and return the canonical paths of the headers it included.
+/// The stage comes from the extension: `.vert`, `.frag`, `.comp`, `.tesc`, `.tese` or `.geom`.
+/// Warnings are logged, errors point at the file and line they're about.
+#[cfg(feature = "shaderc")]
+fn compile_glsl(path: &Path) -> Result<(Vec<u32>, HashSet<PathBuf>), Box<dyn Error>> {
+    let file_name = path.display().to_string();
+    let kind = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("vert") => shaderc::ShaderKind::Vertex,
+        Some("frag") => shaderc::ShaderKind::Fragment,
+        Some("comp") => shaderc::ShaderKind::Compute,
+        Some("tesc") => shaderc::ShaderKind::TessControl,
+        Some("tese") => shaderc::ShaderKind::TessEvaluation,
+        Some("geom") => shaderc::ShaderKind::Geometry,
+        _ => {
+            return Err(Box::new(AppError::new(&format!(
+                "Don't know the shader stage of {}, expected .vert, .frag, .comp, .tesc, .tese or .geom",
+                file_name
+            ))))
+        }
+    };
+    let source = std::fs::read_to_string(path)
+        .map_err(|err| AppError::new(&format!("Failed to read shader {}: {}", file_name, err)))?;
+
+    // Declared before `options`, whose include callback borrows it.
+    let headers = RefCell::new(HashSet::new());
+    let mut compiler = shaderc::Compiler::new()
+        .ok_or_else(|| AppError::new("Failed to initialize the shaderc compiler"))?;
+    let mut options = shaderc::CompileOptions::new()
+        .ok_or_else(|| AppError::new("Failed to initialize shaderc compile options"))?;
+    options.set_target_env(
+        shaderc::TargetEnv::Vulkan,
+        shaderc::EnvVersion::Vulkan1_0 as u32,
+    );
+    options.set_generate_debug_info();
+    options.set_include_callback(|requested, include_type, requesting, _depth| {
+        let directory = match include_type {
+            // Tried again as Standard if this fails, like C compilers do.
+            shaderc::IncludeType::Relative => Path::new(requesting)
+                .parent()
+                .map_or_else(shader_dir, Path::to_path_buf),
+            shaderc::IncludeType::Standard => shader_dir(),
+        };
+        let header_path = directory.join(requested);
+        let content = std::fs::read_to_string(&header_path)
+            .map_err(|err| format!("Failed to read {}: {}", header_path.display(), err))?;
+        // It was just read, so it exists.
+        let header = std::fs::canonicalize(&header_path).unwrap_or_else(|_| header_path.clone());
+        headers.borrow_mut().insert(header);
+        Ok(shaderc::ResolvedInclude {
+            resolved_name: header_path.display().to_string(),
+            content,
+        })
+    });
+
+    match compiler.compile_into_spirv(&source, kind, &file_name, "main", Some(&options)) {
+        Ok(artifact) => {
+            if artifact.get_num_warnings() > 0 {
+                for warning in diagnostics(&file_name, &source, &artifact.get_warning_messages()) {
+                    log::warn!("{}", warning);
+                }
+            }
+            log::debug!("Compiled shader {}", file_name);
+            Ok((artifact.as_binary().to_vec(), headers.take()))
+        }
+        Err(shaderc::Error::CompilationError(count, messages)) => {
+            Err(Box::new(AppError::new(&format!(
+                "Failed to compile {} ({} errors):\n{}",
+                file_name,
+                count,
+                diagnostics(&file_name, &source, &messages).join("\n")
+            ))))
+        }
+        Err(err) => Err(Box::new(AppError::new(&format!(
+            "Failed to compile {}: {}",
+            file_name, err
+        )))),
+    }
+}
+
+/// Compile the HLSL source at `path` to SPIR-V with DXC. The stage comes from the extension before `.hlsl`:
+/// `.vert`, `.frag`, `.comp`, `.tesc` (hull shader), `.tese` (domain shader) or `.geom`.
+/// Whatever the entry point is called, the module's is `main`, which is what pipelines are built with.
+#[cfg(feature = "hlsl")]
+fn compile_hlsl(path: &Path) -> Result<Vec<u32>, Box<dyn Error>> {
+    let file_name = path.display().to_string();
+    let stage = path
+        .file_stem()
+        .map(Path::new)
+        .and_then(|stem| stem.extension())
+        .and_then(|extension| extension.to_str());
+    let stage_profile = match stage {
+        Some("vert") => "vs",
+        Some("frag") => "ps",
+        Some("comp") => "cs",
+        Some("tesc") => "hs",
+        Some("tese") => "ds",
+        Some("geom") => "gs",
+        _ => {
+            return Err(Box::new(AppError::new(&format!(
+                "Don't know the shader stage of {}, expected .vert.hlsl, .frag.hlsl, .comp.hlsl, .tesc.hlsl, .tese.hlsl or .geom.hlsl",
+                file_name
+            ))))
+        }
+    };
+    let source = std::fs::read_to_string(path)
+        .map_err(|err| AppError::new(&format!("Failed to read shader {}: {}", file_name, err)))?;
+
+    let entry_point =
+        std::env::var(HLSL_ENTRY_POINT_ENV).unwrap_or_else(|_| HLSL_ENTRY_POINT.to_string());
+    let shader_model =
+        std::env::var(HLSL_SHADER_MODEL_ENV).unwrap_or_else(|_| HLSL_SHADER_MODEL.to_string());
+    let profile = format!("{}_{}", stage_profile, shader_model);
+    let args = [
+        "-spirv",
+        "-fspv-target-env=vulkan1.0",
+        "-fspv-entrypoint-name=main",
+    ];
+
+    let bytes = hassle_rs::compile_hlsl(&file_name, &source, &entry_point, &profile, &args, &[])
+        .map_err(|err| match err {
+            hassle_rs::HassleError::CompileError(messages) => AppError::new(&format!(
+                "Failed to compile {} ({} {}):\n{}",
+                file_name,
+                entry_point,
+                profile,
+                messages.trim_end()
+            )),
+            err => AppError::new(&format!(
+                "Failed to compile {}: {} (is DXC's libdxcompiler installed?)",
+                file_name, err
+            )),
+        })?;
+    log::debug!(
+        "Compiled shader {} ({} {})",
+        file_name,
+        entry_point,
+        profile
+    );
+    Ok(ash::util::read_spv(&mut Cursor::new(&bytes))?)
+}
+
+/// shaderc's `messages`, one per line. Ones about a line of `file_name` come as
+/// `file_name:line: severity: message`, those get the offending `source` line appended.
+#[cfg(feature = "shaderc")]
+fn diagnostics(file_name: &str, source: &str, messages: &str) -> Vec<String> {
+    messages
+        .lines()
+        .filter(|message| !message.trim().is_empty())
+        .map(|message| {
+            let line = message
+                .strip_prefix(file_name)
+                .and_then(|rest| rest.strip_prefix(':'))
+                .and_then(|rest| rest.split_once(':'))
+                .and_then(|(line, _)| line.parse::<usize>().ok());
+            match line.and_then(|line| Some((line, source.lines().nth(line.checked_sub(1)?)?))) {
+                Some((line, text)) => format!("{}\n    {} | {}", message, line, text.trim_end()),
+                None => message.to_string(),
+            }
+        })
+        .collect()
+}
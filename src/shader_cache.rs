@@ -0,0 +1,160 @@
+use ash::{vk, Device};
+use std::collections::HashMap;
+#[cfg(feature = "hot-reload")]
+use std::collections::HashSet;
+use std::error::Error;
+use std::hash::{DefaultHasher, Hash, Hasher};
+#[cfg(all(feature = "shaderc", feature = "hot-reload"))]
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::shader;
+use crate::vulkan_create;
+
+//////////////// Shader Module Cache ////////////////
+// Shader modules are kept, keyed by a hash of their SPIR-V, instead of being created for every pipeline
+// and destroyed right after. Pipeline variants sharing shaders (e.g. wireframe) and hot reloads of shaders
+// whose SPIR-V didn't change reuse the module, and skip validating it again. When a shader's SPIR-V does
+// change, the module of its previous code is evicted, pipelines don't need their modules once they're built.
+// Each shader's SPIR-V is loaded (or compiled, see shader.rs) once and kept, so reflecting a shader's
+// interface and creating its module don't both load it. Hot reload has the shaders that changed loaded
+// again. With shaders compiled from GLSL, it remembers the headers each one included, for hot reload.
+// The cache also holds a vk::PipelineCache, so the driver can reuse its compiled code between pipelines.
+// Everything lives until `destroy`, which has to come before the device is destroyed.
+
+/// A shader's SPIR-V words, shared by the cache and whatever asked for them.
+pub type Spirv = Arc<[u32]>;
+
+/// Shaders, their modules and the pipeline cache, see the comment above. Clones share them, so whatever builds
+/// pipelines can keep one.
+#[derive(Clone)]
+pub struct ShaderCache {
+    shared: Arc<Mutex<Cache>>,
+}
+
+struct Cache {
+    // The SPIR-V of each shader (by name) loaded so far.
+    code: HashMap<String, Spirv>,
+    // Modules and their SPIR-V (compared on hits, hashes can collide) by hash of the SPIR-V.
+    modules: HashMap<u64, Vec<(Spirv, vk::ShaderModule)>>,
+    // The hash of the SPIR-V each shader (by name) had when its module was last asked for.
+    shaders: HashMap<String, u64>,
+    // The headers (canonical paths) each shader (by name) included when it was last compiled from GLSL.
+    #[cfg(all(feature = "shaderc", feature = "hot-reload"))]
+    includes: HashMap<String, HashSet<PathBuf>>,
+    pipeline_cache: vk::PipelineCache,
+}
+
+impl ShaderCache {
+    /// An empty cache with a new pipeline cache on `device`.
+    pub fn new(device: &Device) -> Result<Self, Box<dyn Error>> {
+        let pipeline_cache_create_info = vk::PipelineCacheCreateInfo::default();
+        let pipeline_cache =
+            unsafe { device.create_pipeline_cache(&pipeline_cache_create_info, None)? };
+        Ok(Self {
+            shared: Arc::new(Mutex::new(Cache {
+                code: HashMap::new(),
+                modules: HashMap::new(),
+                shaders: HashMap::new(),
+                #[cfg(all(feature = "shaderc", feature = "hot-reload"))]
+                includes: HashMap::new(),
+                pipeline_cache,
+            })),
+        })
+    }
+
+    /// The SPIR-V of shader `name`, loaded (see `shader::load`) the first time it's asked for,
+    /// and again after `reload`.
+    pub fn code(&self, name: &str) -> Result<Spirv, Box<dyn Error>> {
+        if let Some(code) = self.shared.lock().unwrap().code.get(name) {
+            return Ok(Arc::clone(code));
+        }
+
+        // Without holding the lock, compiling can take a while.
+        #[cfg(all(feature = "shaderc", feature = "hot-reload"))]
+        let (code, headers) = shader::load_with_headers(name)?;
+        #[cfg(not(all(feature = "shaderc", feature = "hot-reload")))]
+        let code = shader::load(name)?;
+        let code = Spirv::from(code);
+
+        let mut cache = self.shared.lock().unwrap();
+        #[cfg(all(feature = "shaderc", feature = "hot-reload"))]
+        cache.includes.insert(name.to_string(), headers);
+        cache.code.insert(name.to_string(), Arc::clone(&code));
+        Ok(code)
+    }
+
+    /// Load the shaders `names` again the next time they're asked for, e.g. after they changed on disk.
+    #[cfg(feature = "hot-reload")]
+    pub fn reload(&self, names: &HashSet<String>) {
+        let mut cache = self.shared.lock().unwrap();
+        cache.code.retain(|name, _| !names.contains(name));
+    }
+
+    /// The module for the SPIR-V shader `name` (see `code`), created if no module with the same code
+    /// was yet. If `name` had other code before, that code's module is destroyed, unless another
+    /// shader still has it. It's owned by the cache, don't destroy it.
+    pub fn module(&self, device: &Device, name: &str) -> Result<vk::ShaderModule, Box<dyn Error>> {
+        let code = self.code(name)?;
+        let mut hasher = DefaultHasher::new();
+        code.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        let mut cache = self.shared.lock().unwrap();
+        if let Some(previous) = cache.shaders.insert(name.to_string(), hash) {
+            if previous != hash && !cache.shaders.values().any(|other| *other == previous) {
+                log::debug!("Evicting the previous shader module of {}", name);
+                for (_, module) in cache.modules.remove(&previous).into_iter().flatten() {
+                    unsafe { device.destroy_shader_module(module, None) };
+                }
+            }
+        }
+
+        let modules = cache.modules.entry(hash).or_default();
+        if let Some((_, module)) = modules.iter().find(|(cached, _)| *cached == code) {
+            log::trace!("Reusing shader module for {}", name);
+            return Ok(*module);
+        }
+
+        let module = vulkan_create::shader_module(device, name, &code)?;
+        modules.push((code, module));
+        Ok(module)
+    }
+
+    /// The shaders (by name) that included the header at `path` when they were last compiled from GLSL,
+    /// directly or not. For hot reload, which rebuilds them when it changes.
+    #[cfg(all(feature = "shaderc", feature = "hot-reload"))]
+    pub fn including(&self, path: &Path) -> Vec<String> {
+        let Ok(path) = std::fs::canonicalize(path) else {
+            return Vec::new();
+        };
+        let cache = self.shared.lock().unwrap();
+        cache
+            .includes
+            .iter()
+            .filter(|(_, headers)| headers.contains(&path))
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// The pipeline cache to create pipelines with. It's owned by the cache, don't destroy it.
+    pub fn pipeline_cache(&self) -> vk::PipelineCache {
+        self.shared.lock().unwrap().pipeline_cache
+    }
+
+    /// Destroy every module and the pipeline cache. Pipelines built from them don't need them anymore.
+    pub fn destroy(&self, device: &Device) {
+        let mut cache = self.shared.lock().unwrap();
+        unsafe {
+            cache
+                .modules
+                .drain()
+                .flat_map(|(_, modules)| modules)
+                .for_each(|(_, module)| device.destroy_shader_module(module, None));
+            device.destroy_pipeline_cache(cache.pipeline_cache, None);
+        }
+        cache.code.clear();
+        cache.shaders.clear();
+        cache.pipeline_cache = vk::PipelineCache::null();
+    }
+}
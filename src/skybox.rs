@@ -0,0 +1,202 @@
+use ash::{vk, Device};
+use std::error::Error;
+
+use crate::command::Draw;
+use crate::descriptor::{self, DescriptorManager};
+use crate::pipeline::GraphicsPipelineBuilder;
+use crate::reflect::ShaderInterface;
+use crate::render_target::Rendering;
+use crate::shader_cache::ShaderCache;
+use crate::texture::Texture;
+use crate::uniform::{self, Mat4};
+use crate::util::DeviceDetails;
+
+//////////////// Skybox ////////////////
+// A cubemap drawn behind everything else: one screen covering triangle on the far plane,
+// depth tested (LESS_OR_EQUAL, no writes) so it only shows where nothing else was drawn.
+// Rounding can push it just past the far plane, so it's depth clamped where the device supports that.
+// Where it supports depth bounds tests, the covered pixels are also rejected before the fragment shader runs.
+// Drawing it after the scene means the hidden parts aren't shaded at all.
+
+/// Size of `push_constants`: the matrix from clip space to cubemap directions.
+const PUSH_CONSTANTS_SIZE: u32 = 64;
+
+/// The shaders the pipeline is built from.
+pub const SHADERS: [&str; 2] = ["skybox.vert.spv", "skybox.frag.spv"];
+
+/// The pipeline, descriptor set and cubemap to draw the skybox with.
+pub struct Skybox {
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    // Owned by the DescriptorManager, kept to rebuild the pipeline.
+    #[cfg(feature = "hot-reload")]
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_set: vk::DescriptorSet,
+    cubemap: Texture,
+}
+
+impl Skybox {
+    /// Build the pipeline for `rendering` and a descriptor set for `cubemap`
+    /// (from `Texture::cubemap_from_files` or `Texture::cubemap_from_cross`), which the skybox takes over.
+    /// The set's layout lives in `descriptors`.
+    pub fn new(
+        device: &Device,
+        shader_cache: &ShaderCache,
+        device_details: &DeviceDetails,
+        descriptors: &mut DescriptorManager,
+        rendering: Rendering,
+        samples: vk::SampleCountFlags,
+        mut cubemap: Texture,
+    ) -> Result<Self, Box<dyn Error>> {
+        let resources = (|| {
+            // Image and sampler are separate descriptors, see shaders/skybox.frag.
+            let interface = ShaderInterface::from_shaders(shader_cache, &SHADERS)?;
+            let layout = descriptors.create_layout(device, &interface.set_bindings(0))?;
+            let descriptor_set = descriptors.allocate(device, layout, 1)?[0];
+            descriptor::write_separate_texture(device, descriptor_set, 0, 1, &cubemap);
+
+            let (pipeline, pipeline_layout) = build_pipeline(
+                device,
+                shader_cache,
+                &interface,
+                layout,
+                (rendering, samples),
+                device_details,
+            )?;
+
+            Ok::<_, Box<dyn Error>>((pipeline, pipeline_layout, layout, descriptor_set))
+        })();
+
+        match resources {
+            // The layout is only kept to rebuild the pipeline with.
+            #[cfg_attr(not(feature = "hot-reload"), allow(unused_variables))]
+            Ok((pipeline, pipeline_layout, descriptor_set_layout, descriptor_set)) => Ok(Self {
+                pipeline,
+                pipeline_layout,
+                #[cfg(feature = "hot-reload")]
+                descriptor_set_layout,
+                descriptor_set,
+                cubemap,
+            }),
+            Err(err) => {
+                cubemap.destroy(device);
+                Err(err)
+            }
+        }
+    }
+
+    /// Draw `cubemap` from now on (taking it over like `new`), destroying the previous one.
+    /// The GPU must be done with the descriptor set and the previous cubemap.
+    pub fn set_cubemap(&mut self, device: &Device, cubemap: Texture) {
+        descriptor::write_separate_texture(device, self.descriptor_set, 0, 1, &cubemap);
+        let mut previous = std::mem::replace(&mut self.cubemap, cubemap);
+        previous.destroy(device);
+    }
+
+    /// Rebuild the pipeline from the current `SHADERS`, e.g. after they changed on disk.
+    /// Their descriptor set has to stay the same.
+    /// Keeps the previous pipeline if building fails. The GPU must be done with it.
+    #[cfg(feature = "hot-reload")]
+    pub fn rebuild_pipeline(
+        &mut self,
+        device: &Device,
+        shader_cache: &ShaderCache,
+        device_details: &DeviceDetails,
+        rendering: Rendering,
+        samples: vk::SampleCountFlags,
+    ) -> Result<(), Box<dyn Error>> {
+        let interface = ShaderInterface::from_shaders(shader_cache, &SHADERS)?;
+        let (pipeline, pipeline_layout) = build_pipeline(
+            device,
+            shader_cache,
+            &interface,
+            self.descriptor_set_layout,
+            (rendering, samples),
+            device_details,
+        )?;
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        self.pipeline = pipeline;
+        self.pipeline_layout = pipeline_layout;
+        Ok(())
+    }
+
+    /// Push constants for `inverse_view_projection`, which maps clip space positions to directions
+    /// into the cubemap (only the view's rotation matters).
+    pub fn push_constants(inverse_view_projection: &Mat4) -> [u8; 64] {
+        let mut bytes = [0u8; PUSH_CONSTANTS_SIZE as usize];
+        for (chunk, value) in bytes
+            .chunks_exact_mut(4)
+            .zip(inverse_view_projection.iter().flatten())
+        {
+            chunk.copy_from_slice(&value.to_ne_bytes());
+        }
+        bytes
+    }
+
+    /// The draw for the skybox, with `push_constants` from `Skybox::push_constants`.
+    pub fn draw<'a>(&'a self, push_constants: &'a [u8; 64]) -> Draw<'a> {
+        Draw {
+            pipeline: self.pipeline,
+            pipeline_layout: self.pipeline_layout,
+            descriptor_sets: std::slice::from_ref(&self.descriptor_set),
+            push_descriptors: None,
+            push_constants: Some((vk::ShaderStageFlags::VERTEX, push_constants)),
+            vertex_buffer: None,
+            instance_buffer: None,
+            index_buffer: None,
+            vertex_count: 3,
+        }
+    }
+
+    /// Destroy the pipeline and cubemap. The descriptor set is freed with the DescriptorManager.
+    /// The GPU must be done with them.
+    pub fn destroy(&mut self, device: &Device) {
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        self.cubemap.destroy(device);
+    }
+}
+
+fn build_pipeline(
+    device: &Device,
+    shader_cache: &ShaderCache,
+    interface: &ShaderInterface,
+    layout: vk::DescriptorSetLayout,
+    (rendering, samples): (Rendering, vk::SampleCountFlags),
+    device_details: &DeviceDetails,
+) -> Result<(vk::Pipeline, vk::PipelineLayout), Box<dyn Error>> {
+    let push_constant_ranges = interface.push_constant_ranges();
+    let set_layouts = [layout];
+    let mut builder = GraphicsPipelineBuilder::default()
+        .vertex_shader(SHADERS[0])
+        .fragment_shader(SHADERS[1])
+        .vertex_input(&[], &[])
+        .descriptor_set_layouts(&set_layouts)
+        .push_constant_ranges(&push_constant_ranges)
+        .depth_write(false)
+        .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .samples(samples);
+    if device_details.depth_clamp {
+        builder = builder.depth_clamp(device_details);
+    }
+    if device_details.depth_bounds {
+        builder = builder.depth_bounds(device_details, 1.0, 1.0);
+    }
+    builder.build(device, shader_cache, rendering)
+}
+
+/// The inverse view projection for looking down +Z with Y up and no camera,
+/// with the field of view fitted to the height of a target of size `extent`.
+pub fn fixed_view(extent: vk::Extent2D) -> Mat4 {
+    let mut matrix = uniform::IDENTITY;
+    matrix[0][0] = extent.width as f32 / extent.height as f32;
+    // Clip space Y points down.
+    matrix[1][1] = -1.0;
+    matrix
+}
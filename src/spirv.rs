@@ -0,0 +1,86 @@
+use std::error::Error;
+
+//////////////// SPIR-V Validation ////////////////
+// Drivers don't validate SPIR-V, so a broken module tends to show up as a crash or garbage on screen.
+// In debug builds every module is run through naga's validator first, so it fails fast with a readable error.
+// naga's SPIR-V frontend doesn't cover every capability, so this can be turned off with the feature.
+// It has no tessellation or geometry stages or input attachments at all, modules using those are left to
+// the driver (and the validation layers).
+
+/// Validate SPIR-V `code` (identified by `name` in errors).
+#[cfg(feature = "spirv-validation")]
+pub fn validate(name: &str, code: &[u32]) -> Result<(), Box<dyn Error>> {
+    use crate::util::AppError;
+
+    if !naga_supports(code) {
+        log::debug!(
+            "Not validating SPIR-V {}, naga doesn't support its stage or input attachments",
+            name
+        );
+        return Ok(());
+    }
+
+    let bytes: Vec<u8> = code.iter().flat_map(|word| word.to_le_bytes()).collect();
+
+    let module = naga::front::spv::parse_u8_slice(&bytes, &naga::front::spv::Options::default())
+        .map_err(|err| AppError::new(&format!("Failed to parse SPIR-V {}: {}", name, err)))?;
+
+    naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::all(),
+    )
+    .validate(&module)
+    .map_err(|err| {
+        // The interesting detail is usually at the bottom of the error chain.
+        let mut message = format!("SPIR-V validation failed for {}: {}", name, err);
+        let mut source = err.as_inner().source();
+        while let Some(inner) = source {
+            message.push_str(&format!("\n   - {}", inner));
+            source = inner.source();
+        }
+        AppError::new(&message)
+    })?;
+
+    log::debug!("Validated SPIR-V {}", name);
+    Ok(())
+}
+
+/// Whether every entry point of `code` is a vertex, fragment or compute shader, and it doesn't read
+/// input attachments.
+#[cfg(feature = "spirv-validation")]
+fn naga_supports(code: &[u32]) -> bool {
+    use rspirv::spirv::{Capability, ExecutionModel, Op};
+
+    // After the 5 word header, each instruction starts with its word count and opcode.
+    let mut offset = 5;
+    while let Some(&first_word) = code.get(offset) {
+        let word_count = (first_word >> 16) as usize;
+        if first_word & 0xffff == Op::Capability as u32
+            && code.get(offset + 1).copied() == Some(Capability::InputAttachment as u32)
+        {
+            return false;
+        }
+        if first_word & 0xffff == Op::EntryPoint as u32 {
+            let model = code.get(offset + 1).copied();
+            let supported = [
+                ExecutionModel::Vertex,
+                ExecutionModel::Fragment,
+                ExecutionModel::GLCompute,
+            ];
+            if !supported.iter().any(|stage| Some(*stage as u32) == model) {
+                return false;
+            }
+        }
+        if word_count == 0 {
+            break;
+        }
+        offset += word_count;
+    }
+    true
+}
+
+/// Validation is compiled out.
+#[cfg(not(feature = "spirv-validation"))]
+pub fn validate(_name: &str, _code: &[u32]) -> Result<(), Box<dyn Error>> {
+    Ok(())
+}
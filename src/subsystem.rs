@@ -0,0 +1,245 @@
+#[cfg(any(feature = "demos", feature = "asset-import", feature = "ui"))]
+use ash::vk;
+use ash::Device;
+#[cfg(feature = "hot-reload")]
+use std::collections::HashSet;
+#[cfg(all(
+    feature = "hot-reload",
+    any(
+        feature = "demos",
+        feature = "asset-import",
+        feature = "ui",
+        feature = "post-processing"
+    )
+))]
+use std::error::Error;
+
+use crate::command::Draw;
+#[cfg(feature = "demos")]
+use crate::demos::Demos;
+#[cfg(feature = "post-processing")]
+use crate::post::ComputePostProcess;
+#[cfg(any(
+    feature = "demos",
+    feature = "ui",
+    all(feature = "hot-reload", feature = "asset-import")
+))]
+use crate::render_target::Rendering;
+#[cfg(feature = "asset-import")]
+use crate::scenery::Scenery;
+#[cfg(any(
+    feature = "demos",
+    feature = "ui",
+    all(
+        feature = "hot-reload",
+        any(feature = "asset-import", feature = "post-processing")
+    )
+))]
+use crate::shader_cache::ShaderCache;
+#[cfg(feature = "ui")]
+use crate::ui::Ui;
+#[cfg(any(
+    feature = "demos",
+    all(feature = "hot-reload", feature = "asset-import")
+))]
+use crate::util::DeviceDetails;
+
+//////////////// Optional Subsystems ////////////////
+// What each cargo feature (see Cargo.toml) adds to the app lives in one struct, in modules only compiled
+// with the feature: the demos, the imported scenery, the UI and the compute post process. `Subsystems`
+// holds them, and VulkanApp goes through `Subsystem` for what they all do (draw, reload, describe
+// themselves, clean up), so it doesn't need to know which features are enabled.
+// `--no-default-features` leaves `Subsystems` empty.
+
+/// What pipelines are built with and for: the demos and the UI build theirs with it, and every
+/// subsystem rebuilds with it on hot reload. Each field is there with a subsystem that reads it.
+#[cfg(any(
+    feature = "demos",
+    feature = "ui",
+    all(
+        feature = "hot-reload",
+        any(feature = "asset-import", feature = "post-processing")
+    )
+))]
+pub struct PipelineContext<'a> {
+    pub device: &'a Device,
+    pub shader_cache: &'a ShaderCache,
+    #[cfg(any(
+        feature = "demos",
+        all(feature = "hot-reload", feature = "asset-import")
+    ))]
+    pub device_details: &'a DeviceDetails,
+    #[cfg(any(
+        feature = "demos",
+        feature = "ui",
+        all(feature = "hot-reload", feature = "asset-import")
+    ))]
+    pub rendering: Rendering,
+    #[cfg(any(
+        feature = "demos",
+        feature = "ui",
+        all(feature = "hot-reload", feature = "asset-import")
+    ))]
+    pub samples: vk::SampleCountFlags,
+}
+
+/// What the frame being recorded draws into, with the fields some subsystem reads.
+pub struct Frame {
+    /// Seconds since the app started, for animations.
+    #[cfg(feature = "demos")]
+    pub time: f32,
+    #[cfg(any(feature = "demos", feature = "asset-import", feature = "ui"))]
+    pub extent: vk::Extent2D,
+}
+
+/// See the comment at the top.
+pub trait Subsystem {
+    /// What it's drawing, as (name, value) pairs, e.g. for capture metadata.
+    fn describe(&self) -> Vec<(&'static str, String)>;
+
+    /// The shaders (file names) of the pipelines it has.
+    #[cfg(feature = "hot-reload")]
+    fn shaders(&self) -> Vec<&'static str>;
+
+    /// Rebuild the pipelines using any of the `changed` shaders. Failures are logged, and the previous
+    /// pipeline kept. The GPU must be done with them.
+    #[cfg(all(
+        feature = "hot-reload",
+        any(
+            feature = "demos",
+            feature = "asset-import",
+            feature = "ui",
+            feature = "post-processing"
+        )
+    ))]
+    fn rebuild_pipelines(&mut self, context: &PipelineContext, changed: &HashSet<String>);
+
+    /// Whether its draws change every frame, so recorded scenes can't be reused.
+    fn animated(&self) -> bool {
+        false
+    }
+
+    /// Get what its draws push ready for `frame`.
+    fn prepare(&mut self, _frame: &Frame) {}
+
+    /// Add its draws, to `scene` (after `triangles`, the scene's own draw) or `overlays` (after the scene).
+    fn draws<'a>(
+        &'a self,
+        _triangles: &Draw<'a>,
+        _scene: &mut Vec<Draw<'a>>,
+        _overlays: &mut Vec<Draw<'a>>,
+    ) {
+    }
+
+    /// Add its draws that go on top of the finished frame, after post-processing, see
+    /// `ComputePostProcess::final_pass`. Only asked for with post-processing on.
+    #[cfg(feature = "post-processing")]
+    fn final_draws<'a>(&'a self, _draws: &mut Vec<Draw<'a>>) {}
+
+    /// Destroy everything it created. The GPU must be done with it.
+    fn destroy(&mut self, device: &Device);
+}
+
+/// One struct per enabled feature, see the comment at the top.
+#[derive(Default)]
+pub struct Subsystems {
+    #[cfg(feature = "demos")]
+    pub demos: Demos,
+    #[cfg(feature = "asset-import")]
+    pub scenery: Scenery,
+    #[cfg(feature = "ui")]
+    pub ui: Ui,
+    // Set if the compute post process is on and the device supports it.
+    #[cfg(feature = "post-processing")]
+    pub post_process: Option<ComputePostProcess>,
+}
+
+impl Subsystems {
+    /// All of them, in the order they draw.
+    // One `Option` per subsystem, `None` for those whose feature is off.
+    pub fn all(&self) -> Vec<&dyn Subsystem> {
+        #[cfg(feature = "demos")]
+        let demos = Some(&self.demos as &dyn Subsystem);
+        #[cfg(not(feature = "demos"))]
+        let demos = None;
+        #[cfg(feature = "asset-import")]
+        let scenery = Some(&self.scenery as &dyn Subsystem);
+        #[cfg(not(feature = "asset-import"))]
+        let scenery = None;
+        #[cfg(feature = "ui")]
+        let ui = Some(&self.ui as &dyn Subsystem);
+        #[cfg(not(feature = "ui"))]
+        let ui = None;
+        #[cfg(feature = "post-processing")]
+        let post_process = self
+            .post_process
+            .as_ref()
+            .map(|post| post as &dyn Subsystem);
+        #[cfg(not(feature = "post-processing"))]
+        let post_process = None;
+        [demos, scenery, ui, post_process]
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+
+    /// All of them, like `all`.
+    pub fn all_mut(&mut self) -> Vec<&mut dyn Subsystem> {
+        #[cfg(feature = "demos")]
+        let demos = Some(&mut self.demos as &mut dyn Subsystem);
+        #[cfg(not(feature = "demos"))]
+        let demos = None;
+        #[cfg(feature = "asset-import")]
+        let scenery = Some(&mut self.scenery as &mut dyn Subsystem);
+        #[cfg(not(feature = "asset-import"))]
+        let scenery = None;
+        #[cfg(feature = "ui")]
+        let ui = Some(&mut self.ui as &mut dyn Subsystem);
+        #[cfg(not(feature = "ui"))]
+        let ui = None;
+        #[cfg(feature = "post-processing")]
+        let post_process = self
+            .post_process
+            .as_mut()
+            .map(|post| post as &mut dyn Subsystem);
+        #[cfg(not(feature = "post-processing"))]
+        let post_process = None;
+        [demos, scenery, ui, post_process]
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+
+    /// All their `Subsystem::final_draws`, in the order they draw.
+    #[cfg(feature = "post-processing")]
+    pub fn final_draws(&self) -> Vec<Draw<'_>> {
+        let mut draws = Vec::new();
+        for subsystem in self.all() {
+            subsystem.final_draws(&mut draws);
+        }
+        draws
+    }
+}
+
+/// Whether any of `shaders` is one of the `changed` ones.
+#[cfg(feature = "hot-reload")]
+pub fn uses_any(shaders: &[&str], changed: &HashSet<String>) -> bool {
+    shaders.iter().any(|name| changed.contains(*name))
+}
+
+/// Log how rebuilding the pipeline of `what` went, see `Subsystem::rebuild_pipelines`.
+#[cfg(all(
+    feature = "hot-reload",
+    any(
+        feature = "demos",
+        feature = "asset-import",
+        feature = "ui",
+        feature = "post-processing"
+    )
+))]
+pub fn log_rebuild(what: &str, result: Result<(), Box<dyn Error>>) {
+    match result {
+        Ok(()) => log::info!("Reloaded {} shaders", what),
+        Err(err) => log::error!("Failed to reload {} shaders: {}", what, err),
+    }
+}
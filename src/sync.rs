@@ -0,0 +1,334 @@
+use ash::{vk, Device};
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::vulkan_create;
+
+//////////////// Frames In Flight ////////////////
+
+/// Synchronization objects owned by one frame in flight.
+pub struct FrameSync {
+    /// Signaled when the acquired swapchain image can be rendered to.
+    pub image_available: vk::Semaphore,
+    /// Signaled when the GPU is done with this frame's submission (and its command buffer).
+    pub in_flight: vk::Fence,
+}
+
+/// Lets the CPU record frame N+1 while the GPU is still working on frame N,
+/// without ever reusing a frame's objects before the GPU is done with them.
+///
+/// `render_finished` semaphores are per swapchain image rather than per frame:
+/// presentation holds on to them until the image is re-acquired, which isn't tied to our frame fences.
+pub struct FramesInFlight {
+    frames: Vec<FrameSync>,
+    render_finished: Vec<vk::Semaphore>,
+    // Labels of the passes in each frame's last submission, see `submitted`.
+    passes: Vec<Vec<String>>,
+    // Whether each frame's fence will be signaled, false between `reset` and `submitted`.
+    pending: Vec<bool>,
+    current: usize,
+    // Stops `wait` once the window is closed.
+    shutdown: Shutdown,
+}
+
+/// A frame's submission the GPU didn't finish within the watchdog timeout, see `FramesInFlight::wait`.
+#[derive(Debug, Clone)]
+pub struct GpuHang {
+    pub frame: usize,
+    pub passes: Vec<String>,
+    pub waited: Duration,
+}
+
+impl fmt::Display for GpuHang {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Frame {}'s submission hasn't finished after {:.1?} (passes: {})",
+            self.frame,
+            self.waited,
+            self.passes.join(", ")
+        )
+    }
+}
+
+impl Error for GpuHang {}
+
+impl FramesInFlight {
+    pub fn new(
+        device: &Device,
+        frames_in_flight: usize,
+        swapchain_image_count: usize,
+        shutdown: Shutdown,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut frames: Vec<FrameSync> = Vec::new();
+        for _ in 0..frames_in_flight {
+            frames.push(FrameSync {
+                image_available: vulkan_create::semaphore(device)?,
+                // Signaled, so the first wait on each frame doesn't block forever.
+                in_flight: vulkan_create::fence(device, true)?,
+            });
+        }
+
+        let render_finished = render_finished_semaphores(device, swapchain_image_count)?;
+
+        Ok(Self {
+            passes: vec![Vec::new(); frames.len()],
+            pending: vec![true; frames.len()],
+            frames,
+            render_finished,
+            current: 0,
+            shutdown,
+        })
+    }
+
+    /// Index of the current frame in flight (e.g. for per-frame command buffers).
+    pub fn current_index(&self) -> usize {
+        self.current
+    }
+
+    /// Synchronization objects of the current frame in flight.
+    pub fn current(&self) -> &FrameSync {
+        &self.frames[self.current]
+    }
+
+    /// What `wait` gives up on, shared with the other waits of the app.
+    pub fn shutdown(&self) -> &Shutdown {
+        &self.shutdown
+    }
+
+    /// Synchronization objects of every frame in flight.
+    pub fn frames(&self) -> &[FrameSync] {
+        &self.frames
+    }
+
+    /// All per swapchain image semaphores.
+    pub fn render_finished_semaphores(&self) -> &[vk::Semaphore] {
+        &self.render_finished
+    }
+
+    /// Semaphore to signal when rendering into swapchain image `image_index` is done.
+    pub fn render_finished(&self, image_index: usize) -> vk::Semaphore {
+        self.render_finished[image_index]
+    }
+
+    /// Recreate the per swapchain image semaphores after the swapchain was recreated
+    /// (the number of images may have changed). The device must be idle.
+    pub fn recreate_render_finished(
+        &mut self,
+        device: &Device,
+        swapchain_image_count: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        unsafe {
+            self.render_finished
+                .drain(..)
+                .for_each(|semaphore| device.destroy_semaphore(semaphore, None));
+        }
+        self.render_finished = render_finished_semaphores(device, swapchain_image_count)?;
+        Ok(())
+    }
+
+    /// Move on to the next frame in flight.
+    pub fn advance(&mut self) {
+        self.current = (self.current + 1) % self.frames.len();
+    }
+
+    /// Remember the labels of the passes just submitted for the current frame, for `wait` to report.
+    /// Its fence will be signaled again, see `reset`.
+    pub fn submitted(&mut self, passes: Vec<String>) {
+        self.passes[self.current] = passes;
+        self.pending[self.current] = true;
+    }
+
+    /// Labels of the passes in every frame's last submission.
+    pub fn submitted_passes(&self) -> &[Vec<String>] {
+        &self.passes
+    }
+
+    /// Block until the current frame's previous submission has finished, or shutdown is requested.
+    /// With a `watchdog` timeout, a submission that takes longer is logged with its passes, every timeout,
+    /// and if `give_up` is set, waiting stops with a `GpuHang` error instead.
+    pub fn wait(
+        &self,
+        device: &Device,
+        watchdog: Option<Duration>,
+        give_up: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        if !self.pending[self.current] {
+            return Ok(());
+        }
+        let fences = [self.current().in_flight];
+        let timeout = watchdog.unwrap_or(Duration::MAX);
+
+        let started = Instant::now();
+        loop {
+            match wait_for_fences(device, &fences, timeout, Cancel::On(&self.shutdown)) {
+                Ok(()) => {
+                    if started.elapsed() >= timeout {
+                        log::warn!(
+                            "Frame {}'s submission finished after {:.1?}",
+                            self.current,
+                            started.elapsed()
+                        );
+                    }
+                    return Ok(());
+                }
+                Err(WaitError::TimedOut(_)) => {
+                    let hang = GpuHang {
+                        frame: self.current,
+                        passes: self.passes[self.current].clone(),
+                        waited: started.elapsed(),
+                    };
+                    if give_up {
+                        return Err(Box::new(hang));
+                    }
+                    log::warn!("{}, still waiting", hang);
+                }
+                Err(err) => return Err(Box::new(err)),
+            }
+        }
+    }
+
+    /// Block until every frame's last submission has finished, e.g. before destroying what they use.
+    /// A frame whose fence was reset but whose submission failed is skipped, its fence is never signaled.
+    pub fn wait_all(
+        &self,
+        device: &Device,
+        timeout: Duration,
+        cancel: Cancel,
+    ) -> Result<(), WaitError> {
+        let fences = self
+            .frames
+            .iter()
+            .zip(&self.pending)
+            .filter(|(_, &pending)| pending)
+            .map(|(frame, _)| frame.in_flight)
+            .collect::<Vec<_>>();
+        if fences.is_empty() {
+            return Ok(());
+        }
+        wait_for_fences(device, &fences, timeout, cancel)
+    }
+
+    /// Reset the current frame's fence, right before submitting work that signals it (see `submitted`).
+    /// Done separately from `wait` so a failed acquire doesn't leave an unsignaled fence behind.
+    pub fn reset(&mut self, device: &Device) -> Result<(), Box<dyn Error>> {
+        unsafe { device.reset_fences(&[self.current().in_flight])? };
+        self.pending[self.current] = false;
+        Ok(())
+    }
+
+    /// Destroy all synchronization objects. The device must be idle.
+    pub fn destroy(&mut self, device: &Device) {
+        unsafe {
+            self.frames.drain(..).for_each(|frame| {
+                device.destroy_semaphore(frame.image_available, None);
+                device.destroy_fence(frame.in_flight, None);
+            });
+            self.render_finished
+                .drain(..)
+                .for_each(|semaphore| device.destroy_semaphore(semaphore, None));
+        }
+    }
+}
+
+//////////////// Bounded Waits ////////////////
+// Waits for the GPU give up after a timeout, and as soon as shutdown is requested (the window was closed),
+// so a driver that stops making progress can't keep the app from exiting. Fences are waited on a
+// slice at a time to notice the request. Cleanup waits ignore it, they still have a timeout.
+
+/// The longest a fence is waited on before checking for a shutdown request.
+const WAIT_SLICE: Duration = Duration::from_millis(50);
+
+/// Shared between the event loop, which requests shutdown when the window is closed, and what waits for
+/// the GPU on the graphics thread. Clones share the request.
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown(Arc<AtomicBool>);
+
+impl Shutdown {
+    /// Make waits that can be cancelled give up.
+    pub fn request(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether `request` was called on this or a clone.
+    pub fn requested(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Whether a wait gives up when shutdown is requested.
+#[derive(Debug, Clone, Copy)]
+pub enum Cancel<'a> {
+    On(&'a Shutdown),
+    /// For waits before destroying what the GPU may still use, which mustn't be skipped.
+    Never,
+}
+
+/// Why a wait stopped before the GPU finished.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitError {
+    TimedOut(Duration),
+    Cancelled,
+    Vulkan(vk::Result),
+}
+
+impl fmt::Display for WaitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WaitError::TimedOut(waited) => write!(f, "GPU didn't finish within {:.1?}", waited),
+            WaitError::Cancelled => write!(f, "Stopped waiting for the GPU, shutting down"),
+            WaitError::Vulkan(err) => write!(f, "Failed to wait for the GPU: {}", err),
+        }
+    }
+}
+
+impl Error for WaitError {}
+
+impl WaitError {
+    /// Whether waiting failed because the device is lost.
+    pub fn is_device_lost(&self) -> bool {
+        *self == WaitError::Vulkan(vk::Result::ERROR_DEVICE_LOST)
+    }
+}
+
+/// Block until all `fences` are signaled, for at most `timeout`.
+pub fn wait_for_fences(
+    device: &Device,
+    fences: &[vk::Fence],
+    timeout: Duration,
+    cancel: Cancel,
+) -> Result<(), WaitError> {
+    let started = Instant::now();
+    loop {
+        if let Cancel::On(shutdown) = cancel {
+            if shutdown.requested() {
+                return Err(WaitError::Cancelled);
+            }
+        }
+        let waited = started.elapsed();
+        if waited >= timeout {
+            return Err(WaitError::TimedOut(waited));
+        }
+        let slice = (timeout - waited).min(WAIT_SLICE);
+        match unsafe { device.wait_for_fences(fences, true, slice.as_nanos() as u64) } {
+            Ok(()) => return Ok(()),
+            Err(vk::Result::TIMEOUT) => {}
+            Err(err) => return Err(WaitError::Vulkan(err)),
+        }
+    }
+}
+
+/// One semaphore per swapchain image.
+fn render_finished_semaphores(
+    device: &Device,
+    count: usize,
+) -> Result<Vec<vk::Semaphore>, Box<dyn Error>> {
+    let mut semaphores: Vec<vk::Semaphore> = Vec::new();
+    for _ in 0..count {
+        semaphores.push(vulkan_create::semaphore(device)?);
+    }
+    Ok(semaphores)
+}
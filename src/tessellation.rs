@@ -0,0 +1,185 @@
+use ash::{vk, Device};
+use std::error::Error;
+
+use crate::buffer::Buffer;
+use crate::command::{Draw, TransferQueue};
+use crate::memory::Allocator;
+use crate::pipeline::GraphicsPipelineBuilder;
+use crate::reflect::ShaderInterface;
+use crate::render_target::Rendering;
+use crate::shader_cache::ShaderCache;
+
+//////////////// Displaced Plane ////////////////
+// A plane below the triangles, made of a few quad patches that the tessellation shaders subdivide
+// and displace with a moving wave. Only the patch corners are in the vertex buffer, the GPU generates
+// the rest. Needs the tessellationShader feature.
+
+/// Patches along each side of the plane.
+const PATCHES: usize = 4;
+
+/// How often each patch edge is subdivided. Devices support at least 64.
+const TESSELLATION_LEVEL: f32 = 16.0;
+
+/// Size of `push_constants`: the tessellation level and the time the wave is at.
+const PUSH_CONSTANTS_SIZE: u32 = 8;
+
+/// Corners of every patch, clockwise from the top left.
+const CONTROL_POINTS: u32 = 4;
+
+/// The shaders the pipeline is built from: vertex, tessellation control and evaluation, fragment.
+pub const SHADERS: [&str; 4] = [
+    "plane.vert.spv",
+    "plane.tesc.spv",
+    "plane.tese.spv",
+    "shader.frag.spv",
+];
+
+/// The pipeline and patches to draw the plane with.
+pub struct DisplacedPlane {
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    push_constant_stages: vk::ShaderStageFlags,
+    vertex_buffer: Buffer,
+    vertex_count: u32,
+}
+
+impl DisplacedPlane {
+    /// Upload the patches through `transfer_queue` and build the pipeline for `rendering`.
+    pub fn new(
+        device: &Device,
+        shader_cache: &ShaderCache,
+        allocator: &Allocator,
+        transfer_queue: &TransferQueue,
+        rendering: Rendering,
+        samples: vk::SampleCountFlags,
+    ) -> Result<Self, Box<dyn Error>> {
+        let corners = patch_corners();
+        let mut vertex_buffer = Buffer::device_local_with_data(
+            device,
+            allocator,
+            transfer_queue,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            &corners,
+        )?;
+
+        match build_pipeline(device, shader_cache, rendering, samples) {
+            Ok((pipeline, pipeline_layout, push_constant_stages)) => Ok(Self {
+                pipeline,
+                pipeline_layout,
+                push_constant_stages,
+                vertex_buffer,
+                vertex_count: corners.len() as u32,
+            }),
+            Err(err) => {
+                vertex_buffer.destroy(device);
+                Err(err)
+            }
+        }
+    }
+
+    /// Rebuild the pipeline from the current `SHADERS`, e.g. after they changed on disk.
+    /// Keeps the previous pipeline if building fails. The GPU must be done with it.
+    #[cfg(feature = "hot-reload")]
+    pub fn rebuild_pipeline(
+        &mut self,
+        device: &Device,
+        shader_cache: &ShaderCache,
+        rendering: Rendering,
+        samples: vk::SampleCountFlags,
+    ) -> Result<(), Box<dyn Error>> {
+        let (pipeline, pipeline_layout, push_constant_stages) =
+            build_pipeline(device, shader_cache, rendering, samples)?;
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        self.pipeline = pipeline;
+        self.pipeline_layout = pipeline_layout;
+        self.push_constant_stages = push_constant_stages;
+        Ok(())
+    }
+
+    /// Push constants for the wave at `time` (in seconds).
+    pub fn push_constants(time: f32) -> [u8; 8] {
+        let mut bytes = [0u8; PUSH_CONSTANTS_SIZE as usize];
+        for (chunk, value) in bytes
+            .chunks_exact_mut(4)
+            .zip([TESSELLATION_LEVEL, time].iter())
+        {
+            chunk.copy_from_slice(&value.to_ne_bytes());
+        }
+        bytes
+    }
+
+    /// The draw for the plane, with `push_constants` from `DisplacedPlane::push_constants`.
+    pub fn draw<'a>(&self, push_constants: &'a [u8; 8]) -> Draw<'a> {
+        Draw {
+            pipeline: self.pipeline,
+            pipeline_layout: self.pipeline_layout,
+            descriptor_sets: &[],
+            push_descriptors: None,
+            push_constants: Some((self.push_constant_stages, push_constants)),
+            vertex_buffer: Some(self.vertex_buffer.buffer),
+            instance_buffer: None,
+            index_buffer: None,
+            vertex_count: self.vertex_count,
+        }
+    }
+
+    /// Destroy the pipeline and patches. The GPU must be done with them.
+    pub fn destroy(&mut self, device: &Device) {
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        self.vertex_buffer.destroy(device);
+    }
+}
+
+/// The corners of a PATCHES x PATCHES grid of patches over the bottom of the screen (in NDC),
+/// `CONTROL_POINTS` per patch.
+fn patch_corners() -> Vec<[f32; 2]> {
+    let (left, right, top, bottom) = (-0.9, 0.9, 0.35, 0.95);
+    let x = |column: usize| left + (right - left) * column as f32 / PATCHES as f32;
+    let y = |row: usize| top + (bottom - top) * row as f32 / PATCHES as f32;
+
+    let mut corners = Vec::with_capacity(PATCHES * PATCHES * CONTROL_POINTS as usize);
+    for row in 0..PATCHES {
+        for column in 0..PATCHES {
+            corners.extend([
+                [x(column), y(row)],
+                [x(column + 1), y(row)],
+                [x(column + 1), y(row + 1)],
+                [x(column), y(row + 1)],
+            ]);
+        }
+    }
+    corners
+}
+
+fn build_pipeline(
+    device: &Device,
+    shader_cache: &ShaderCache,
+    rendering: Rendering,
+    samples: vk::SampleCountFlags,
+) -> Result<(vk::Pipeline, vk::PipelineLayout, vk::ShaderStageFlags), Box<dyn Error>> {
+    let interface = ShaderInterface::from_shaders(shader_cache, &SHADERS)?;
+    // The patch corners are just positions.
+    let (vertex_bindings, vertex_attributes) =
+        interface.vertex_input(&[(vk::VertexInputRate::VERTEX, 0..1, size_of::<[f32; 2]>())])?;
+    let push_constant_ranges = interface.push_constant_ranges();
+    let push_constant_stages = push_constant_ranges
+        .first()
+        .map_or(vk::ShaderStageFlags::empty(), |range| range.stage_flags);
+
+    let (pipeline, pipeline_layout) = GraphicsPipelineBuilder::default()
+        .vertex_shader(SHADERS[0])
+        .tessellation(SHADERS[1], SHADERS[2], CONTROL_POINTS)
+        .fragment_shader(SHADERS[3])
+        .vertex_input(&vertex_bindings, &vertex_attributes)
+        .push_constant_ranges(&push_constant_ranges)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .samples(samples)
+        .build(device, shader_cache, rendering)?;
+    Ok((pipeline, pipeline_layout, push_constant_stages))
+}
@@ -0,0 +1,686 @@
+#[cfg(feature = "asset-import")]
+use ash::Instance;
+use ash::{vk, Device};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use crate::buffer::Buffer;
+use crate::command::{Handover, TransferQueue};
+use crate::image::{self, AllocatedImage};
+use crate::memory::Allocator;
+use crate::sync::WaitError;
+use crate::util::AppError;
+
+//////////////// Textures ////////////////
+// Images loaded from disk (PNG or JPEG through the `image` crate, or block compressed KTX2 files)
+// into sampled images, 2D or cubemaps.
+// The pixels go through a host visible staging buffer and are copied on the transfer queue (see
+// `command::TransferQueue`). The graphics queue then blits the rest of the mip chain if asked to.
+// Afterwards the image stays in SHADER_READ_ONLY_OPTIMAL, ready for a combined image sampler.
+
+/// Textures hold color data, so they're sampled as SRGB.
+pub const TEXTURE_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+/// How a texture is sampled.
+#[derive(Debug, Clone, Copy)]
+pub struct SamplerDesc {
+    pub mag_filter: vk::Filter,
+    pub min_filter: vk::Filter,
+    pub mipmap_mode: vk::SamplerMipmapMode,
+    /// Used for U, V and W.
+    pub address_mode: vk::SamplerAddressMode,
+    /// None disables anisotropic filtering. Needs the samplerAnisotropy feature and must not exceed
+    /// the device's maxSamplerAnisotropy.
+    pub max_anisotropy: Option<f32>,
+    pub mip_lod_bias: f32,
+}
+
+impl Default for SamplerDesc {
+    /// Linear filtering between and within mips, repeating, no anisotropy.
+    fn default() -> Self {
+        Self {
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            address_mode: vk::SamplerAddressMode::REPEAT,
+            max_anisotropy: None,
+            mip_lod_bias: 0.0,
+        }
+    }
+}
+
+/// How a texture is created.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TextureOptions {
+    /// Generate the full mip chain on the GPU, which needs `image::supports_mipmap_generation`
+    /// for `TEXTURE_FORMAT`. Otherwise there's a single mip.
+    pub mipmapped: bool,
+    pub sampler: SamplerDesc,
+}
+
+/// A sampled image and the sampler to read it with.
+pub struct Texture {
+    pub image: AllocatedImage,
+    pub sampler: vk::Sampler,
+    pub extent: vk::Extent2D,
+    pub mip_levels: u32,
+}
+
+impl Texture {
+    /// Load the image file at `path` (PNG or JPEG), converted to RGBA.
+    /// Blocks until the upload through `transfer_queue` is done.
+    pub fn from_file<P: AsRef<Path>>(
+        device: &Device,
+        allocator: &Allocator,
+        transfer_queue: &TransferQueue,
+        path: P,
+        options: &TextureOptions,
+    ) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let pixels = ::image::open(path)?.into_rgba8();
+        let extent = vk::Extent2D {
+            width: pixels.width(),
+            height: pixels.height(),
+        };
+        log::debug!(
+            "Loaded texture {} ({}x{})",
+            path.display(),
+            extent.width,
+            extent.height
+        );
+
+        Self::from_rgba(
+            device,
+            allocator,
+            transfer_queue,
+            extent,
+            pixels.as_raw(),
+            options,
+        )
+    }
+
+    /// Upload `pixels`, tightly packed 8 bit RGBA rows of `extent`.
+    /// Blocks until the upload through `transfer_queue` is done.
+    pub fn from_rgba(
+        device: &Device,
+        allocator: &Allocator,
+        transfer_queue: &TransferQueue,
+        extent: vk::Extent2D,
+        pixels: &[u8],
+        options: &TextureOptions,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mip_levels = if options.mipmapped {
+            image::mip_levels(extent)
+        } else {
+            1
+        };
+
+        let contents = Contents {
+            format: TEXTURE_FORMAT,
+            extent,
+            mip_levels,
+            data: pixels,
+            regions: vec![copy_region(0, 0, 0, extent)],
+            generate_mipmaps: options.mipmapped,
+            cube: false,
+        };
+        Self::new(
+            device,
+            allocator,
+            transfer_queue,
+            &contents,
+            &options.sampler,
+        )
+    }
+
+    /// Upload the 2D texture in `file`, in a block compressed format (e.g. BC7 or ASTC) or any other
+    /// format the file specifies, with all mips it contains. The format must be sampleable on this device,
+    /// check with `compressed_format_supported`. Zstandard supercompressed mips are decompressed here, Basis
+    /// Universal payloads (which need transcoding) and other supercompression schemes are rejected.
+    /// Blocks until the upload through `transfer_queue` is done.
+    pub fn from_ktx2(
+        device: &Device,
+        allocator: &Allocator,
+        transfer_queue: &TransferQueue,
+        file: &Ktx2File,
+        sampler: &SamplerDesc,
+    ) -> Result<Self, Box<dyn Error>> {
+        let reader = ktx2::Reader::new(file.bytes.as_slice())?;
+        let header = reader.header();
+
+        let unsupported = |what: &str| {
+            Box::new(AppError::new(&format!(
+                "{}: {} isn't supported",
+                file.path.display(),
+                what
+            ))) as Box<dyn Error>
+        };
+        let format = match header.format {
+            Some(format) => vk::Format::from_raw(format.value() as i32),
+            None => return Err(unsupported("Basis Universal transcoding")),
+        };
+        let zstd = match header.supercompression_scheme {
+            None => false,
+            Some(ktx2::SupercompressionScheme::Zstandard) => true,
+            Some(scheme) => return Err(unsupported(&format!("supercompression {:?}", scheme))),
+        };
+        if header.face_count != 1 || header.layer_count > 1 || header.pixel_depth > 1 {
+            return Err(unsupported("anything but a single 2D image"));
+        }
+
+        let extent = vk::Extent2D {
+            width: header.pixel_width,
+            height: header.pixel_height,
+        };
+        // Copies have to start at a multiple of the texel block size, and of 4.
+        let alignment = lcm(texel_block_size(&reader, extent)?, 4);
+        let mut decoder = ruzstd::decoding::FrameDecoder::new();
+        let mut data = Vec::new();
+        let mut regions = Vec::new();
+        for (level, mip) in reader.levels().enumerate() {
+            let level = level as u32;
+            data.resize(data.len().next_multiple_of(alignment), 0);
+            let mip_extent = vk::Extent2D {
+                width: (extent.width >> level).max(1),
+                height: (extent.height >> level).max(1),
+            };
+            regions.push(copy_region(
+                level,
+                0,
+                data.len() as vk::DeviceSize,
+                mip_extent,
+            ));
+            if zstd {
+                let start = data.len();
+                data.reserve_exact(mip.uncompressed_byte_length as usize);
+                decoder.decode_all_to_vec(mip.data, &mut data)?;
+                if (data.len() - start) as u64 != mip.uncompressed_byte_length {
+                    return Err(Box::new(AppError::new(&format!(
+                        "{}: mip {} decompressed to {} bytes instead of {}",
+                        file.path.display(),
+                        level,
+                        data.len() - start,
+                        mip.uncompressed_byte_length
+                    ))));
+                }
+            } else {
+                data.extend_from_slice(mip.data);
+            }
+        }
+        log::debug!(
+            "Loaded texture {} ({}x{}, {:?}, {} mips)",
+            file.path.display(),
+            extent.width,
+            extent.height,
+            format,
+            regions.len()
+        );
+
+        let contents = Contents {
+            format,
+            extent,
+            mip_levels: regions.len() as u32,
+            data: &data,
+            regions,
+            generate_mipmaps: false,
+            cube: false,
+        };
+        Self::new(device, allocator, transfer_queue, &contents, sampler)
+    }
+
+    /// Load a cubemap from six square images of the same size, in layer order: +X, -X, +Y, -Y, +Z, -Z.
+    /// Cubemaps have a single mip. Blocks until the upload through `transfer_queue` is done.
+    pub fn cubemap_from_files<P: AsRef<Path>>(
+        device: &Device,
+        allocator: &Allocator,
+        transfer_queue: &TransferQueue,
+        faces: &[P; 6],
+        sampler: &SamplerDesc,
+    ) -> Result<Self, Box<dyn Error>> {
+        let faces = faces
+            .iter()
+            .map(|path| Ok(::image::open(path)?.into_rgba8()))
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+
+        Self::cubemap(device, allocator, transfer_queue, &faces, sampler)
+    }
+
+    /// Load a cubemap from a single image with the faces laid out as a horizontal cross (4x3 faces):
+    /// +Y on top, -X, +Z, +X, -Z in the middle row and -Y below.
+    /// Blocks until the upload through `transfer_queue` is done.
+    pub fn cubemap_from_cross<P: AsRef<Path>>(
+        device: &Device,
+        allocator: &Allocator,
+        transfer_queue: &TransferQueue,
+        path: P,
+        sampler: &SamplerDesc,
+    ) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let cross = ::image::open(path)?.into_rgba8();
+        let size = cross.width() / 4;
+        if size == 0 || cross.width() != size * 4 || cross.height() != size * 3 {
+            return Err(Box::new(AppError::new(&format!(
+                "{} ({}x{}) isn't a 4x3 cross of square faces",
+                path.display(),
+                cross.width(),
+                cross.height()
+            ))));
+        }
+
+        // Column and row of each face in the cross, in layer order.
+        let cells = [(2, 1), (0, 1), (1, 0), (1, 2), (1, 1), (3, 1)];
+        let faces = cells
+            .iter()
+            .map(|(column, row)| {
+                ::image::imageops::crop_imm(&cross, column * size, row * size, size, size)
+                    .to_image()
+            })
+            .collect::<Vec<_>>();
+
+        Self::cubemap(device, allocator, transfer_queue, &faces, sampler)
+    }
+
+    /// A cubemap of six square `faces` of the same size, in layer order (see `cubemap_from_files`).
+    /// Blocks until the upload through `transfer_queue` is done.
+    pub fn cubemap(
+        device: &Device,
+        allocator: &Allocator,
+        transfer_queue: &TransferQueue,
+        faces: &[::image::RgbaImage],
+        sampler: &SamplerDesc,
+    ) -> Result<Self, Box<dyn Error>> {
+        let size = faces[0].width();
+        if faces
+            .iter()
+            .any(|face| face.width() != size || face.height() != size)
+        {
+            return Err(Box::new(AppError::new(
+                "Cubemap faces must be square and all the same size",
+            )));
+        }
+
+        let extent = vk::Extent2D {
+            width: size,
+            height: size,
+        };
+        let mut data = Vec::new();
+        let mut regions = Vec::new();
+        for (layer, face) in faces.iter().enumerate() {
+            regions.push(copy_region(
+                0,
+                layer as u32,
+                data.len() as vk::DeviceSize,
+                extent,
+            ));
+            data.extend_from_slice(face.as_raw());
+        }
+        log::debug!("Loaded cubemap ({}x{} faces)", size, size);
+
+        let contents = Contents {
+            format: TEXTURE_FORMAT,
+            extent,
+            mip_levels: 1,
+            data: &data,
+            regions,
+            generate_mipmaps: false,
+            cube: true,
+        };
+        Self::new(device, allocator, transfer_queue, &contents, sampler)
+    }
+
+    /// Create the image, upload `contents` into it through a staging buffer and create the view and sampler.
+    fn new(
+        device: &Device,
+        allocator: &Allocator,
+        transfer_queue: &TransferQueue,
+        contents: &Contents,
+        sampler_desc: &SamplerDesc,
+    ) -> Result<Self, Box<dyn Error>> {
+        // Generated mips are blitted from each other, so the image is a transfer source too.
+        let usage = if contents.generate_mipmaps {
+            vk::ImageUsageFlags::TRANSFER_SRC
+                | vk::ImageUsageFlags::TRANSFER_DST
+                | vk::ImageUsageFlags::SAMPLED
+        } else {
+            vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED
+        };
+
+        let mut staging = Buffer::new(
+            device,
+            allocator,
+            contents.data.len() as vk::DeviceSize,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+
+        let result = staging.write(device, contents.data).and_then(|_| {
+            let (vk_image, memory) = if contents.cube {
+                image::cube_image(
+                    device,
+                    allocator,
+                    contents.extent.width,
+                    contents.format,
+                    usage,
+                )?
+            } else {
+                image::image(
+                    device,
+                    allocator,
+                    contents.extent,
+                    contents.format,
+                    contents.mip_levels,
+                    vk::SampleCountFlags::TYPE_1,
+                    usage,
+                )?
+            };
+            let mut texture_image = AllocatedImage {
+                image: vk_image,
+                memory,
+                view: vk::ImageView::null(),
+                format: contents.format,
+                allocator: allocator.clone(),
+            };
+
+            let uploaded =
+                upload(device, transfer_queue, &staging, vk_image, contents).and_then(|_| {
+                    texture_image.view = if contents.cube {
+                        image::cube_view(device, vk_image, contents.format)?
+                    } else {
+                        image::image_view(
+                            device,
+                            vk_image,
+                            contents.format,
+                            vk::ImageAspectFlags::COLOR,
+                            contents.mip_levels,
+                        )?
+                    };
+                    sampler(device, sampler_desc, contents.mip_levels)
+                });
+
+            match uploaded {
+                Ok(sampler) => Ok(Self {
+                    image: texture_image,
+                    sampler,
+                    extent: contents.extent,
+                    mip_levels: contents.mip_levels,
+                }),
+                // After a failed wait the GPU may still be using it, see `command::one_time_submit`.
+                Err(err) if err.downcast_ref::<WaitError>().is_some() => Err(err),
+                Err(err) => {
+                    texture_image.destroy(device);
+                    Err(err)
+                }
+            }
+        });
+
+        // The upload has completed (or failed), so the staging buffer isn't needed anymore.
+        if !result
+            .as_ref()
+            .is_err_and(|err| err.downcast_ref::<WaitError>().is_some())
+        {
+            staging.destroy(device);
+        }
+        result
+    }
+
+    /// Destroy the sampler and image. The GPU must be done with them.
+    pub fn destroy(&mut self, device: &Device) {
+        unsafe { device.destroy_sampler(self.sampler, None) };
+        self.sampler = vk::Sampler::null();
+        self.image.destroy(device);
+    }
+}
+
+/// A KTX2 file read into memory, so its format can be checked before it's uploaded without reading it again.
+pub struct Ktx2File {
+    path: PathBuf,
+    bytes: Vec<u8>,
+}
+
+impl Ktx2File {
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        Ok(Self {
+            path: path.to_path_buf(),
+            bytes: std::fs::read(path)?,
+        })
+    }
+
+    /// The texture's format, None for Basis Universal payloads (which need transcoding).
+    pub fn format(&self) -> Result<Option<vk::Format>, Box<dyn Error>> {
+        let header = ktx2::Reader::new(self.bytes.as_slice())?.header();
+        Ok(header
+            .format
+            .map(|format| vk::Format::from_raw(format.value() as i32)))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Bytes per texel block of `reader`'s format: mip 0's size over its number of blocks, whose dimensions
+/// are in the data format descriptor. The descriptor's own byte count is zero for supercompressed files.
+fn texel_block_size(
+    reader: &ktx2::Reader<&[u8]>,
+    extent: vk::Extent2D,
+) -> Result<usize, Box<dyn Error>> {
+    let block = reader
+        .dfd_blocks()
+        .find(|block| block.header.descriptor_type == 0)
+        .map(|block| ktx2::DfdBlockBasic::parse(block.data))
+        .transpose()?
+        .ok_or_else(|| AppError::new("The KTX2 file has no basic data format descriptor"))?;
+    let [block_width, block_height, ..] = block.header.texel_block_dimensions;
+    let blocks = extent.width.div_ceil(block_width.get() as u32) as u64
+        * extent.height.div_ceil(block_height.get() as u32) as u64;
+    let level_size = reader
+        .levels()
+        .next()
+        .map_or(0, |level| level.uncompressed_byte_length);
+    if blocks == 0 || level_size == 0 || !level_size.is_multiple_of(blocks) {
+        return Err(Box::new(AppError::new(&format!(
+            "A first mip of {} bytes isn't {} whole texel blocks",
+            level_size, blocks
+        ))));
+    }
+    Ok((level_size / blocks) as usize)
+}
+
+/// The least common multiple of `a` and `b`, which are not zero.
+fn lcm(a: usize, b: usize) -> usize {
+    let (mut x, mut y) = (a, b);
+    while y != 0 {
+        (x, y) = (y, x % y);
+    }
+    a / x * b
+}
+
+/// Whether optimally tiled images of the (block compressed) `format` can be sampled on this device.
+pub fn compressed_format_supported(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+    format: vk::Format,
+) -> bool {
+    let properties =
+        unsafe { instance.get_physical_device_format_properties(physical_device, format) };
+    properties
+        .optimal_tiling_features
+        .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE | vk::FormatFeatureFlags::TRANSFER_DST)
+}
+
+/// What goes into a texture's image: `data` is copied to it with `regions`,
+/// and with `generate_mipmaps` mips 1.. are blitted from mip 0 afterwards.
+/// A `cube` image has 6 layers (faces) of a single mip.
+struct Contents<'a> {
+    format: vk::Format,
+    extent: vk::Extent2D,
+    mip_levels: u32,
+    data: &'a [u8],
+    regions: Vec<vk::BufferImageCopy>,
+    generate_mipmaps: bool,
+    cube: bool,
+}
+
+impl Contents<'_> {
+    fn layer_count(&self) -> u32 {
+        if self.cube {
+            6
+        } else {
+            1
+        }
+    }
+}
+
+/// A copy of the tightly packed mip `level` of `layer`, of size `extent`, starting at `offset` in the buffer.
+fn copy_region(
+    level: u32,
+    layer: u32,
+    offset: vk::DeviceSize,
+    extent: vk::Extent2D,
+) -> vk::BufferImageCopy {
+    vk::BufferImageCopy::default()
+        .buffer_offset(offset)
+        // Zero means tightly packed.
+        .buffer_row_length(0)
+        .buffer_image_height(0)
+        .image_subresource(vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: level,
+            base_array_layer: layer,
+            layer_count: 1,
+        })
+        .image_offset(vk::Offset3D::default())
+        .image_extent(vk::Extent3D {
+            width: extent.width,
+            height: extent.height,
+            depth: 1,
+        })
+}
+
+/// Copy `staging` into `image` (in UNDEFINED layout) as described by `contents` on `transfer_queue`,
+/// leaving all mips SHADER_READ_ONLY_OPTIMAL. Generating mips happens on the graphics queue afterwards.
+fn upload(
+    device: &Device,
+    transfer_queue: &TransferQueue,
+    staging: &Buffer,
+    image: vk::Image,
+    contents: &Contents,
+) -> Result<(), Box<dyn Error>> {
+    let generate_mipmaps = contents.generate_mipmaps && contents.mip_levels > 1;
+    // Mipmap blits need the image as copied, otherwise it's ready to be sampled.
+    let (handed_over_layout, first_access) = if generate_mipmaps {
+        (
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::AccessFlags::TRANSFER_READ | vk::AccessFlags::TRANSFER_WRITE,
+        )
+    } else {
+        (
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            vk::AccessFlags::SHADER_READ,
+        )
+    };
+    let mut handover = Handover::default();
+    if let Some(transfer) = transfer_queue.transfer() {
+        let range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: contents.mip_levels,
+            base_array_layer: 0,
+            layer_count: contents.layer_count(),
+        };
+        let (release, acquire) = transfer.image(
+            image,
+            range,
+            (vk::ImageLayout::TRANSFER_DST_OPTIMAL, handed_over_layout),
+            vk::AccessFlags::TRANSFER_WRITE,
+            first_access,
+        );
+        handover.release.images.push(release);
+        handover.acquire.images.push(acquire);
+    }
+
+    let mut copied = Ok(());
+    let mut finished = Ok(());
+    transfer_queue.submit(
+        device,
+        |command_buffer| {
+            if let Some(transfer) = transfer_queue.transfer() {
+                // Discards what the image held, on whichever family it was.
+                let barrier = vk::ImageMemoryBarrier::default()
+                    .image(image)
+                    .old_layout(vk::ImageLayout::UNDEFINED)
+                    .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                    .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE);
+                transfer.track_image(transfer.src, &barrier);
+            }
+            copied = image::transition_layout(
+                device,
+                command_buffer,
+                image,
+                contents.mip_levels,
+                contents.layer_count(),
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            )
+            .map(|()| unsafe {
+                device.cmd_copy_buffer_to_image(
+                    command_buffer,
+                    staging.buffer,
+                    image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &contents.regions,
+                )
+            });
+        },
+        handover,
+        |command_buffer| {
+            if generate_mipmaps {
+                image::generate_mipmaps(
+                    device,
+                    command_buffer,
+                    image,
+                    contents.extent,
+                    contents.mip_levels,
+                );
+            } else if transfer_queue.transfer().is_none() {
+                // Without a handover, the layout changes here.
+                finished = image::transition_layout(
+                    device,
+                    command_buffer,
+                    image,
+                    contents.mip_levels,
+                    contents.layer_count(),
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                );
+            }
+        },
+    )?;
+    copied.and(finished)
+}
+
+/// A sampler as described by `desc`, using all `mip_levels`.
+fn sampler(
+    device: &Device,
+    desc: &SamplerDesc,
+    mip_levels: u32,
+) -> Result<vk::Sampler, Box<dyn Error>> {
+    let sampler_create_info = vk::SamplerCreateInfo::default()
+        .mag_filter(desc.mag_filter)
+        .min_filter(desc.min_filter)
+        .address_mode_u(desc.address_mode)
+        .address_mode_v(desc.address_mode)
+        .address_mode_w(desc.address_mode)
+        .anisotropy_enable(desc.max_anisotropy.is_some())
+        .max_anisotropy(desc.max_anisotropy.unwrap_or(1.0))
+        .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+        .unnormalized_coordinates(false)
+        .compare_enable(false)
+        .mipmap_mode(desc.mipmap_mode)
+        .mip_lod_bias(desc.mip_lod_bias)
+        .min_lod(0.0)
+        .max_lod(mip_levels as f32);
+
+    unsafe { Ok(device.create_sampler(&sampler_create_info, None)?) }
+}
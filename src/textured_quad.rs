@@ -0,0 +1,269 @@
+use ash::{vk, Device};
+use std::error::Error;
+
+use crate::buffer::{Buffer, Uploads};
+use crate::command::{Draw, TransferQueue};
+use crate::descriptor::{self, DescriptorManager};
+use crate::memory::Allocator;
+use crate::pipeline::GraphicsPipelineBuilder;
+use crate::precision::Precision;
+use crate::reflect::ShaderInterface;
+use crate::render_target::Rendering;
+use crate::shader_cache::ShaderCache;
+use crate::sync::WaitError;
+use crate::texture::Texture;
+use crate::vertex;
+
+//////////////// Textured Quad ////////////////
+// A texture loaded from disk (see `VulkanApp::load_texture` and `VulkanApp::load_compressed_texture`),
+// drawn on `vertex::QUAD` in the bottom right corner, over the scene.
+// The quad's colors are stored as halves (see precision.rs), its positions at full precision.
+
+/// Size of `push_constants`: the quad's center and its size, both in NDC.
+const PUSH_CONSTANTS_SIZE: u32 = 16;
+
+/// The quad's height, as a fraction of the target's, and its distance from the corner in NDC.
+const HEIGHT: f32 = 0.25;
+const MARGIN: f32 = 0.05;
+
+/// How the quad's vertex positions and colors are stored, see `vertices`.
+const POSITION_PRECISION: Precision = Precision::Single;
+const COLOR_PRECISION: Precision = Precision::Half;
+
+/// The shaders the pipeline is built from.
+pub const SHADERS: [&str; 2] = ["textured.vert.spv", "textured.frag.spv"];
+
+/// The pipeline, geometry, descriptor set and texture to draw the quad with.
+pub struct TexturedQuad {
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    // Owned by the DescriptorManager, kept to rebuild the pipeline.
+    #[cfg(feature = "hot-reload")]
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_set: vk::DescriptorSet,
+    vertex_buffer: Buffer,
+    index_buffer: Buffer,
+    texture: Texture,
+}
+
+impl TexturedQuad {
+    /// Upload the quad through `transfer_queue`, build the pipeline for `rendering` and a descriptor set
+    /// for `texture`, which the quad takes over. The set's layout lives in `descriptors`.
+    /// It isn't depth tested, so it's drawn over everything drawn before it.
+    pub fn new(
+        device: &Device,
+        shader_cache: &ShaderCache,
+        allocator: &Allocator,
+        transfer_queue: &TransferQueue,
+        descriptors: &mut DescriptorManager,
+        (rendering, samples): (Rendering, vk::SampleCountFlags),
+        mut texture: Texture,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut buffers: Vec<Buffer> = Vec::new();
+        let resources = (|| {
+            let mut uploads = Uploads::default();
+            let added = (|| -> Result<(), Box<dyn Error>> {
+                buffers.push(uploads.add(
+                    device,
+                    allocator,
+                    vk::BufferUsageFlags::VERTEX_BUFFER,
+                    &vertices(),
+                )?);
+                buffers.push(uploads.add(
+                    device,
+                    allocator,
+                    vk::BufferUsageFlags::INDEX_BUFFER,
+                    &vertex::QUAD_INDICES,
+                )?);
+                Ok(())
+            })();
+            match added {
+                Ok(()) => uploads.submit(device, transfer_queue)?,
+                Err(err) => {
+                    uploads.destroy(device);
+                    return Err(err);
+                }
+            }
+
+            // Image and sampler are separate descriptors, see shaders/textured.frag.
+            let interface = ShaderInterface::from_shaders(shader_cache, &SHADERS)?;
+            let layout = descriptors.create_layout(device, &interface.set_bindings(0))?;
+            let descriptor_set = descriptors.allocate(device, layout, 1)?[0];
+            descriptor::write_separate_texture(device, descriptor_set, 0, 1, &texture);
+
+            let (pipeline, pipeline_layout) =
+                build_pipeline(device, shader_cache, &interface, layout, rendering, samples)?;
+
+            Ok::<_, Box<dyn Error>>((pipeline, pipeline_layout, layout, descriptor_set))
+        })();
+
+        match resources {
+            // The layout is only kept to rebuild the pipeline with.
+            #[cfg_attr(not(feature = "hot-reload"), allow(unused_variables))]
+            Ok((pipeline, pipeline_layout, descriptor_set_layout, descriptor_set)) => {
+                let mut buffers = buffers.into_iter();
+                Ok(Self {
+                    pipeline,
+                    pipeline_layout,
+                    #[cfg(feature = "hot-reload")]
+                    descriptor_set_layout,
+                    descriptor_set,
+                    vertex_buffer: buffers.next().unwrap(),
+                    index_buffer: buffers.next().unwrap(),
+                    texture,
+                })
+            }
+            // After a failed wait the GPU may still be uploading, see `command::one_time_submit`.
+            Err(err) if err.downcast_ref::<WaitError>().is_some() => Err(err),
+            Err(err) => {
+                for buffer in buffers.iter_mut() {
+                    buffer.destroy(device);
+                }
+                texture.destroy(device);
+                Err(err)
+            }
+        }
+    }
+
+    /// Rebuild the pipeline from the current `SHADERS`, e.g. after they changed on disk.
+    /// Their descriptor set has to stay the same.
+    /// Keeps the previous pipeline if building fails. The GPU must be done with it.
+    #[cfg(feature = "hot-reload")]
+    pub fn rebuild_pipeline(
+        &mut self,
+        device: &Device,
+        shader_cache: &ShaderCache,
+        rendering: Rendering,
+        samples: vk::SampleCountFlags,
+    ) -> Result<(), Box<dyn Error>> {
+        let interface = ShaderInterface::from_shaders(shader_cache, &SHADERS)?;
+        let (pipeline, pipeline_layout) = build_pipeline(
+            device,
+            shader_cache,
+            &interface,
+            self.descriptor_set_layout,
+            rendering,
+            samples,
+        )?;
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        self.pipeline = pipeline;
+        self.pipeline_layout = pipeline_layout;
+        Ok(())
+    }
+
+    /// The texture's size and mip count, e.g. "512x512, 10 mips".
+    pub fn describe(&self) -> String {
+        format!(
+            "{}x{}, {} mips",
+            self.texture.extent.width, self.texture.extent.height, self.texture.mip_levels
+        )
+    }
+
+    /// Push constants placing a square quad in the bottom right corner of a target of size `extent`.
+    pub fn push_constants(extent: vk::Extent2D) -> [u8; 16] {
+        let height = HEIGHT * 2.0;
+        let width = height * extent.height as f32 / extent.width.max(1) as f32;
+        let values = [
+            1.0 - MARGIN - width / 2.0,
+            1.0 - MARGIN - height / 2.0,
+            width,
+            height,
+        ];
+
+        let mut bytes = [0u8; PUSH_CONSTANTS_SIZE as usize];
+        for (chunk, value) in bytes.chunks_exact_mut(4).zip(values.iter()) {
+            chunk.copy_from_slice(&value.to_ne_bytes());
+        }
+        bytes
+    }
+
+    /// The draw for the quad, with `push_constants` from `TexturedQuad::push_constants`.
+    pub fn draw<'a>(&'a self, push_constants: &'a [u8; 16]) -> Draw<'a> {
+        Draw {
+            pipeline: self.pipeline,
+            pipeline_layout: self.pipeline_layout,
+            descriptor_sets: std::slice::from_ref(&self.descriptor_set),
+            push_descriptors: None,
+            push_constants: Some((vk::ShaderStageFlags::VERTEX, push_constants)),
+            vertex_buffer: Some(self.vertex_buffer.buffer),
+            instance_buffer: None,
+            index_buffer: Some(self.index_buffer.buffer),
+            vertex_count: vertex::QUAD_INDICES.len() as u32,
+        }
+    }
+
+    /// Destroy the pipeline, geometry and texture. The descriptor set is freed with the DescriptorManager.
+    /// The GPU must be done with them.
+    pub fn destroy(&mut self, device: &Device) {
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        self.vertex_buffer.destroy(device);
+        self.index_buffer.destroy(device);
+        self.texture.destroy(device);
+    }
+}
+
+fn build_pipeline(
+    device: &Device,
+    shader_cache: &ShaderCache,
+    interface: &ShaderInterface,
+    layout: vk::DescriptorSetLayout,
+    rendering: Rendering,
+    samples: vk::SampleCountFlags,
+) -> Result<(vk::Pipeline, vk::PipelineLayout), Box<dyn Error>> {
+    let (vertex_bindings, vertex_attributes) = vertex_input();
+    let push_constant_ranges = interface.push_constant_ranges();
+    GraphicsPipelineBuilder::default()
+        .vertex_shader(SHADERS[0])
+        .fragment_shader(SHADERS[1])
+        .vertex_input(&vertex_bindings, &vertex_attributes)
+        .descriptor_set_layouts(&[layout])
+        .push_constant_ranges(&push_constant_ranges)
+        .depth_test(false)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .samples(samples)
+        .build(device, shader_cache, rendering)
+}
+
+/// `vertex::QUAD` packed at POSITION_PRECISION and COLOR_PRECISION, see `vertex_input`.
+fn vertices() -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for vertex in vertex::QUAD.iter() {
+        POSITION_PRECISION.pack_attribute(&vertex.position, &mut bytes);
+        COLOR_PRECISION.pack_attribute(&vertex.color, &mut bytes);
+    }
+    bytes
+}
+
+/// The binding and attributes for `vertices`: position at location 0, color at location 1.
+fn vertex_input() -> (
+    Vec<vk::VertexInputBindingDescription>,
+    Vec<vk::VertexInputAttributeDescription>,
+) {
+    let mut attributes = Vec::new();
+    let mut offset = 0;
+    for (location, (precision, components)) in [(POSITION_PRECISION, 2), (COLOR_PRECISION, 3)]
+        .into_iter()
+        .enumerate()
+    {
+        let (format, stored) = precision.vertex_format(components).unwrap();
+        attributes.push(
+            vk::VertexInputAttributeDescription::default()
+                .binding(0)
+                .location(location as u32)
+                .format(format)
+                .offset(offset as u32),
+        );
+        offset += stored * precision.size();
+    }
+    let binding = vk::VertexInputBindingDescription::default()
+        .binding(0)
+        .stride(offset as u32)
+        .input_rate(vk::VertexInputRate::VERTEX);
+    (vec![binding], attributes)
+}
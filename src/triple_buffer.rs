@@ -0,0 +1,152 @@
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+//////////////// Triple Buffer ////////////////
+// Hands the latest snapshot of some state from one thread to another without either waiting:
+// the writer owns one slot, the reader another, and the third is swapped with them atomically.
+// Publishing puts the written slot in the middle (marked fresh), reading takes the middle slot if it's
+// fresh. Snapshots the reader didn't get to are skipped, it always sees the most recent one.
+
+/// Set in `Shared::middle` while the middle slot holds a snapshot the reader hasn't taken.
+const FRESH: u8 = 0b100;
+const INDEX: u8 = 0b011;
+
+struct Shared<T> {
+    slots: [UnsafeCell<T>; 3],
+    // Index of the slot neither side owns, and FRESH.
+    middle: AtomicU8,
+}
+
+// Each slot is only accessed by whoever owns its index, and ownership changes hands through `middle`.
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+/// Publishes snapshots, see `triple_buffer`.
+pub struct Writer<T> {
+    shared: Arc<Shared<T>>,
+    index: u8,
+}
+
+/// Reads the latest published snapshot, see `triple_buffer`.
+pub struct Reader<T> {
+    shared: Arc<Shared<T>>,
+    index: u8,
+}
+
+/// A writer and reader for snapshots of `T`. The reader sees `initial` until something is published.
+pub fn triple_buffer<T: Clone>(initial: T) -> (Writer<T>, Reader<T>) {
+    let shared = Arc::new(Shared {
+        slots: [
+            UnsafeCell::new(initial.clone()),
+            UnsafeCell::new(initial.clone()),
+            UnsafeCell::new(initial),
+        ],
+        middle: AtomicU8::new(1),
+    });
+    (
+        Writer {
+            shared: Arc::clone(&shared),
+            index: 0,
+        },
+        Reader { shared, index: 2 },
+    )
+}
+
+impl<T: Clone> Writer<T> {
+    /// Make a copy of `snapshot` the one the reader sees next.
+    pub fn publish(&mut self, snapshot: &T) {
+        // Only the writer has this slot.
+        unsafe { (*self.shared.slots[self.index as usize].get()).clone_from(snapshot) };
+        let previous = self
+            .shared
+            .middle
+            .swap(self.index | FRESH, Ordering::AcqRel);
+        self.index = previous & INDEX;
+    }
+}
+
+impl<T> Reader<T> {
+    /// The most recently published snapshot (the same as last time if nothing was published since).
+    pub fn read(&mut self) -> &T {
+        if self.shared.middle.load(Ordering::Relaxed) & FRESH != 0 {
+            let previous = self.shared.middle.swap(self.index, Ordering::AcqRel);
+            self.index = previous & INDEX;
+        }
+        // Only the reader has this slot.
+        unsafe { &*self.shared.slots[self.index as usize].get() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn reads_initial_until_published() {
+        let (_writer, mut reader) = triple_buffer(7);
+        assert_eq!(*reader.read(), 7);
+    }
+
+    #[test]
+    fn reads_latest_published() {
+        let (mut writer, mut reader) = triple_buffer(0);
+        writer.publish(&1);
+        assert_eq!(*reader.read(), 1);
+        writer.publish(&2);
+        writer.publish(&3);
+        assert_eq!(*reader.read(), 3);
+        // Published after a read, so the reader gets it next.
+        writer.publish(&4);
+        assert_eq!(*reader.read(), 4);
+    }
+
+    #[test]
+    fn nothing_new_reads_same_slot() {
+        let (mut writer, mut reader) = triple_buffer(0);
+        writer.publish(&1);
+        let first: *const i32 = reader.read();
+        let second: *const i32 = reader.read();
+        assert_eq!(first, second);
+        assert_eq!(*reader.read(), 1);
+    }
+
+    #[test]
+    fn slots_stay_distinct() {
+        let (mut writer, mut reader) = triple_buffer(0);
+        for value in 1..10 {
+            writer.publish(&value);
+            if value % 3 == 0 {
+                reader.read();
+            }
+            let middle = writer.shared.middle.load(Ordering::Relaxed) & INDEX;
+            assert_ne!(writer.index, reader.index);
+            assert_ne!(writer.index, middle);
+            assert_ne!(reader.index, middle);
+        }
+    }
+
+    // Snapshots are pairs of the same number, a torn one would have two different numbers.
+    #[test]
+    fn two_threads() {
+        const SNAPSHOTS: u64 = 100_000;
+        let (mut writer, mut reader) = triple_buffer((0u64, 0u64));
+        let writing = thread::spawn(move || {
+            for value in 1..=SNAPSHOTS {
+                writer.publish(&(value, value));
+            }
+        });
+        let mut last = 0;
+        while last < SNAPSHOTS {
+            let &(a, b) = reader.read();
+            assert_eq!(a, b);
+            assert!(a >= last, "read {} after {}", a, last);
+            last = a;
+            if writing.is_finished() && last < SNAPSHOTS {
+                assert_eq!(reader.read().0, SNAPSHOTS);
+                break;
+            }
+        }
+        writing.join().unwrap();
+    }
+}
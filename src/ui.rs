@@ -0,0 +1,146 @@
+use ash::{vk, Device};
+#[cfg(feature = "hot-reload")]
+use std::collections::HashSet;
+use std::error::Error;
+
+use crate::command::{Draw, TransferQueue};
+#[cfg(feature = "hot-reload")]
+use crate::cursor;
+use crate::cursor::SoftwareCursor;
+use crate::memory::Allocator;
+use crate::render_target::Rendering;
+#[cfg(feature = "hot-reload")]
+use crate::subsystem;
+use crate::subsystem::{Frame, PipelineContext, Subsystem};
+
+//////////////// UI ////////////////
+// What's drawn for the user rather than the scene: for now the software cursor
+// (see util::SOFTWARE_CURSOR), following the cursor position the event loop reports.
+// With post-processing it's drawn after it, in the post process's final pass, so it isn't processed along.
+
+/// The UI's draws.
+#[derive(Default)]
+pub struct Ui {
+    software_cursor: Option<SoftwareCursor>,
+    // The post process's final pass the cursor is drawn in, None to draw it as an overlay of the main pass.
+    final_pass: Option<Rendering>,
+    // In the window (physical pixels), None while it's outside.
+    pub cursor_position: Option<(f64, f64)>,
+    // For the next frame, None while the cursor is outside.
+    cursor_push_constants: Option<[u8; 16]>,
+}
+
+impl Ui {
+    /// With the software cursor if `software_cursor`, uploaded through `transfer_queue`. It's drawn in
+    /// `final_pass` if there is one (see `ComputePostProcess::final_pass`), else in the main pass.
+    pub fn new(
+        context: &PipelineContext,
+        allocator: &Allocator,
+        transfer_queue: &TransferQueue,
+        software_cursor: bool,
+        final_pass: Option<Rendering>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let software_cursor = if software_cursor {
+            let (rendering, samples) = target(context, final_pass);
+            Some(SoftwareCursor::new(
+                context.device,
+                context.shader_cache,
+                allocator,
+                transfer_queue,
+                rendering,
+                samples,
+            )?)
+        } else {
+            None
+        };
+        Ok(Self {
+            software_cursor,
+            final_pass,
+            ..Self::default()
+        })
+    }
+
+    /// The cursor's draw for the next frame, if it's shown.
+    fn cursor_draw(&self) -> Option<Draw<'_>> {
+        match (&self.software_cursor, &self.cursor_push_constants) {
+            (Some(software_cursor), Some(push_constants)) => {
+                Some(software_cursor.draw(push_constants))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// What the cursor's pipeline is built for: `final_pass` (single sampled), or `context`'s main pass.
+fn target(
+    context: &PipelineContext,
+    final_pass: Option<Rendering>,
+) -> (Rendering, vk::SampleCountFlags) {
+    match final_pass {
+        Some(final_pass) => (final_pass, vk::SampleCountFlags::TYPE_1),
+        None => (context.rendering, context.samples),
+    }
+}
+
+impl Subsystem for Ui {
+    fn describe(&self) -> Vec<(&'static str, String)> {
+        vec![(
+            "software cursor",
+            self.software_cursor.is_some().to_string(),
+        )]
+    }
+
+    #[cfg(feature = "hot-reload")]
+    fn shaders(&self) -> Vec<&'static str> {
+        match self.software_cursor {
+            Some(_) => cursor::SHADERS.to_vec(),
+            None => Vec::new(),
+        }
+    }
+
+    #[cfg(feature = "hot-reload")]
+    fn rebuild_pipelines(&mut self, context: &PipelineContext, changed: &HashSet<String>) {
+        if let Some(software_cursor) = &mut self.software_cursor {
+            if subsystem::uses_any(&cursor::SHADERS, changed) {
+                let (rendering, samples) = target(context, self.final_pass);
+                let result = software_cursor.rebuild_pipeline(
+                    context.device,
+                    context.shader_cache,
+                    rendering,
+                    samples,
+                );
+                subsystem::log_rebuild("cursor", result);
+            }
+        }
+    }
+
+    fn prepare(&mut self, frame: &Frame) {
+        self.cursor_push_constants = self
+            .cursor_position
+            .map(|position| SoftwareCursor::push_constants(position, frame.extent));
+    }
+
+    fn draws<'a>(
+        &'a self,
+        _triangles: &Draw<'a>,
+        _scene: &mut Vec<Draw<'a>>,
+        overlays: &mut Vec<Draw<'a>>,
+    ) {
+        if self.final_pass.is_none() {
+            overlays.extend(self.cursor_draw());
+        }
+    }
+
+    #[cfg(feature = "post-processing")]
+    fn final_draws<'a>(&'a self, draws: &mut Vec<Draw<'a>>) {
+        if self.final_pass.is_some() {
+            draws.extend(self.cursor_draw());
+        }
+    }
+
+    fn destroy(&mut self, device: &Device) {
+        if let Some(software_cursor) = &mut self.software_cursor {
+            software_cursor.destroy(device);
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use ash::{vk, Device};
+use std::error::Error;
+
+use crate::buffer::Buffer;
+use crate::memory::Allocator;
+
+//////////////// Uniform Buffers ////////////////
+
+/// A column-major 4x4 matrix, laid out like a GLSL `mat4`.
+pub type Mat4 = [[f32; 4]; 4];
+
+pub const IDENTITY: Mat4 = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+/// Rotation by `angle` radians around the Z axis.
+pub fn rotation_z(angle: f32) -> Mat4 {
+    let (sin, cos) = angle.sin_cos();
+    [
+        [cos, sin, 0.0, 0.0],
+        [-sin, cos, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+    ]
+}
+
+/// Scale X so one unit is as wide on screen as one unit is high, whatever the window's aspect ratio.
+pub fn aspect_correction(extent: vk::Extent2D) -> Mat4 {
+    let mut matrix = IDENTITY;
+    matrix[0][0] = extent.height as f32 / extent.width as f32;
+    matrix
+}
+
+/// The uniform block `shaders/shader.vert` reads at binding 0 (std140: three mat4s, no padding needed).
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct UniformBufferObject {
+    pub model: Mat4,
+    pub view: Mat4,
+    pub proj: Mat4,
+}
+
+/// One host visible uniform buffer per frame in flight, mapped for as long as it exists,
+/// so updating it every frame is just a copy.
+/// A frame's buffer may only be written once that frame's fence has been waited on.
+pub struct UniformBuffers<T: Copy> {
+    buffers: Vec<Buffer>,
+    mapped: Vec<*mut T>,
+}
+
+impl<T: Copy> UniformBuffers<T> {
+    pub fn new(
+        device: &Device,
+        allocator: &Allocator,
+        count: usize,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut uniform_buffers = Self {
+            buffers: Vec::new(),
+            mapped: Vec::new(),
+        };
+
+        for _ in 0..count {
+            let buffer = match Buffer::new(
+                device,
+                allocator,
+                std::mem::size_of::<T>() as vk::DeviceSize,
+                vk::BufferUsageFlags::UNIFORM_BUFFER,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            ) {
+                Ok(buffer) => buffer,
+                Err(err) => {
+                    uniform_buffers.destroy(device);
+                    return Err(err);
+                }
+            };
+            let mapped = unsafe {
+                device.map_memory(
+                    buffer.memory,
+                    0,
+                    vk::WHOLE_SIZE,
+                    vk::MemoryMapFlags::empty(),
+                )
+            };
+            uniform_buffers.buffers.push(buffer);
+            match mapped {
+                Ok(mapped) => uniform_buffers.mapped.push(mapped as *mut T),
+                Err(err) => {
+                    uniform_buffers.destroy(device);
+                    return Err(Box::new(err));
+                }
+            }
+        }
+
+        Ok(uniform_buffers)
+    }
+
+    /// The buffer of frame in flight `index`, e.g. for descriptor writes.
+    pub fn buffer(&self, index: usize) -> &Buffer {
+        &self.buffers[index]
+    }
+
+    /// Write `value` into the buffer of frame in flight `index`.
+    /// The memory is coherent, so no flush is needed before submitting.
+    pub fn update(&mut self, index: usize, value: &T) {
+        unsafe { self.mapped[index].write(*value) };
+    }
+
+    /// Unmap and destroy all buffers. The GPU must be done with them.
+    pub fn destroy(&mut self, device: &Device) {
+        for (index, mut buffer) in self.buffers.drain(..).enumerate() {
+            if index < self.mapped.len() {
+                unsafe { device.unmap_memory(buffer.memory) };
+            }
+            buffer.destroy(device);
+        }
+        self.mapped.clear();
+    }
+}
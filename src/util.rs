@@ -1,31 +1,194 @@
-use ash::ext::debug_utils;
-use ash::khr::{surface, swapchain};
+use ash::ext::{conservative_rasterization, debug_utils, memory_budget};
+use ash::google::display_timing;
+use ash::khr::{
+    image_format_list, maintenance2, push_descriptor, storage_buffer_storage_class, surface,
+    swapchain, swapchain_mutable_format,
+};
 use ash::vk::SurfaceKHR;
 use ash::{vk, Entry, Instance};
 use core::fmt;
 use std::collections::HashMap;
 use std::ffi::{CStr, CString};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 use std::{borrow::Cow, error::Error, os::raw::c_void, result::Result};
 use winit::raw_window_handle::RawDisplayHandle;
+use winit::window::Window;
+
+use crate::backend::BackendKind;
+use crate::settings::Args;
 
 //////////////// Constants ////////////////
 // This doesn't exist in this version of Ash
 // const REQUIRED_LAYERS: [&'static str; 1] = ["VK_LAYER_LUNARG_standard_validation"];
 // But this does
-pub const REQUIRED_LAYERS: [&'static str; 1] = ["VK_LAYER_KHRONOS_validation"];
-pub const REQUIRED_DEVICE_EXTENSIONS: [&'static CStr; 1] = [swapchain::NAME];
+pub const REQUIRED_LAYERS: [&str; 1] = ["VK_LAYER_KHRONOS_validation"];
+// Validation layers (and the debug_utils extension that reports through them) are only requested in debug builds.
+pub const ENABLE_VALIDATION_LAYERS: bool = cfg!(debug_assertions);
+// If the validation layers are missing (e.g. no Vulkan SDK installed), startup fails instead of
+// continuing without them. Also --strict-validation-layers on, see Settings.
+pub const STRICT_VALIDATION_LAYERS: bool = false;
+// Extra instance layers to enable, comma separated, e.g.
+// VULKAN_ASH_LAYERS=VK_LAYER_LUNARG_api_dump,VK_LAYER_LUNARG_gfxreconstruct cargo run
+// Layers that aren't installed are skipped with a warning.
+pub const EXTRA_LAYERS_ENV: &str = "VULKAN_ASH_LAYERS";
+// Force a physical device instead of the best ranked one, by its index in enumerate_physical_devices' order
+// or a case insensitive part of its name, e.g. VULKAN_ASH_DEVICE_INDEX=1 cargo run,
+// VULKAN_ASH_DEVICE=nvidia cargo run, cargo run -- --gpu-index 1 or cargo run -- --gpu "NVIDIA" (always a name,
+// even a number). The command line wins over the environment. Startup fails, listing the devices, if it
+// matches none.
+pub const DEVICE_INDEX_ENV: &str = "VULKAN_ASH_DEVICE_INDEX";
+pub const DEVICE_NAME_ENV: &str = "VULKAN_ASH_DEVICE";
+pub const DEVICE_INDEX_ARG: &str = "--gpu-index";
+pub const DEVICE_ARG: &str = "--gpu";
+// Which kind of physical device ranks first when none is forced, e.g. LowPower so a laptop renders on
+// its integrated GPU. Also VULKAN_ASH_DEVICE_PREFERENCE=low-power cargo run or
+// cargo run -- --gpu-preference low-power, which win over it.
+pub const DEVICE_PREFERENCE: DevicePreference = DevicePreference::HighPerformance;
+pub const DEVICE_PREFERENCE_ENV: &str = "VULKAN_ASH_DEVICE_PREFERENCE";
+pub const DEVICE_PREFERENCE_ARG: &str = "--gpu-preference";
+// How the scene is shaded (see backend.rs), e.g. Deferred. Also VULKAN_ASH_BACKEND=deferred cargo run or
+// cargo run -- --backend visibility-buffer, which win over it. The B key switches to the next one while running.
+pub const RENDERER_BACKEND: BackendKind = BackendKind::Forward;
+pub const RENDERER_BACKEND_ENV: &str = "VULKAN_ASH_BACKEND";
+pub const RENDERER_BACKEND_ARG: &str = "--backend";
+pub const REQUIRED_DEVICE_EXTENSIONS: [&CStr; 1] = [swapchain::NAME];
+// Enabled if available, so swapchain images can have both UNORM and SRGB views.
+// image_format_list and maintenance2 are core in Vulkan 1.2/1.1, but we ask for 1.0.
+pub const MUTABLE_SWAPCHAIN_FORMAT_EXTENSIONS: [&CStr; 3] = [
+    swapchain_mutable_format::NAME,
+    image_format_list::NAME,
+    maintenance2::NAME,
+];
+// Enabled if available, for present timing feedback.
+pub const DISPLAY_TIMING_EXTENSION: &CStr = display_timing::NAME;
+// Enabled if available, for pipelines that rasterize every pixel a primitive touches (e.g. voxelization).
+pub const CONSERVATIVE_RASTERIZATION_EXTENSION: &CStr = conservative_rasterization::NAME;
+// Enabled if available, for descriptors pushed into command buffers instead of allocated from pools.
+pub const PUSH_DESCRIPTOR_EXTENSION: &CStr = push_descriptor::NAME;
+// Enabled if available (on Vulkan 1.1), so allocations stay within the heap budgets, see memory.rs.
+pub const MEMORY_BUDGET_EXTENSION: &CStr = memory_budget::NAME;
+// Enabled if available on Vulkan 1.0 devices (it's core in 1.1), for the storage buffers in naga's SPIR-V.
+pub const STORAGE_BUFFER_STORAGE_CLASS_EXTENSION: &CStr = storage_buffer_storage_class::NAME;
+
+// How many frames the CPU may record ahead of the GPU.
+pub const MAX_FRAMES_IN_FLIGHT: usize = 2;
+
+// How long waits for the GPU outside of drawing frames (uploads, waiting for it to go idle)
+// may take before giving up. They also give up when the window is closed, see sync.rs.
+pub const WAIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Log a frame's submission (and the passes in it) if the GPU hasn't finished it after this long,
+// instead of waiting on its fence without a word. None turns the watchdog off.
+pub const GPU_WATCHDOG_TIMEOUT: Option<Duration> = Some(Duration::from_secs(5));
+
+// How often the last frame's draw statistics and the recent present timing are logged while running.
+pub const STATISTICS_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+// Stop drawing and log diagnostics when the watchdog catches a submission, rather than keep waiting for it.
+// The device isn't lost though, so shutting down still waits for the GPU (and leaks everything if it
+// doesn't finish within WAIT_TIMEOUT).
+pub const GPU_HANG_STOPS_DRAWING: bool = false;
+
+// Use a combined depth/stencil buffer and have the pipeline write to the stencil buffer
+// (e.g. for outline or portal effects). Also --enable-stencil on, see Settings.
+pub const ENABLE_STENCIL: bool = false;
+
+// How many copies of the triangle the demo draws, side by side, with one instanced draw.
+pub const TRIANGLE_INSTANCES: usize = 3;
+
+// At startup, voxelize the triangle with and without conservative rasterization and log how many
+// voxels each covers. Needs the fragmentStoresAndAtomics feature. Also --voxelization-demo on, see Settings.
+#[cfg(feature = "demos")]
+pub const VOXELIZATION_DEMO: bool = false;
+
+// Draw a plane below the triangles, subdivided and displaced by tessellation shaders.
+// Needs the tessellationShader feature. Also --tessellation-demo on, see Settings.
+#[cfg(feature = "demos")]
+pub const TESSELLATION_DEMO: bool = false;
+
+// Mark the triangles' vertices with small squares, which a geometry shader expands every corner into.
+// Needs the geometryShader feature. Also --vertex-markers-demo on, see Settings.
+#[cfg(feature = "demos")]
+pub const VERTEX_MARKERS_DEMO: bool = false;
+
+// Draw a cube (vertex::CUBE_POSITIONS, turned on the CPU, see cube.rs) above the triangles, with their pipeline.
+// Also --cube-demo on, see Settings.
+#[cfg(feature = "demos")]
+pub const CUBE_DEMO: bool = false;
+
+// A horizontal cross image (see Texture::cubemap_from_cross), or a directory with an image per face (see
+// Texture::cubemap_from_files), to draw behind the scene as a skybox, None for none. A checkerboard if it
+// can't be loaded. Also --skybox path/to/cross.png, see Settings.
+#[cfg(feature = "asset-import")]
+pub const SKYBOX: Option<&str> = None;
+
+// An image file (PNG or JPEG) or KTX2 file to draw on a quad in the bottom right corner, None for none.
+// A checkerboard if it doesn't exist. Also --texture path/to/texture.ktx2, see Settings.
+#[cfg(feature = "asset-import")]
+pub const TEXTURE: Option<&str> = None;
+
+// Log how far apart the GPU starts frames, measured with timestamps recorded through the escape hatches
+// (see frame_timer.rs). Also --gpu-frame-timer on, see Settings.
+pub const GPU_FRAME_TIMER: bool = false;
+
+// Hide the OS cursor and draw one ourselves, so it shows up in captures that don't include the OS cursor.
+// Also --software-cursor on, see Settings.
+pub const SOFTWARE_CURSOR: bool = false;
+
+// Record the scene and the overlays (the software cursor) into secondary command buffers.
+// The scene's are reused until something it draws changes, only the overlays are re-recorded every frame.
+// Also --secondary-command-buffers on, see Settings.
+pub const SECONDARY_COMMAND_BUFFERS: bool = false;
+
+// Threads to record each frame's draws on, into secondary command buffers. 0 records on the
+// graphics thread. Takes precedence over SECONDARY_COMMAND_BUFFERS. Also --recording-threads 4, see Settings.
+#[cfg(feature = "parallel-recording")]
+pub const RECORDING_THREADS: usize = 0;
+
+// Multisample anti-aliasing. Lowered to what the device supports, TYPE_1 turns it off.
+// Also --msaa-samples 8, see Settings.
+pub const MSAA_SAMPLES: vk::SampleCountFlags = vk::SampleCountFlags::TYPE_4;
+
+// Watch the shader directory and rebuild pipelines when their shaders change, see hot_reload.rs.
+#[cfg(feature = "hot-reload")]
+pub const SHADER_HOT_RELOAD: bool = cfg!(debug_assertions);
+
+// Draw with Vulkan 1.3 dynamic rendering instead of a render pass and framebuffers, when the
+// device supports it. See dynamic_rendering.rs.
+pub const DYNAMIC_RENDERING: bool = true;
+
+// Push the triangles' uniform buffer into the command buffer with every draw instead of binding
+// a descriptor set per frame in flight, when the device supports it. See descriptor.rs.
+pub const PUSH_DESCRIPTORS: bool = true;
+
+// Create a queue on the device's transfer-only and compute-only queue families, when it has them.
+// Buffer uploads then run on the transfer queue, see `command::TransferQueue`.
+pub const DEDICATED_QUEUES: bool = true;
+
+// Run a compute shader over every finished frame before presenting it, when the device can.
+// See post.rs. Also --compute-post-process on, see Settings.
+pub const COMPUTE_POST_PROCESS: bool = false;
+
+// Background color (RGBA) until VulkanApp::set_clear_color says otherwise.
+// Also --clear-color 0.1,0.1,0.2 (alpha optional), see Settings.
+pub const CLEAR_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+
+// Level the startup decisions report (see StartupDecisions) is logged at.
+// Also --startup-decisions-log-level debug, see Settings.
+pub const STARTUP_DECISIONS_LOG_LEVEL: log::Level = log::Level::Info;
 
 pub const WIDTH: u32 = 800;
 pub const HEIGHT: u32 = 600;
 
 //////////////// My Error (AppError) ////////////////
 #[derive(Debug)]
-struct AppError {
+pub struct AppError {
     details: String,
 }
 
 impl AppError {
-    fn new(msg: &str) -> Self {
+    pub fn new(msg: &str) -> Self {
         AppError {
             details: msg.to_string(),
         }
@@ -44,30 +207,156 @@ impl Error for AppError {
     }
 }
 
+//////////////// Startup Timings ////////////////
+/// Collects how long each initialization step took, so time-to-first-frame can be broken down.
+pub struct StartupTimings {
+    start: Instant,
+    steps: Vec<(&'static str, Duration)>,
+}
+
+impl StartupTimings {
+    pub fn start() -> Self {
+        Self {
+            start: Instant::now(),
+            steps: Vec::new(),
+        }
+    }
+
+    /// Run `f`, recording how long it took under `step`.
+    pub fn time<T>(&mut self, step: &'static str, f: impl FnOnce() -> T) -> T {
+        let started = Instant::now();
+        let result = f();
+        self.record(step, started.elapsed());
+        result
+    }
+
+    /// Record a step that was timed elsewhere (e.g. on another thread).
+    pub fn record(&mut self, step: &'static str, duration: Duration) {
+        self.steps.push((step, duration));
+    }
+
+    /// Log the breakdown at INFO level.
+    /// Steps run in parallel overlap, so they can add up to more than the total.
+    pub fn report(&self) {
+        log::info!("Startup took {:?}", self.start.elapsed());
+        for (step, duration) in self.steps.iter() {
+            log::info!("   - {}: {:?}", step, duration);
+        }
+    }
+}
+
+//////////////// Startup Decisions ////////////////
+/// What startup picked and why: which devices were rejected (and the one chosen), the swapchain's format,
+/// present mode and extent, and so on. Logged once the app is created, and again if the device is lost
+/// (see `VulkanApp::log_device_lost_diagnostics`), to attach to issues when a machine picks something
+/// unexpected.
+#[derive(Debug, Clone, Default)]
+pub struct StartupDecisions {
+    decisions: Vec<Decision>,
+}
+
+/// One thing startup decided.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decision {
+    /// What was decided, e.g. "device" or "present mode".
+    pub topic: &'static str,
+    pub outcome: String,
+    pub reason: String,
+}
+
+impl StartupDecisions {
+    /// Record that `topic` came out as `outcome`, because of `reason`.
+    pub fn record(
+        &mut self,
+        topic: &'static str,
+        outcome: impl Into<String>,
+        reason: impl Into<String>,
+    ) {
+        self.decisions.push(Decision {
+            topic,
+            outcome: outcome.into(),
+            reason: reason.into(),
+        });
+    }
+
+    /// Everything recorded, in order.
+    pub fn decisions(&self) -> &[Decision] {
+        &self.decisions
+    }
+
+    /// Log the report at `level`, see STARTUP_DECISIONS_LOG_LEVEL.
+    pub fn report(&self, level: log::Level) {
+        log::log!(level, "Startup decisions:");
+        for decision in self.decisions.iter() {
+            log::log!(level, "   - {}", decision);
+        }
+    }
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {} ({})", self.topic, self.outcome, self.reason)
+    }
+}
+
+impl fmt::Display for StartupDecisions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for decision in self.decisions.iter() {
+            writeln!(f, "{}", decision)?;
+        }
+        Ok(())
+    }
+}
+
+//////////////// Swapchain Support Errors ////////////////
+/// Why a swapchain can't be configured for a surface, with a hint on what to try.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwapchainSupportError {
+    /// The driver reported no surface formats (or only unusable ones).
+    NoSurfaceFormats,
+    /// The driver reported no present modes.
+    NoPresentModes,
+}
+
+impl fmt::Display for SwapchainSupportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SwapchainSupportError::NoSurfaceFormats => write!(
+                f,
+                "The surface reports no usable formats. \
+                 Try updating the graphics driver, or select another GPU if several are installed."
+            ),
+            SwapchainSupportError::NoPresentModes => write!(
+                f,
+                "The surface reports no present modes (FIFO is required by the spec, so the driver is non-conformant). \
+                 Try updating the graphics driver, or select another GPU if several are installed."
+            ),
+        }
+    }
+}
+
+impl Error for SwapchainSupportError {}
+
 /// Check if the required validation set in `REQUIRED_LAYERS`
 /// are supported by the Vulkan instance.
 ///
-/// # Panics
+/// # Errors
 ///
-/// Panic if at least one on the layer is not supported.
+/// Lists the missing layers if at least one of them is not supported.
 pub fn check_validation_layer_support(entry: &Entry) -> Result<(), Box<dyn Error>> {
     let mut missing_layers: Vec<&str> = Vec::new();
 
-    let instance_layer_properties = unsafe {
-        entry
-            .enumerate_instance_layer_properties()?
-            .iter()
-            .map(|layer| {
-                CStr::from_ptr(layer.layer_name.as_ptr())
-                    .to_str()
-                    .expect("Failed to get layer name pointer")
-            })
-            .collect::<Vec<_>>()
-    };
+    let instance_layer_properties = available_instance_layers(entry)?
+        .into_iter()
+        .map(|layer| layer.name)
+        .collect::<Vec<_>>();
 
     for required_layer in REQUIRED_LAYERS.iter() {
         log::info!("Searching for {:?}", required_layer);
-        if !instance_layer_properties.contains(required_layer) {
+        if !instance_layer_properties
+            .iter()
+            .any(|name| name == required_layer)
+        {
             log::info!("Missing {:?}", required_layer);
             missing_layers.push(required_layer);
         } else {
@@ -83,12 +372,62 @@ pub fn check_validation_layer_support(entry: &Entry) -> Result<(), Box<dyn Error
     }
 }
 
-/// Get the pointers to the validation layers names.
+/// An instance layer the Vulkan loader knows about.
+#[derive(Debug, Clone)]
+pub struct LayerInfo {
+    pub name: String,
+    pub description: String,
+}
+
+/// All instance layers that can be enabled, e.g. to offer them to the user.
+pub fn available_instance_layers(entry: &Entry) -> Result<Vec<LayerInfo>, Box<dyn Error>> {
+    let properties = unsafe { entry.enumerate_instance_layer_properties()? };
+
+    Ok(properties
+        .iter()
+        .map(|layer| LayerInfo {
+            name: layer
+                .layer_name_as_c_str()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            description: layer
+                .description_as_c_str()
+                .map(|description| description.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        })
+        .collect())
+}
+
+/// Layers named in the EXTRA_LAYERS_ENV environment variable that are installed.
+/// The others are left out with a warning.
+pub fn extra_instance_layers(entry: &Entry) -> Result<Vec<String>, Box<dyn Error>> {
+    let requested = match std::env::var(EXTRA_LAYERS_ENV) {
+        Ok(requested) => requested,
+        Err(_) => return Ok(Vec::new()),
+    };
+    let available = available_instance_layers(entry)?;
+
+    Ok(requested
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .filter(|name| {
+            let found = available.iter().any(|layer| layer.name == *name);
+            if !found {
+                log::warn!("Layer {} from {} isn't installed", name, EXTRA_LAYERS_ENV);
+            }
+            found
+        })
+        .map(str::to_string)
+        .collect())
+}
+
+/// Get the pointers to the names of `layers`.
 /// Also return the corresponding `CString` to avoid dangling pointers.
-pub fn get_layer_names_and_pointers() -> (Vec<CString>, Vec<*const i8>) {
-    let layer_names = REQUIRED_LAYERS
+pub fn get_layer_names_and_pointers(layers: &[String]) -> (Vec<CString>, Vec<*const i8>) {
+    let layer_names = layers
         .iter()
-        .map(|name| CString::new(*name).expect("Failed to build CString"))
+        .map(|name| CString::new(name.as_str()).expect("Failed to build CString"))
         .collect::<Vec<_>>();
 
     let layer_names_ptrs = layer_names
@@ -100,24 +439,27 @@ pub fn get_layer_names_and_pointers() -> (Vec<CString>, Vec<*const i8>) {
 }
 
 /// Vulkan extensions required by this application.
+/// Only request what is actually used: the window system's surface extensions,
+/// plus debug_utils when validation is enabled.
 pub fn get_extension_names(
     display_handle: Option<RawDisplayHandle>,
+    enable_validation: bool,
 ) -> Result<Vec<*const i8>, Box<dyn Error>> {
-    let extension_names = match display_handle {
+    let mut extension_names = match display_handle {
+        // Already includes VK_KHR_surface.
         Some(raw_display_handle) => {
-            let mut extension_names =
-                ash_window::enumerate_required_extensions(raw_display_handle)?.to_vec();
-            extension_names.push(surface::NAME.as_ptr());
-            extension_names.push(debug_utils::NAME.as_ptr());
-            extension_names
+            ash_window::enumerate_required_extensions(raw_display_handle)?.to_vec()
         }
         None => vec![
             surface::NAME.as_ptr(),
             // win32_surface::NAME.as_ptr(), // Does not work (on linux?)
-            debug_utils::NAME.as_ptr(),
         ],
     };
 
+    if enable_validation {
+        extension_names.push(debug_utils::NAME.as_ptr());
+    }
+
     Ok(extension_names)
 }
 
@@ -181,6 +523,36 @@ pub unsafe extern "system" fn vulkan_debug_callback(
     vk::FALSE
 }
 
+/// Find a memory type allowed by `type_bits` (from memory requirements) that has all of `properties`.
+pub fn find_memory_type(
+    memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    type_bits: u32,
+    properties: vk::MemoryPropertyFlags,
+) -> Result<u32, Box<dyn Error>> {
+    memory_properties.memory_types[..memory_properties.memory_type_count as usize]
+        .iter()
+        .enumerate()
+        .find(|(index, memory_type)| {
+            type_bits & (1 << index) != 0 && memory_type.property_flags.contains(properties)
+        })
+        .map(|(index, _)| index as u32)
+        .ok_or_else(|| {
+            Box::new(AppError::new(&format!(
+                "No memory type with {:?} for type bits {:#b}",
+                properties, type_bits
+            ))) as Box<dyn Error>
+        })
+}
+
+/// The window's current size as a Vulkan extent (0x0 while minimized on some platforms).
+pub fn window_extent(window: &Window) -> vk::Extent2D {
+    let size = window.inner_size();
+    vk::Extent2D {
+        width: size.width,
+        height: size.height,
+    }
+}
+
 /// Place to store some information about physical devices Vulkan discovers,
 /// mostly to determine their suitability for what we are attempting to do.
 #[derive(Default, Debug, Clone)]
@@ -188,6 +560,61 @@ pub struct DeviceDetails {
     pub name: String,
     pub graphics_queue_index: u32,
     pub present_queue_index: u32,
+    /// A family that can transfer but neither draw nor compute (usually a DMA engine), if DEDICATED_QUEUES
+    /// is set. It gets a queue of its own.
+    pub transfer_queue_index: Option<u32>,
+    /// A family that can compute but not draw (for async compute), if DEDICATED_QUEUES is set.
+    /// It gets a queue of its own.
+    pub compute_queue_index: Option<u32>,
+    /// MUTABLE_SWAPCHAIN_FORMAT_EXTENSIONS are supported (and get enabled).
+    pub mutable_swapchain_format: bool,
+    /// DISPLAY_TIMING_EXTENSION is supported (and gets enabled).
+    pub display_timing: bool,
+    /// The fillModeNonSolid feature is supported (and gets enabled), needed for wireframe.
+    pub fill_mode_non_solid: bool,
+    /// The samplerAnisotropy feature is supported (and gets enabled), up to this many samples.
+    pub sampler_anisotropy: Option<f32>,
+    /// The depthBounds feature is supported (and gets enabled), see `GraphicsPipelineBuilder::depth_bounds`.
+    pub depth_bounds: bool,
+    /// The depthClamp feature is supported (and gets enabled), see `GraphicsPipelineBuilder::depth_clamp`.
+    pub depth_clamp: bool,
+    /// The fragmentStoresAndAtomics feature is supported (and gets enabled), for storage buffer writes
+    /// from fragment shaders.
+    pub fragment_stores_and_atomics: bool,
+    /// The tessellationShader feature is supported (and gets enabled), for tessellation stages in pipelines.
+    pub tessellation_shader: bool,
+    /// The geometryShader feature is supported (and gets enabled), for geometry stages in pipelines.
+    pub geometry_shader: bool,
+    /// CONSERVATIVE_RASTERIZATION_EXTENSION is supported (and gets enabled).
+    pub conservative_rasterization: bool,
+    /// PUSH_DESCRIPTOR_EXTENSION is supported (and gets enabled), and PUSH_DESCRIPTORS is set.
+    pub push_descriptor: bool,
+    /// Vulkan 1.3 and its dynamicRendering feature are supported (and get enabled),
+    /// and DYNAMIC_RENDERING is set.
+    pub dynamic_rendering: bool,
+    /// Vulkan 1.2's shaderFloat16 feature is supported (and gets enabled), for float16_t math in shaders.
+    pub shader_float16: bool,
+    /// Vulkan 1.1's storageBuffer16BitAccess and uniformAndStorageBuffer16BitAccess features are supported
+    /// (and get enabled), for 16-bit values in uniform and storage buffers. See precision.rs.
+    pub storage_16bit: bool,
+    /// COMPUTE_POST_PROCESS is set and supported, see `device_supports_compute_post_process`.
+    /// The swapchain images then get SAMPLED and TRANSFER_DST usage.
+    pub compute_post_process: bool,
+    /// Both the device and the instance are Vulkan 1.1 or newer.
+    pub vulkan_1_1: bool,
+    /// Shaders may use the StorageBuffer storage class, which naga puts storage buffers in: Vulkan 1.1 is
+    /// supported, or STORAGE_BUFFER_STORAGE_CLASS_EXTENSION is (and gets enabled).
+    pub storage_buffer_storage_class: bool,
+    /// MEMORY_BUDGET_EXTENSION is supported (and gets enabled), and so is Vulkan 1.1 to query it with.
+    pub memory_budget: bool,
+}
+
+impl DeviceDetails {
+    /// The dedicated compute family the post process is dispatched on, see post.rs.
+    pub fn post_process_compute_family(&self) -> Option<u32> {
+        self.compute_queue_index
+            .filter(|_| self.compute_post_process)
+    }
 }
 
 impl fmt::Display for DeviceDetails {
@@ -224,13 +651,7 @@ pub fn physical_devices(instance: &Instance) -> Result<Vec<vk::PhysicalDevice>,
     unsafe { instance.enumerate_physical_devices()? }
         .iter()
         .for_each(|device| {
-            let device_name = unsafe {
-                let device_properties = instance.get_physical_device_properties(*device);
-                CStr::from_ptr(device_properties.device_name.as_ptr())
-                    .to_str()
-                    .expect("Could not convert pointer into string.")
-            };
-            log::debug!("Discovered Device: {:?}", device_name);
+            log::debug!("Discovered Device: {:?}", device_name(instance, *device));
 
             devices.push(*device);
         });
@@ -238,43 +659,178 @@ pub fn physical_devices(instance: &Instance) -> Result<Vec<vk::PhysicalDevice>,
     Ok(devices)
 }
 
+/// The name the driver reports for `device`.
+pub fn device_name(instance: &Instance, device: vk::PhysicalDevice) -> String {
+    let device_properties = unsafe { instance.get_physical_device_properties(device) };
+    device_properties.device_name_as_c_str().map_or_else(
+        |_| format!("{:?}", device),
+        |name| name.to_string_lossy().into_owned(),
+    )
+}
+
 /// Determine if discovered devices support the required device extensions (e.g., swapchain).
+/// Devices that don't are recorded in `decisions`, with what they're missing.
 pub fn devices_extension_support(
     instance: &Instance,
     devices: Vec<vk::PhysicalDevice>,
+    decisions: &mut StartupDecisions,
 ) -> Result<Vec<vk::PhysicalDevice>, Box<dyn Error>> {
     log::debug!("Check Device Extension Support.");
     let mut supported_devices: Vec<vk::PhysicalDevice> = Vec::new();
 
     for device in devices.iter() {
-        let extension_props = unsafe {
-            let extension_props = instance.enumerate_device_extension_properties(*device)?;
-            extension_props
-                .iter()
-                .map(|property| CStr::from_ptr(property.extension_name.as_ptr()))
-                .collect::<Vec<_>>()
-        };
+        let missing = missing_extensions(instance, *device, &REQUIRED_DEVICE_EXTENSIONS)?;
+        if missing.is_empty() {
+            supported_devices.push(*device);
+        } else {
+            decisions.record(
+                "device",
+                format!("rejected {}", device_name(instance, *device)),
+                format!("missing extensions {:?}", missing),
+            );
+        }
+    }
 
-        // log::debug!("{:?}", extension_props);
+    Ok(supported_devices)
+}
+
+/// Whether `device` supports all of `extensions`.
+pub fn device_supports_extensions(
+    instance: &Instance,
+    device: vk::PhysicalDevice,
+    extensions: &[&CStr],
+) -> Result<bool, Box<dyn Error>> {
+    Ok(missing_extensions(instance, device, extensions)?.is_empty())
+}
 
-        let extensions_support = REQUIRED_DEVICE_EXTENSIONS.iter().all(|name| {
+/// Which of `extensions` `device` doesn't support.
+fn missing_extensions<'a>(
+    instance: &Instance,
+    device: vk::PhysicalDevice,
+    extensions: &[&'a CStr],
+) -> Result<Vec<&'a CStr>, Box<dyn Error>> {
+    let extension_props = unsafe { instance.enumerate_device_extension_properties(device)? };
+    let extension_names = extension_props
+        .iter()
+        .map(|property| unsafe { CStr::from_ptr(property.extension_name.as_ptr()) })
+        .collect::<Vec<_>>();
+
+    Ok(extensions
+        .iter()
+        .filter(|name| {
             log::debug!("Checking device {:?} for support for {:?}", device, name);
-            extension_props.contains(name)
-        });
+            !extension_names.contains(name)
+        })
+        .copied()
+        .collect())
+}
 
-        if extensions_support {
-            supported_devices.push(*device);
-        }
+/// The Vulkan version to create the instance with: what the loader supports, up to 1.3.
+pub fn instance_api_version(entry: &Entry) -> Result<u32, Box<dyn Error>> {
+    let loader_version = unsafe { entry.try_enumerate_instance_version()? };
+    // A 1.0 loader doesn't have vkEnumerateInstanceVersion.
+    Ok(loader_version.map_or(vk::API_VERSION_1_0, |version| {
+        version.min(vk::API_VERSION_1_3)
+    }))
+}
+
+/// Whether `device` supports Vulkan 1.3's dynamicRendering feature, used through an instance
+/// created with `instance_version` (see `instance_api_version`).
+pub fn device_supports_dynamic_rendering(
+    instance: &Instance,
+    instance_version: u32,
+    device: vk::PhysicalDevice,
+) -> bool {
+    let device_version = unsafe { instance.get_physical_device_properties(device) }.api_version;
+    if instance_version < vk::API_VERSION_1_3 || device_version < vk::API_VERSION_1_3 {
+        return false;
     }
 
-    Ok(supported_devices)
+    let mut vulkan_13_features = vk::PhysicalDeviceVulkan13Features::default();
+    let mut features = vk::PhysicalDeviceFeatures2::default().push_next(&mut vulkan_13_features);
+    unsafe { instance.get_physical_device_features2(device, &mut features) };
+    vulkan_13_features.dynamic_rendering == vk::TRUE
+}
+
+/// Whether `device` supports (shaderFloat16, 16-bit uniform and storage buffer access), used through an
+/// instance created with `instance_version`. Both are queried through the Vulkan 1.1 and 1.2 feature
+/// structs, so they need Vulkan 1.2.
+pub fn device_supports_half_precision(
+    instance: &Instance,
+    instance_version: u32,
+    device: vk::PhysicalDevice,
+) -> (bool, bool) {
+    let device_version = unsafe { instance.get_physical_device_properties(device) }.api_version;
+    if instance_version < vk::API_VERSION_1_2 || device_version < vk::API_VERSION_1_2 {
+        return (false, false);
+    }
+
+    let mut vulkan_11_features = vk::PhysicalDeviceVulkan11Features::default();
+    let mut vulkan_12_features = vk::PhysicalDeviceVulkan12Features::default();
+    let mut features = vk::PhysicalDeviceFeatures2::default()
+        .push_next(&mut vulkan_11_features)
+        .push_next(&mut vulkan_12_features);
+    unsafe { instance.get_physical_device_features2(device, &mut features) };
+    (
+        vulkan_12_features.shader_float16 == vk::TRUE,
+        vulkan_11_features.storage_buffer16_bit_access == vk::TRUE
+            && vulkan_11_features.uniform_and_storage_buffer16_bit_access == vk::TRUE,
+    )
+}
+
+/// Whether the compute post process (see post.rs) can run on `device`: its graphics queue family can run
+/// compute, and the images of a swapchain on `surface_khr` can be sampled and blitted into.
+pub fn device_supports_compute_post_process(
+    instance: &Instance,
+    device: vk::PhysicalDevice,
+    graphics_queue_index: u32,
+    surface: &surface::Instance,
+    surface_khr: SurfaceKHR,
+) -> Result<bool, Box<dyn Error>> {
+    let queue_families = unsafe { instance.get_physical_device_queue_family_properties(device) };
+    let compute = queue_families
+        .get(graphics_queue_index as usize)
+        .is_some_and(|family| family.queue_flags.contains(vk::QueueFlags::COMPUTE));
+
+    let support = SwapChainSupportDetails::new(device, surface, surface_khr)?;
+    let usage = vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST;
+    let swapchain_usage = support.capabilities.supported_usage_flags.contains(usage);
+
+    let format = support.choose_swapchain_surface_format()?.0.format;
+    let features = vk::FormatFeatureFlags::SAMPLED_IMAGE | vk::FormatFeatureFlags::BLIT_DST;
+    let format_features = unsafe { instance.get_physical_device_format_properties(device, format) }
+        .optimal_tiling_features
+        .contains(features);
+
+    Ok(compute && swapchain_usage && format_features)
 }
 
-/// Determine if discovered devices have an adequate swapchain
+/// The UNORM and SRGB variants of `format` (in that order), if it has both.
+/// A MUTABLE_FORMAT swapchain of either can be viewed as the other.
+pub fn srgb_format_pair(format: vk::Format) -> Option<[vk::Format; 2]> {
+    match format {
+        vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB => {
+            Some([vk::Format::B8G8R8A8_UNORM, vk::Format::B8G8R8A8_SRGB])
+        }
+        vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB => {
+            Some([vk::Format::R8G8B8A8_UNORM, vk::Format::R8G8B8A8_SRGB])
+        }
+        vk::Format::A8B8G8R8_UNORM_PACK32 | vk::Format::A8B8G8R8_SRGB_PACK32 => Some([
+            vk::Format::A8B8G8R8_UNORM_PACK32,
+            vk::Format::A8B8G8R8_SRGB_PACK32,
+        ]),
+        _ => None,
+    }
+}
+
+/// Determine if discovered devices have an adequate swapchain.
+/// Devices that don't are recorded in `decisions`.
 pub fn devices_swapchain_adequate(
+    instance: &Instance,
     surface: &surface::Instance,
     surface_khr: SurfaceKHR,
     devices: Vec<vk::PhysicalDevice>,
+    decisions: &mut StartupDecisions,
 ) -> Result<Vec<vk::PhysicalDevice>, Box<dyn Error>> {
     log::debug!("Check SwapChain Adequacy.");
 
@@ -290,6 +846,15 @@ pub fn devices_swapchain_adequate(
                 "Device {:?} swapchain is inadequate (does not support format or present_modes).",
                 device
             );
+            decisions.record(
+                "device",
+                format!("rejected {}", device_name(instance, *device)),
+                format!(
+                    "the surface reports {} formats and {} present modes for it",
+                    formats.len(),
+                    present_modes.len()
+                ),
+            );
         } else {
             supported_devices.push(*device);
         }
@@ -304,11 +869,15 @@ type DeviceMap = HashMap<vk::PhysicalDevice, DeviceDetails>;
 /// Filter physical devices based on if they support required queues (present and graphics).
 /// This SHOULD take only some form of "suitable" device,
 /// filtered by devices_swapchain_adequate and devices_extension_support.
+/// A family that can do both is preferred, as it needs no ownership transfers or CONCURRENT swapchain
+/// images. Otherwise the graphics and present families are different ones.
+/// The families picked, and devices without them, are recorded in `decisions`.
 pub fn devices_queue_family_support(
     instance: &Instance,
     surface: &surface::Instance,
     surface_khr: SurfaceKHR,
     devices: Vec<vk::PhysicalDevice>,
+    decisions: &mut StartupDecisions,
 ) -> Result<DeviceMap, Box<dyn Error>> {
     log::debug!("Find queue families.");
 
@@ -317,68 +886,493 @@ pub fn devices_queue_family_support(
     for device in devices.iter() {
         let props = unsafe { instance.get_physical_device_queue_family_properties(*device) };
 
-        let device_name = unsafe {
-            let device_properties = instance.get_physical_device_properties(*device);
-            CStr::from_ptr(device_properties.device_name.as_ptr())
-                .to_str()
-                .expect("Could not convert pointer into string.")
-        };
-
-        for (index, family) in props.iter().filter(|f| f.queue_count > 0).enumerate() {
-            let mut graphics: Option<u32> = None;
-            let mut present: Option<u32> = None;
+        let device_name = device_name(instance, *device);
 
+        // Every family that can draw, and every one that can present, in index order.
+        let mut graphics_families = Vec::new();
+        let mut present_families = Vec::new();
+        // The first transfer-only and compute-only families.
+        let mut transfer_family = None;
+        let mut compute_family = None;
+        for (index, family) in props.iter().enumerate() {
             let index = index as u32;
-            log::debug!("Property {}: {:?}", index, family);
+            log::debug!("Queue family {}: {:?}", index, family);
+            if family.queue_count == 0 {
+                continue;
+            }
 
             if family.queue_flags.contains(vk::QueueFlags::GRAPHICS) {
-                graphics = Some(index);
+                graphics_families.push(index);
+            } else if family.queue_flags.contains(vk::QueueFlags::COMPUTE) {
+                compute_family = compute_family.or(Some(index));
+            } else if family.queue_flags.contains(vk::QueueFlags::TRANSFER) {
+                transfer_family = transfer_family.or(Some(index));
             }
 
             let present_support = unsafe {
                 surface.get_physical_device_surface_support(*device, index, surface_khr)?
             };
-
             if present_support {
-                present = Some(index);
+                present_families.push(index);
             }
+        }
 
-            if graphics.is_some() && present.is_some() {
-                supported_devices.insert(
-                    *device,
-                    DeviceDetails {
-                        name: device_name.to_string(),
-                        graphics_queue_index: index,
-                        present_queue_index: index,
-                    },
-                );
-            } else {
-                log::debug!(
-                    "Device {:?} does not support graphics or presentation.",
-                    device
-                );
+        let combined = graphics_families
+            .iter()
+            .find(|index| present_families.contains(index));
+        let (graphics, present) = match (
+            combined,
+            graphics_families.first(),
+            present_families.first(),
+        ) {
+            (Some(&index), _, _) => (index, index),
+            (None, Some(&graphics), Some(&present)) => (graphics, present),
+            (_, graphics, _) => {
+                let reason = if graphics.is_none() {
+                    "no queue family can draw"
+                } else {
+                    "no queue family can present to the surface"
+                };
+                decisions.record("device", format!("rejected {}", device_name), reason);
+                continue;
             }
+        };
+
+        let outcome = format!(
+            "graphics {}, present {} on {}",
+            graphics, present, device_name
+        );
+        if graphics == present {
+            decisions.record("queue families", outcome, "one family does both");
+        } else {
+            decisions.record(
+                "queue families",
+                outcome,
+                "no family does both, so presented images are shared between them",
+            );
         }
+        let (transfer, compute) = if DEDICATED_QUEUES {
+            (transfer_family, compute_family)
+        } else {
+            (None, None)
+        };
+        let describe = |family: Option<u32>| family.map_or("none".to_string(), |f| f.to_string());
+        decisions.record(
+            "dedicated queue families",
+            format!(
+                "transfer {}, compute {} on {}",
+                describe(transfer),
+                describe(compute),
+                device_name
+            ),
+            if DEDICATED_QUEUES {
+                "the first families that can only transfer, and compute but not draw"
+            } else {
+                "DEDICATED_QUEUES is off"
+            },
+        );
+        supported_devices.insert(
+            *device,
+            DeviceDetails {
+                name: device_name,
+                graphics_queue_index: graphics,
+                present_queue_index: present,
+                transfer_queue_index: transfer,
+                compute_queue_index: compute,
+                ..Default::default()
+            },
+        );
     }
 
     Ok(supported_devices)
 }
 
-/// Picks the first available physical device in the device map
-/// Extend functionality (e.g. rank devices) later.
+/// How suitable a supported device is, see `rank_physical_devices`.
+#[derive(Debug, Clone)]
+pub struct DeviceRanking {
+    pub device: vk::PhysicalDevice,
+    pub name: String,
+    pub score: u32,
+    /// What the score is made of, each part with the points it gave.
+    pub breakdown: Vec<(String, u32)>,
+}
+
+impl fmt::Display for DeviceRanking {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let breakdown = self
+            .breakdown
+            .iter()
+            .map(|(part, points)| format!("{} +{}", part, points))
+            .collect::<Vec<_>>();
+        write!(
+            f,
+            "{} scores {} ({})",
+            self.name,
+            self.score,
+            breakdown.join(", ")
+        )
+    }
+}
+
+/// What kind of physical device `rank_physical_devices` puts first.
+/// CPU devices (software rasterizers) come last under every preference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DevicePreference {
+    /// A discrete GPU if there is one, otherwise the most capable device.
+    Discrete,
+    /// An integrated GPU if there is one, otherwise the most capable device.
+    Integrated,
+    /// The most capable device, whatever its type.
+    Any,
+    /// Integrated GPUs, then discrete, virtual and CPU ones. Capability doesn't count, a bigger GPU draws
+    /// more power.
+    LowPower,
+    /// Discrete GPUs, then integrated, virtual and CPU ones, the most capable first.
+    HighPerformance,
+}
+
+impl DevicePreference {
+    /// The points a device of `device_type` scores for its type.
+    fn type_points(self, device_type: vk::PhysicalDeviceType) -> u32 {
+        use vk::PhysicalDeviceType as Type;
+        match (self, device_type) {
+            // Far enough below every GPU that capability can't make up for it.
+            (_, Type::CPU) => 0,
+            (Self::Discrete, Type::DISCRETE_GPU) => 2000,
+            (Self::Integrated, Type::INTEGRATED_GPU) => 2000,
+            (Self::Discrete | Self::Integrated | Self::Any, _) => 1000,
+            (Self::LowPower, Type::INTEGRATED_GPU) => 1000,
+            (Self::LowPower, Type::DISCRETE_GPU) => 500,
+            (Self::LowPower, Type::VIRTUAL_GPU) => 200,
+            (Self::HighPerformance, Type::DISCRETE_GPU) => 1000,
+            (Self::HighPerformance, Type::INTEGRATED_GPU) => 500,
+            (Self::HighPerformance, Type::VIRTUAL_GPU) => 200,
+            _ => 100,
+        }
+    }
+
+    /// Whether memory, image size and queue families add to the score.
+    fn counts_capability(self) -> bool {
+        self != Self::LowPower
+    }
+}
+
+impl FromStr for DevicePreference {
+    type Err = AppError;
+
+    /// The names `Display` gives, any case, with or without the dash.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().replace(['-', '_'], "").as_str() {
+            "discrete" => Ok(Self::Discrete),
+            "integrated" => Ok(Self::Integrated),
+            "any" => Ok(Self::Any),
+            "lowpower" => Ok(Self::LowPower),
+            "highperformance" => Ok(Self::HighPerformance),
+            _ => Err(AppError::new(&format!(
+                "Unknown device preference {:?}, it's one of discrete, integrated, any, low-power \
+                 or high-performance",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for DevicePreference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Discrete => "discrete",
+            Self::Integrated => "integrated",
+            Self::Any => "any",
+            Self::LowPower => "low-power",
+            Self::HighPerformance => "high-performance",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Score the supported `devices` (see devices_queue_family_support), best first.
+/// The device type counts most, how depends on `preference`. Then (unless it's LowPower) more device
+/// local memory, bigger images, and queue families that let work run beside graphics count.
+/// Ties go by name, so the order is stable.
+pub fn rank_physical_devices(
+    instance: &Instance,
+    devices: &DeviceMap,
+    preference: DevicePreference,
+) -> Vec<DeviceRanking> {
+    let mut rankings = devices
+        .iter()
+        .map(|(device, details)| {
+            let properties = unsafe { instance.get_physical_device_properties(*device) };
+            let memory_properties =
+                unsafe { instance.get_physical_device_memory_properties(*device) };
+            let queue_families =
+                unsafe { instance.get_physical_device_queue_family_properties(*device) };
+
+            let mut breakdown = Vec::new();
+            breakdown.push((
+                format!("{:?}", properties.device_type),
+                preference.type_points(properties.device_type),
+            ));
+            let capability = |points: u32| {
+                if preference.counts_capability() {
+                    points
+                } else {
+                    0
+                }
+            };
+
+            let device_local_bytes: u64 = memory_properties.memory_heaps
+                [..memory_properties.memory_heap_count as usize]
+                .iter()
+                .filter(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+                .map(|heap| heap.size)
+                .sum();
+            let gib = (device_local_bytes >> 30) as u32;
+            // Capped, so memory doesn't outweigh the device type.
+            breakdown.push((
+                format!("{} GiB device local memory", gib),
+                capability(gib.min(32) * 10),
+            ));
+
+            let max_image = properties.limits.max_image_dimension2_d;
+            breakdown.push((
+                format!("{} max 2D image size", max_image),
+                capability(max_image / 1024),
+            ));
+
+            let families = |wanted: vk::QueueFlags, unwanted: vk::QueueFlags| {
+                queue_families.iter().any(|family| {
+                    family.queue_count > 0
+                        && family.queue_flags.contains(wanted)
+                        && !family.queue_flags.intersects(unwanted)
+                })
+            };
+            if families(vk::QueueFlags::COMPUTE, vk::QueueFlags::GRAPHICS) {
+                breakdown.push(("async compute queue family".to_string(), capability(20)));
+            }
+            if families(
+                vk::QueueFlags::TRANSFER,
+                vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE,
+            ) {
+                breakdown.push(("transfer queue family".to_string(), capability(10)));
+            }
+
+            DeviceRanking {
+                device: *device,
+                name: details.name.clone(),
+                score: breakdown.iter().map(|(_, points)| points).sum(),
+                breakdown,
+            }
+        })
+        .collect::<Vec<_>>();
+    rankings.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.name.cmp(&b.name)));
+    rankings
+}
+
+//////////////// Device Selection ////////////////
+/// How the user wants the physical device picked, from the command line or the environment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceSelection {
+    /// Forced device, see DEVICE_INDEX_ENV.
+    pub device_override: Option<DeviceOverride>,
+    /// How the others are ranked, see DEVICE_PREFERENCE.
+    pub preference: DevicePreference,
+}
+
+impl DeviceSelection {
+    /// From DEVICE_ARG (or DEVICE_INDEX_ARG) and DEVICE_PREFERENCE_ARG in `args`,
+    /// or else the environment variables next to them, or else DEVICE_PREFERENCE and no override.
+    pub fn from_args_and_env(args: &Args) -> Result<Self, Box<dyn Error>> {
+        let device_override = match (args.value(DEVICE_ARG)?, args.value(DEVICE_INDEX_ARG)?) {
+            (Some(_), Some(_)) => {
+                return Err(Box::new(AppError::new(&format!(
+                    "{} and {} can't both be given",
+                    DEVICE_ARG, DEVICE_INDEX_ARG
+                ))))
+            }
+            // It would match every device's name.
+            (Some(name), None) if name.is_empty() => {
+                return Err(Box::new(AppError::new(&format!(
+                    "{} needs a device name, not an empty one",
+                    DEVICE_ARG
+                ))))
+            }
+            (Some(name), None) => Some(DeviceOverride::Name(name)),
+            (None, Some(index)) => Some(DeviceOverride::Index(index.parse().map_err(|_| {
+                AppError::new(&format!(
+                    "{} must be a device index, not {:?}",
+                    DEVICE_INDEX_ARG, index
+                ))
+            })?)),
+            (None, None) => DeviceOverride::from_env()?,
+        };
+
+        let preference = match args.value(DEVICE_PREFERENCE_ARG)? {
+            Some(value) => value.parse()?,
+            None => match std::env::var(DEVICE_PREFERENCE_ENV) {
+                Ok(value) => value.parse()?,
+                Err(_) => DEVICE_PREFERENCE,
+            },
+        };
+
+        Ok(Self {
+            device_override,
+            preference,
+        })
+    }
+}
+
+/// A physical device the user forced, see DEVICE_INDEX_ENV.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeviceOverride {
+    /// Index in enumerate_physical_devices' order.
+    Index(usize),
+    /// Case insensitive part of the device's name, the first device it's in is picked.
+    Name(String),
+}
+
+impl DeviceOverride {
+    /// From the DEVICE_INDEX_ENV or DEVICE_NAME_ENV environment variables, None if neither is set.
+    fn from_env() -> Result<Option<Self>, Box<dyn Error>> {
+        if let Ok(index) = std::env::var(DEVICE_INDEX_ENV) {
+            let index = index.trim().parse().map_err(|_| {
+                AppError::new(&format!(
+                    "{} must be a device index, not {:?}",
+                    DEVICE_INDEX_ENV, index
+                ))
+            })?;
+            return Ok(Some(Self::Index(index)));
+        }
+        Ok(std::env::var(DEVICE_NAME_ENV)
+            .ok()
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty())
+            .map(Self::Name))
+    }
+
+    /// Whether the device at `index` in enumerate_physical_devices' order, called `name`, is the one.
+    fn matches(&self, index: usize, name: &str) -> bool {
+        match self {
+            Self::Index(wanted) => index == *wanted,
+            Self::Name(part) => name.to_lowercase().contains(&part.to_lowercase()),
+        }
+    }
+}
+
+impl fmt::Display for DeviceOverride {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Index(index) => write!(f, "device index {}", index),
+            Self::Name(part) => write!(f, "device name {:?}", part),
+        }
+    }
+}
+
+/// The device `device_override` asks for out of `enumerated` (all devices, in enumerate_physical_devices'
+/// order), which has to be one of the supported `devices`. Otherwise the error lists the devices.
+fn overridden_device(
+    instance: &Instance,
+    enumerated: &[vk::PhysicalDevice],
+    devices: &DeviceMap,
+    device_override: &DeviceOverride,
+) -> Result<vk::PhysicalDevice, Box<dyn Error>> {
+    let names = enumerated
+        .iter()
+        .map(|device| device_name(instance, *device))
+        .collect::<Vec<_>>();
+    let available = enumerated
+        .iter()
+        .zip(names.iter())
+        .enumerate()
+        .map(|(index, (device, name))| {
+            let supported = if devices.contains_key(device) {
+                ""
+            } else {
+                " (unsupported)"
+            };
+            format!("\n  {}: {}{}", index, name, supported)
+        })
+        .collect::<String>();
+
+    let matched = enumerated
+        .iter()
+        .zip(names.iter())
+        .enumerate()
+        .find(|(index, (_, name))| device_override.matches(*index, name));
+    match matched {
+        Some((_, (device, _))) if devices.contains_key(device) => Ok(*device),
+        Some((_, (_, name))) => Err(Box::new(AppError::new(&format!(
+            "{} picks {}, which doesn't support what the app needs. Devices:{}",
+            device_override, name, available
+        )))),
+        None => Err(Box::new(AppError::new(&format!(
+            "{} doesn't match any device. Devices:{}",
+            device_override, available
+        )))),
+    }
+}
+
+/// Picks the best device in the device map, see `rank_physical_devices` with `selection`'s preference,
+/// unless its override asks for one of `enumerated` (all devices, in enumerate_physical_devices' order).
 /// Also, devices_..._support functions must be run first, which isn't enforced (and needs to be).
+/// The ranking and pick are recorded in `decisions`.
 pub fn pick_physical_device(
+    instance: &Instance,
+    enumerated: &[vk::PhysicalDevice],
     devices: &DeviceMap,
+    selection: &DeviceSelection,
+    decisions: &mut StartupDecisions,
 ) -> Result<(vk::PhysicalDevice, DeviceDetails), Box<dyn Error>> {
-    for (device, details) in devices.iter() {
-        return Ok((*device, details.clone()));
+    decisions.record(
+        "device preference",
+        selection.preference.to_string(),
+        format!(
+            "from {}, {} or util::DEVICE_PREFERENCE",
+            DEVICE_PREFERENCE_ARG, DEVICE_PREFERENCE_ENV
+        ),
+    );
+    let rankings = rank_physical_devices(instance, devices, selection.preference);
+    for (rank, ranking) in rankings.iter().enumerate() {
+        decisions.record(
+            "device ranking",
+            format!("#{}", rank + 1),
+            ranking.to_string(),
+        );
+    }
+
+    if let Some(device_override) = &selection.device_override {
+        let device = overridden_device(instance, enumerated, devices, device_override)?;
+        let details = devices[&device].clone();
+        decisions.record(
+            "device",
+            format!("picked {}", details.name),
+            format!("forced by {}, instead of the ranking", device_override),
+        );
+        return Ok((device, details));
+    }
+
+    match rankings.first() {
+        Some(best) => {
+            decisions.record(
+                "device",
+                format!("picked {}", best.name),
+                format!(
+                    "the highest score ({}) of {} supported devices, preferring {}",
+                    best.score,
+                    rankings.len(),
+                    selection.preference
+                ),
+            );
+            Ok((best.device, devices[&best.device].clone()))
+        }
+        None => Err(Box::new(AppError::new(
+            "No supported physical devices to choose from!",
+        ))),
     }
-    return Err(Box::new(AppError::new(
-        "No supported physical devices to choose from!",
-    )));
 }
 
-/// [ ] ToDo: Some meaningful Description.
+/// What a surface supports for a given physical device (capabilities, formats, present modes),
+/// used to pick the swapchain's settings.
 pub struct SwapChainSupportDetails {
     pub capabilities: vk::SurfaceCapabilitiesKHR,
     pub formats: Vec<vk::SurfaceFormatKHR>,
@@ -406,44 +1400,188 @@ impl SwapChainSupportDetails {
         })
     }
 
-    pub fn choose_swapchain_surface_format(&self) -> vk::SurfaceFormatKHR {
+    /// Prefer B8G8R8A8_UNORM / SRGB_NONLINEAR, otherwise take the first usable format.
+    /// A single UNDEFINED format means the surface has no preference. With why, for `StartupDecisions`.
+    pub fn choose_swapchain_surface_format(
+        &self,
+    ) -> Result<(vk::SurfaceFormatKHR, String), SwapchainSupportError> {
+        let preferred = vk::SurfaceFormatKHR {
+            format: vk::Format::B8G8R8A8_UNORM,
+            color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+        };
+
         if self.formats.len() == 1 && self.formats[0].format == vk::Format::UNDEFINED {
-            return vk::SurfaceFormatKHR {
-                format: vk::Format::B8G8R8A8_UNORM,
-                color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
-            };
+            let reason = "the surface has no preference, so the preferred one";
+            return Ok((preferred, reason.to_string()));
         }
 
-        *self
+        if self.formats.contains(&preferred) {
+            return Ok((preferred, "the preferred one".to_string()));
+        }
+
+        // UNDEFINED among other formats can't be used to create a swapchain.
+        let format = self
             .formats
             .iter()
-            .find(|format| {
-                format.format == vk::Format::B8G8R8A8_UNORM
-                    && format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
-            })
-            .unwrap_or(&self.formats[0])
+            .find(|format| format.format != vk::Format::UNDEFINED)
+            .copied()
+            .ok_or(SwapchainSupportError::NoSurfaceFormats)?;
+        let reason = format!(
+            "B8G8R8A8_UNORM/SRGB_NONLINEAR isn't supported, the first usable of {:?}",
+            self.formats
+        );
+        Ok((format, reason))
     }
 
-    pub fn choose_swapchain_surface_present_mode(&self) -> vk::PresentModeKHR {
+    /// Prefer MAILBOX, then FIFO (always supported by conformant drivers), then whatever is reported first.
+    /// With why, for `StartupDecisions`.
+    pub fn choose_swapchain_surface_present_mode(
+        &self,
+    ) -> Result<(vk::PresentModeKHR, String), SwapchainSupportError> {
         if self.present_modes.contains(&vk::PresentModeKHR::MAILBOX) {
-            vk::PresentModeKHR::MAILBOX
+            Ok((vk::PresentModeKHR::MAILBOX, "preferred".to_string()))
         } else if self.present_modes.contains(&vk::PresentModeKHR::FIFO) {
-            vk::PresentModeKHR::FIFO
+            let reason = format!("MAILBOX isn't supported ({:?})", self.present_modes);
+            Ok((vk::PresentModeKHR::FIFO, reason))
         } else {
-            vk::PresentModeKHR::IMMEDIATE
+            let present_mode = self
+                .present_modes
+                .first()
+                .copied()
+                .ok_or(SwapchainSupportError::NoPresentModes)?;
+            let reason = format!(
+                "neither MAILBOX nor FIFO is supported, the first of {:?}",
+                self.present_modes
+            );
+            Ok((present_mode, reason))
         }
     }
 
-    pub fn choose_swapchain_extent(&self) -> vk::Extent2D {
-        if self.capabilities.current_extent.width != std::u32::MAX {
-            return self.capabilities.current_extent;
+    /// Record what the `choose_swapchain_*` methods choose, and why, in `decisions`.
+    pub fn record_decisions(
+        &self,
+        window_extent: vk::Extent2D,
+        decisions: &mut StartupDecisions,
+    ) -> Result<(), SwapchainSupportError> {
+        let (format, reason) = self.choose_swapchain_surface_format()?;
+        decisions.record(
+            "surface format",
+            format!("{:?} / {:?}", format.format, format.color_space),
+            reason,
+        );
+
+        let (present_mode, reason) = self.choose_swapchain_surface_present_mode()?;
+        decisions.record("present mode", format!("{:?}", present_mode), reason);
+
+        let (extent, reason) = self.choose_swapchain_extent(window_extent);
+        decisions.record(
+            "extent",
+            format!("{}x{}", extent.width, extent.height),
+            reason,
+        );
+        Ok(())
+    }
+
+    /// Use the surface's extent if it has one, otherwise fit the window's size within the supported range.
+    /// Can be 0x0 (e.g. while the window is minimized), in which case no swapchain can be created.
+    /// With why, for `StartupDecisions`.
+    pub fn choose_swapchain_extent(&self, window_extent: vk::Extent2D) -> (vk::Extent2D, String) {
+        if self.capabilities.current_extent.width != u32::MAX {
+            let reason = "the surface's current extent".to_string();
+            return (self.capabilities.current_extent, reason);
         }
 
         let min = self.capabilities.min_image_extent;
         let max = self.capabilities.max_image_extent;
-        let width = WIDTH.min(max.width).max(min.width);
-        let height = HEIGHT.min(max.height).max(min.height);
+        let width = window_extent.width.min(max.width).max(min.width);
+        let height = window_extent.height.min(max.height).max(min.height);
+        let reason = format!(
+            "the window's {}x{}, within {}x{} to {}x{}",
+            window_extent.width, window_extent.height, min.width, min.height, max.width, max.height
+        );
+
+        (vk::Extent2D { width, height }, reason)
+    }
+}
 
-        vk::Extent2D { width, height }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vk::PhysicalDeviceType as Type;
+
+    const PREFERENCES: [DevicePreference; 5] = [
+        DevicePreference::Discrete,
+        DevicePreference::Integrated,
+        DevicePreference::Any,
+        DevicePreference::LowPower,
+        DevicePreference::HighPerformance,
+    ];
+
+    // Capability adds a few hundred points at most, so a CPU device can't make up the difference.
+    #[test]
+    fn cpu_ranks_last() {
+        for preference in PREFERENCES {
+            let cpu = preference.type_points(Type::CPU);
+            for device_type in [
+                Type::DISCRETE_GPU,
+                Type::INTEGRATED_GPU,
+                Type::VIRTUAL_GPU,
+                Type::OTHER,
+            ] {
+                assert!(
+                    cpu < preference.type_points(device_type),
+                    "{} ranks CPU above {:?}",
+                    preference,
+                    device_type
+                );
+            }
+            assert!(preference.type_points(Type::INTEGRATED_GPU) >= cpu + 500);
+        }
+    }
+
+    #[test]
+    fn preferred_type_first() {
+        let ranks = |preference: DevicePreference, first: Type, second: Type| {
+            preference.type_points(first) > preference.type_points(second)
+        };
+        assert!(ranks(
+            DevicePreference::Discrete,
+            Type::DISCRETE_GPU,
+            Type::INTEGRATED_GPU
+        ));
+        assert!(ranks(
+            DevicePreference::Integrated,
+            Type::INTEGRATED_GPU,
+            Type::DISCRETE_GPU
+        ));
+        assert!(ranks(
+            DevicePreference::LowPower,
+            Type::INTEGRATED_GPU,
+            Type::DISCRETE_GPU
+        ));
+        assert!(ranks(
+            DevicePreference::HighPerformance,
+            Type::DISCRETE_GPU,
+            Type::INTEGRATED_GPU
+        ));
+        assert_eq!(
+            DevicePreference::Any.type_points(Type::DISCRETE_GPU),
+            DevicePreference::Any.type_points(Type::INTEGRATED_GPU)
+        );
+    }
+
+    #[test]
+    fn preference_names_round_trip() {
+        for preference in PREFERENCES {
+            assert_eq!(
+                preference.to_string().parse::<DevicePreference>().unwrap(),
+                preference
+            );
+        }
+        assert_eq!(
+            "High_Performance".parse::<DevicePreference>().unwrap(),
+            DevicePreference::HighPerformance
+        );
+        assert!("fastest".parse::<DevicePreference>().is_err());
     }
 }
@@ -0,0 +1,171 @@
+use ash::vk;
+use std::mem::offset_of;
+
+//////////////// Vertices ////////////////
+
+/// A vertex as `shaders/shader.vert` reads it: a 2D position and a color.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Vertex {
+    pub position: [f32; 2],
+    pub color: [f32; 3],
+}
+
+impl Vertex {
+    /// One tightly packed vertex per vertex, from binding 0.
+    pub fn binding_descriptions() -> [vk::VertexInputBindingDescription; 1] {
+        [vk::VertexInputBindingDescription::default()
+            .binding(0)
+            .stride(std::mem::size_of::<Self>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX)]
+    }
+
+    /// Position at location 0, color at location 1.
+    pub fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 2] {
+        [
+            vk::VertexInputAttributeDescription::default()
+                .binding(0)
+                .location(0)
+                .format(vk::Format::R32G32_SFLOAT)
+                .offset(offset_of!(Self, position) as u32),
+            vk::VertexInputAttributeDescription::default()
+                .binding(0)
+                .location(1)
+                .format(vk::Format::R32G32B32_SFLOAT)
+                .offset(offset_of!(Self, color) as u32),
+        ]
+    }
+}
+
+/// Per instance data as `shaders/shader.vert` reads it: where a copy of the mesh goes, and how big it is.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct InstanceData {
+    pub offset: [f32; 2],
+    pub scale: f32,
+}
+
+impl InstanceData {
+    /// One tightly packed entry per instance, from binding 1.
+    pub fn binding_descriptions() -> [vk::VertexInputBindingDescription; 1] {
+        [vk::VertexInputBindingDescription::default()
+            .binding(1)
+            .stride(std::mem::size_of::<Self>() as u32)
+            .input_rate(vk::VertexInputRate::INSTANCE)]
+    }
+
+    /// Offset at location 2, scale at location 3.
+    pub fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 2] {
+        [
+            vk::VertexInputAttributeDescription::default()
+                .binding(1)
+                .location(2)
+                .format(vk::Format::R32G32_SFLOAT)
+                .offset(offset_of!(Self, offset) as u32),
+            vk::VertexInputAttributeDescription::default()
+                .binding(1)
+                .location(3)
+                .format(vk::Format::R32_SFLOAT)
+                .offset(offset_of!(Self, scale) as u32),
+        ]
+    }
+
+    /// `count` copies side by side, scaled to fit next to each other. A single copy is left as it is.
+    pub fn row(count: usize) -> Vec<Self> {
+        let scale = 1.0 / count as f32;
+        (0..count)
+            .map(|index| Self {
+                offset: [(index as f32 + 0.5) * 2.0 * scale - 1.0, 0.0],
+                scale,
+            })
+            .collect()
+    }
+}
+
+/// The demo triangle, one red, green and blue corner each.
+pub const TRIANGLE: [Vertex; 3] = [
+    Vertex {
+        position: [0.0, -0.5],
+        color: [1.0, 0.0, 0.0],
+    },
+    Vertex {
+        position: [0.5, 0.5],
+        color: [0.0, 1.0, 0.0],
+    },
+    Vertex {
+        position: [-0.5, 0.5],
+        color: [0.0, 0.0, 1.0],
+    },
+];
+
+/// Indices into `TRIANGLE`.
+pub const TRIANGLE_INDICES: [u16; 3] = [0, 1, 2];
+
+/// A unit square around the origin, white, e.g. to draw a texture on.
+// Only the textured quad uses it.
+#[cfg(feature = "asset-import")]
+pub const QUAD: [Vertex; 4] = [
+    Vertex {
+        position: [-0.5, -0.5],
+        color: [1.0, 1.0, 1.0],
+    },
+    Vertex {
+        position: [0.5, -0.5],
+        color: [1.0, 1.0, 1.0],
+    },
+    Vertex {
+        position: [0.5, 0.5],
+        color: [1.0, 1.0, 1.0],
+    },
+    Vertex {
+        position: [-0.5, 0.5],
+        color: [1.0, 1.0, 1.0],
+    },
+];
+
+/// Indices into `QUAD`, two triangles wound like `TRIANGLE`.
+#[cfg(feature = "asset-import")]
+pub const QUAD_INDICES: [u16; 6] = [0, 1, 2, 2, 3, 0];
+
+/// The corners of a unit cube around the origin. Vertex only has 2D positions, so they're projected
+/// before drawing (see cube.rs).
+#[cfg(feature = "demos")]
+pub const CUBE_POSITIONS: [[f32; 3]; 8] = [
+    [-0.5, -0.5, -0.5],
+    [0.5, -0.5, -0.5],
+    [0.5, 0.5, -0.5],
+    [-0.5, 0.5, -0.5],
+    [-0.5, -0.5, 0.5],
+    [0.5, -0.5, 0.5],
+    [0.5, 0.5, 0.5],
+    [-0.5, 0.5, 0.5],
+];
+
+/// Indices into `CUBE_POSITIONS`, two triangles per face, counter-clockwise seen from outside
+/// (in a right-handed, Y up space).
+#[cfg(feature = "demos")]
+pub const CUBE_INDICES: [u16; 36] = [
+    0, 3, 2, 2, 1, 0, // -Z
+    4, 5, 6, 6, 7, 4, // +Z
+    0, 4, 7, 7, 3, 0, // -X
+    1, 2, 6, 6, 5, 1, // +X
+    0, 1, 5, 5, 4, 0, // -Y
+    3, 7, 6, 6, 2, 3, // +Y
+];
+
+/// A white arrow for the software cursor, in pixels, with its tip at the origin (Y down).
+#[cfg(feature = "ui")]
+pub const CURSOR: [Vertex; 3] = [
+    Vertex {
+        position: [0.0, 0.0],
+        color: [1.0, 1.0, 1.0],
+    },
+    Vertex {
+        position: [0.0, 18.0],
+        color: [1.0, 1.0, 1.0],
+    },
+    Vertex {
+        position: [12.0, 13.0],
+        color: [1.0, 1.0, 1.0],
+    },
+];
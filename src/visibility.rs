@@ -0,0 +1,233 @@
+use ash::{vk, Device};
+use std::error::Error;
+
+use crate::backend::{self, BackendKind, BackendSetup, GeometryPipelines, GeometryTargets};
+use crate::backend::{PipelineSetup, RendererBackend, Target};
+use crate::command::{Draw, DrawStatistics};
+use crate::descriptor::{self, DescriptorManager};
+use crate::memory::Allocator;
+use crate::reflect::ShaderInterface;
+use crate::util::AppError;
+use crate::vertex::InstanceData;
+
+//////////////// Visibility Buffer ////////////////
+// The geometry pass only writes which triangle covers each pixel (its instance and index, see
+// shaders/visibility.frag) and its depth. The resolve pass is a fullscreen draw in the main pass: for every
+// covered pixel it fetches that triangle's vertices and instance from the scene's buffers (bound as storage
+// buffers), transforms them again and interpolates the color at the pixel (see shaders/visibility_resolve.frag).
+// A prototype: the triangles' transform is repeated in the resolve shader, so changing shaders/shader.vert
+// means changing it there too, and only 2^16 - 1 instances of meshes with up to 2^16 triangles fit in an id
+// (`new` checks the scene against that).
+
+/// The shaders the pipelines are built from: the geometry pass's, then the resolve pass's.
+pub const SHADERS: [&str; 4] = [
+    "visibility.vert.spv",
+    "visibility.frag.spv",
+    "fullscreen.vert.spv",
+    "visibility_resolve.frag.spv",
+];
+
+/// Format of the triangle ids, 0 where there is no triangle.
+const ID_FORMAT: vk::Format = vk::Format::R32_UINT;
+/// Format of the depth the resolve pass writes.
+const DEPTH_FORMAT: vk::Format = vk::Format::R32_SFLOAT;
+/// How many instances and triangles per instance fit in an id: 16 bits each, the instance's offset by one.
+const MAX_INSTANCES: u64 = 0xffff;
+const MAX_TRIANGLES: u64 = 0x10000;
+
+/// The visibility buffer and the pipelines drawing into and resolving it.
+pub struct VisibilityBuffer {
+    targets: GeometryTargets,
+    geometry: GeometryPipelines,
+    resolve: (vk::Pipeline, vk::PipelineLayout),
+    // Owns the resolve pass's set layout and sets.
+    descriptors: DescriptorManager,
+    // Kept to rebuild the pipelines.
+    #[cfg(feature = "hot-reload")]
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    // One per frame in flight, for its uniform buffer. The targets and scene buffers are the same in all.
+    descriptor_sets: Vec<vk::DescriptorSet>,
+}
+
+impl VisibilityBuffer {
+    pub fn new(device: &Device, setup: &BackendSetup) -> Result<Self, Box<dyn Error>> {
+        let (vertices, indices, instances) = setup.scene;
+        let instance_count = instances.size / std::mem::size_of::<InstanceData>() as u64;
+        let triangle_count = indices.size / std::mem::size_of::<u16>() as u64 / 3;
+        if instance_count > MAX_INSTANCES || triangle_count > MAX_TRIANGLES {
+            return Err(Box::new(AppError::new(&format!(
+                "The scene has {} instances of {} triangles, the visibility buffer's ids fit at most {} of {}",
+                instance_count, triangle_count, MAX_INSTANCES, MAX_TRIANGLES
+            ))));
+        }
+
+        // Read back a texel at a time by the resolve pass.
+        let target = |format, clear_value| Target {
+            format,
+            clear_value,
+            usage: vk::ImageUsageFlags::STORAGE,
+        };
+        let target_descriptions = [
+            target(
+                ID_FORMAT,
+                vk::ClearValue {
+                    color: vk::ClearColorValue { uint32: [0; 4] },
+                },
+            ),
+            target(
+                DEPTH_FORMAT,
+                vk::ClearValue {
+                    color: vk::ClearColorValue { float32: [1.0; 4] },
+                },
+            ),
+        ];
+        let mut targets = GeometryTargets::new(
+            device,
+            setup.allocator,
+            &target_descriptions,
+            setup.depth_format,
+            setup.extent,
+        )?;
+        let mut descriptors = DescriptorManager::default();
+        // The layout is only kept to rebuild the pipelines with.
+        #[cfg_attr(not(feature = "hot-reload"), allow(unused_variables))]
+        let (descriptor_set_layout, descriptor_sets, (geometry, resolve)) =
+            match resolve_sets_and_pipelines(device, setup.pipelines, &mut descriptors, &targets) {
+                Ok(created) => created,
+                Err(err) => {
+                    descriptors.destroy(device);
+                    targets.destroy(device);
+                    return Err(err);
+                }
+            };
+
+        for (index, set) in descriptor_sets.iter().enumerate() {
+            descriptor::write_uniform_buffer(device, *set, 0, setup.uniform_buffers.buffer(index));
+            descriptor::write_storage_buffer(device, *set, 3, vertices);
+            descriptor::write_storage_buffer(device, *set, 4, indices);
+            descriptor::write_storage_buffer(device, *set, 5, instances);
+        }
+        let visibility_buffer = Self {
+            targets,
+            geometry,
+            resolve,
+            descriptors,
+            #[cfg(feature = "hot-reload")]
+            descriptor_set_layout,
+            descriptor_sets,
+        };
+        visibility_buffer.write_target_descriptors(device);
+        Ok(visibility_buffer)
+    }
+
+    /// Destroy the geometry and resolve pipelines. The GPU must be done with them.
+    fn destroy_pipelines(&mut self, device: &Device) {
+        self.geometry.destroy(device);
+        unsafe {
+            device.destroy_pipeline(self.resolve.0, None);
+            device.destroy_pipeline_layout(self.resolve.1, None);
+        }
+    }
+
+    /// Point every set's bindings 1 and 2 at the current targets.
+    fn write_target_descriptors(&self, device: &Device) {
+        for set in self.descriptor_sets.iter() {
+            for (index, view) in self.targets.views().into_iter().enumerate() {
+                descriptor::write_storage_image(device, *set, index as u32 + 1, view);
+            }
+        }
+    }
+}
+
+/// The geometry pass's pipelines, and the resolve pass's.
+type Pipelines = (GeometryPipelines, (vk::Pipeline, vk::PipelineLayout));
+
+/// The resolve pass's set layout and per frame sets (in `descriptors`), and the pipelines.
+fn resolve_sets_and_pipelines(
+    device: &Device,
+    setup: PipelineSetup<'_>,
+    descriptors: &mut DescriptorManager,
+    targets: &GeometryTargets,
+) -> Result<(vk::DescriptorSetLayout, Vec<vk::DescriptorSet>, Pipelines), Box<dyn Error>> {
+    let interface = ShaderInterface::from_shaders(setup.shader_cache, &SHADERS[2..])?;
+    let layout = descriptors.create_layout(device, &interface.set_bindings(0))?;
+    let sets = descriptors.allocate_per_frame(device, layout)?;
+    let pipelines = build_pipelines(device, setup, targets, layout)?;
+    Ok((layout, sets, pipelines))
+}
+
+/// Build the geometry pass's pipelines drawing into `targets`, and the resolve pass's reading them through
+/// a set of `layout`. If the resolve pass's fails, the others are destroyed.
+fn build_pipelines(
+    device: &Device,
+    setup: PipelineSetup<'_>,
+    targets: &GeometryTargets,
+    layout: vk::DescriptorSetLayout,
+) -> Result<Pipelines, Box<dyn Error>> {
+    let mut geometry =
+        GeometryPipelines::new(device, setup, [SHADERS[0], SHADERS[1]], (targets, 2))?;
+    match backend::resolve_pipeline(device, setup, [SHADERS[2], SHADERS[3]], layout) {
+        Ok(resolve) => Ok((geometry, resolve)),
+        Err(err) => {
+            geometry.destroy(device);
+            Err(err)
+        }
+    }
+}
+
+impl RendererBackend for VisibilityBuffer {
+    fn kind(&self) -> BackendKind {
+        BackendKind::VisibilityBuffer
+    }
+
+    fn record_geometry(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        triangles: &Draw,
+        wireframe: bool,
+    ) -> Option<DrawStatistics> {
+        let draw = self.geometry.draw(triangles, wireframe);
+        Some(self.targets.record(device, command_buffer, &[&[draw]]))
+    }
+
+    fn main_pass_draw<'a>(&'a self, frame_index: usize, _triangles: &Draw<'a>) -> Draw<'a> {
+        backend::resolve_draw(self.resolve, &self.descriptor_sets[frame_index])
+    }
+
+    fn resize(
+        &mut self,
+        device: &Device,
+        allocator: &Allocator,
+        extent: vk::Extent2D,
+    ) -> Result<(), Box<dyn Error>> {
+        self.targets.resize(device, allocator, extent)?;
+        self.write_target_descriptors(device);
+        Ok(())
+    }
+
+    #[cfg(feature = "hot-reload")]
+    fn shaders(&self) -> Vec<&'static str> {
+        SHADERS.to_vec()
+    }
+
+    #[cfg(feature = "hot-reload")]
+    fn rebuild_pipelines(
+        &mut self,
+        device: &Device,
+        setup: PipelineSetup<'_>,
+    ) -> Result<(), Box<dyn Error>> {
+        let (geometry, resolve) =
+            build_pipelines(device, setup, &self.targets, self.descriptor_set_layout)?;
+        self.destroy_pipelines(device);
+        self.geometry = geometry;
+        self.resolve = resolve;
+        Ok(())
+    }
+
+    fn destroy(&mut self, device: &Device) {
+        self.destroy_pipelines(device);
+        self.descriptors.destroy(device);
+        self.targets.destroy(device);
+    }
+}
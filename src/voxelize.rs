@@ -0,0 +1,222 @@
+use ash::{vk, Device};
+use std::error::Error;
+
+use crate::buffer::Buffer;
+use crate::command::{self, Draw, PassBegin};
+use crate::descriptor::{self, DescriptorManager};
+use crate::memory::Allocator;
+use crate::pipeline::GraphicsPipelineBuilder;
+use crate::reflect::ShaderInterface;
+use crate::render_target::Rendering;
+use crate::shader_cache::ShaderCache;
+use crate::sync::{Cancel, WaitError};
+use crate::util::DeviceDetails;
+
+//////////////// Voxelization ////////////////
+// Renders geometry into a GRID_SIZE³ grid of voxels: a render pass without attachments, sized to the
+// grid, whose fragment shader marks the voxel of every fragment in a storage buffer. Each triangle is
+// projected along the axis it faces the most, with the depth picking the slice (see shaders/voxelize.vert).
+// Without conservative rasterization, thin or small triangles miss voxels they only partly cover,
+// which leaves holes in the grid. Comparing both counts shows the difference.
+
+/// Voxels along each side of the grid.
+const GRID_SIZE: u32 = 32;
+
+const SHADERS: [&str; 2] = ["voxelize.vert.spv", "voxelize.frag.spv"];
+
+/// How many voxels `vertex_count` vertices from `vertex_buffer` (a triangle list of `vertex::Vertex`,
+/// read as a storage buffer) cover, rasterized normally and, if the device supports it
+/// (see `DeviceDetails::conservative_rasterization`), with OVERESTIMATE conservative rasterization.
+/// Needs the fragmentStoresAndAtomics feature. The descriptor set's layout lives in `descriptors`, the
+/// shader modules in `shader_cache`.
+pub fn count_voxels(
+    device: &Device,
+    allocator: &Allocator,
+    (command_pool, queue): (vk::CommandPool, vk::Queue),
+    cancel: Cancel,
+    (descriptors, shader_cache): (&mut DescriptorManager, &ShaderCache),
+    (vertex_buffer, vertex_count): (&Buffer, u32),
+    device_details: &DeviceDetails,
+) -> Result<(u32, Option<u32>), Box<dyn Error>> {
+    let voxel_count = (GRID_SIZE * GRID_SIZE * GRID_SIZE) as usize;
+    let mut voxels = Buffer::new(
+        device,
+        allocator,
+        (voxel_count * std::mem::size_of::<u32>()) as vk::DeviceSize,
+        vk::BufferUsageFlags::STORAGE_BUFFER,
+        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+    )?;
+
+    let mut render_pass = vk::RenderPass::null();
+    let mut framebuffer = vk::Framebuffer::null();
+    let counts = (|| -> Result<_, Box<dyn Error>> {
+        let subpasses =
+            [vk::SubpassDescription::default()
+                .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)];
+        let render_pass_create_info = vk::RenderPassCreateInfo::default().subpasses(&subpasses);
+        render_pass = unsafe { device.create_render_pass(&render_pass_create_info, None)? };
+
+        let framebuffer_create_info = vk::FramebufferCreateInfo::default()
+            .render_pass(render_pass)
+            .width(GRID_SIZE)
+            .height(GRID_SIZE)
+            .layers(1);
+        framebuffer = unsafe { device.create_framebuffer(&framebuffer_create_info, None)? };
+
+        let interface = ShaderInterface::from_shaders(shader_cache, &SHADERS)?;
+        let layout = descriptors.create_layout(device, &interface.set_bindings(0))?;
+        let descriptor_set = descriptors.allocate(device, layout, 1)?[0];
+        descriptor::write_storage_buffer(device, descriptor_set, 0, &voxels);
+        descriptor::write_storage_buffer(device, descriptor_set, 1, vertex_buffer);
+
+        let context = Context {
+            device,
+            shader_cache,
+            command_pool,
+            queue,
+            cancel,
+            render_pass,
+            framebuffer,
+            layout,
+            push_constant_ranges: interface.push_constant_ranges(),
+            descriptor_set,
+            vertex_count,
+        };
+        let regular = context.voxelize(&mut voxels, None)?;
+        let conservative = if device_details.conservative_rasterization {
+            let mode = vk::ConservativeRasterizationModeEXT::OVERESTIMATE;
+            Some(context.voxelize(&mut voxels, Some((mode, device_details)))?)
+        } else {
+            None
+        };
+
+        Ok((regular, conservative))
+    })();
+
+    // After a failed wait the GPU may still be using them, see `command::one_time_submit`.
+    if counts
+        .as_ref()
+        .is_err_and(|err| err.downcast_ref::<WaitError>().is_some())
+    {
+        return counts;
+    }
+    unsafe {
+        device.destroy_framebuffer(framebuffer, None);
+        device.destroy_render_pass(render_pass, None);
+    }
+    voxels.destroy(device);
+    counts
+}
+
+/// What every voxelization run shares.
+struct Context<'a> {
+    device: &'a Device,
+    shader_cache: &'a ShaderCache,
+    command_pool: vk::CommandPool,
+    queue: vk::Queue,
+    cancel: Cancel<'a>,
+    render_pass: vk::RenderPass,
+    framebuffer: vk::Framebuffer,
+    layout: vk::DescriptorSetLayout,
+    push_constant_ranges: Vec<vk::PushConstantRange>,
+    descriptor_set: vk::DescriptorSet,
+    vertex_count: u32,
+}
+
+impl Context<'_> {
+    /// Clear `voxels`, draw into it with a pipeline using `conservative_rasterization` (on a device with
+    /// those details), and count the occupied voxels. Blocks until the GPU is done.
+    fn voxelize(
+        &self,
+        voxels: &mut Buffer,
+        conservative_rasterization: Option<(vk::ConservativeRasterizationModeEXT, &DeviceDetails)>,
+    ) -> Result<u32, Box<dyn Error>> {
+        let device = self.device;
+        let voxel_count = (GRID_SIZE * GRID_SIZE * GRID_SIZE) as usize;
+        voxels.write(device, &vec![0u32; voxel_count])?;
+
+        let mut builder = GraphicsPipelineBuilder::default()
+            .vertex_shader(SHADERS[0])
+            .fragment_shader(SHADERS[1])
+            // The vertices are read from the buffer, see shaders/voxelize.vert.
+            .vertex_input(&[], &[])
+            .descriptor_set_layouts(std::slice::from_ref(&self.layout))
+            .push_constant_ranges(&self.push_constant_ranges)
+            .depth_test(false)
+            .cull_mode(vk::CullModeFlags::NONE)
+            .color_attachment(false);
+        if let Some((mode, device_details)) = conservative_rasterization {
+            builder = builder.conservative_rasterization(mode, device_details);
+        }
+        let (pipeline, pipeline_layout) = builder.build(
+            device,
+            self.shader_cache,
+            Rendering::RenderPass(self.render_pass),
+        )?;
+
+        let push_constants = GRID_SIZE.to_ne_bytes();
+        let draws = [Draw {
+            pipeline,
+            pipeline_layout,
+            descriptor_sets: std::slice::from_ref(&self.descriptor_set),
+            push_descriptors: None,
+            push_constants: Some((vk::ShaderStageFlags::FRAGMENT, &push_constants)),
+            vertex_buffer: None,
+            instance_buffer: None,
+            index_buffer: None,
+            vertex_count: self.vertex_count,
+        }];
+        let render_pass_begin_info = vk::RenderPassBeginInfo::default()
+            .render_pass(self.render_pass)
+            .framebuffer(self.framebuffer)
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: vk::Extent2D {
+                    width: GRID_SIZE,
+                    height: GRID_SIZE,
+                },
+            });
+        let submitted =
+            command::one_time_submit(device, self.command_pool, self.queue, self.cancel, |cb| {
+                let pass = PassBegin::RenderPass(&render_pass_begin_info);
+                command::record_render_pass(device, cb, pass, &draws);
+                // Make the shader writes visible to the read back below.
+                let memory_barriers = [vk::MemoryBarrier::default()
+                    .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                    .dst_access_mask(vk::AccessFlags::HOST_READ)];
+                unsafe {
+                    device.cmd_pipeline_barrier(
+                        cb,
+                        vk::PipelineStageFlags::FRAGMENT_SHADER,
+                        vk::PipelineStageFlags::HOST,
+                        vk::DependencyFlags::empty(),
+                        &memory_barriers,
+                        &[],
+                        &[],
+                    )
+                };
+            });
+        // After a failed wait the GPU may still be using it.
+        if !submitted
+            .as_ref()
+            .is_err_and(|err| err.downcast_ref::<WaitError>().is_some())
+        {
+            unsafe {
+                device.destroy_pipeline(pipeline, None);
+                device.destroy_pipeline_layout(pipeline_layout, None);
+            }
+        }
+        submitted?;
+
+        let occupied = unsafe {
+            let mapped =
+                device.map_memory(voxels.memory, 0, voxels.size, vk::MemoryMapFlags::empty())?;
+            let values = std::slice::from_raw_parts(mapped as *const u32, voxel_count);
+            let occupied = values.iter().filter(|&&value| value != 0).count();
+            device.unmap_memory(voxels.memory);
+            occupied
+        };
+
+        Ok(occupied as u32)
+    }
+}
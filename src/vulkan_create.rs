@@ -4,7 +4,7 @@ use ash::{
     vk::SurfaceKHR,
     Device,
 };
-use std::{error::Error, ffi::CStr, sync::Arc};
+use std::{error::Error, sync::Arc};
 use winit::{
     raw_window_handle::{HasDisplayHandle, HasWindowHandle},
     window::Window,
@@ -12,6 +12,10 @@ use winit::{
 
 use ash::{vk, Entry, Instance};
 
+use crate::image;
+use crate::render_pass::{RenderPassBuilder, Subpass};
+use crate::render_target::RenderTargets;
+use crate::spirv;
 use crate::util::{self, DeviceDetails, SwapChainSupportDetails};
 
 //////////////// Create Vulkan Things Helper Functions ////////////////
@@ -19,6 +23,7 @@ use crate::util::{self, DeviceDetails, SwapChainSupportDetails};
 /// Create Vulkan instance from an entry point, layers (e.g. validation), and extensions.
 pub fn instance(
     entry: &Entry,
+    api_version: u32,
     layer_names_ptrs: Vec<*const i8>,
     extension_names: Vec<*const i8>,
 ) -> Result<Instance, Box<dyn Error>> {
@@ -38,15 +43,11 @@ pub fn instance(
 
     // let extension_names = util::get_extension_names(Some(window.display_handle()?.as_raw()));
 
-    let app_info = unsafe {
-        vk::ApplicationInfo::default()
-            .api_version(vk::make_api_version(0, 1, 0, 0))
-            .application_name(CStr::from_bytes_with_nul_unchecked(
-                b"Tutorial Vulkan Application\0",
-            ))
-            .engine_name(CStr::from_bytes_with_nul_unchecked(b"No Engine\0"))
-            .engine_version(ash::vk::make_api_version(0, 1, 0, 0))
-    };
+    let app_info = vk::ApplicationInfo::default()
+        .api_version(api_version)
+        .application_name(c"Tutorial Vulkan Application")
+        .engine_name(c"No Engine")
+        .engine_version(ash::vk::make_api_version(0, 1, 0, 0));
 
     let instance_create_info = vk::InstanceCreateInfo::default()
         .application_info(&app_info)
@@ -57,11 +58,10 @@ pub fn instance(
     unsafe { Ok(entry.create_instance(&instance_create_info, None)?) }
 }
 
-/// Setup the debug message if validation layers are enabled.
+/// Setup the debug message if validation layers are enabled, through `debug_utils_loader`.
 pub fn debug_messenger(
-    entry: &Entry,
-    instance: &Instance,
-) -> Result<(vk::DebugUtilsMessengerEXT, debug_utils::Instance), Box<dyn Error>> {
+    debug_utils_loader: &debug_utils::Instance,
+) -> Result<vk::DebugUtilsMessengerEXT, Box<dyn Error>> {
     let debug_utils_create_info = vk::DebugUtilsMessengerCreateInfoEXT::default()
         .message_severity(
             // vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE | // They aren't joking
@@ -76,12 +76,11 @@ pub fn debug_messenger(
         )
         .pfn_user_callback(Some(util::vulkan_debug_callback));
 
-    let debug_utils_loader = debug_utils::Instance::new(entry, instance);
     unsafe {
         let debug_callback =
             debug_utils_loader.create_debug_utils_messenger(&debug_utils_create_info, None)?;
 
-        Ok((debug_callback, debug_utils_loader))
+        Ok(debug_callback)
     }
 }
 
@@ -106,12 +105,26 @@ pub fn surface(
     Ok((surface_khr, surface_loader))
 }
 
-/// Create the Vulkan Device with a graphics queue.
+/// The queues `logical_device_with_graphics_queue` creates, the first of their families.
+/// Graphics and present are the same queue if they're the same family.
+pub struct DeviceQueues {
+    pub graphics: vk::Queue,
+    pub present: vk::Queue,
+    /// On `DeviceDetails::transfer_queue_index`, if there is one.
+    pub transfer: Option<vk::Queue>,
+    /// On `DeviceDetails::compute_queue_index`, if there is one. Only the post process dispatches on it.
+    #[cfg(feature = "post-processing")]
+    pub compute: Option<vk::Queue>,
+}
+
+/// Create the Vulkan Device with a graphics queue (and present, transfer and compute queues, see `DeviceQueues`).
+/// Optional extensions and features (mutable swapchain format, display timing, fillModeNonSolid) are enabled
+/// if `device_details` says they are supported.
 pub fn logical_device_with_graphics_queue(
     instance: &Instance,
     device: vk::PhysicalDevice,
     device_details: &DeviceDetails,
-) -> Result<(Device, vk::Queue, vk::Queue), Box<dyn Error>> {
+) -> Result<(Device, DeviceQueues), Box<dyn Error>> {
     let (graphics_family_index, present_family_index) = (
         device_details.graphics_queue_index,
         device_details.present_queue_index,
@@ -122,6 +135,9 @@ pub fn logical_device_with_graphics_queue(
     let mut queue_create_infos: Vec<vk::DeviceQueueCreateInfo> = vec![];
 
     let mut queue_indices = vec![graphics_family_index, present_family_index];
+    queue_indices.extend(device_details.transfer_queue_index);
+    queue_indices.extend(device_details.compute_queue_index);
+    queue_indices.sort();
     queue_indices.dedup();
 
     for index in queue_indices.iter() {
@@ -132,27 +148,82 @@ pub fn logical_device_with_graphics_queue(
         queue_create_infos.push(queue_create_info);
     }
 
-    let device_extensions = util::REQUIRED_DEVICE_EXTENSIONS;
+    let mut device_extensions = util::REQUIRED_DEVICE_EXTENSIONS.to_vec();
+    if device_details.mutable_swapchain_format {
+        device_extensions.extend(util::MUTABLE_SWAPCHAIN_FORMAT_EXTENSIONS);
+    }
+    if device_details.display_timing {
+        device_extensions.push(util::DISPLAY_TIMING_EXTENSION);
+    }
+    if device_details.conservative_rasterization {
+        device_extensions.push(util::CONSERVATIVE_RASTERIZATION_EXTENSION);
+    }
+    if device_details.push_descriptor {
+        device_extensions.push(util::PUSH_DESCRIPTOR_EXTENSION);
+    }
+    if device_details.memory_budget {
+        device_extensions.push(util::MEMORY_BUDGET_EXTENSION);
+    }
+    if device_details.storage_buffer_storage_class && !device_details.vulkan_1_1 {
+        device_extensions.push(util::STORAGE_BUFFER_STORAGE_CLASS_EXTENSION);
+    }
     let device_extension_ptrs = device_extensions
         .iter()
         .map(|ext| ext.as_ptr())
         .collect::<Vec<_>>();
 
-    let device_features = vk::PhysicalDeviceFeatures::default();
-
-    let device_create_info = vk::DeviceCreateInfo::default()
+    let device_features = vk::PhysicalDeviceFeatures::default()
+        .fill_mode_non_solid(device_details.fill_mode_non_solid)
+        .sampler_anisotropy(device_details.sampler_anisotropy.is_some())
+        .depth_bounds(device_details.depth_bounds)
+        .depth_clamp(device_details.depth_clamp)
+        .fragment_stores_and_atomics(device_details.fragment_stores_and_atomics)
+        .tessellation_shader(device_details.tessellation_shader)
+        .geometry_shader(device_details.geometry_shader);
+
+    let mut vulkan_13_features =
+        vk::PhysicalDeviceVulkan13Features::default().dynamic_rendering(true);
+    let mut device_create_info = vk::DeviceCreateInfo::default()
         .queue_create_infos(&queue_create_infos)
         .enabled_features(&device_features)
         .enabled_extension_names(&device_extension_ptrs);
+    if device_details.dynamic_rendering {
+        device_create_info = device_create_info.push_next(&mut vulkan_13_features);
+    }
+    let mut vulkan_11_features = vk::PhysicalDeviceVulkan11Features::default()
+        .storage_buffer16_bit_access(true)
+        .uniform_and_storage_buffer16_bit_access(true);
+    if device_details.storage_16bit {
+        device_create_info = device_create_info.push_next(&mut vulkan_11_features);
+    }
+    let mut vulkan_12_features = vk::PhysicalDeviceVulkan12Features::default().shader_float16(true);
+    if device_details.shader_float16 {
+        device_create_info = device_create_info.push_next(&mut vulkan_12_features);
+    }
 
     let device = unsafe { instance.create_device(device, &device_create_info, None)? };
 
-    let graphics_queue = unsafe { device.get_device_queue(graphics_family_index, 0) };
-    let present_queue = unsafe { device.get_device_queue(present_family_index, 0) };
+    let queue = |family_index: u32| unsafe { device.get_device_queue(family_index, 0) };
+    let queues = DeviceQueues {
+        graphics: queue(graphics_family_index),
+        present: queue(present_family_index),
+        transfer: device_details.transfer_queue_index.map(queue),
+        #[cfg(feature = "post-processing")]
+        compute: device_details.compute_queue_index.map(queue),
+    };
 
-    Ok((device, graphics_queue, present_queue))
+    Ok((device, queues))
 }
 
+/// (loader, swapchain, format, extent, images) is a lot to write out in a return type.
+type SwapchainAndImages = (
+    swapchain::Device,
+    vk::SwapchainKHR,
+    vk::Format,
+    vk::Extent2D,
+    Vec<vk::Image>,
+);
+
 /// Construct swapchain and image views.
 pub fn swapchain_and_images(
     instance: &Instance,
@@ -161,22 +232,14 @@ pub fn swapchain_and_images(
     device: &Device,
     surface: &surface::Instance,
     surface_khr: SurfaceKHR,
-) -> Result<
-    (
-        swapchain::Device,
-        vk::SwapchainKHR,
-        vk::Format,
-        vk::Extent2D,
-        Vec<vk::Image>,
-    ),
-    Box<dyn Error>,
-> {
+    window_extent: vk::Extent2D,
+) -> Result<SwapchainAndImages, Box<dyn Error>> {
     let swapchain_support_details =
         SwapChainSupportDetails::new(physical_device, surface, surface_khr)?;
 
-    let format = swapchain_support_details.choose_swapchain_surface_format();
-    let present_mode = swapchain_support_details.choose_swapchain_surface_present_mode();
-    let extent = swapchain_support_details.choose_swapchain_extent();
+    let (format, _) = swapchain_support_details.choose_swapchain_surface_format()?;
+    let (present_mode, _) = swapchain_support_details.choose_swapchain_surface_present_mode()?;
+    let (extent, _) = swapchain_support_details.choose_swapchain_extent(window_extent);
 
     let image_count = {
         let max = swapchain_support_details.capabilities.max_image_count;
@@ -194,10 +257,27 @@ pub fn swapchain_and_images(
     log::debug!("   - Extent: {:?}", extent);
     log::debug!("   - ImageCount: {:?}", image_count);
 
-    let families_indices = [
+    // The post process on a dedicated compute queue reads the images too, see post.rs.
+    let mut families_indices = vec![
         device_details.graphics_queue_index,
         device_details.present_queue_index,
     ];
+    families_indices.extend(device_details.post_process_compute_family());
+    families_indices.sort_unstable();
+    families_indices.dedup();
+
+    let view_formats = swapchain_view_formats(device_details, format.format);
+    let mut format_list_info = vk::ImageFormatListCreateInfo::default();
+    if let Some(view_formats) = &view_formats {
+        log::debug!("   - ViewFormats: {:?}", view_formats);
+        format_list_info = format_list_info.view_formats(view_formats);
+    }
+
+    let mut image_usage = vk::ImageUsageFlags::COLOR_ATTACHMENT;
+    if device_details.compute_post_process {
+        // Read by the post process, which then blits its result back.
+        image_usage |= vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST;
+    }
 
     let swapchain_create_info = {
         let mut swapchain_create_info = vk::SwapchainCreateInfoKHR::default()
@@ -207,18 +287,22 @@ pub fn swapchain_and_images(
             .image_color_space(format.color_space)
             .image_extent(extent)
             .image_array_layers(1)
-            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT);
+            .image_usage(image_usage);
 
-        swapchain_create_info = match (
-            device_details.graphics_queue_index,
-            device_details.present_queue_index,
-        ) {
-            (graphics, present) if graphics != present => swapchain_create_info
+        swapchain_create_info = if families_indices.len() > 1 {
+            swapchain_create_info
                 .image_sharing_mode(vk::SharingMode::CONCURRENT)
-                .queue_family_indices(&families_indices),
-            (_, _) => swapchain_create_info.image_sharing_mode(vk::SharingMode::EXCLUSIVE),
+                .queue_family_indices(&families_indices)
+        } else {
+            swapchain_create_info.image_sharing_mode(vk::SharingMode::EXCLUSIVE)
         };
 
+        if view_formats.is_some() {
+            swapchain_create_info = swapchain_create_info
+                .flags(vk::SwapchainCreateFlagsKHR::MUTABLE_FORMAT)
+                .push_next(&mut format_list_info);
+        }
+
         swapchain_create_info
             .pre_transform(swapchain_support_details.capabilities.current_transform)
             .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
@@ -241,35 +325,224 @@ pub fn swapchain_and_images(
     ))
 }
 
-/// Create image views from swapchain images.
+/// Formats the swapchain images can be viewed as when created with MUTABLE_FORMAT,
+/// or None if the device can't do that (or `format` has no UNORM/SRGB counterpart).
+fn swapchain_view_formats(
+    device_details: &DeviceDetails,
+    format: vk::Format,
+) -> Option<[vk::Format; 2]> {
+    if device_details.mutable_swapchain_format {
+        util::srgb_format_pair(format)
+    } else {
+        None
+    }
+}
+
+/// Create image views from swapchain images: one in `swapchain_format`, plus one in its UNORM/SRGB
+/// counterpart if the swapchain was created with MUTABLE_FORMAT.
 pub fn swapchain_image_views(
     device: &Device,
+    device_details: &DeviceDetails,
     swapchain_images: &[vk::Image],
     swapchain_format: vk::Format,
-) -> Result<Vec<vk::ImageView>, Box<dyn Error>> {
-    let mut image_views: Vec<vk::ImageView> = Vec::new();
-    for image in swapchain_images.into_iter() {
-        let image_view_create_info = vk::ImageViewCreateInfo::default()
-            .image(*image)
-            .view_type(vk::ImageViewType::TYPE_2D)
-            .format(swapchain_format)
-            .components(vk::ComponentMapping {
-                r: vk::ComponentSwizzle::IDENTITY,
-                g: vk::ComponentSwizzle::IDENTITY,
-                b: vk::ComponentSwizzle::IDENTITY,
-                a: vk::ComponentSwizzle::IDENTITY,
-            })
-            .subresource_range(vk::ImageSubresourceRange {
-                aspect_mask: vk::ImageAspectFlags::COLOR,
-                base_mip_level: 0,
-                level_count: 1,
-                base_array_layer: 0,
-                layer_count: 1,
-            });
-
-        let image_view = unsafe { device.create_image_view(&image_view_create_info, None)? };
-        image_views.push(image_view);
+) -> Result<Vec<image::SwapchainImageViews>, Box<dyn Error>> {
+    let alternate_format =
+        swapchain_view_formats(device_details, swapchain_format).and_then(|view_formats| {
+            view_formats
+                .into_iter()
+                .find(|format| *format != swapchain_format)
+        });
+
+    let mut image_views: Vec<image::SwapchainImageViews> = Vec::new();
+    for image in swapchain_images.iter() {
+        let view = image::image_view(
+            device,
+            *image,
+            swapchain_format,
+            vk::ImageAspectFlags::COLOR,
+            1,
+        )?;
+        let alternate = match alternate_format {
+            Some(format) => Some((
+                format,
+                image::image_view(device, *image, format, vk::ImageAspectFlags::COLOR, 1)?,
+            )),
+            None => None,
+        };
+        image_views.push(image::SwapchainImageViews {
+            format: swapchain_format,
+            view,
+            alternate,
+        });
     }
 
     Ok(image_views)
 }
+
+/// Create a render pass with a color attachment (the swapchain image) and a depth attachment,
+/// loaded and stored as `targets` says.
+/// With more than one sample, color and depth are multisampled and the color attachment is resolved
+/// into a third attachment, the swapchain image. `targets.color.store_op` then applies to the resolved image.
+pub fn render_pass(
+    device: &Device,
+    swapchain_format: vk::Format,
+    depth_format: vk::Format,
+    samples: vk::SampleCountFlags,
+    targets: &RenderTargets,
+) -> Result<vk::RenderPass, Box<dyn Error>> {
+    let multisampled = samples != vk::SampleCountFlags::TYPE_1;
+
+    // Stencil follows depth if the depth buffer has a stencil component.
+    let (stencil_load_op, stencil_store_op) = if image::has_stencil_component(depth_format) {
+        (targets.depth.effective_load_op(), targets.depth.store_op)
+    } else {
+        (
+            vk::AttachmentLoadOp::DONT_CARE,
+            vk::AttachmentStoreOp::DONT_CARE,
+        )
+    };
+
+    let mut attachment_descs = vec![
+        vk::AttachmentDescription::default()
+            .format(swapchain_format)
+            .samples(samples)
+            .load_op(targets.color.effective_load_op())
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED),
+        vk::AttachmentDescription::default()
+            .format(depth_format)
+            .samples(samples)
+            .load_op(targets.depth.effective_load_op())
+            .store_op(targets.depth.store_op)
+            .stencil_load_op(stencil_load_op)
+            .stencil_store_op(stencil_store_op)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL),
+    ];
+    if multisampled {
+        // The samples are only needed until they're resolved at the end of the subpass.
+        attachment_descs[0] = attachment_descs[0]
+            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+        // Every pixel is written by the resolve, so there is nothing to load.
+        attachment_descs.push(
+            vk::AttachmentDescription::default()
+                .format(swapchain_format)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .load_op(vk::AttachmentLoadOp::DONT_CARE)
+                .store_op(targets.color.store_op)
+                .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .final_layout(vk::ImageLayout::PRESENT_SRC_KHR),
+        );
+    } else {
+        attachment_descs[0] = attachment_descs[0]
+            .store_op(targets.color.store_op)
+            .final_layout(vk::ImageLayout::PRESENT_SRC_KHR);
+    }
+
+    let mut subpass = Subpass::default().color(&[0]).depth_stencil(1);
+    if multisampled {
+        subpass = subpass.resolve(&[2]);
+    }
+
+    // Wait for the swapchain image to be released by the presentation engine before writing to it,
+    // and for the previous frame's depth writes before clearing the (shared) depth buffer.
+    let external_dependency = vk::SubpassDependency::default()
+        .src_subpass(vk::SUBPASS_EXTERNAL)
+        .dst_subpass(0)
+        .src_stage_mask(
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+        )
+        .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+        .dst_stage_mask(
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+        )
+        .dst_access_mask(
+            vk::AccessFlags::COLOR_ATTACHMENT_READ
+                | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        );
+
+    attachment_descs
+        .into_iter()
+        .fold(RenderPassBuilder::default(), RenderPassBuilder::attachment)
+        .subpass(subpass)
+        .dependency(external_dependency)
+        .build(device)
+}
+
+/// Create a shader module from `code`, loaded from the SPIR-V file `name` (see shader.rs).
+/// In debug builds the SPIR-V is validated first. Pipelines get theirs from shader_cache.rs.
+pub fn shader_module(
+    device: &Device,
+    name: &str,
+    code: &[u32],
+) -> Result<vk::ShaderModule, Box<dyn Error>> {
+    if cfg!(debug_assertions) {
+        spirv::validate(name, code)?;
+    }
+
+    let shader_module_create_info = vk::ShaderModuleCreateInfo::default().code(code);
+
+    let shader_module = unsafe { device.create_shader_module(&shader_module_create_info, None) }
+        .map_err(|err| {
+            util::AppError::new(&format!("Failed to create shader module {}: {}", name, err))
+        })?;
+    Ok(shader_module)
+}
+
+/// Create one framebuffer per swapchain image view, all targeting the same render pass.
+/// The depth buffer is shared between them (only one frame uses it at a time, see the render pass dependency).
+/// With multisampling, `color_image_view` is the multisampled target and the swapchain image is the resolve attachment.
+pub fn framebuffers(
+    device: &Device,
+    swapchain_image_views: &[vk::ImageView],
+    color_image_view: Option<vk::ImageView>,
+    depth_image_view: vk::ImageView,
+    render_pass: vk::RenderPass,
+    swapchain_extent: vk::Extent2D,
+) -> Result<Vec<vk::Framebuffer>, Box<dyn Error>> {
+    let mut framebuffers: Vec<vk::Framebuffer> = Vec::new();
+    for image_view in swapchain_image_views.iter() {
+        let attachments = match color_image_view {
+            Some(color_image_view) => vec![color_image_view, depth_image_view, *image_view],
+            None => vec![*image_view, depth_image_view],
+        };
+        let framebuffer_create_info = vk::FramebufferCreateInfo::default()
+            .render_pass(render_pass)
+            .attachments(&attachments)
+            .width(swapchain_extent.width)
+            .height(swapchain_extent.height)
+            .layers(1);
+
+        let framebuffer = unsafe { device.create_framebuffer(&framebuffer_create_info, None)? };
+        framebuffers.push(framebuffer);
+    }
+
+    Ok(framebuffers)
+}
+
+/// Create a (binary) semaphore for GPU-GPU synchronization.
+pub fn semaphore(device: &Device) -> Result<vk::Semaphore, Box<dyn Error>> {
+    let semaphore_create_info = vk::SemaphoreCreateInfo::default();
+
+    unsafe { Ok(device.create_semaphore(&semaphore_create_info, None)?) }
+}
+
+/// Create a fence for GPU-CPU synchronization.
+/// Create it `signaled` if the first wait on it should return immediately.
+pub fn fence(device: &Device, signaled: bool) -> Result<vk::Fence, Box<dyn Error>> {
+    let flags = if signaled {
+        vk::FenceCreateFlags::SIGNALED
+    } else {
+        vk::FenceCreateFlags::empty()
+    };
+    let fence_create_info = vk::FenceCreateInfo::default().flags(flags);
+
+    unsafe { Ok(device.create_fence(&fence_create_info, None)?) }
+}